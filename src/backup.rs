@@ -0,0 +1,156 @@
+// Daily snapshots of local.db in ~/.risu/backups, a safety net independent of
+// sync: the first launch of each calendar day writes `daily-YYYY-MM-DD.db`
+// and prunes all but the newest `general.daily_backups`. `risu db
+// restore-snapshot` puts one back.
+
+use crate::config;
+use crate::db::Repo;
+use anyhow::{bail, Context, Result};
+use chrono::{Local, Utc};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// kv_store key for the last day a snapshot was considered, and what the
+// notes looked like then: `{"date": "YYYY-MM-DD", "fingerprint": ...}`
+const KV_KEY: &str = "daily_backup";
+const PREFIX: &str = "daily-";
+
+#[derive(Serialize, Deserialize)]
+struct LastBackup {
+    date: String,
+    fingerprint: String,
+}
+
+/// One file in the backups folder, as listed by `risu db list-snapshots`.
+pub struct Snapshot {
+    pub path: PathBuf,
+    /// `YYYY-MM-DD`, from the file name
+    pub date: String,
+    pub size: u64,
+    /// Live notes in the copy; None when it can't be opened
+    pub notes: Option<i64>,
+}
+
+pub fn backups_dir() -> PathBuf {
+    config::get_config_dir().join("backups")
+}
+
+/// Today's snapshot, unless one was already taken today or no note changed
+/// since the last one. Returns the file written, if any.
+pub async fn run_daily(repo: &Repo, keep: usize) -> Result<Option<PathBuf>> {
+    if keep == 0 {
+        return Ok(None);
+    }
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let last = repo
+        .get_kv(KV_KEY)
+        .await?
+        .and_then(|json| serde_json::from_str::<LastBackup>(&json).ok());
+    if last.as_ref().is_some_and(|l| l.date == today) {
+        return Ok(None);
+    }
+
+    let fingerprint = repo.change_fingerprint().await?;
+    let written = if last.as_ref().is_some_and(|l| l.fingerprint == fingerprint) {
+        None
+    } else {
+        let dir = backups_dir();
+        std::fs::create_dir_all(&dir).context("Failed to create backups directory")?;
+        let path = dir.join(format!("{}{}.db", PREFIX, today));
+        // VACUUM INTO won't overwrite; a file here is from a failed run
+        let _ = std::fs::remove_file(&path);
+        repo.snapshot_to(path.clone()).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Some(path)
+    };
+
+    let last = LastBackup {
+        date: today,
+        fingerprint,
+    };
+    repo.set_kv(KV_KEY, &serde_json::to_string(&last)?).await?;
+    prune(keep)?;
+    Ok(written)
+}
+
+/// Snapshots oldest first. Each is opened read-only to count its notes.
+pub fn list() -> Result<Vec<Snapshot>> {
+    let entries = match std::fs::read_dir(backups_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Failed to read backups directory"),
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(date) = snapshot_date(&path) else {
+            continue;
+        };
+        let size = std::fs::metadata(&path)?.len();
+        let notes = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .and_then(|conn| {
+                conn.query_row(
+                    "SELECT count(*) FROM notes WHERE is_deleted = 0",
+                    [],
+                    |row| row.get(0),
+                )
+            })
+            .ok();
+        snapshots.push(Snapshot {
+            path,
+            date,
+            size,
+            notes,
+        });
+    }
+    snapshots.sort_by(|a, b| a.date.cmp(&b.date));
+    Ok(snapshots)
+}
+
+/// The snapshot taken on `date` (`YYYY-MM-DD`).
+pub fn find(date: &str) -> Result<Snapshot> {
+    list()?
+        .into_iter()
+        .find(|s| s.date == date)
+        .with_context(|| format!("No snapshot from {}. See `risu db list-snapshots`.", date))
+}
+
+/// Replaces the database with `snapshot`. A sync in another process holds
+/// the lease all through its pass, so taking it first means none is running,
+/// and none can start until the copy is in place.
+pub async fn restore(repo: &Repo, snapshot: &Snapshot) -> Result<()> {
+    let owner = format!("restore-{}", std::process::id());
+    let until = Utc::now() + chrono::Duration::minutes(10);
+    if !repo.take_sync_lease(&owner, until).await? {
+        bail!("Another risu process is syncing this database. Quit it and try again.");
+    }
+    let restored = repo.restore_from(snapshot.path.clone()).await;
+    // Only still held if the restore failed
+    let _ = repo.release_sync_lease(&owner).await;
+    restored
+}
+
+fn prune(keep: usize) -> Result<()> {
+    let snapshots = list()?;
+    let excess = snapshots.len().saturating_sub(keep);
+    for snapshot in &snapshots[..excess] {
+        std::fs::remove_file(&snapshot.path)
+            .with_context(|| format!("Failed to remove {:?}", snapshot.path))?;
+    }
+    Ok(())
+}
+
+fn snapshot_date(path: &std::path::Path) -> Option<String> {
+    let date = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(PREFIX)?
+        .strip_suffix(".db")?;
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(date.to_string())
+}
@@ -0,0 +1,180 @@
+// Blockwise (rectangular) edits for visual block mode. tui-textarea only knows
+// linear selections, so these work on plain lines and the caller swaps the
+// affected rows back into the textarea. Columns are char indices.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockSelection {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+    // Set by `$`: every row extends to its own line end
+    pub to_eol: bool,
+}
+
+impl BlockSelection {
+    pub fn new(anchor: (usize, usize), cursor: (usize, usize), to_eol: bool) -> Self {
+        Self {
+            top: anchor.0.min(cursor.0),
+            bottom: anchor.0.max(cursor.0),
+            left: anchor.1.min(cursor.1),
+            right: anchor.1.max(cursor.1),
+            to_eol,
+        }
+    }
+
+    // Char range of `line` covered by the block, clamped to the line length
+    pub fn span(&self, line: &str) -> (usize, usize) {
+        let len = line.chars().count();
+        let end = if self.to_eol {
+            len
+        } else {
+            (self.right + 1).min(len)
+        };
+        (self.left.min(len), end)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsertEdge {
+    // `I`: lines that don't reach the column are left alone
+    Left(usize),
+    // `A`: short lines are padded with spaces up to the column
+    Right(usize),
+    // `A` on a `$` block: appended to each line end
+    LineEnd,
+}
+
+fn byte_index(line: &str, ch: usize) -> usize {
+    line.char_indices().nth(ch).map_or(line.len(), |(i, _)| i)
+}
+
+pub fn yank(lines: &[String], block: &BlockSelection) -> Vec<String> {
+    lines[block.top..=block.bottom]
+        .iter()
+        .map(|line| {
+            let (start, end) = block.span(line);
+            line[byte_index(line, start)..byte_index(line, end)].to_string()
+        })
+        .collect()
+}
+
+// Returns the replacement for rows `top..=bottom`
+pub fn delete(lines: &[String], block: &BlockSelection) -> Vec<String> {
+    lines[block.top..=block.bottom]
+        .iter()
+        .map(|line| {
+            let (start, end) = block.span(line);
+            let mut out = line[..byte_index(line, start)].to_string();
+            out.push_str(&line[byte_index(line, end)..]);
+            out
+        })
+        .collect()
+}
+
+pub fn insert(rows: &[String], edge: InsertEdge, text: &str) -> Vec<String> {
+    rows.iter()
+        .map(|line| {
+            let len = line.chars().count();
+            let mut out = line.clone();
+            match edge {
+                InsertEdge::Left(col) if len > col => {
+                    out.insert_str(byte_index(line, col), text);
+                }
+                InsertEdge::Left(_) => {}
+                InsertEdge::Right(col) => {
+                    if len < col {
+                        out.push_str(&" ".repeat(col - len));
+                    }
+                    out.insert_str(byte_index(&out, col), text);
+                }
+                InsertEdge::LineEnd => out.push_str(text),
+            }
+            out
+        })
+        .collect()
+}
+
+// Pastes `pieces` as a column starting at (`row`, `col`). Returns the replacement
+// for rows `row..` up to the last existing row touched, plus any rows appended
+// past the end of the buffer.
+pub fn paste(lines: &[String], row: usize, col: usize, pieces: &[String]) -> Vec<String> {
+    let width = pieces.iter().map(|p| p.chars().count()).max().unwrap_or(0);
+    pieces
+        .iter()
+        .enumerate()
+        .map(|(i, piece)| {
+            let mut line = lines.get(row + i).cloned().unwrap_or_default();
+            let len = line.chars().count();
+            if len < col {
+                line.push_str(&" ".repeat(col - len));
+            }
+            // Keep text to the right of the block aligned
+            let mut piece = piece.clone();
+            if len > col {
+                piece.push_str(&" ".repeat(width - piece.chars().count()));
+            }
+            line.insert_str(byte_index(&line, col), &piece);
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn selection_is_the_same_from_either_corner() {
+        let block = BlockSelection::new((2, 4), (0, 2), false);
+        assert_eq!(block, BlockSelection::new((0, 2), (2, 4), false));
+        assert_eq!((block.top, block.bottom), (0, 2));
+        assert_eq!((block.left, block.right), (2, 4));
+        assert_eq!(block.span("0123456"), (2, 5));
+        assert_eq!(block.span("01"), (2, 2));
+        assert_eq!(block.span("0123"), (2, 4));
+    }
+
+    #[test]
+    fn yank_and_delete_cut_the_same_columns() {
+        let text = lines(&["0123456", "01", "日本語テキスト"]);
+        let block = BlockSelection::new((0, 2), (2, 4), false);
+        assert_eq!(yank(&text, &block), ["234", "", "語テキ"]);
+        assert_eq!(delete(&text, &block), ["0156", "01", "日本スト"]);
+
+        let to_eol = BlockSelection::new((0, 2), (2, 2), true);
+        assert_eq!(yank(&text, &to_eol), ["23456", "", "語テキスト"]);
+        assert_eq!(delete(&text, &to_eol), ["01", "01", "日本"]);
+    }
+
+    #[test]
+    fn insert_left_skips_short_lines_and_right_pads_them() {
+        let rows = lines(&["abc", "a", "ab", "日本語"]);
+        assert_eq!(
+            insert(&rows, InsertEdge::Left(2), "X"),
+            ["abXc", "a", "ab", "日本X語"]
+        );
+        assert_eq!(
+            insert(&rows, InsertEdge::Right(3), "X"),
+            ["abcX", "a  X", "ab X", "日本語X"]
+        );
+        assert_eq!(
+            insert(&rows, InsertEdge::LineEnd, ";"),
+            ["abc;", "a;", "ab;", "日本語;"]
+        );
+    }
+
+    #[test]
+    fn paste_keeps_text_right_of_the_block_aligned() {
+        let text = lines(&["abcd", "ab"]);
+        let pieces = lines(&["X", "YZ", "W"]);
+        // The third piece goes past the end of the buffer
+        assert_eq!(paste(&text, 0, 2, &pieces), ["abX cd", "abYZ", "  W"]);
+        assert_eq!(paste(&text, 1, 4, &pieces[..1]), ["ab  X"]);
+        assert_eq!(paste(&lines(&["日本語"]), 0, 1, &pieces[..1]), ["日X本語"]);
+    }
+}
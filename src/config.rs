@@ -1,9 +1,11 @@
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
@@ -33,7 +35,7 @@ pub fn get_user_id_from_token(token: &str) -> anyhow::Result<String> {
     Ok(sub.to_string())
 }
 
-pub fn get_user_email_from_token(token: &str) -> anyhow::Result<String> {
+fn decode_token_claims(token: &str) -> anyhow::Result<serde_json::Value> {
     let parts: Vec<&str> = token.split('.').collect();
     if parts.len() != 3 {
         return Err(anyhow::anyhow!("Invalid token format"));
@@ -41,7 +43,11 @@ pub fn get_user_email_from_token(token: &str) -> anyhow::Result<String> {
 
     let payload = parts[1];
     let decoded = URL_SAFE_NO_PAD.decode(payload)?;
-    let claims: serde_json::Value = serde_json::from_slice(&decoded)?;
+    Ok(serde_json::from_slice(&decoded)?)
+}
+
+pub fn get_user_email_from_token(token: &str) -> anyhow::Result<String> {
+    let claims = decode_token_claims(token)?;
 
     let email = claims["email"]
         .as_str()
@@ -49,6 +55,48 @@ pub fn get_user_email_from_token(token: &str) -> anyhow::Result<String> {
     Ok(email.to_string())
 }
 
+/// `exp` / `iat` claims of an id token, as unix seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenTimes {
+    pub expires_at: i64,
+    pub issued_at: Option<i64>,
+}
+
+pub fn get_token_times(token: &str) -> anyhow::Result<TokenTimes> {
+    let claims = decode_token_claims(token)?;
+
+    let expires_at = claims["exp"]
+        .as_i64()
+        .ok_or_else(|| anyhow::anyhow!("No exp in token"))?;
+    Ok(TokenTimes {
+        expires_at,
+        issued_at: claims["iat"].as_i64(),
+    })
+}
+
+/// Checks a token pasted by hand before it's saved: a JWT whose payload names
+/// a user and hasn't expired. The signature is left to the server.
+pub fn validate_token(token: &str) -> anyhow::Result<()> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "Not a login token (expected three parts separated by dots)"
+        ));
+    }
+    let claims = decode_token_claims(token)
+        .map_err(|_| anyhow::anyhow!("Token payload couldn't be read; was it copied whole?"))?;
+    if claims["sub"].as_str().is_none() {
+        return Err(anyhow::anyhow!("Token has no user id"));
+    }
+    match claims["exp"].as_i64() {
+        Some(exp) if exp <= chrono::Utc::now().timestamp() => {
+            Err(anyhow::anyhow!("Token has expired; get a new one"))
+        }
+        Some(_) => Ok(()),
+        None => Err(anyhow::anyhow!("Token has no expiry")),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum TokenSource {
     File,
@@ -80,13 +128,196 @@ pub struct AppConfig {
     #[serde(default)]
     pub general: GeneralConfig,
     #[serde(default)]
+    pub editor: EditorConfig,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub timer: TimerConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
     pub theme: ThemeConfig,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncConfig {
+    // Days a synced deletion is kept locally before its row is removed, after
+    // a sync and on startup; 0 keeps them forever
+    #[serde(default = "default_tombstone_retention_days")]
+    pub tombstone_retention_days: u64,
+    // Stamp saves with the server's time when the local clock is measurably off
+    #[serde(default)]
+    pub compensate_clock_skew: bool,
+    // Push color labels with the note (unencrypted); off keeps them on this device
+    #[serde(default)]
+    pub labels: bool,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            tombstone_retention_days: default_tombstone_retention_days(),
+            compensate_clock_skew: false,
+            labels: false,
+        }
+    }
+}
+
+fn default_tombstone_retention_days() -> u64 {
+    30
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimerConfig {
+    // Length of a focus block started without a number
+    #[serde(default = "default_timer_minutes")]
+    pub minutes: u64,
+    // Title of the note finished blocks are logged to, created if missing;
+    // empty logs nothing
+    #[serde(default = "default_timer_log_note")]
+    pub log_note: String,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            minutes: default_timer_minutes(),
+            log_note: default_timer_log_note(),
+        }
+    }
+}
+
+fn default_timer_minutes() -> u64 {
+    25
+}
+
+fn default_timer_log_note() -> String {
+    "Focus Log".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DatabaseConfig {
+    // Seconds to wait for the local database before giving up on a request; 0
+    // waits forever. Kept well above SQLite's 5 s busy timeout, so a request
+    // only times out when the database is stuck rather than just locked.
+    // Bulk work and sync passes always wait.
+    #[serde(default = "default_db_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_db_timeout_secs(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs)
+    }
+}
+
+fn default_db_timeout_secs() -> u64 {
+    15
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct EditorConfig {
+    #[serde(default)]
+    pub match_brackets: bool,
+    #[serde(default)]
+    pub auto_pairs: bool,
+    // Lines of context kept above and below the cursor
+    #[serde(default)]
+    pub scrolloff: usize,
+    #[serde(default)]
+    pub esc_behavior: EscBehavior,
+    // Load notes with very long lines as they are instead of split, read-only
+    #[serde(default)]
+    pub keep_long_lines: bool,
+    // On save: LF line endings, no trailing blanks but a two-space hard
+    // break, at most two blank lines in a row, one final newline
+    #[serde(default)]
+    pub normalize_on_save: bool,
+}
+
+/// What Esc in Normal mode does with unsaved edits when leaving the editor
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EscBehavior {
+    #[default]
+    Save,
+    Prompt,
+    Discard,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GeneralConfig {
     #[serde(default)]
     pub offline_mode: bool,
+    #[serde(default = "default_true")]
+    pub restore_session: bool,
+    #[serde(default)]
+    pub sort_by: NoteSort,
+    // Folder of markdown files mirroring the notes; empty turns the mirror off
+    #[serde(default)]
+    pub notes_dir: String,
+    // Deleting a file from notes_dir deletes its note, on every device once
+    // synced. Off, a missing file is written back
+    #[serde(default)]
+    pub notes_dir_deletes: bool,
+    // Log a summary of render, key, sync and save counts on exit
+    #[serde(default)]
+    pub metrics: bool,
+    // Keep note titles out of audit.log; entries name notes by id only
+    #[serde(default)]
+    pub redact_titles: bool,
+    // Command that opens links, e.g. "firefox -P work {url}"; {url} is
+    // appended when missing. Empty uses the system default browser
+    #[serde(default)]
+    pub browser_command: String,
+    // Pin the local-only scratch note (`S`) at the top of the list
+    #[serde(default)]
+    pub show_scratch: bool,
+    // Daily copies of the local database kept in ~/.risu/backups; 0 takes none
+    #[serde(default = "default_daily_backups")]
+    pub daily_backups: usize,
+}
+
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        Self {
+            offline_mode: false,
+            restore_session: true,
+            sort_by: NoteSort::default(),
+            notes_dir: String::new(),
+            notes_dir_deletes: false,
+            metrics: false,
+            redact_titles: false,
+            browser_command: String::new(),
+            show_scratch: false,
+            daily_backups: default_daily_backups(),
+        }
+    }
+}
+
+fn default_daily_backups() -> usize {
+    7
+}
+
+/// Order of the note list, newest first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteSort {
+    #[default]
+    Updated,
+    Created,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -108,6 +339,120 @@ pub struct ThemeConfig {
     pub mode_normal: Color,
     pub mode_insert: Color,
     pub editor_cursor_line: Color,
+    #[serde(default)]
+    pub color_mode: ColorMode,
+    // Show a leading `# Heading` in the preview as a centered title block
+    #[serde(default = "default_true")]
+    pub fancy_title: bool,
+    // Bars at the start of list rows for color-labeled notes
+    #[serde(default = "default_label_red")]
+    pub label_red: Color,
+    #[serde(default = "default_label_yellow")]
+    pub label_yellow: Color,
+    #[serde(default = "default_label_green")]
+    pub label_green: Color,
+    #[serde(default = "default_label_blue")]
+    pub label_blue: Color,
+}
+
+fn default_label_red() -> Color {
+    Color::Rgb(255, 85, 85)
+}
+
+fn default_label_yellow() -> Color {
+    Color::Rgb(241, 250, 140)
+}
+
+fn default_label_green() -> Color {
+    Color::Rgb(80, 250, 123)
+}
+
+fn default_label_blue() -> Color {
+    Color::Rgb(98, 160, 234)
+}
+
+/// How many colors the terminal gets; RGB theme colors are reduced to fit.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Picked from NO_COLOR, TERM and COLORTERM
+    #[default]
+    Auto,
+    Truecolor,
+    #[serde(rename = "256")]
+    Indexed,
+    /// No colors at all: bold, reverse and underline only
+    Mono,
+}
+
+impl ColorMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Truecolor => "truecolor",
+            ColorMode::Indexed => "256",
+            ColorMode::Mono => "mono",
+        }
+    }
+
+    /// The mode to draw with: `Auto` becomes what the environment supports.
+    pub fn resolve(self) -> ColorMode {
+        if self != ColorMode::Auto {
+            return self;
+        }
+        let var = |name| std::env::var(name).unwrap_or_default();
+        // https://no-color.org: any non-empty value
+        if !var("NO_COLOR").is_empty() || var("TERM") == "dumb" {
+            return ColorMode::Mono;
+        }
+        if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+            return ColorMode::Truecolor;
+        }
+        // The Windows console has handled 24-bit color since Windows 10 and
+        // doesn't set COLORTERM
+        if cfg!(windows) {
+            return ColorMode::Truecolor;
+        }
+        ColorMode::Indexed
+    }
+}
+
+impl ThemeConfig {
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "dark" => Some(Self::default()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+
+    fn light() -> Self {
+        Self {
+            background: Color::Reset,
+            foreground: Color::Rgb(40, 42, 54),
+            border_active: Color::Rgb(183, 28, 128),
+            border_inactive: Color::Rgb(140, 140, 160),
+            selection_bg: Color::Rgb(220, 222, 232),
+            selection_fg: Color::Rgb(183, 28, 128),
+            search_border: Color::Rgb(0, 121, 150),
+            logo: Color::Rgb(110, 64, 170),
+            header: Color::Rgb(183, 28, 128),
+            sync_synced: Color::Rgb(30, 140, 60),
+            sync_syncing: Color::Rgb(190, 100, 0),
+            sync_error: Color::Rgb(200, 30, 30),
+            sync_payment_required: Color::Magenta,
+            sync_offline: Color::Rgb(0, 121, 150),
+            mode_normal: Color::Rgb(110, 64, 170),
+            mode_insert: Color::Rgb(30, 140, 60),
+            editor_cursor_line: Color::Rgb(230, 230, 236),
+            color_mode: ColorMode::default(),
+            fancy_title: true,
+            label_red: Color::Rgb(200, 30, 30),
+            label_yellow: Color::Rgb(190, 150, 0),
+            label_green: Color::Rgb(30, 140, 60),
+            label_blue: Color::Rgb(30, 90, 200),
+        }
+    }
 }
 
 impl Default for ThemeConfig {
@@ -130,59 +475,616 @@ impl Default for ThemeConfig {
             mode_normal: Color::Rgb(189, 147, 249),
             mode_insert: Color::Rgb(80, 250, 123),
             editor_cursor_line: Color::DarkGray,
+            color_mode: ColorMode::default(),
+            fancy_title: true,
+            label_red: default_label_red(),
+            label_yellow: default_label_yellow(),
+            label_green: default_label_green(),
+            label_blue: default_label_blue(),
         }
     }
 }
 
 pub fn get_config_dir() -> PathBuf {
-    let mut path = dirs::home_dir().expect("Could not find home directory");
-    path.push(".risu");
-    path
+    let home = dirs::home_dir().expect("Could not find home directory");
+    #[cfg(windows)]
+    return windows_config_dir(&home, dirs::config_dir());
+    #[cfg(not(windows))]
+    home.join(".risu")
 }
 
-pub fn load_config() -> AppConfig {
-    let mut path = get_config_dir();
-    fs::create_dir_all(&path).ok();
-    path.push("config.toml");
+// Windows keeps app data under %APPDATA%. A ~/.risu left by older versions
+// stays in use so existing notes and sessions aren't lost.
+#[cfg(windows)]
+fn windows_config_dir(home: &Path, app_data: Option<PathBuf>) -> PathBuf {
+    let legacy = home.join(".risu");
+    match app_data {
+        Some(dir) if !legacy.exists() => dir.join("risu"),
+        _ => legacy,
+    }
+}
 
-    if !path.exists() {
-        let default_config = AppConfig::default();
-        if let Ok(toml_str) = toml::to_string_pretty(&default_config) {
-            let mut options = OpenOptions::new();
-            options.write(true).create(true).truncate(true);
-            #[cfg(unix)]
-            {
-                options.mode(0o600);
+/// Write options for files holding secrets or notes: owner-only on unix.
+pub fn private_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+
+    // No ACL is applied on Windows yet; the file inherits the config
+    // directory's, which under the user profile is normally owner-only.
+    #[cfg(windows)]
+    {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            log("Warning: files are written with the config directory's default ACL (no owner-only restriction on Windows)");
+        });
+    }
+
+    options
+}
+
+/// Replaces `path` without ever leaving it truncated: the new contents go to a
+/// temp file next to it, are synced, and then renamed over it. With
+/// `keep_prev`, the file being replaced is first copied to `<name>.prev`.
+pub fn write_private_file(path: &Path, contents: &[u8], keep_prev: bool) -> std::io::Result<()> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!("{}.tmp", name));
+
+    let written = private_file_options().open(&tmp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    if keep_prev && path.exists() {
+        // Best effort: a missing backup shouldn't block the save itself
+        if let Err(e) = fs::copy(path, path.with_file_name(format!("{}.prev", name))) {
+            log(&format!("Failed to keep a backup of {:?}: {}", path, e));
+        }
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Where each effective setting came from, keyed by dotted path (`general.offline_mode`)
+pub type ConfigSources = BTreeMap<String, String>;
+
+pub struct LoadedConfig {
+    pub config: AppConfig,
+    /// Config files that were read, in load order
+    pub files: Vec<PathBuf>,
+    pub sources: ConfigSources,
+    /// Problems found while loading; the offending values were skipped
+    pub issues: Vec<ConfigIssue>,
+}
+
+/// A problem in a config file, with a 1-based location
+pub struct ConfigIssue {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    /// Dotted key whose value is ignored, if the problem is with a single value
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+// Main config, machine-local overrides, then the `--config` file
+fn config_layer_paths(extra_path: Option<&Path>) -> Vec<PathBuf> {
+    let dir = get_config_dir();
+    let mut paths = vec![dir.join("config.toml"), dir.join("config.local.toml")];
+    paths.extend(extra_path.map(Path::to_path_buf));
+    paths
+}
+
+/// Validates every config file that exists, without loading anything.
+pub fn check_config(extra_path: Option<&Path>) -> (Vec<PathBuf>, Vec<ConfigIssue>) {
+    let mut checked = Vec::new();
+    let mut issues = Vec::new();
+    for path in config_layer_paths(extra_path) {
+        if !path.exists() {
+            continue;
+        }
+        match fs::read_to_string(&path) {
+            Ok(content) => issues.extend(check_config_str(&path, &content)),
+            Err(e) => issues.push(ConfigIssue {
+                file: path.clone(),
+                line: 1,
+                column: 1,
+                key: None,
+                message: format!("cannot read file: {}", e),
+            }),
+        }
+        checked.push(path);
+    }
+    (checked, issues)
+}
+
+fn check_config_str(file: &Path, content: &str) -> Vec<ConfigIssue> {
+    let issue = |offset: usize, key: Option<String>, message: String| {
+        let before = &content[..offset.min(content.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ConfigIssue {
+            file: file.to_path_buf(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            key,
+            message,
+        }
+    };
+
+    let doc = match toml::de::DeTable::parse(content) {
+        Ok(doc) => doc,
+        Err(e) => {
+            let offset = e.span().map_or(0, |span| span.start);
+            return vec![issue(offset, None, e.message().trim().to_string())];
+        }
+    };
+    // The defaults double as the schema: every known key with a value of the right type
+    let Ok(toml::Value::Table(schema)) = toml::Value::try_from(AppConfig::default()) else {
+        return Vec::new();
+    };
+
+    let mut issues = Vec::new();
+    for (section, value) in doc.get_ref() {
+        let name = section.get_ref().as_ref();
+        let Some(toml::Value::Table(fields)) = schema.get(name) else {
+            issues.push(issue(
+                section.span().start,
+                Some(name.to_string()),
+                format!("unknown section `{}`", name),
+            ));
+            continue;
+        };
+        let toml::de::DeValue::Table(entries) = value.get_ref() else {
+            issues.push(issue(
+                value.span().start,
+                Some(name.to_string()),
+                format!("`{}` must be a table", name),
+            ));
+            continue;
+        };
+        for (key, value) in entries {
+            let path = format!("{}.{}", name, key.get_ref());
+            let problem = match fields.get(key.get_ref().as_ref()) {
+                None => Some((key.span().start, format!("unknown key `{}`", path))),
+                Some(expected) => check_config_value(&path, expected, value.get_ref())
+                    .map(|message| (value.span().start, message)),
+            };
+            if let Some((offset, message)) = problem {
+                issues.push(issue(offset, Some(path), message));
             }
-            if let Ok(mut file) = options.open(&path) {
-                let _ = file.write_all(toml_str.as_bytes());
+        }
+    }
+    issues.sort_by_key(|i| (i.line, i.column));
+    issues
+}
+
+fn check_config_value(
+    path: &str,
+    expected: &toml::Value,
+    value: &toml::de::DeValue,
+) -> Option<String> {
+    use toml::de::DeValue;
+    match (expected, value) {
+        (toml::Value::Boolean(_), DeValue::Boolean(_)) => None,
+        (toml::Value::Integer(_), DeValue::Integer(n)) => {
+            let digits = n.as_str().replace('_', "");
+            match i64::from_str_radix(&digits, n.radix()) {
+                Ok(n) if n >= 0 => None,
+                _ => Some(format!(
+                    "`{}` must be a whole number of 0 or more, got {}",
+                    path,
+                    n.as_str()
+                )),
             }
         }
-        return default_config;
+        (toml::Value::String(_), DeValue::String(mode)) if path == "theme.color_mode" => {
+            (!matches!(mode.as_ref(), "auto" | "truecolor" | "256" | "mono")).then(|| {
+                format!(
+                    "`{}` must be \"auto\", \"truecolor\", \"256\" or \"mono\", got \"{}\"",
+                    path, mode
+                )
+            })
+        }
+        (toml::Value::String(_), DeValue::String(color)) if path.starts_with("theme.") => {
+            Color::from_str(color).err().map(|_| {
+                format!(
+                    "`{}` has invalid color \"{}\" (use a name like \"red\", \"#rrggbb\", or 0-255)",
+                    path, color
+                )
+            })
+        }
+        (toml::Value::String(_), DeValue::String(sort)) if path == "general.sort_by" => {
+            (!matches!(sort.as_ref(), "updated" | "created")).then(|| {
+                format!(
+                    "`{}` must be \"updated\" or \"created\", got \"{}\"",
+                    path, sort
+                )
+            })
+        }
+        (toml::Value::String(_), DeValue::String(esc)) if path == "editor.esc_behavior" => {
+            (!matches!(esc.as_ref(), "save" | "prompt" | "discard")).then(|| {
+                format!(
+                    "`{}` must be \"save\", \"prompt\" or \"discard\", got \"{}\"",
+                    path, esc
+                )
+            })
+        }
+        (toml::Value::String(_), DeValue::String(_)) => None,
+        (expected, _) => Some(format!(
+            "`{}` must be a {}",
+            path,
+            match expected {
+                toml::Value::Boolean(_) => "boolean (true or false)",
+                toml::Value::Integer(_) => "number",
+                _ => "string",
+            }
+        )),
+    }
+}
+
+fn config_schema() -> toml::Table {
+    match toml::Value::try_from(AppConfig::default()) {
+        Ok(toml::Value::Table(schema)) => schema,
+        _ => toml::Table::new(),
     }
+}
 
-    match fs::read_to_string(&path) {
-        Ok(content) => match toml::from_str(&content) {
-            Ok(config) => config,
-            Err(e) => {
-                eprintln!("Failed to parse config.toml: {}.", e);
-                let backup_path = path.with_extension("toml.bak");
-                if let Err(backup_err) = fs::rename(&path, &backup_path) {
-                    eprintln!("Failed to backup corrupted config: {}", backup_err);
-                } else {
-                    eprintln!("Corrupted config backed up to {:?}", backup_path);
-                }
-                eprintln!("Using default configuration.");
-                AppConfig::default()
+/// Every settable dotted key, sorted
+pub fn config_keys() -> Vec<String> {
+    config_schema()
+        .iter()
+        .flat_map(|(section, fields)| {
+            fields
+                .as_table()
+                .into_iter()
+                .flat_map(|t| t.keys())
+                .map(move |key| format!("{}.{}", section, key))
+        })
+        .collect()
+}
+
+fn lookup_config_key<'t>(table: &'t toml::Table, key: &str) -> Option<&'t toml::Value> {
+    let (section, field) = key.split_once('.')?;
+    table.get(section)?.as_table()?.get(field)
+}
+
+fn unknown_config_key(key: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Unknown key `{}`. Valid keys:\n  {}",
+        key,
+        config_keys().join("\n  ")
+    )
+}
+
+/// Effective value of `key` after all layers, formatted for printing.
+pub fn get_config_value(extra_path: Option<&Path>, key: &str) -> anyhow::Result<String> {
+    let config = load_config_layers(extra_path).config;
+    let toml::Value::Table(effective) = toml::Value::try_from(config)? else {
+        return Err(anyhow::anyhow!("Config did not serialize to a table"));
+    };
+    match lookup_config_key(&effective, key) {
+        Some(toml::Value::String(s)) => Ok(s.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => Err(unknown_config_key(key)),
+    }
+}
+
+/// Sets `key` in the `--config` file if given, otherwise in config.toml. Other
+/// keys in that file are kept (comments are not). Returns the file written.
+pub fn set_config_value(
+    extra_path: Option<&Path>,
+    key: &str,
+    raw: &str,
+) -> anyhow::Result<PathBuf> {
+    let schema = config_schema();
+    let expected = lookup_config_key(&schema, key).ok_or_else(|| unknown_config_key(key))?;
+    let value = parse_config_value(key, expected, raw)?;
+
+    let path = extra_path
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| get_config_dir().join("config.toml"));
+    let mut table = if path.exists() {
+        toml::from_str::<toml::Table>(&fs::read_to_string(&path)?).map_err(|e| {
+            anyhow::anyhow!(
+                "{} does not parse ({}). Run `risu config check`.",
+                path.display(),
+                e.message().trim()
+            )
+        })?
+    } else {
+        toml::Table::new()
+    };
+
+    let (section, field) = key.split_once('.').unwrap_or((key, ""));
+    match table
+        .entry(section)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+    {
+        toml::Value::Table(fields) => {
+            fields.insert(field.to_string(), value);
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "`{}` in {} is not a table. Run `risu config check`.",
+                section,
+                path.display()
+            ))
+        }
+    }
+
+    // The file on its own, over defaults, must still make a valid config
+    let mut merged = schema.clone();
+    merge_config_layer(
+        &mut merged,
+        table.clone(),
+        "",
+        "",
+        &mut ConfigSources::new(),
+    );
+    toml::Value::Table(merged)
+        .try_into::<AppConfig>()
+        .map_err(|e| anyhow::anyhow!("Resulting config is invalid: {}", e.message().trim()))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_private_file(&path, toml::to_string_pretty(&table)?.as_bytes(), true)?;
+    log(&format!(
+        "set_config_value: {} = {} in {:?}",
+        key, raw, path
+    ));
+    Ok(path)
+}
+
+fn parse_config_value(key: &str, expected: &toml::Value, raw: &str) -> anyhow::Result<toml::Value> {
+    let raw = raw.trim();
+    let value = match expected {
+        toml::Value::Boolean(_) => match raw.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => toml::Value::Boolean(true),
+            "false" | "0" | "no" | "off" => toml::Value::Boolean(false),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "`{}` takes true or false, got {:?}",
+                    key,
+                    raw
+                ))
+            }
+        },
+        toml::Value::Integer(_) => match raw.parse::<u32>() {
+            Ok(n) => toml::Value::Integer(n.into()),
+            Err(_) => {
+                return Err(anyhow::anyhow!(
+                    "`{}` takes a whole number of 0 or more, got {:?}",
+                    key,
+                    raw
+                ))
+            }
+        },
+        _ if key.starts_with("theme.") => {
+            if Color::from_str(raw).is_err() {
+                return Err(anyhow::anyhow!(
+                    "`{}` takes a color like \"red\", \"#rrggbb\", or 0-255, got {:?}",
+                    key,
+                    raw
+                ));
+            }
+            toml::Value::String(raw.to_string())
+        }
+        _ => toml::Value::String(raw.to_string()),
+    };
+    Ok(value)
+}
+
+fn remove_config_key(table: &mut toml::Table, path: &str) {
+    match path.split_once('.') {
+        Some((section, rest)) => {
+            if let Some(toml::Value::Table(inner)) = table.get_mut(section) {
+                remove_config_key(inner, rest);
+            }
+        }
+        None => {
+            table.remove(path);
+        }
+    }
+}
+
+/// Layers, later ones winning per field: defaults, `~/.risu/config.toml`,
+/// `~/.risu/config.local.toml`, `--config <path>`, then environment overrides.
+pub fn load_config_layers(extra_path: Option<&Path>) -> LoadedConfig {
+    let mut merged = toml::Table::new();
+    let mut sources = ConfigSources::new();
+    let mut files = Vec::new();
+    let mut issues = Vec::new();
+
+    if let Ok(toml::Value::Table(defaults)) = toml::Value::try_from(AppConfig::default()) {
+        merge_config_layer(&mut merged, defaults, "", "default", &mut sources);
+    }
+
+    fs::create_dir_all(get_config_dir()).ok();
+    if let Some(path) = extra_path.filter(|p| !p.exists()) {
+        eprintln!("Config file {:?} not found. Ignoring.", path);
+    }
+    for (i, path) in config_layer_paths(extra_path).into_iter().enumerate() {
+        if !path.exists() {
+            if i == 0 {
+                write_default_config(&path);
             }
+            continue;
+        }
+        if let Some(layer) = read_config_layer(&path, &mut issues) {
+            merge_config_layer(
+                &mut merged,
+                layer,
+                "",
+                &path.display().to_string(),
+                &mut sources,
+            );
+            files.push(path);
+        }
+    }
+
+    for (var, layer) in env_config_layers() {
+        merge_config_layer(
+            &mut merged,
+            layer,
+            "",
+            &format!("env {}", var),
+            &mut sources,
+        );
+    }
+
+    log(&format!(
+        "load_config: Loaded {}",
+        if files.is_empty() {
+            "defaults only".to_string()
+        } else {
+            files
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    ));
+
+    match toml::Value::Table(merged).try_into::<AppConfig>() {
+        Ok(config) => LoadedConfig {
+            config,
+            files,
+            sources,
+            issues,
         },
         Err(e) => {
-            eprintln!("Failed to read config file: {}. Using default.", e);
-            AppConfig::default()
+            eprintln!("Invalid configuration: {}. Using default.", e);
+            LoadedConfig {
+                config: AppConfig::default(),
+                files: Vec::new(),
+                sources: ConfigSources::new(),
+                issues,
+            }
         }
     }
 }
 
+fn write_default_config(path: &Path) {
+    if let Ok(toml_str) = toml::to_string_pretty(&AppConfig::default()) {
+        let _ = write_private_file(path, toml_str.as_bytes(), false);
+    }
+}
+
+// Invalid values are dropped so the rest of the file still applies; a file
+// that doesn't parse at all is skipped. Either way the problem is reported.
+fn read_config_layer(path: &Path, issues: &mut Vec<ConfigIssue>) -> Option<toml::Table> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to read config file {:?}: {}. Skipping.", path, e);
+            return None;
+        }
+    };
+    let found = check_config_str(path, &content);
+    let mut table = toml::from_str::<toml::Table>(&content).ok();
+    for issue in &found {
+        log(&format!("load_config: {}", issue));
+        if let (Some(table), Some(key)) = (table.as_mut(), &issue.key) {
+            remove_config_key(table, key);
+        }
+    }
+    issues.extend(found);
+    table
+}
+
+// TOML has no layering of its own: tables merge key by key, anything else in
+// `layer` replaces what `base` had.
+fn merge_config_layer(
+    base: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    source: &str,
+    sources: &mut ConfigSources,
+) {
+    for (key, value) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table)) => {
+                merge_config_layer(base_table, table, &path, source, sources);
+            }
+            (_, value) => {
+                sources.insert(path, source.to_string());
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn env_config_layers() -> Vec<(&'static str, toml::Table)> {
+    let mut layers = Vec::new();
+
+    if let Ok(value) = std::env::var("RISU_OFFLINE") {
+        let offline = match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(true),
+            "0" | "false" | "no" | "off" | "" => Some(false),
+            _ => None,
+        };
+        match offline {
+            Some(offline) => {
+                let mut general = toml::Table::new();
+                general.insert("offline_mode".to_string(), toml::Value::Boolean(offline));
+                let mut layer = toml::Table::new();
+                layer.insert("general".to_string(), toml::Value::Table(general));
+                layers.push(("RISU_OFFLINE", layer));
+            }
+            None => eprintln!("Ignoring RISU_OFFLINE={:?}: expected 1 or 0.", value),
+        }
+    }
+
+    if let Ok(name) = std::env::var("RISU_THEME_PRESET") {
+        match ThemeConfig::preset(name.trim()).map(toml::Value::try_from) {
+            Some(Ok(mut theme)) => {
+                // A preset only picks colors; it shouldn't reset color_mode
+                if let toml::Value::Table(theme) = &mut theme {
+                    theme.remove("color_mode");
+                }
+                let mut layer = toml::Table::new();
+                layer.insert("theme".to_string(), theme);
+                layers.push(("RISU_THEME_PRESET", layer));
+            }
+            _ => eprintln!(
+                "Ignoring RISU_THEME_PRESET={:?}: expected dark or light.",
+                name
+            ),
+        }
+    }
+
+    layers
+}
+
 pub fn get_token_data() -> TokenData {
     log("get_token_data: Start");
 
@@ -196,6 +1098,16 @@ pub fn get_token_data() -> TokenData {
             data.source = TokenSource::File;
             return data;
         }
+        // An unreadable token.json (e.g. torn by an older version's in-place
+        // write) falls back to the copy kept from the save before
+        let prev = fs::read_to_string(path.with_file_name("token.json.prev"))
+            .ok()
+            .and_then(|c| serde_json::from_str::<TokenData>(&c).ok());
+        if let Some(mut data) = prev {
+            log("get_token_data: token.json unreadable, loaded token.json.prev");
+            data.source = TokenSource::File;
+            return data;
+        }
     }
 
     // Try legacy "token" file (Migration)
@@ -243,17 +1155,8 @@ fn save_token_to_file(json: &str) -> anyhow::Result<()> {
     let mut token_path = config_dir;
     token_path.push("token.json");
 
-    let mut options = OpenOptions::new();
-    options.write(true).create(true).truncate(true);
-
-    #[cfg(unix)]
-    {
-        options.mode(0o600);
-    }
-
-    match options.open(&token_path) {
-        Ok(mut file) => {
-            file.write_all(json.as_bytes())?;
+    match write_private_file(&token_path, json.as_bytes(), true) {
+        Ok(()) => {
             log("save_token_data: Saved to token.json");
             Ok(())
         }
@@ -272,9 +1175,11 @@ pub fn delete_token_data() -> anyhow::Result<()> {
     let mut path = config_dir.clone();
     path.push("token.json");
     if path.exists() {
-        fs::remove_file(path)?;
+        fs::remove_file(&path)?;
         log("delete_token_data: token.json deleted");
     }
+    // The backup holds the same secrets
+    let _ = fs::remove_file(path.with_file_name("token.json.prev"));
 
     let mut legacy_path = config_dir;
     legacy_path.push("token");
@@ -295,16 +1200,10 @@ pub fn save_passphrase(passphrase: &str) -> anyhow::Result<()> {
     let mut path = config_dir;
     path.push("passphrase");
 
-    let mut options = OpenOptions::new();
-    options.write(true).create(true).truncate(true);
-
-    #[cfg(unix)]
-    {
-        options.mode(0o600);
-    }
-
-    let mut file = options.open(&path)?;
-    file.write_all(passphrase.as_bytes())?;
+    // No backup: after a passphrase change the old one must not linger on
+    // disk, and one left by older versions goes now
+    write_private_file(&path, passphrase.as_bytes(), false)?;
+    let _ = fs::remove_file(path.with_file_name("passphrase.prev"));
     Ok(())
 }
 
@@ -324,8 +1223,257 @@ pub fn delete_passphrase() -> anyhow::Result<()> {
     let mut path = get_config_dir();
     path.push("passphrase");
 
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    let _ = fs::remove_file(path.with_file_name("passphrase.prev"));
+    Ok(())
+}
+
+// --- Crash Recovery Swap Files ---
+// Notes are stored in plaintext in the local database, so swap files are too;
+// they get the same 0600 protection as the other files in ~/.risu.
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SwapFile {
+    pub note_id: Option<String>,
+    pub content: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+pub fn get_recovery_dir() -> PathBuf {
+    let mut path = get_config_dir();
+    path.push("recovery");
+    path
+}
+
+/// The buffer a swap file belongs to.
+#[derive(Clone, Copy, Debug)]
+pub enum SwapOwner<'a> {
+    Note(&'a str),
+    /// A note not saved yet, by an id its buffer gets when it's started, so
+    /// two unsaved buffers (in two processes, say) never share a file
+    Draft(&'a str),
+}
+
+// Longest swap file name, well under the usual 255-byte limit
+const MAX_SWAP_NAME_LEN: usize = 128;
+
+fn swap_file_path(owner: SwapOwner) -> PathBuf {
+    let name = match owner {
+        SwapOwner::Note(id) if crate::db::valid_note_id(id) => id.to_string(),
+        // Only ids stored before pulled ones were checked get here. Base64
+        // keeps them apart and out of the path syntax
+        SwapOwner::Note(id) => {
+            let mut name = format!("note-{}", URL_SAFE_NO_PAD.encode(id));
+            name.truncate(MAX_SWAP_NAME_LEN);
+            name
+        }
+        SwapOwner::Draft(id) => format!("new-{}", id),
+    };
+    get_recovery_dir().join(format!("{}.swp", name))
+}
+
+pub fn write_swap_file(owner: SwapOwner, content: &str) -> anyhow::Result<()> {
+    fs::create_dir_all(get_recovery_dir())?;
+    let swap = SwapFile {
+        note_id: match owner {
+            SwapOwner::Note(id) => Some(id.to_string()),
+            SwapOwner::Draft(_) => None,
+        },
+        content: content.to_string(),
+        path: PathBuf::new(),
+    };
+    let json = serde_json::to_string(&swap)?;
+
+    // Written aside and renamed so a crash mid-write can't leave a torn file
+    write_private_file(&swap_file_path(owner), json.as_bytes(), false)?;
+    Ok(())
+}
+
+pub fn delete_swap_file(owner: SwapOwner) -> anyhow::Result<()> {
+    let path = swap_file_path(owner);
     if path.exists() {
         fs::remove_file(path)?;
     }
     Ok(())
 }
+
+pub fn load_swap_files() -> Vec<SwapFile> {
+    let Ok(entries) = fs::read_dir(get_recovery_dir()) else {
+        return Vec::new();
+    };
+
+    let mut swaps = Vec::new();
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("swp") {
+            continue;
+        }
+        match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| serde_json::from_str::<SwapFile>(&s).map_err(Into::into))
+        {
+            Ok(mut swap) => {
+                swap.path = path;
+                swaps.push(swap);
+            }
+            Err(e) => log(&format!("load_swap_files: Skipping {:?}: {}", path, e)),
+        }
+    }
+    swaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn write_private_file_replaces_and_keeps_one_generation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("token.json");
+        write_private_file(&path, b"one", true).unwrap();
+        write_private_file(&path, b"two", true).unwrap();
+        write_private_file(&path, b"three", true).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("token.json.prev")).unwrap(),
+            "two"
+        );
+        assert!(!dir.path().join("token.json.tmp").exists());
+    }
+
+    #[test]
+    fn write_private_file_without_backup_leaves_no_prev() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("passphrase");
+        write_private_file(&path, b"old secret", false).unwrap();
+        write_private_file(&path, b"new secret", false).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new secret");
+        assert!(!dir.path().join("passphrase.prev").exists());
+    }
+
+    #[test]
+    fn failed_write_leaves_the_old_file_intact() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        write_private_file(&path, b"offline_mode = true", true).unwrap();
+        // The temp file can't be created where a directory is in the way
+        fs::create_dir(dir.path().join("config.toml.tmp")).unwrap();
+
+        assert!(write_private_file(&path, b"offline_mode = false", true).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "offline_mode = true");
+        assert!(!dir.path().join("config.toml.prev").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_private_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("token.json");
+        write_private_file(&path, b"{}", false).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // An unsigned JWT carrying `claims`
+    fn token(claims: serde_json::Value) -> String {
+        format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims.to_string()))
+    }
+
+    #[test]
+    fn token_claims_are_read_from_the_payload() {
+        let t = token(serde_json::json!({
+            "sub": "user-1",
+            "email": "a@example.com",
+            "exp": 2000,
+            "iat": 1000,
+        }));
+        assert_eq!(get_user_id_from_token(&t).unwrap(), "user-1");
+        assert_eq!(get_user_email_from_token(&t).unwrap(), "a@example.com");
+        assert_eq!(
+            get_token_times(&t).unwrap(),
+            TokenTimes {
+                expires_at: 2000,
+                issued_at: Some(1000),
+            }
+        );
+
+        let no_iat = token(serde_json::json!({ "exp": 2000 }));
+        assert_eq!(get_token_times(&no_iat).unwrap().issued_at, None);
+        assert!(get_token_times(&token(serde_json::json!({}))).is_err());
+        assert!(get_token_times("not-a-token").is_err());
+        assert!(get_token_times("a.!!!.c").is_err());
+    }
+
+    #[test]
+    fn validate_token_wants_a_live_token_with_a_user() {
+        let now = chrono::Utc::now().timestamp();
+        let live = serde_json::json!({ "sub": "user-1", "exp": now + 3600 });
+        assert!(validate_token(&token(live)).is_ok());
+
+        for claims in [
+            serde_json::json!({ "sub": "user-1", "exp": now - 1 }),
+            serde_json::json!({ "sub": "user-1" }),
+            serde_json::json!({ "exp": now + 3600 }),
+        ] {
+            assert!(validate_token(&token(claims)).is_err());
+        }
+        assert!(validate_token("a..c").is_err());
+        assert!(validate_token("only.two").is_err());
+    }
+
+    #[test]
+    fn swap_file_names_are_safe_and_never_shared() {
+        let name = |owner| {
+            let path = swap_file_path(owner);
+            assert_eq!(path.parent(), Some(get_recovery_dir().as_path()));
+            path.file_name().unwrap().to_str().unwrap().to_string()
+        };
+        let id = "0f8fad5b-d9cb-469f-a165-70867728950e";
+        assert_eq!(name(SwapOwner::Note(id)), format!("{}.swp", id));
+
+        let long = "x/".repeat(500);
+        for hostile in ["../../token.json", "a/b", "..", &long] {
+            let swap = name(SwapOwner::Note(hostile));
+            assert!(swap.len() <= MAX_SWAP_NAME_LEN + ".swp".len(), "{:?}", swap);
+            assert!(!swap.contains('/') && !swap.starts_with('.'), "{:?}", swap);
+        }
+        assert_ne!(name(SwapOwner::Note("a/b")), name(SwapOwner::Note("a_b")));
+
+        assert_ne!(
+            name(SwapOwner::Draft(&uuid::Uuid::new_v4().to_string())),
+            name(SwapOwner::Draft(&uuid::Uuid::new_v4().to_string()))
+        );
+    }
+
+    #[cfg(windows)]
+    mod windows {
+        use super::*;
+
+        #[test]
+        fn the_config_dir_is_under_app_data_unless_risu_was_there_first() {
+            let home = TempDir::new().unwrap();
+            let app_data = home.path().join("AppData").join("Roaming");
+            assert_eq!(
+                windows_config_dir(home.path(), Some(app_data.clone())),
+                app_data.join("risu")
+            );
+            // No %APPDATA% to be had
+            assert_eq!(
+                windows_config_dir(home.path(), None),
+                home.path().join(".risu")
+            );
+
+            fs::create_dir(home.path().join(".risu")).unwrap();
+            assert_eq!(
+                windows_config_dir(home.path(), Some(app_data)),
+                home.path().join(".risu")
+            );
+        }
+    }
+}
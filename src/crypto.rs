@@ -16,6 +16,9 @@ const ARGON2_M_COST: u32 = 65536; // 64 MiB
 const ARGON2_T_COST: u32 = 3;
 const ARGON2_P_COST: u32 = 4;
 
+/// 検証用の既知平文 (サーバーには暗号化された状態で保存される)
+pub const VALIDATOR_PLAINTEXT: &str = "RISU-VALID";
+
 /// パスフレーズとソルトから暗号化キーを導出する
 pub fn derive_key(passphrase: &str, salt_b64: &str) -> Result<Zeroizing<[u8; 32]>> {
     // Saltのデコード (APIからはBase64で渡される)
@@ -67,6 +70,14 @@ pub fn encrypt(content: &str, key: &[u8; 32]) -> Result<String> {
 
 /// 復号化
 pub fn decrypt(payload_b64: &str, key: &[u8; 32]) -> Result<String> {
+    let plaintext = decrypt_bytes(payload_b64, key)?;
+    let content = String::from_utf8(plaintext).context("Decrypted content is not valid UTF-8")?;
+
+    Ok(content)
+}
+
+/// 復号化 (UTF-8として検証しない生のバイト列を返す)
+pub fn decrypt_bytes(payload_b64: &str, key: &[u8; 32]) -> Result<Vec<u8>> {
     let payload = BASE64
         .decode(payload_b64)
         .context("Failed to decode payload from Base64")?;
@@ -83,9 +94,44 @@ pub fn decrypt(payload_b64: &str, key: &[u8; 32]) -> Result<String> {
         .decrypt(nonce, ciphertext)
         .map_err(|e| anyhow::anyhow!("Decryption failed (invalid key or corrupted data): {}", e))?;
 
-    let content = String::from_utf8(plaintext).context("Decrypted content is not valid UTF-8")?;
+    Ok(plaintext)
+}
 
-    Ok(content)
+/// encryptの出力らしき文字列か判定する (Nonce + 認証タグ以上の長さのBase64 1行)
+pub fn looks_like_ciphertext(content: &str) -> bool {
+    const MIN_PAYLOAD: usize = 12 + 16;
+    let content = content.trim();
+    content.len() >= MIN_PAYLOAD * 4 / 3
+        && !content.contains(char::is_whitespace)
+        && BASE64
+            .decode(content)
+            .is_ok_and(|payload| payload.len() >= MIN_PAYLOAD)
+}
+
+/// Validatorを復号し、鍵が正しいか検証する
+pub fn verify_validator(validator_b64: &str, key: &[u8; 32]) -> bool {
+    matches!(decrypt(validator_b64, key), Ok(plaintext) if plaintext == VALIDATOR_PLAINTEXT)
+}
+
+/// 鍵ローテーション中に旧鍵を新鍵で暗号化して保持する
+pub fn wrap_key(key: &[u8; 32], wrapping_key: &[u8; 32]) -> Result<String> {
+    let encoded = Zeroizing::new(BASE64.encode(key));
+    encrypt(&encoded, wrapping_key)
+}
+
+/// wrap_keyで暗号化された鍵を復元する
+pub fn unwrap_key(wrapped_b64: &str, wrapping_key: &[u8; 32]) -> Result<Zeroizing<[u8; 32]>> {
+    let encoded = Zeroizing::new(decrypt(wrapped_b64, wrapping_key)?);
+    let bytes = Zeroizing::new(
+        BASE64
+            .decode(encoded.as_bytes())
+            .context("Failed to decode wrapped key")?,
+    );
+    let key: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Wrapped key has invalid length"))?;
+    Ok(Zeroizing::new(key))
 }
 
 /// ランダムなソルト(16バイト)を生成しBase64エンコードして返す
@@ -94,3 +140,29 @@ pub fn generate_salt() -> String {
     OsRng.fill_bytes(&mut salt);
     BASE64.encode(salt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validator_accepts_only_the_key_it_was_made_with() {
+        let key = [1; 32];
+        let validator = encrypt(VALIDATOR_PLAINTEXT, &key).unwrap();
+        assert!(verify_validator(&validator, &key));
+        assert!(!verify_validator(&validator, &[2; 32]));
+
+        let other_text = encrypt("not the validator", &key).unwrap();
+        assert!(!verify_validator(&other_text, &key));
+        assert!(!verify_validator("", &key));
+        assert!(!verify_validator("not base64!", &key));
+    }
+
+    #[test]
+    fn wrapped_key_round_trips_under_the_wrapping_key_only() {
+        let (old_key, new_key) = ([1; 32], [2; 32]);
+        let wrapped = wrap_key(&old_key, &new_key).unwrap();
+        assert_eq!(*unwrap_key(&wrapped, &new_key).unwrap(), old_key);
+        assert!(unwrap_key(&wrapped, &old_key).is_err());
+    }
+}
@@ -1,8 +1,15 @@
 use crate::config;
+use crate::merge;
+use crate::reminder;
 use anyhow::{Context, Result};
-use chrono::Utc;
-use rusqlite::{params, Connection};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
@@ -11,42 +18,291 @@ pub struct Note {
     pub id: String,
     pub content: String,
     pub updated_at: String,
+    /// Empty in payloads from servers that don't send it
+    #[serde(default)]
+    pub created_at: String,
     pub is_deleted: i32,
     #[serde(default)]
     pub is_synced: i32,
     #[serde(default)]
     pub is_encrypted: i32,
+    /// Version of the E2E key this note was last pushed/pulled under (local only)
+    #[serde(skip)]
+    pub key_version: i32,
+    /// Set when a pull merge left conflict markers in the content (local only)
+    #[serde(skip)]
+    pub has_conflict: i32,
+    /// Pulled text that wasn't valid UTF-8, stored with the bad bytes replaced.
+    /// Held back from push until accepted; the server copy is in
+    /// `quarantined_notes` (local only)
+    #[serde(skip)]
+    pub garbled: i32,
+    /// Edited while E2E was locked, before the server copy could be read (local only)
+    #[serde(skip)]
+    pub modified_while_locked: i32,
+    /// `updated_at` the server sent with this note in the last pull (local only)
+    #[serde(skip)]
+    pub remote_updated_at: Option<String>,
+    /// `updated_at` of the version this device last pushed (local only)
+    #[serde(skip)]
+    pub pushed_updated_at: Option<String>,
+    /// The server has a newer version than this copy, yet this copy doesn't
+    /// match what was last synced: last-write-wins likely kept the wrong side
+    #[serde(skip)]
+    pub older_than_remote: bool,
+    /// Color label (`red`, `yellow`, ...). Only in payloads with `sync.labels`;
+    /// there `""` clears it, while a missing field leaves the local one alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Row counts for `risu status`.
+#[derive(Debug, Clone, Copy)]
+pub struct NoteCounts {
+    pub live: usize,
+    pub unsynced: usize,
+    /// Live notes still holding merge conflict markers
+    pub conflicts: usize,
+    pub tombstones: usize,
+}
+
+/// The earliest `@due`/`@remind` time of a live note.
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub note_id: String,
+    /// RFC 3339, UTC
+    pub due_at: String,
+}
+
+/// What an id or id prefix typed by the user refers to.
+#[derive(Debug)]
+pub enum ResolvedId {
+    Unique(String),
+    /// Every live note whose id starts with the prefix
+    Ambiguous(Vec<String>),
+    NotFound,
+}
+
+/// Shortest prefix shown for a note id; longer when notes share it.
+pub const SHORT_ID_LEN: usize = 8;
+
+// Longest note id accepted from the server; UUIDs are 36
+const MAX_NOTE_ID_LEN: usize = 64;
+
+/// Ids are used in file names, kv keys and log lines, so pulled notes must
+/// have a UUID-like one: ASCII letters, digits, `-` and `_`, at most 64 long.
+pub fn valid_note_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_NOTE_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The first `len` characters of `id` (see `Repo::short_id_len`).
+pub fn short_id(id: &str, len: usize) -> &str {
+    id.get(..len).unwrap_or(id)
+}
+
+// A row selected with the column list the note queries share
+fn note_from_row(row: &rusqlite::Row) -> rusqlite::Result<Note> {
+    Ok(Note {
+        id: row.get(0)?,
+        content: row.get(1)?,
+        updated_at: row.get(2)?,
+        is_deleted: row.get(3)?,
+        is_synced: row.get(4)?,
+        is_encrypted: row.get(5)?,
+        key_version: row.get(6)?,
+        has_conflict: row.get(7)?,
+        modified_while_locked: row.get(8)?,
+        created_at: row.get(9)?,
+        remote_updated_at: row.get(10)?,
+        pushed_updated_at: row.get(11)?,
+        older_than_remote: row.get::<_, Option<bool>>(12)?.unwrap_or(false),
+        label: row.get(13)?,
+        garbled: row.get(14)?,
+    })
+}
+
+/// Passphrase rotation job, persisted until every note is on the new key.
+#[derive(Debug, Clone)]
+pub struct KeyRotation {
+    pub target_version: i32,
+    pub new_salt: String,
+    pub new_validator: String,
+    /// The previous key, encrypted with the new key
+    pub wrapped_old_key: String,
+    /// The server already holds the new salt/validator
+    pub server_updated: bool,
+}
+
+/// Last-known `/auth/me` fields, shown at startup until a fresh check completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountCache {
+    pub plan: String,
+    pub subscription_status: String,
+    pub subscription_end_date: Option<String>,
+    /// RFC 3339 time of the check these fields came from
+    pub fetched_at: String,
+}
+
+const ACCOUNT_CACHE_KEY: &str = "account_cache";
+
+const LAST_SYNC_KEY: &str = "last_sync_completed_at";
+
+// Held by the process running a sync pass, so a TUI and `risu sync --watch`
+// never sync at the same time: `{"owner": ..., "until": RFC 3339 UTC}`
+const SYNC_LEASE_KEY: &str = "sync_lease";
+
+// Id of the scratch note: local-only (`sync_excluded`) and kept out of the
+// note list, the notes folder and exports
+const SCRATCH_KEY: &str = "scratch_note_id";
+
+// Set while a bulk encrypted re-upload still has notes to push
+const REUPLOAD_KEY: &str = "reupload_pending";
+
+const ROTATION_KEYS: [&str; 4] = [
+    "rotation_target_version",
+    "rotation_new_salt",
+    "rotation_new_validator",
+    "rotation_old_key",
+];
+
+// Set once the server has taken the rotation's salt/validator
+const ROTATION_SERVER_KEY: &str = "rotation_server_updated";
+
+/// Why a `Repo` call got no answer, as opposed to an error from SQLite itself.
+/// Callers find it with `downcast_ref` to tell a stuck or dead database apart.
+#[derive(Debug)]
+pub enum DbError {
+    /// No reply within `database.timeout_secs`; the request may still complete
+    NotResponding(Duration),
+    /// The actor thread is gone, so no request will be answered again
+    Shutdown,
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::NotResponding(timeout) => write!(
+                f,
+                "Database is not responding (no reply in {}s)",
+                timeout.as_secs()
+            ),
+            DbError::Shutdown => write!(f, "Database stopped running"),
+        }
+    }
 }
 
+impl std::error::Error for DbError {}
+
+// Requests slower than this are logged by the actor with their name
+const SLOW_REQUEST: Duration = Duration::from_millis(250);
+
 pub enum DbRequest {
     GetNotes {
+        include_deleted: bool,
         reply: oneshot::Sender<Result<Vec<Note>>>,
     },
     GetNote {
         id: String,
         reply: oneshot::Sender<Result<Option<Note>>>,
     },
+    // Live notes one row at a time, for scans that shouldn't hold every note
+    StreamNotes {
+        include_deleted: bool,
+        tx: mpsc::Sender<Result<Note>>,
+    },
     SaveNote {
         id: Option<String>,
         content: String,
         is_encrypted: bool,
+        while_locked: bool,
         reply: oneshot::Sender<Result<String>>,
     },
     DeleteNote {
         id: String,
         reply: oneshot::Sender<Result<()>>,
     },
+    SetLabel {
+        id: String,
+        label: Option<String>,
+        synced: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    ScratchNote {
+        reply: oneshot::Sender<Result<Note>>,
+    },
     GetUnsyncedNotes {
         reply: oneshot::Sender<Result<Vec<Note>>>,
     },
     MarkAsSynced {
         id: String,
+        key_version: i32,
+        content: String,
+        updated_at: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    MarkUnsyncedBelowKeyVersion {
+        key_version: i32,
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    CountBelowKeyVersion {
+        key_version: i32,
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    AbortKeyRotation {
+        target_version: i32,
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    CountModifiedWhileLocked {
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    CountNotes {
+        reply: oneshot::Sender<Result<NoteCounts>>,
+    },
+    DataVersion {
+        reply: oneshot::Sender<Result<i64>>,
+    },
+    ChangeFingerprint {
+        reply: oneshot::Sender<Result<String>>,
+    },
+    SnapshotTo {
+        path: PathBuf,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    RestoreFrom {
+        path: PathBuf,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    ResolveId {
+        prefix: String,
+        include_deleted: bool,
+        reply: oneshot::Sender<Result<ResolvedId>>,
+    },
+    ShortIdLen {
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    GetReminders {
+        reply: oneshot::Sender<Result<Vec<Reminder>>>,
+    },
+    CompactTombstones {
+        older_than: String,
+        reply: oneshot::Sender<Result<usize>>,
+    },
+    QuarantineNotes {
+        notes: Vec<Note>,
         reply: oneshot::Sender<Result<()>>,
     },
+    AcceptGarbled {
+        id: String,
+        reply: oneshot::Sender<Result<bool>>,
+    },
     PullUpsertNotes {
         notes: Vec<Note>,
         cursor: String,
-        reply: oneshot::Sender<Result<()>>,
+        reply: oneshot::Sender<Result<usize>>,
     },
     GetKV {
         key: String,
@@ -61,127 +317,405 @@ pub enum DbRequest {
         key: String,
         reply: oneshot::Sender<Result<()>>,
     },
+    /// Takes the sync lease unless another owner holds one that hasn't expired
+    TakeSyncLease {
+        owner: String,
+        until: String,
+        reply: oneshot::Sender<Result<bool>>,
+    },
+    ReleaseSyncLease {
+        owner: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Sets (Some) or deletes (None) several keys in one transaction
+    SetKVBatch {
+        entries: Vec<(String, Option<String>)>,
+        reply: oneshot::Sender<Result<()>>,
+    },
     #[allow(dead_code)]
-    ClearAllData { reply: oneshot::Sender<Result<()>> },
+    ClearAllData {
+        reply: oneshot::Sender<Result<()>>,
+    },
     SetNotesEncryptedStatus {
         is_encrypted: i32,
         reply: oneshot::Sender<Result<()>>,
     },
 }
 
+impl DbRequest {
+    // Work over the whole database, which can outlast any timeout. Waiting is
+    // the only honest answer: an abandoned one still commits afterwards.
+    fn is_bulk(&self) -> bool {
+        matches!(
+            self,
+            DbRequest::PullUpsertNotes { .. }
+                | DbRequest::QuarantineNotes { .. }
+                | DbRequest::CompactTombstones { .. }
+                | DbRequest::SnapshotTo { .. }
+                | DbRequest::RestoreFrom { .. }
+                | DbRequest::ClearAllData { .. }
+                | DbRequest::SetNotesEncryptedStatus { .. }
+                | DbRequest::MarkUnsyncedBelowKeyVersion { .. }
+                | DbRequest::AbortKeyRotation { .. }
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DbRequest::GetNotes { .. } => "GetNotes",
+            DbRequest::GetNote { .. } => "GetNote",
+            DbRequest::StreamNotes { .. } => "StreamNotes",
+            DbRequest::SaveNote { .. } => "SaveNote",
+            DbRequest::DeleteNote { .. } => "DeleteNote",
+            DbRequest::SetLabel { .. } => "SetLabel",
+            DbRequest::ScratchNote { .. } => "ScratchNote",
+            DbRequest::GetUnsyncedNotes { .. } => "GetUnsyncedNotes",
+            DbRequest::MarkAsSynced { .. } => "MarkAsSynced",
+            DbRequest::MarkUnsyncedBelowKeyVersion { .. } => "MarkUnsyncedBelowKeyVersion",
+            DbRequest::CountBelowKeyVersion { .. } => "CountBelowKeyVersion",
+            DbRequest::AbortKeyRotation { .. } => "AbortKeyRotation",
+            DbRequest::CountModifiedWhileLocked { .. } => "CountModifiedWhileLocked",
+            DbRequest::CountNotes { .. } => "CountNotes",
+            DbRequest::DataVersion { .. } => "DataVersion",
+            DbRequest::ChangeFingerprint { .. } => "ChangeFingerprint",
+            DbRequest::SnapshotTo { .. } => "SnapshotTo",
+            DbRequest::RestoreFrom { .. } => "RestoreFrom",
+            DbRequest::ResolveId { .. } => "ResolveId",
+            DbRequest::ShortIdLen { .. } => "ShortIdLen",
+            DbRequest::GetReminders { .. } => "GetReminders",
+            DbRequest::CompactTombstones { .. } => "CompactTombstones",
+            DbRequest::QuarantineNotes { .. } => "QuarantineNotes",
+            DbRequest::AcceptGarbled { .. } => "AcceptGarbled",
+            DbRequest::PullUpsertNotes { .. } => "PullUpsertNotes",
+            DbRequest::GetKV { .. } => "GetKV",
+            DbRequest::SetKV { .. } => "SetKV",
+            DbRequest::DeleteKV { .. } => "DeleteKV",
+            DbRequest::TakeSyncLease { .. } => "TakeSyncLease",
+            DbRequest::ReleaseSyncLease { .. } => "ReleaseSyncLease",
+            DbRequest::SetKVBatch { .. } => "SetKVBatch",
+            DbRequest::ClearAllData { .. } => "ClearAllData",
+            DbRequest::SetNotesEncryptedStatus { .. } => "SetNotesEncryptedStatus",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Repo {
     tx: mpsc::UnboundedSender<DbRequest>,
+    // How long a call waits for its reply; zero waits forever
+    timeout: Duration,
+    // Most requests ever waiting on the actor at once
+    queue_high_water: Arc<AtomicUsize>,
+    // Seconds added to the local clock when stamping rows (see `RepoInternal::now`)
+    clock_offset: Arc<AtomicI64>,
+}
+
+/// Where risu keeps its notes: `local.db` in the config directory.
+pub fn db_path() -> PathBuf {
+    config::get_config_dir().join("local.db")
 }
 
 impl Repo {
+    /// Opens risu's own database (see [`db_path`]), creating or upgrading it.
     pub fn new() -> Result<Self> {
+        let config_dir = config::get_config_dir();
+        std::fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+        Self::open(&db_path())
+    }
+
+    /// Opens the database at `path`, creating or upgrading it as needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).context("Failed to open database")?;
+        Self::spawn(RepoInternal::with_connection(conn))
+    }
+
+    /// Opens an existing database without changing it: no tables are created
+    /// and no migrations run, and any request that would write fails. For
+    /// tools reading notes alongside a running risu; the file must have been
+    /// opened by a risu of the same version at least once.
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .context("Failed to open database")?;
+        Self::spawn(Ok(RepoInternal {
+            conn,
+            clock_offset: Arc::new(AtomicI64::new(0)),
+        }))
+    }
+
+    fn spawn(actor: Result<RepoInternal>) -> Result<Self> {
         // Initialize DB synchronously so we fail early if DB can't be created/opened.
-        let mut actor = RepoInternal::new().context("Failed to initialize database actor")?;
+        let mut actor = actor.context("Failed to initialize database actor")?;
 
         let (tx, rx) = mpsc::unbounded_channel();
+        let queue_high_water = Arc::new(AtomicUsize::new(0));
+        let clock_offset = Arc::clone(&actor.clock_offset);
 
         // Spawn the actor thread with the already initialized actor.
+        let high_water = Arc::clone(&queue_high_water);
         std::thread::spawn(move || {
-            actor.run(rx);
+            actor.run(rx, &high_water);
         });
 
-        Ok(Self { tx })
+        Ok(Self {
+            tx,
+            timeout: config::DatabaseConfig::default().timeout(),
+            queue_high_water,
+            clock_offset,
+        })
+    }
+
+    /// Shifts the time saves and deletes are stamped with, for a local clock
+    /// known to be off (`sync.compensate_clock_skew`).
+    pub fn set_clock_offset(&self, seconds: i64) {
+        self.clock_offset.store(seconds, Ordering::Relaxed);
+    }
+
+    /// Applies `database.timeout_secs`; clones made afterwards share it.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// False once the actor thread has exited, e.g. after a panic.
+    pub fn is_alive(&self) -> bool {
+        !self.tx.is_closed()
     }
 
-    pub async fn get_notes(&self) -> Result<Vec<Note>> {
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<Result<T>>) -> DbRequest,
+    ) -> Result<T> {
         let (reply, rx) = oneshot::channel();
+        let request = request(reply);
+        let bulk = request.is_bulk();
+        self.tx.send(request).map_err(|_| DbError::Shutdown)?;
+        let reply = if self.timeout.is_zero() || bulk {
+            rx.await
+        } else {
+            tokio::time::timeout(self.timeout, rx)
+                .await
+                .map_err(|_| DbError::NotResponding(self.timeout))?
+        };
+        reply.map_err(|_| DbError::Shutdown)?
+    }
+
+    /// The deepest the request queue has been, counting the request being handled.
+    pub fn queue_high_water(&self) -> usize {
+        self.queue_high_water.load(Ordering::Relaxed)
+    }
+
+    /// Newest first. Tombstones are left out unless `include_deleted`.
+    pub async fn get_notes(&self, include_deleted: bool) -> Result<Vec<Note>> {
+        self.request(|reply| DbRequest::GetNotes {
+            include_deleted,
+            reply,
+        })
+        .await
+    }
+
+    /// Notes oldest first, sent as they're read; tombstones only with
+    /// `include_deleted`. Rows are produced as the receiver drains them, so
+    /// large databases stay out of memory.
+    pub fn stream_notes(&self, include_deleted: bool) -> Result<mpsc::Receiver<Result<Note>>> {
+        let (tx, rx) = mpsc::channel(32);
         self.tx
-            .send(DbRequest::GetNotes { reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+            .send(DbRequest::StreamNotes {
+                include_deleted,
+                tx,
+            })
+            .map_err(|_| DbError::Shutdown)?;
+        Ok(rx)
     }
 
     pub async fn get_note(&self, id: String) -> Result<Option<Note>> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::GetNote { id, reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::GetNote { id, reply }).await
     }
 
+    /// Saving unchanged content is a no-op, so `updated_at` (and with it the
+    /// list order) only moves when the note really changed.
     pub async fn save_note(
         &self,
         id: Option<String>,
         content: String,
         is_encrypted: bool,
+        while_locked: bool,
     ) -> Result<String> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::SaveNote {
-                id,
-                content,
-                is_encrypted,
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::SaveNote {
+            id,
+            content,
+            is_encrypted,
+            while_locked,
+            reply,
+        })
+        .await
     }
 
+    /// Deleting a note that is already in the trash leaves it as it was.
     pub async fn delete_note(&self, id: String) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::DeleteNote { id, reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::DeleteNote { id, reply })
+            .await
     }
 
+    /// With `synced`, the change is pushed like an edit; otherwise the note's
+    /// sync state and `updated_at` are left alone.
+    pub async fn set_label(&self, id: String, label: Option<String>, synced: bool) -> Result<()> {
+        self.request(|reply| DbRequest::SetLabel {
+            id,
+            label,
+            synced,
+            reply,
+        })
+        .await
+    }
+
+    /// The scratch note, created empty the first time it's asked for.
+    pub async fn scratch_note(&self) -> Result<Note> {
+        self.request(|reply| DbRequest::ScratchNote { reply }).await
+    }
+
+    /// Notes waiting to be pushed. Ones with conflict markers or garbled
+    /// text stay local until they are resolved or accepted.
     pub async fn get_unsynced_notes(&self) -> Result<Vec<Note>> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::GetUnsyncedNotes { reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::GetUnsyncedNotes { reply })
+            .await
     }
 
-    pub async fn mark_as_synced(&self, id: String) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::MarkAsSynced { id, reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+    /// `content` is the plaintext that was pushed; it becomes the merge base.
+    pub async fn mark_as_synced(
+        &self,
+        id: String,
+        key_version: i32,
+        content: String,
+        updated_at: String,
+    ) -> Result<()> {
+        self.request(|reply| DbRequest::MarkAsSynced {
+            id,
+            key_version,
+            content,
+            updated_at,
+            reply,
+        })
+        .await
     }
 
-    pub async fn pull_upsert_notes(&self, notes: Vec<Note>, cursor: String) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::PullUpsertNotes {
-                notes,
-                cursor,
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped.reply")?
+    pub async fn mark_unsynced_below_key_version(&self, key_version: i32) -> Result<usize> {
+        self.request(|reply| DbRequest::MarkUnsyncedBelowKeyVersion { key_version, reply })
+            .await
+    }
+
+    pub async fn count_below_key_version(&self, key_version: i32) -> Result<usize> {
+        self.request(|reply| DbRequest::CountBelowKeyVersion { key_version, reply })
+            .await
+    }
+
+    pub async fn count_modified_while_locked(&self) -> Result<usize> {
+        self.request(|reply| DbRequest::CountModifiedWhileLocked { reply })
+            .await
+    }
+
+    pub async fn count_notes(&self) -> Result<NoteCounts> {
+        self.request(|reply| DbRequest::CountNotes { reply }).await
+    }
+
+    /// SQLite's `data_version`: changes whenever another connection (e.g. a
+    /// second risu process) commits to the database.
+    pub async fn data_version(&self) -> Result<i64> {
+        self.request(|reply| DbRequest::DataVersion { reply }).await
+    }
+
+    /// Changes whenever a note is added, edited, deleted or synced. Unlike
+    /// `data_version` it survives a restart, so it can be compared with one
+    /// stored on an earlier day.
+    pub async fn change_fingerprint(&self) -> Result<String> {
+        self.request(|reply| DbRequest::ChangeFingerprint { reply })
+            .await
+    }
+
+    /// Writes a consistent copy of the whole database to `path`, which must
+    /// not exist yet.
+    pub async fn snapshot_to(&self, path: PathBuf) -> Result<()> {
+        self.request(|reply| DbRequest::SnapshotTo { path, reply })
+            .await
+    }
+
+    /// Replaces the whole database with the copy at `path`, as written by
+    /// [`Repo::snapshot_to`], upgrading it if it predates this version.
+    pub async fn restore_from(&self, path: PathBuf) -> Result<()> {
+        self.request(|reply| DbRequest::RestoreFrom { path, reply })
+            .await
+    }
+
+    /// The live note `prefix` names: its full id, or a prefix only one id
+    /// starts with.
+    pub async fn resolve_id(&self, prefix: String, include_deleted: bool) -> Result<ResolvedId> {
+        self.request(|reply| DbRequest::ResolveId {
+            prefix,
+            include_deleted,
+            reply,
+        })
+        .await
+    }
+
+    /// Prefix length, at least `SHORT_ID_LEN`, that tells every live note apart.
+    pub async fn short_id_len(&self) -> Result<usize> {
+        self.request(|reply| DbRequest::ShortIdLen { reply }).await
+    }
+
+    /// One entry per live note with a reminder, soonest first.
+    pub async fn get_reminders(&self) -> Result<Vec<Reminder>> {
+        self.request(|reply| DbRequest::GetReminders { reply })
+            .await
+    }
+
+    /// Physically removes synced tombstones last touched before `older_than`
+    /// (RFC 3339). Returns how many rows were removed.
+    pub async fn compact_tombstones(&self, older_than: String) -> Result<usize> {
+        self.request(|reply| DbRequest::CompactTombstones { older_than, reply })
+            .await
+    }
+
+    /// Keeps pulled notes exactly as the server sent them, for ones whose
+    /// stored copy had to be altered.
+    pub async fn quarantine_notes(&self, notes: Vec<Note>) -> Result<()> {
+        self.request(|reply| DbRequest::QuarantineNotes { notes, reply })
+            .await
+    }
+
+    /// Lets a garbled note be pushed again. False if it wasn't garbled.
+    pub async fn accept_garbled(&self, id: String) -> Result<bool> {
+        self.request(|reply| DbRequest::AcceptGarbled { id, reply })
+            .await
+    }
+
+    /// Returns how many notes were left with merge conflicts.
+    pub async fn pull_upsert_notes(&self, notes: Vec<Note>, cursor: String) -> Result<usize> {
+        self.request(|reply| DbRequest::PullUpsertNotes {
+            notes,
+            cursor,
+            reply,
+        })
+        .await
     }
 
     // --- KV Store Helpers ---
 
     pub async fn get_kv(&self, key: &str) -> Result<Option<String>> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::GetKV {
-                key: key.to_string(),
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::GetKV {
+            key: key.to_string(),
+            reply,
+        })
+        .await
     }
 
     pub async fn set_kv(&self, key: &str, value: &str) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::SetKV {
-                key: key.to_string(),
-                value: value.to_string(),
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::SetKV {
+            key: key.to_string(),
+            value: value.to_string(),
+            reply,
+        })
+        .await
     }
 
     pub async fn get_cursor(&self) -> Result<String> {
@@ -194,6 +728,16 @@ impl Repo {
         self.set_kv("last_synced_at", cursor).await
     }
 
+    /// When a sync last finished without errors (RFC 3339, local clock). Unlike
+    /// the cursor, this moves even when there was nothing to pull.
+    pub async fn get_last_sync_time(&self) -> Result<Option<String>> {
+        self.get_kv(LAST_SYNC_KEY).await
+    }
+
+    pub async fn set_last_sync_time(&self, time: &str) -> Result<()> {
+        self.set_kv(LAST_SYNC_KEY, time).await
+    }
+
     pub async fn get_salt(&self) -> Result<Option<String>> {
         self.get_kv("encryption_salt").await
     }
@@ -203,62 +747,225 @@ impl Repo {
     }
 
     pub async fn delete_kv(&self, key: &str) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::DeleteKV {
-                key: key.to_string(),
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::DeleteKV {
+            key: key.to_string(),
+            reply,
+        })
+        .await
     }
 
     pub async fn delete_salt(&self) -> Result<()> {
+        // The cached validator belongs to the salt, so drop it too
+        self.delete_kv("encryption_validator").await?;
         self.delete_kv("encryption_salt").await
     }
 
+    pub async fn set_kv_batch(&self, entries: Vec<(String, Option<String>)>) -> Result<()> {
+        self.request(|reply| DbRequest::SetKVBatch { entries, reply })
+            .await
+    }
+
+    /// Switches to a salt set up elsewhere (e.g. passphrase changed on another device).
+    /// The cursor is reset so notes skipped under the old key are pulled again.
+    pub async fn replace_salt(&self, salt: &str) -> Result<()> {
+        self.set_kv_batch(vec![
+            ("encryption_salt".to_string(), Some(salt.to_string())),
+            ("encryption_validator".to_string(), None),
+            (
+                "last_synced_at".to_string(),
+                Some("1970-01-01T00:00:00Z".to_string()),
+            ),
+        ])
+        .await
+    }
+
+    pub async fn get_key_version(&self) -> Result<i32> {
+        self.get_kv("key_version")
+            .await
+            .map(|v| v.and_then(|v| v.parse().ok()).unwrap_or(0))
+    }
+
+    /// An unreadable cache is treated as missing; the next account check rewrites it.
+    pub async fn get_account_cache(&self) -> Result<Option<AccountCache>> {
+        Ok(self
+            .get_kv(ACCOUNT_CACHE_KEY)
+            .await?
+            .and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    pub async fn set_account_cache(&self, cache: &AccountCache) -> Result<()> {
+        self.set_kv(ACCOUNT_CACHE_KEY, &serde_json::to_string(cache)?)
+            .await
+    }
+
+    pub async fn delete_account_cache(&self) -> Result<()> {
+        self.delete_kv(ACCOUNT_CACHE_KEY).await
+    }
+
+    /// Whether `owner` now holds the sync lease, until `until` or its release.
+    /// Taking it is a single statement, so two processes can't both win.
+    pub async fn take_sync_lease(&self, owner: &str, until: DateTime<Utc>) -> Result<bool> {
+        self.request(|reply| DbRequest::TakeSyncLease {
+            owner: owner.to_string(),
+            until: until.to_rfc3339_opts(SecondsFormat::Secs, true),
+            reply,
+        })
+        .await
+    }
+
+    pub async fn release_sync_lease(&self, owner: &str) -> Result<()> {
+        self.request(|reply| DbRequest::ReleaseSyncLease {
+            owner: owner.to_string(),
+            reply,
+        })
+        .await
+    }
+
+    pub async fn get_key_rotation(&self) -> Result<Option<KeyRotation>> {
+        let [version_key, salt_key, validator_key, old_key_key] = ROTATION_KEYS;
+        let (Some(target_version), Some(new_salt), Some(new_validator), Some(wrapped_old_key)) = (
+            self.get_kv(version_key).await?,
+            self.get_kv(salt_key).await?,
+            self.get_kv(validator_key).await?,
+            self.get_kv(old_key_key).await?,
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(Some(KeyRotation {
+            target_version: target_version
+                .parse()
+                .context("Invalid rotation key version")?,
+            new_salt,
+            new_validator,
+            wrapped_old_key,
+            server_updated: self.get_kv(ROTATION_SERVER_KEY).await?.is_some(),
+        }))
+    }
+
+    pub async fn begin_key_rotation(&self, rotation: &KeyRotation) -> Result<()> {
+        let values = [
+            rotation.target_version.to_string(),
+            rotation.new_salt.clone(),
+            rotation.new_validator.clone(),
+            rotation.wrapped_old_key.clone(),
+        ];
+        let mut entries: Vec<_> = ROTATION_KEYS
+            .iter()
+            .zip(values)
+            .map(|(k, v)| (k.to_string(), Some(v)))
+            .collect();
+        entries.push((ROTATION_SERVER_KEY.to_string(), None));
+        self.set_kv_batch(entries).await
+    }
+
+    pub async fn mark_rotation_server_updated(&self) -> Result<()> {
+        self.set_kv(ROTATION_SERVER_KEY, "1").await
+    }
+
+    /// Adopts the new salt/validator and discards the wrapped old key.
+    pub async fn finish_key_rotation(&self, rotation: &KeyRotation) -> Result<()> {
+        let mut entries = vec![
+            (
+                "encryption_salt".to_string(),
+                Some(rotation.new_salt.clone()),
+            ),
+            (
+                "encryption_validator".to_string(),
+                Some(rotation.new_validator.clone()),
+            ),
+            (
+                "key_version".to_string(),
+                Some(rotation.target_version.to_string()),
+            ),
+        ];
+        entries.extend(
+            ROTATION_KEYS
+                .iter()
+                .chain([&ROTATION_SERVER_KEY])
+                .map(|k| (k.to_string(), None)),
+        );
+        self.set_kv_batch(entries).await
+    }
+
+    /// Drops the rotation job and queues every note already pushed under its
+    /// key to go out again under the previous one. Returns how many.
+    pub async fn abort_key_rotation(&self, rotation: &KeyRotation) -> Result<usize> {
+        let target_version = rotation.target_version;
+        self.request(|reply| DbRequest::AbortKeyRotation {
+            target_version,
+            reply,
+        })
+        .await
+    }
+
+    /// Queues every note for an encrypted re-upload. An interrupted re-upload is
+    /// resumed as-is, so notes already pushed are not sent again.
+    pub async fn begin_reupload(&self) -> Result<()> {
+        if self.is_reupload_pending().await? {
+            return Ok(());
+        }
+        // Flag last: a crash in between just restarts the re-upload
+        self.set_notes_encrypted_status(1).await?;
+        self.set_kv(REUPLOAD_KEY, "1").await
+    }
+
+    pub async fn is_reupload_pending(&self) -> Result<bool> {
+        Ok(self.get_kv(REUPLOAD_KEY).await?.is_some())
+    }
+
+    pub async fn finish_reupload(&self) -> Result<()> {
+        self.delete_kv(REUPLOAD_KEY).await
+    }
+
+    pub async fn get_validator(&self) -> Result<Option<String>> {
+        self.get_kv("encryption_validator").await
+    }
+
+    pub async fn set_validator(&self, validator: &str) -> Result<()> {
+        self.set_kv("encryption_validator", validator).await
+    }
+
     #[allow(dead_code)]
     pub async fn clear_all_data(&self) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::ClearAllData { reply })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::ClearAllData { reply })
+            .await
     }
 
     pub async fn set_notes_encrypted_status(&self, is_encrypted: i32) -> Result<()> {
-        let (reply, rx) = oneshot::channel();
-        self.tx
-            .send(DbRequest::SetNotesEncryptedStatus {
-                is_encrypted,
-                reply,
-            })
-            .map_err(|_| anyhow::anyhow!("DB actor shutdown"))?;
-        rx.await.context("DB actor dropped reply")?
+        self.request(|reply| DbRequest::SetNotesEncryptedStatus {
+            is_encrypted,
+            reply,
+        })
+        .await
     }
 }
 
 // Synchronous internal implementation
 struct RepoInternal {
     conn: Connection,
+    clock_offset: Arc<AtomicI64>,
 }
 
 impl RepoInternal {
-    fn new() -> Result<Self> {
-        let config_dir = config::get_config_dir();
-        std::fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
-
-        let mut db_path = config_dir;
-        db_path.push("local.db");
-
-        let conn = Connection::open(db_path).context("Failed to open database")?;
-        let internal = Self { conn };
+    fn with_connection(conn: Connection) -> Result<Self> {
+        let internal = Self {
+            conn,
+            clock_offset: Arc::new(AtomicI64::new(0)),
+        };
         internal
             .create_tables()
             .context("Failed to create tables")?;
+        internal.migrate().context("Failed to migrate database")?;
         Ok(internal)
     }
 
+    /// RFC 3339 time for `updated_at`, corrected by the measured clock skew.
+    fn now(&self) -> String {
+        let offset = self.clock_offset.load(Ordering::Relaxed);
+        (Utc::now() + chrono::Duration::seconds(offset)).to_rfc3339()
+    }
+
     fn create_tables(&self) -> Result<()> {
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS notes (
@@ -283,87 +990,292 @@ impl RepoInternal {
         Ok(())
     }
 
-    fn run(&mut self, mut rx: mpsc::UnboundedReceiver<DbRequest>) {
-        while let Some(req) = rx.blocking_recv() {
-            match req {
-                DbRequest::GetNotes { reply } => {
-                    let _ = reply.send(self.get_notes());
-                }
-                DbRequest::GetNote { id, reply } => {
-                    let _ = reply.send(self.get_note(&id));
-                }
-                DbRequest::SaveNote {
-                    id,
-                    content,
-                    is_encrypted,
-                    reply,
-                } => {
-                    let _ = reply.send(self.save_note(id, &content, is_encrypted));
-                }
-                DbRequest::DeleteNote { id, reply } => {
-                    let _ = reply.send(self.delete_note(&id));
-                }
-                DbRequest::GetUnsyncedNotes { reply } => {
-                    let _ = reply.send(self.get_unsynced_notes());
-                }
-                DbRequest::MarkAsSynced { id, reply } => {
-                    let _ = reply.send(self.mark_as_synced(&id));
-                }
-                DbRequest::PullUpsertNotes {
-                    notes,
-                    cursor,
-                    reply,
-                } => {
-                    let _ = reply.send(self.pull_upsert_notes(notes, &cursor));
-                }
-                DbRequest::GetKV { key, reply } => {
-                    let _ = reply.send(self.get_kv(&key));
-                }
-                DbRequest::SetKV { key, value, reply } => {
-                    let _ = reply.send(self.set_kv(&key, &value));
-                }
-                DbRequest::DeleteKV { key, reply } => {
-                    let _ = reply.send(self.delete_kv(&key));
-                }
-                DbRequest::ClearAllData { reply } => {
-                    let _ = reply.send(self.clear_all_data());
-                }
-                DbRequest::SetNotesEncryptedStatus {
-                    is_encrypted,
-                    reply,
-                } => {
-                    let _ = reply.send(self.set_notes_encrypted_status(is_encrypted));
-                }
-            }
+    fn migrate(&self) -> Result<()> {
+        if !self.has_column("notes", "key_version")? {
+            self.conn.execute(
+                "ALTER TABLE notes ADD COLUMN key_version INTEGER DEFAULT 0",
+                [],
+            )?;
         }
-    }
-
-    fn get_notes(&self) -> Result<Vec<Note>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted
+        // Content as of the last successful push/pull, used as the merge base
+        if !self.has_column("notes", "base_content")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN base_content TEXT", [])?;
+        }
+        if !self.has_column("notes", "has_conflict")? {
+            self.conn.execute(
+                "ALTER TABLE notes ADD COLUMN has_conflict INTEGER DEFAULT 0",
+                [],
+            )?;
+        }
+        if !self.has_column("notes", "modified_while_locked")? {
+            self.conn.execute(
+                "ALTER TABLE notes ADD COLUMN modified_while_locked INTEGER DEFAULT 0",
+                [],
+            )?;
+        }
+        // Sync timestamps kept for diagnosing lost edits
+        if !self.has_column("notes", "remote_updated_at")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN remote_updated_at TEXT", [])?;
+        }
+        if !self.has_column("notes", "pushed_updated_at")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN pushed_updated_at TEXT", [])?;
+        }
+        // Older notes only know when they were last edited
+        if !self.has_column("notes", "created_at")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN created_at TEXT", [])?;
+            self.conn.execute(
+                "UPDATE notes SET created_at = updated_at WHERE created_at IS NULL",
+                [],
+            )?;
+        }
+        // Color label; NULL when the note has none
+        if !self.has_column("notes", "label")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN label TEXT", [])?;
+        }
+        // Pulled notes with an id `valid_note_id` rejects, or one differing
+        // from a known id only in case, kept as received instead of applied
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS quarantined_notes (
+                id TEXT,
+                note TEXT,
+                received_at TEXT
+            )",
+            [],
+        )?;
+        if !self.has_column("notes", "garbled")? {
+            self.conn
+                .execute("ALTER TABLE notes ADD COLUMN garbled INTEGER DEFAULT 0", [])?;
+        }
+        // Local-only notes, never pushed
+        if !self.has_column("notes", "sync_excluded")? {
+            self.conn.execute(
+                "ALTER TABLE notes ADD COLUMN sync_excluded INTEGER DEFAULT 0",
+                [],
+            )?;
+        }
+        // Due times parsed from `@due`/`@remind` tokens, keyed by the token's
+        // text so relative dates keep the time they were first resolved to
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS reminders (
+                note_id TEXT NOT NULL,
+                spec TEXT NOT NULL,
+                due_at TEXT NOT NULL,
+                PRIMARY KEY (note_id, spec)
+            )",
+            [],
+        )?;
+        // Notes written before the table existed have tokens but no rows
+        let unindexed = self
+            .conn
+            .prepare(
+                "SELECT id, content FROM notes
+                 WHERE is_deleted = 0
+                   AND (content LIKE '%@due(%' OR content LIKE '%@remind(%')
+                   AND id NOT IN (SELECT note_id FROM reminders)",
+            )?
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        for (id, content) in unindexed {
+            update_reminders(&self.conn, &id, &content)?;
+        }
+        Ok(())
+    }
 
-             FROM notes 
+    fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", table))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(1)?;
+            if name == column {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
-             WHERE is_deleted = 0
+    fn run(&mut self, mut rx: mpsc::UnboundedReceiver<DbRequest>, high_water: &AtomicUsize) {
+        while let Some(req) = rx.blocking_recv() {
+            high_water.fetch_max(rx.len() + 1, Ordering::Relaxed);
+            let name = req.name();
+            // A stream is paced by its reader, so its time says nothing about SQLite
+            let timed = !matches!(req, DbRequest::StreamNotes { .. });
+            let started = Instant::now();
+            self.handle(req);
+            let took = started.elapsed();
+            if timed && took > SLOW_REQUEST {
+                crate::logger::log(&format!("DB: {} took {} ms", name, took.as_millis()));
+            }
+        }
+    }
 
-             ORDER BY updated_at DESC",
-        )?;
+    fn handle(&mut self, req: DbRequest) {
+        match req {
+            DbRequest::GetNotes {
+                include_deleted,
+                reply,
+            } => {
+                let _ = reply.send(self.get_notes(include_deleted));
+            }
+            DbRequest::GetNote { id, reply } => {
+                let _ = reply.send(self.get_note(&id));
+            }
+            DbRequest::StreamNotes {
+                include_deleted,
+                tx,
+            } => {
+                if let Err(e) = self.stream_notes(include_deleted, &tx) {
+                    let _ = tx.blocking_send(Err(e));
+                }
+            }
+            DbRequest::SaveNote {
+                id,
+                content,
+                is_encrypted,
+                while_locked,
+                reply,
+            } => {
+                let _ = reply.send(self.save_note(id, &content, is_encrypted, while_locked));
+            }
+            DbRequest::DeleteNote { id, reply } => {
+                let _ = reply.send(self.delete_note(&id));
+            }
+            DbRequest::SetLabel {
+                id,
+                label,
+                synced,
+                reply,
+            } => {
+                let _ = reply.send(self.set_label(&id, label.as_deref(), synced));
+            }
+            DbRequest::ScratchNote { reply } => {
+                let _ = reply.send(self.scratch_note());
+            }
+            DbRequest::GetUnsyncedNotes { reply } => {
+                let _ = reply.send(self.get_unsynced_notes());
+            }
+            DbRequest::MarkAsSynced {
+                id,
+                key_version,
+                content,
+                updated_at,
+                reply,
+            } => {
+                let _ = reply.send(self.mark_as_synced(&id, key_version, &content, &updated_at));
+            }
+            DbRequest::MarkUnsyncedBelowKeyVersion { key_version, reply } => {
+                let _ = reply.send(self.mark_unsynced_below_key_version(key_version));
+            }
+            DbRequest::CountBelowKeyVersion { key_version, reply } => {
+                let _ = reply.send(self.count_below_key_version(key_version));
+            }
+            DbRequest::AbortKeyRotation {
+                target_version,
+                reply,
+            } => {
+                let _ = reply.send(self.abort_key_rotation(target_version));
+            }
+            DbRequest::CountModifiedWhileLocked { reply } => {
+                let _ = reply.send(self.count_modified_while_locked());
+            }
+            DbRequest::CountNotes { reply } => {
+                let _ = reply.send(self.count_notes());
+            }
+            DbRequest::DataVersion { reply } => {
+                let _ = reply.send(self.data_version());
+            }
+            DbRequest::ChangeFingerprint { reply } => {
+                let _ = reply.send(self.change_fingerprint());
+            }
+            DbRequest::SnapshotTo { path, reply } => {
+                let _ = reply.send(self.snapshot_to(&path));
+            }
+            DbRequest::RestoreFrom { path, reply } => {
+                let _ = reply.send(self.restore_from(&path));
+            }
+            DbRequest::ResolveId {
+                prefix,
+                include_deleted,
+                reply,
+            } => {
+                let _ = reply.send(self.resolve_id(&prefix, include_deleted));
+            }
+            DbRequest::ShortIdLen { reply } => {
+                let _ = reply.send(self.short_id_len());
+            }
+            DbRequest::GetReminders { reply } => {
+                let _ = reply.send(self.get_reminders());
+            }
+            DbRequest::CompactTombstones { older_than, reply } => {
+                let _ = reply.send(self.compact_tombstones(&older_than));
+            }
+            DbRequest::QuarantineNotes { notes, reply } => {
+                let _ = reply.send(self.quarantine_notes(&notes));
+            }
+            DbRequest::AcceptGarbled { id, reply } => {
+                let _ = reply.send(self.accept_garbled(&id));
+            }
+            DbRequest::PullUpsertNotes {
+                notes,
+                cursor,
+                reply,
+            } => {
+                let _ = reply.send(self.pull_upsert_notes(notes, &cursor));
+            }
+            DbRequest::GetKV { key, reply } => {
+                let _ = reply.send(self.get_kv(&key));
+            }
+            DbRequest::SetKV { key, value, reply } => {
+                let _ = reply.send(self.set_kv(&key, &value));
+            }
+            DbRequest::DeleteKV { key, reply } => {
+                let _ = reply.send(self.delete_kv(&key));
+            }
+            DbRequest::TakeSyncLease {
+                owner,
+                until,
+                reply,
+            } => {
+                let _ = reply.send(self.take_sync_lease(&owner, &until));
+            }
+            DbRequest::ReleaseSyncLease { owner, reply } => {
+                let _ = reply.send(self.release_sync_lease(&owner));
+            }
+            DbRequest::SetKVBatch { entries, reply } => {
+                let _ = reply.send(self.set_kv_batch(entries));
+            }
+            DbRequest::ClearAllData { reply } => {
+                let _ = reply.send(self.clear_all_data());
+            }
+            DbRequest::SetNotesEncryptedStatus {
+                is_encrypted,
+                reply,
+            } => {
+                let _ = reply.send(self.set_notes_encrypted_status(is_encrypted));
+            }
+        }
+    }
 
-        let note_iter = stmt.query_map([], |row| {
-            Ok(Note {
-                id: row.get(0)?,
+    fn get_notes(&self, include_deleted: bool) -> Result<Vec<Note>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted, key_version, has_conflict, modified_while_locked, COALESCE(created_at, updated_at), remote_updated_at, pushed_updated_at, (remote_updated_at > updated_at AND content IS NOT base_content), label, garbled
 
-                content: row.get(1)?,
+             FROM notes 
 
-                updated_at: row.get(2)?,
+             WHERE (?1 OR is_deleted = 0)
 
-                is_deleted: row.get(3)?,
+                AND id IS NOT (SELECT value FROM kv_store WHERE key = 'scratch_note_id')
 
-                is_synced: row.get(4)?,
+             ORDER BY updated_at DESC",
+        )?;
 
-                is_encrypted: row.get(5)?,
-            })
-        })?;
+        let note_iter = stmt.query_map(params![include_deleted], note_from_row)?;
 
         let mut notes = Vec::new();
 
@@ -374,9 +1286,27 @@ impl RepoInternal {
         Ok(notes)
     }
 
+    fn stream_notes(&self, include_deleted: bool, tx: &mpsc::Sender<Result<Note>>) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted, key_version, has_conflict, modified_while_locked, COALESCE(created_at, updated_at), remote_updated_at, pushed_updated_at, (remote_updated_at > updated_at AND content IS NOT base_content), label, garbled
+             FROM notes WHERE (?1 OR is_deleted = 0)
+                AND id IS NOT (SELECT value FROM kv_store WHERE key = 'scratch_note_id')
+             ORDER BY COALESCE(created_at, updated_at)",
+        )?;
+        let mut rows = stmt.query(params![include_deleted])?;
+        while let Some(row) = rows.next()? {
+            let note = note_from_row(row)?;
+            // The receiver went away; stop reading
+            if tx.blocking_send(Ok(note)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn get_note(&self, id: &str) -> Result<Option<Note>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted 
+            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted, key_version, has_conflict, modified_while_locked, COALESCE(created_at, updated_at), remote_updated_at, pushed_updated_at, (remote_updated_at > updated_at AND content IS NOT base_content), label, garbled 
 
              FROM notes WHERE id = ?1",
         )?;
@@ -384,35 +1314,29 @@ impl RepoInternal {
         let mut rows = stmt.query(params![id])?;
 
         if let Some(row) = rows.next()? {
-            Ok(Some(Note {
-                id: row.get(0)?,
-
-                content: row.get(1)?,
-
-                updated_at: row.get(2)?,
-
-                is_deleted: row.get(3)?,
-
-                is_synced: row.get(4)?,
-
-                is_encrypted: row.get(5)?,
-            }))
+            Ok(Some(note_from_row(row)?))
         } else {
             Ok(None)
         }
     }
 
-    fn save_note(&self, id: Option<String>, content: &str, is_encrypted: bool) -> Result<String> {
+    fn save_note(
+        &self,
+        id: Option<String>,
+        content: &str,
+        is_encrypted: bool,
+        while_locked: bool,
+    ) -> Result<String> {
         let id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
 
-        let now = Utc::now().to_rfc3339();
+        let now = self.now();
 
         let encrypted_flag = if is_encrypted { 1 } else { 0 };
 
         self.conn.execute(
-            "INSERT INTO notes (id, content, updated_at, is_deleted, is_synced, is_encrypted)
+            "INSERT INTO notes (id, content, updated_at, created_at, is_deleted, is_synced, is_encrypted, has_conflict, modified_while_locked)
 
-             VALUES (?1, ?2, ?3, 0, 0, ?4)
+             VALUES (?1, ?2, ?3, ?3, 0, 0, ?4, ?5, ?6)
 
              ON CONFLICT(id) DO UPDATE SET
 
@@ -424,73 +1348,472 @@ impl RepoInternal {
 
                 is_synced = 0,
 
-                is_encrypted = excluded.is_encrypted",
-            params![id, content, now, encrypted_flag],
+                is_encrypted = excluded.is_encrypted,
+
+                has_conflict = excluded.has_conflict,
+
+                modified_while_locked = MAX(modified_while_locked, excluded.modified_while_locked)
+
+             WHERE notes.content IS NOT excluded.content OR notes.is_deleted = 1",
+            params![
+                id,
+                content,
+                now,
+                encrypted_flag,
+                merge::has_conflict_markers(content) as i32,
+                while_locked as i32
+            ],
         )?;
+        update_reminders(&self.conn, &id, content)?;
 
         Ok(id)
     }
 
     fn delete_note(&self, id: &str) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
+        let now = self.now();
 
         self.conn.execute(
             "UPDATE notes SET is_deleted = 1, is_synced = 0, updated_at = ?1 
 
-             WHERE id = ?2",
+             WHERE id = ?2 AND is_deleted = 0",
             params![now, id],
         )?;
+        update_reminders(&self.conn, id, "")?;
+
+        Ok(())
+    }
 
+    fn set_label(&self, id: &str, label: Option<&str>, synced: bool) -> Result<()> {
+        if synced {
+            self.conn.execute(
+                "UPDATE notes SET label = ?1, is_synced = 0, updated_at = ?2
+                 WHERE id = ?3 AND label IS NOT ?1",
+                params![label, self.now(), id],
+            )?;
+        } else {
+            self.conn.execute(
+                "UPDATE notes SET label = ?1 WHERE id = ?2",
+                params![label, id],
+            )?;
+        }
         Ok(())
     }
 
+    fn scratch_note(&self) -> Result<Note> {
+        let id: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM kv_store WHERE key = ?1",
+                params![SCRATCH_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let existing = id.map(|id| self.get_note(&id)).transpose()?.flatten();
+        if let Some(note) = existing.filter(|n| n.is_deleted == 0) {
+            return Ok(note);
+        }
+        let id = Uuid::new_v4().to_string();
+        let now = self.now();
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO notes (id, content, updated_at, created_at, is_deleted, is_synced, is_encrypted, sync_excluded)
+             VALUES (?1, '', ?2, ?2, 0, 1, 0, 1)",
+            params![id, now],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO kv_store (key, value) VALUES (?1, ?2)",
+            params![SCRATCH_KEY, id],
+        )?;
+        tx.commit()?;
+        self.get_note(&id)?
+            .ok_or_else(|| anyhow::anyhow!("scratch note {} vanished", id))
+    }
+
     fn get_unsynced_notes(&self) -> Result<Vec<Note>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted 
+            "SELECT id, content, updated_at, is_deleted, is_synced, is_encrypted, key_version, has_conflict, modified_while_locked, COALESCE(created_at, updated_at), remote_updated_at, pushed_updated_at, (remote_updated_at > updated_at AND content IS NOT base_content), label, garbled 
 
-             FROM notes WHERE is_synced = 0",
+             FROM notes WHERE is_synced = 0 AND sync_excluded = 0 AND garbled = 0 AND has_conflict = 0",
         )?;
 
-        let note_iter = stmt.query_map([], |row| {
-            Ok(Note {
-                id: row.get(0)?,
+        let note_iter = stmt.query_map([], note_from_row)?;
 
-                content: row.get(1)?,
+        let mut notes = Vec::new();
 
-                updated_at: row.get(2)?,
+        for note in note_iter {
+            notes.push(note?);
+        }
 
-                is_deleted: row.get(3)?,
+        Ok(notes)
+    }
 
-                is_synced: row.get(4)?,
+    fn mark_as_synced(
+        &self,
+        id: &str,
+        key_version: i32,
+        content: &str,
+        updated_at: &str,
+    ) -> Result<()> {
+        // An edit saved while the push was in flight stays unsynced
+        self.conn.execute(
+            "UPDATE notes SET
 
-                is_encrypted: row.get(5)?,
-            })
-        })?;
+                is_synced = CASE WHEN content = ?3 THEN 1 ELSE is_synced END,
 
-        let mut notes = Vec::new();
+                key_version = ?1,
 
-        for note in note_iter {
-            notes.push(note?);
+                base_content = ?3,
+
+                pushed_updated_at = ?4,
+
+                modified_while_locked = 0
+
+             WHERE id = ?2",
+            params![key_version, id, content, updated_at],
+        )?;
+
+        Ok(())
+    }
+
+    fn mark_unsynced_below_key_version(&self, key_version: i32) -> Result<usize> {
+        let changed = self.conn.execute(
+            "UPDATE notes SET is_synced = 0
+             WHERE is_synced = 1 AND key_version < ?1 AND sync_excluded = 0",
+            params![key_version],
+        )?;
+
+        Ok(changed)
+    }
+
+    fn count_below_key_version(&self, key_version: i32) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM notes WHERE key_version < ?1 AND sync_excluded = 0",
+            params![key_version],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as usize)
+    }
+
+    fn abort_key_rotation(&mut self, target_version: i32) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let requeued = tx.execute(
+            "UPDATE notes SET is_synced = 0
+             WHERE key_version >= ?1 AND sync_excluded = 0",
+            params![target_version],
+        )?;
+        tx.execute(
+            "UPDATE notes SET key_version = ?1 - 1 WHERE key_version >= ?1",
+            params![target_version],
+        )?;
+        for key in ROTATION_KEYS.iter().chain([&ROTATION_SERVER_KEY]) {
+            tx.execute("DELETE FROM kv_store WHERE key = ?1", params![key])?;
         }
+        tx.commit()?;
 
-        Ok(notes)
+        Ok(requeued)
+    }
+
+    fn count_modified_while_locked(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM notes WHERE modified_while_locked = 1 AND sync_excluded = 0",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as usize)
+    }
+
+    fn data_version(&self) -> Result<i64> {
+        Ok(self
+            .conn
+            .query_row("PRAGMA data_version", [], |row| row.get(0))?)
+    }
+
+    fn change_fingerprint(&self) -> Result<String> {
+        Ok(self.conn.query_row(
+            "SELECT count(*) || ':' || coalesce(max(updated_at), '') || ':'
+                 || coalesce(sum(length(content)), 0) || ':'
+                 || coalesce(sum(is_synced), 0) || ':' || coalesce(sum(is_deleted), 0)
+             FROM notes",
+            [],
+            |row| row.get(0),
+        )?)
     }
 
-    fn mark_as_synced(&self, id: &str) -> Result<()> {
+    fn snapshot_to(&self, path: &Path) -> Result<()> {
         self.conn
-            .execute("UPDATE notes SET is_synced = 1 WHERE id = ?", [id])?;
+            .execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    fn restore_from(&mut self, path: &Path) -> Result<()> {
+        let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .context("Failed to open snapshot")?;
+        // One step, so other connections never see a half-copied database
+        let step = rusqlite::backup::Backup::new(&source, &mut self.conn)?.step(-1)?;
+        if step != rusqlite::backup::StepResult::Done {
+            anyhow::bail!("The database is busy ({:?}); try again", step);
+        }
+        // A lease held when the copy was taken belongs to no one now
+        self.conn.execute(
+            "DELETE FROM kv_store WHERE key = ?1",
+            params![SYNC_LEASE_KEY],
+        )?;
+        self.create_tables()?;
+        self.migrate()
+    }
+
+    fn resolve_id(&self, prefix: &str, include_deleted: bool) -> Result<ResolvedId> {
+        if prefix.is_empty() {
+            return Ok(ResolvedId::NotFound);
+        }
+        // An exact match sorts first and wins over longer ids sharing it as a prefix
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM notes
+             WHERE (?2 OR is_deleted = 0) AND substr(id, 1, length(?1)) = ?1
+             ORDER BY id = ?1 DESC, id",
+        )?;
+        let ids = stmt
+            .query_map(params![prefix, include_deleted], |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(match ids.as_slice() {
+            [] => ResolvedId::NotFound,
+            [first, ..] if first == prefix => ResolvedId::Unique(first.clone()),
+            [only] => ResolvedId::Unique(only.clone()),
+            _ => ResolvedId::Ambiguous(ids),
+        })
+    }
+
+    fn short_id_len(&self) -> Result<usize> {
+        let longest: i64 = self.conn.query_row(
+            "SELECT COALESCE(MAX(length(id)), 0) FROM notes WHERE is_deleted = 0",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut len = SHORT_ID_LEN;
+        while (len as i64) < longest {
+            let unique: bool = self.conn.query_row(
+                "SELECT COUNT(*) = COUNT(DISTINCT substr(id, 1, ?1))
+                 FROM notes WHERE is_deleted = 0",
+                params![len as i64],
+                |row| row.get(0),
+            )?;
+            if unique {
+                break;
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    fn get_reminders(&self) -> Result<Vec<Reminder>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.note_id, MIN(r.due_at) FROM reminders r
+             JOIN notes n ON n.id = r.note_id AND n.is_deleted = 0
+             GROUP BY r.note_id ORDER BY MIN(r.due_at)",
+        )?;
+        let reminders = stmt
+            .query_map([], |row| {
+                Ok(Reminder {
+                    note_id: row.get(0)?,
+                    due_at: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(reminders)
+    }
+
+    fn count_notes(&self) -> Result<NoteCounts> {
+        let (live, unsynced, conflicts, tombstones): (i64, i64, i64, i64) = self.conn.query_row(
+            "SELECT COALESCE(SUM(is_deleted = 0 AND sync_excluded = 0), 0),
+                    COALESCE(SUM(is_deleted = 0 AND is_synced = 0 AND sync_excluded = 0), 0),
+                    COALESCE(SUM(is_deleted = 0 AND has_conflict = 1), 0),
+                    COALESCE(SUM(is_deleted = 1), 0)
+             FROM notes",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+        Ok(NoteCounts {
+            live: live as usize,
+            unsynced: unsynced as usize,
+            conflicts: conflicts as usize,
+            tombstones: tombstones as usize,
+        })
+    }
+
+    // A tombstone the server sends again after compaction is simply re-inserted
+    // by the pull upsert and compacted on a later pass.
+    fn compact_tombstones(&self, older_than: &str) -> Result<usize> {
+        let removed = self.conn.execute(
+            "DELETE FROM notes WHERE is_deleted = 1 AND is_synced = 1 AND updated_at < ?1",
+            params![older_than],
+        )?;
+
+        Ok(removed)
+    }
 
+    fn quarantine_notes(&mut self, notes: &[Note]) -> Result<()> {
+        let now = self.now();
+        let tx = self.conn.transaction()?;
+        for note in notes {
+            quarantine_note(&tx, note, &now)?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
-    fn pull_upsert_notes(&mut self, notes: Vec<Note>, cursor: &str) -> Result<()> {
+    fn accept_garbled(&self, id: &str) -> Result<bool> {
+        let content: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT content FROM notes WHERE id = ?1 AND garbled = 1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(content) = content else {
+            return Ok(false);
+        };
+        // The conflict flag came with the garbling; merge markers keep it
+        self.conn.execute(
+            "UPDATE notes SET garbled = 0, has_conflict = ?2 WHERE id = ?1",
+            params![id, merge::has_conflict_markers(&content) as i32],
+        )?;
+        Ok(true)
+    }
+
+    fn pull_upsert_notes(&mut self, notes: Vec<Note>, cursor: &str) -> Result<usize> {
+        // Merged notes are local edits, stamped like a save
+        let now = self.now();
         let tx = self.conn.transaction()?;
+        // Ids are kept and pushed exactly as the server has them. One that
+        // differs from another only in case would make a near-duplicate note,
+        // so it's quarantined like an invalid one rather than renamed.
+        let mut accepted = Vec::with_capacity(notes.len());
+        let mut folded_ids: HashMap<String, String> = HashMap::new();
+        for n in notes {
+            let reason = if valid_note_id(&n.id) {
+                let folded = n.id.to_ascii_lowercase();
+                let twin: Option<String> = match folded_ids.get(&folded) {
+                    Some(id) => (*id != n.id).then(|| id.clone()),
+                    None => tx
+                        .query_row(
+                            "SELECT id FROM notes WHERE lower(id) = ?1 AND id <> ?2 LIMIT 1",
+                            params![folded, n.id],
+                            |row| row.get(0),
+                        )
+                        .optional()?,
+                };
+                folded_ids.entry(folded).or_insert_with(|| n.id.clone());
+                twin.map(|id| format!("id differs only in case from {}", id))
+            } else {
+                Some("invalid id".to_string())
+            };
+            let Some(reason) = reason else {
+                accepted.push(n);
+                continue;
+            };
+            let shown: String = n.id.chars().take(MAX_NOTE_ID_LEN).collect();
+            crate::logger::log(&format!(
+                "Quarantined pulled note {:?} ({} bytes): {}",
+                shown,
+                n.id.len(),
+                reason
+            ));
+            quarantine_note(&tx, &n, &now)?;
+        }
+        let notes = accepted;
+        let mut conflicts = 0;
+        let ids: Vec<String> = notes.iter().map(|n| n.id.clone()).collect();
+        // Whatever the merge below decides, remember what the server said
+        let remote_times: Vec<(String, String)> = notes
+            .iter()
+            .map(|n| (n.id.clone(), n.updated_at.clone()))
+            .collect();
 
         for n in notes {
+            let local: Option<(String, i32, i32, Option<String>, i32)> = tx
+                .query_row(
+                    "SELECT content, is_deleted, is_synced, base_content, modified_while_locked
+
+                     FROM notes WHERE id = ?1",
+                    params![n.id],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    },
+                )
+                .optional()?;
+
+            // Local edits not yet pushed: merge instead of letting the newer side win
+            if let Some((content, is_deleted, 0, base, while_locked)) = local {
+                // Edits made while locked may predate remote changes we never
+                // got to read, so they always go through the merge
+                let base = base.or_else(|| (while_locked == 1).then(String::new));
+                if n.is_deleted == 1 && is_deleted == 0 {
+                    // Deleted remotely, edited locally: keep the edit, it will be pushed back
+                    tx.execute(
+                        "UPDATE notes SET base_content = NULL WHERE id = ?1",
+                        params![n.id],
+                    )?;
+                    continue;
+                }
+                if let (Some(base), 0, 0) = (base, is_deleted, n.is_deleted) {
+                    if content != n.content {
+                        let (merged, conflict) = match merge::merge3(&base, &content, &n.content) {
+                            // A garbled pull stays flagged for review however it merged
+                            merge::MergeResult::Clean(text) => (text, n.has_conflict),
+                            merge::MergeResult::Conflicted(text) => {
+                                conflicts += 1;
+                                (text, 1)
+                            }
+                        };
+                        tx.execute(
+                            "UPDATE notes SET
+
+                                content = ?1,
+
+                                updated_at = ?2,
+
+                                base_content = ?3,
+
+                                key_version = ?4,
+
+                                has_conflict = ?5,
+
+                                garbled = MAX(garbled, ?7),
+
+                                modified_while_locked = 0
+
+                             WHERE id = ?6",
+                            params![
+                                merged,
+                                &now,
+                                n.content,
+                                n.key_version,
+                                conflict,
+                                n.id,
+                                n.garbled
+                            ],
+                        )?;
+                        continue;
+                    }
+                }
+            }
+
             tx.execute(
-                "INSERT INTO notes (id, content, updated_at, is_deleted, is_synced, is_encrypted)
+                "INSERT INTO notes (id, content, updated_at, created_at, is_deleted, is_synced, is_encrypted, key_version, base_content, has_conflict, label, garbled)
 
-                 VALUES (?1, ?2, ?3, ?4, 1, ?5)
+                 VALUES (?1, ?2, ?3, COALESCE(NULLIF(?7, ''), ?3), ?4, 1, ?5, ?6, ?2, ?8, NULLIF(?9, ''), ?10)
 
                  ON CONFLICT(id) DO UPDATE SET
 
@@ -502,20 +1825,64 @@ impl RepoInternal {
 
                     is_synced = 1,
 
-                    is_encrypted = excluded.is_encrypted
+                    is_encrypted = excluded.is_encrypted,
+
+                    key_version = excluded.key_version,
+
+                    base_content = excluded.base_content,
+
+                    created_at = CASE WHEN ?7 = '' THEN notes.created_at ELSE excluded.created_at END,
+
+                    has_conflict = excluded.has_conflict,
+
+                    label = CASE WHEN ?9 IS NULL THEN notes.label ELSE excluded.label END,
+
+                    garbled = excluded.garbled,
+
+                    modified_while_locked = 0
 
                  WHERE excluded.updated_at > notes.updated_at",
-                params![n.id, n.content, n.updated_at, n.is_deleted, n.is_encrypted],
+                params![
+                    n.id,
+                    n.content,
+                    n.updated_at,
+                    n.is_deleted,
+                    n.is_encrypted,
+                    n.key_version,
+                    n.created_at,
+                    n.has_conflict,
+                    n.label,
+                    n.garbled
+                ],
             )?;
         }
 
+        for (id, updated_at) in &remote_times {
+            tx.execute(
+                "UPDATE notes SET remote_updated_at = ?2 WHERE id = ?1",
+                params![id, updated_at],
+            )?;
+        }
+
+        // Reminders follow whatever content each note ended up with
+        for id in &ids {
+            let content: Option<String> = tx
+                .query_row(
+                    "SELECT content FROM notes WHERE id = ?1 AND is_deleted = 0",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            update_reminders(&tx, id, content.as_deref().unwrap_or(""))?;
+        }
+
         tx.execute(
             "INSERT OR REPLACE INTO kv_store (key, value) VALUES (?1, ?2)",
             params!["last_synced_at", cursor],
         )?;
 
         tx.commit()?;
-        Ok(())
+        Ok(conflicts)
     }
 
     fn get_kv(&self, key: &str) -> Result<Option<String>> {
@@ -550,10 +1917,54 @@ impl RepoInternal {
         Ok(())
     }
 
+    fn take_sync_lease(&self, owner: &str, until: &str) -> Result<bool> {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let value = serde_json::json!({ "owner": owner, "until": until }).to_string();
+        let taken = self.conn.execute(
+            "INSERT INTO kv_store (key, value) VALUES (?1, ?2)
+
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value
+
+             WHERE json_extract(kv_store.value, '$.until') < ?3
+                OR json_extract(kv_store.value, '$.owner') = ?4",
+            params![SYNC_LEASE_KEY, value, now, owner],
+        )?;
+        Ok(taken > 0)
+    }
+
+    fn release_sync_lease(&self, owner: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM kv_store WHERE key = ?1 AND json_extract(value, '$.owner') = ?2",
+            params![SYNC_LEASE_KEY, owner],
+        )?;
+        Ok(())
+    }
+
+    fn set_kv_batch(&mut self, entries: Vec<(String, Option<String>)>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        for (key, value) in entries {
+            match value {
+                Some(value) => tx.execute(
+                    "INSERT OR REPLACE INTO kv_store (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )?,
+                None => tx.execute("DELETE FROM kv_store WHERE key = ?1", params![key])?,
+            };
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
     #[allow(dead_code)]
     fn clear_all_data(&self) -> Result<()> {
         self.conn.execute("DELETE FROM notes", [])?;
 
+        self.conn.execute("DELETE FROM reminders", [])?;
+
+        self.conn.execute("DELETE FROM quarantined_notes", [])?;
+
         self.conn.execute("DELETE FROM kv_store", [])?;
 
         Ok(())
@@ -570,3 +1981,510 @@ impl RepoInternal {
         Ok(())
     }
 }
+
+// A pulled note kept as the server sent it, in place of or beside its row
+fn quarantine_note(conn: &Connection, note: &Note, received_at: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO quarantined_notes (id, note, received_at) VALUES (?1, ?2, ?3)",
+        params![note.id, serde_json::to_string(note)?, received_at],
+    )?;
+    Ok(())
+}
+
+/// Rewrites the reminder rows of `note_id` from the tokens in `content`. A
+/// token that was already there keeps its due time, so `@due(tomorrow)` doesn't
+/// move a day later on every save.
+fn update_reminders(conn: &Connection, note_id: &str, content: &str) -> Result<()> {
+    let specs = reminder::tokens(content);
+    let mut stmt = conn.prepare("SELECT spec, due_at FROM reminders WHERE note_id = ?1")?;
+    let existing = stmt
+        .query_map(params![note_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+    if specs.is_empty() && existing.is_empty() {
+        return Ok(());
+    }
+
+    conn.execute("DELETE FROM reminders WHERE note_id = ?1", params![note_id])?;
+    let now = Local::now();
+    for spec in specs {
+        let due_at = match existing.get(&spec) {
+            Some(due_at) => due_at.clone(),
+            None => match reminder::parse_due(&spec, now) {
+                Some(due) => due.to_rfc3339(),
+                None => continue,
+            },
+        };
+        conn.execute(
+            "INSERT OR IGNORE INTO reminders (note_id, spec, due_at) VALUES (?1, ?2, ?3)",
+            params![note_id, spec, due_at],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> RepoInternal {
+        RepoInternal::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    // A note as the server sends it
+    fn pulled(id: &str, content: &str, updated_at: &str) -> Note {
+        Note {
+            id: id.to_string(),
+            content: content.to_string(),
+            updated_at: updated_at.to_string(),
+            created_at: String::new(),
+            is_deleted: 0,
+            is_synced: 1,
+            is_encrypted: 0,
+            key_version: 0,
+            has_conflict: 0,
+            garbled: 0,
+            modified_while_locked: 0,
+            remote_updated_at: None,
+            pushed_updated_at: None,
+            older_than_remote: false,
+            label: None,
+        }
+    }
+
+    fn quarantined(repo: &RepoInternal) -> Vec<String> {
+        let mut stmt = repo
+            .conn
+            .prepare("SELECT id FROM quarantined_notes ORDER BY rowid")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn valid_note_id_accepts_uuids_in_either_case() {
+        assert!(valid_note_id("0f8fad5b-d9cb-469f-a165-70867728950e"));
+        assert!(valid_note_id("0F8FAD5B-D9CB-469F-A165-70867728950E"));
+        assert!(valid_note_id("note_1"));
+    }
+
+    #[test]
+    fn valid_note_id_rejects_hostile_ids() {
+        for id in [
+            "",
+            "../../etc/passwd",
+            "notes/abc",
+            "notes\\abc",
+            "abc\ndef",
+            "abc\r",
+            "abc\0",
+            "last_synced_at ",
+            "caf\u{e9}",
+        ] {
+            assert!(!valid_note_id(id), "{:?}", id);
+        }
+        assert!(valid_note_id(&"a".repeat(MAX_NOTE_ID_LEN)));
+        assert!(!valid_note_id(&"a".repeat(MAX_NOTE_ID_LEN + 1)));
+        assert!(!valid_note_id(&"a".repeat(10 * 1024)));
+    }
+
+    #[test]
+    fn pull_quarantines_hostile_ids() {
+        let mut repo = repo();
+        let long = "x".repeat(10 * 1024);
+        let notes = vec![
+            pulled("../escape", "a", "2026-01-01T00:00:00Z"),
+            pulled("line\nbreak", "b", "2026-01-01T00:00:00Z"),
+            pulled(&long, "c", "2026-01-01T00:00:00Z"),
+            pulled("good", "d", "2026-01-01T00:00:00Z"),
+        ];
+        repo.pull_upsert_notes(notes, "cursor").unwrap();
+
+        let ids: Vec<String> = repo
+            .get_notes(false)
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(ids, ["good"]);
+        assert_eq!(
+            quarantined(&repo),
+            ["../escape", "line\nbreak", long.as_str()]
+        );
+
+        // Clearing local data leaves none of them behind
+        repo.clear_all_data().unwrap();
+        assert!(quarantined(&repo).is_empty());
+    }
+
+    #[test]
+    fn pull_keeps_id_case_and_quarantines_case_twins() {
+        let mut repo = repo();
+        repo.pull_upsert_notes(vec![pulled("ABC", "first", "2026-01-01T00:00:00Z")], "1")
+            .unwrap();
+        repo.pull_upsert_notes(
+            vec![
+                pulled("abc", "twin", "2026-01-02T00:00:00Z"),
+                pulled("Def", "second", "2026-01-02T00:00:00Z"),
+                pulled("DEF", "twin in batch", "2026-01-02T00:00:00Z"),
+            ],
+            "2",
+        )
+        .unwrap();
+
+        let mut ids: Vec<String> = repo
+            .get_notes(false)
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["ABC", "Def"]);
+        assert_eq!(repo.get_note("ABC").unwrap().unwrap().content, "first");
+        assert_eq!(quarantined(&repo), ["abc", "DEF"]);
+    }
+
+    #[test]
+    fn scratch_note_does_not_hold_up_key_rotation() {
+        let mut repo = repo();
+        repo.pull_upsert_notes(vec![pulled("synced", "a", "2026-01-01T00:00:00Z")], "1")
+            .unwrap();
+        repo.scratch_note().unwrap();
+        assert_eq!(repo.mark_unsynced_below_key_version(1).unwrap(), 1);
+        assert_eq!(repo.count_below_key_version(1).unwrap(), 1);
+
+        repo.conn
+            .execute("UPDATE notes SET key_version = 1 WHERE id = 'synced'", [])
+            .unwrap();
+        assert_eq!(repo.count_below_key_version(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn ids_sharing_a_prefix_are_told_apart() {
+        let mut repo = repo();
+        let first = "0f8fad5b-0000-469f-a165-70867728950e";
+        let second = "0f8fad5b-1111-469f-a165-70867728950e";
+        repo.pull_upsert_notes(vec![pulled(first, "a", "2026-01-01T00:00:00Z")], "1")
+            .unwrap();
+        assert_eq!(repo.short_id_len().unwrap(), SHORT_ID_LEN);
+
+        repo.pull_upsert_notes(vec![pulled(second, "b", "2026-01-01T00:00:00Z")], "2")
+            .unwrap();
+        // "0f8fad5b-0" and "0f8fad5b-1" are the shortest prefixes that differ
+        assert_eq!(repo.short_id_len().unwrap(), 10);
+        assert!(matches!(
+            repo.resolve_id("0f8fad5b", false).unwrap(),
+            ResolvedId::Ambiguous(ids) if ids == [first, second]
+        ));
+        assert!(matches!(
+            repo.resolve_id(short_id(second, 10), false).unwrap(),
+            ResolvedId::Unique(id) if id == second
+        ));
+        assert!(matches!(
+            repo.resolve_id("0f8fad5c", false).unwrap(),
+            ResolvedId::NotFound
+        ));
+
+        // A deleted note no longer needs telling apart
+        repo.delete_note(second).unwrap();
+        assert_eq!(repo.short_id_len().unwrap(), SHORT_ID_LEN);
+        assert!(matches!(
+            repo.resolve_id("0f8fad5b", false).unwrap(),
+            ResolvedId::Unique(id) if id == first
+        ));
+        assert!(matches!(
+            repo.resolve_id("0f8fad5b", true).unwrap(),
+            ResolvedId::Ambiguous(_)
+        ));
+    }
+
+    #[test]
+    fn deleted_note_leaves_search_and_stays_in_trash() {
+        let repo = repo();
+        let id = repo.save_note(None, "groceries", false, false).unwrap();
+        repo.delete_note(&id).unwrap();
+
+        assert!(repo.get_notes(false).unwrap().is_empty());
+        assert!(matches!(
+            repo.resolve_id(&id[..8], false).unwrap(),
+            ResolvedId::NotFound
+        ));
+
+        let trash = repo.get_notes(true).unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(
+            (trash[0].id.as_str(), trash[0].is_deleted),
+            (id.as_str(), 1)
+        );
+        assert!(matches!(
+            repo.resolve_id(&id[..8], true).unwrap(),
+            ResolvedId::Unique(found) if found == id
+        ));
+    }
+
+    #[test]
+    fn compacted_tombstone_sent_again_is_reinserted() {
+        let mut repo = repo();
+        repo.pull_upsert_notes(
+            vec![pulled("gone", "groceries", "2026-01-01T00:00:00Z")],
+            "1",
+        )
+        .unwrap();
+        let mut tombstone = pulled("gone", "groceries", "2026-01-02T00:00:00Z");
+        tombstone.is_deleted = 1;
+        repo.pull_upsert_notes(vec![tombstone.clone()], "2")
+            .unwrap();
+        assert_eq!(repo.count_notes().unwrap().tombstones, 1);
+
+        assert_eq!(repo.compact_tombstones("2026-02-01T00:00:00Z").unwrap(), 1);
+        assert!(repo.get_note("gone").unwrap().is_none());
+
+        // The server doesn't know the row is gone and sends the deletion again
+        assert_eq!(repo.pull_upsert_notes(vec![tombstone], "3").unwrap(), 0);
+        let note = repo.get_note("gone").unwrap().unwrap();
+        assert_eq!((note.is_deleted, note.is_synced), (1, 1));
+        assert!(repo.get_notes(false).unwrap().is_empty());
+        assert!(repo.get_unsynced_notes().unwrap().is_empty());
+        let counts = repo.count_notes().unwrap();
+        assert_eq!((counts.live, counts.tombstones), (0, 1));
+
+        // It is compacted again like any other old tombstone
+        assert_eq!(repo.compact_tombstones("2026-02-01T00:00:00Z").unwrap(), 1);
+    }
+
+    #[test]
+    fn garbled_note_is_held_back_until_accepted() {
+        let mut repo = repo();
+        let mut note = pulled("garbled", "a\u{fffd}b", "2026-01-01T00:00:00Z");
+        note.garbled = 1;
+        note.has_conflict = 1;
+        repo.pull_upsert_notes(vec![note], "1").unwrap();
+
+        // Saving clears the conflict flag (no markers) but not the hold
+        repo.save_note(Some("garbled".to_string()), "ab", false, false)
+            .unwrap();
+        assert!(repo.get_unsynced_notes().unwrap().is_empty());
+
+        assert!(repo.accept_garbled("garbled").unwrap());
+        assert!(!repo.accept_garbled("garbled").unwrap());
+        let unsynced = repo.get_unsynced_notes().unwrap();
+        assert_eq!(unsynced.len(), 1);
+        assert_eq!(unsynced[0].content, "ab");
+    }
+
+    #[test]
+    fn conflicted_note_is_held_back_until_resolved() {
+        let repo = repo();
+        let conflicted = format!(
+            "a\n{}\nL\n{}\nR\n{}",
+            merge::CONFLICT_LOCAL,
+            merge::CONFLICT_SEPARATOR,
+            merge::CONFLICT_REMOTE
+        );
+        let id = repo.save_note(None, &conflicted, false, false).unwrap();
+        assert_eq!(repo.get_note(&id).unwrap().unwrap().has_conflict, 1);
+        assert!(repo.get_unsynced_notes().unwrap().is_empty());
+
+        repo.save_note(Some(id.clone()), "a\nL", false, false)
+            .unwrap();
+        let unsynced = repo.get_unsynced_notes().unwrap();
+        assert_eq!(unsynced.len(), 1);
+        assert_eq!(unsynced[0].id, id);
+    }
+
+    #[test]
+    fn accepting_a_garbled_note_unflags_it_as_a_conflict() {
+        let mut repo = repo();
+        let mut note = pulled("garbled", "a\u{fffd}b", "2026-01-01T00:00:00Z");
+        note.garbled = 1;
+        note.has_conflict = 1;
+        repo.pull_upsert_notes(vec![note], "1").unwrap();
+        repo.conn
+            .execute("UPDATE notes SET is_synced = 0 WHERE id = 'garbled'", [])
+            .unwrap();
+
+        assert!(repo.accept_garbled("garbled").unwrap());
+        let unsynced = repo.get_unsynced_notes().unwrap();
+        assert_eq!(unsynced.len(), 1);
+        assert_eq!(unsynced[0].has_conflict, 0);
+    }
+
+    #[test]
+    fn pulls_and_saves_keep_when_a_note_was_created() {
+        let mut repo = repo();
+        let mut note = pulled("kept", "v1", "2026-01-02T00:00:00Z");
+        note.created_at = "2025-06-01T00:00:00Z".to_string();
+        repo.pull_upsert_notes(vec![note], "1").unwrap();
+
+        // A newer version from a server that doesn't send the field
+        repo.pull_upsert_notes(vec![pulled("kept", "v2", "2026-01-03T00:00:00Z")], "2")
+            .unwrap();
+        let stored = repo.get_note("kept").unwrap().unwrap();
+        assert_eq!(
+            (stored.content.as_str(), stored.created_at.as_str()),
+            ("v2", "2025-06-01T00:00:00Z")
+        );
+
+        // Nor does a local edit move it
+        repo.save_note(Some("kept".to_string()), "v3", false, false)
+            .unwrap();
+        let stored = repo.get_note("kept").unwrap().unwrap();
+        assert_eq!(stored.created_at, "2025-06-01T00:00:00Z");
+    }
+
+    #[test]
+    fn a_pulled_note_without_created_at_dates_from_its_update() {
+        let mut repo = repo();
+        let note: Note = serde_json::from_value(serde_json::json!({
+            "id": "old-server",
+            "content": "from before created_at",
+            "updated_at": "2026-01-01T00:00:00Z",
+            "is_deleted": 0,
+        }))
+        .unwrap();
+        assert_eq!(note.created_at, "");
+        repo.pull_upsert_notes(vec![note], "1").unwrap();
+
+        let stored = repo.get_note("old-server").unwrap().unwrap();
+        assert_eq!(stored.created_at, "2026-01-01T00:00:00Z");
+        // Later versions don't move it either
+        repo.pull_upsert_notes(
+            vec![pulled("old-server", "edited", "2026-02-01T00:00:00Z")],
+            "2",
+        )
+        .unwrap();
+        let stored = repo.get_note("old-server").unwrap().unwrap();
+        assert_eq!(stored.created_at, "2026-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn saving_unchanged_content_leaves_the_note_alone() {
+        let mut repo = repo();
+        repo.pull_upsert_notes(
+            vec![pulled("synced", "groceries", "2026-01-01T00:00:00Z")],
+            "1",
+        )
+        .unwrap();
+
+        repo.save_note(Some("synced".to_string()), "groceries", false, false)
+            .unwrap();
+        let note = repo.get_note("synced").unwrap().unwrap();
+        assert_eq!(
+            (note.updated_at.as_str(), note.is_synced),
+            ("2026-01-01T00:00:00Z", 1)
+        );
+        assert!(repo.get_unsynced_notes().unwrap().is_empty());
+
+        repo.save_note(Some("synced".to_string()), "groceries\nmilk", false, false)
+            .unwrap();
+        let note = repo.get_note("synced").unwrap().unwrap();
+        assert!(note.updated_at.as_str() > "2026-01-01T00:00:00Z");
+        assert_eq!(note.is_synced, 0);
+    }
+
+    #[test]
+    fn notes_from_before_reminders_existed_are_indexed_once() {
+        let repo = repo();
+        let id = repo
+            .save_note(None, "dentist @due(2030-01-02 09:00)", false, false)
+            .unwrap();
+        repo.conn.execute("DROP TABLE reminders", []).unwrap();
+
+        repo.migrate().unwrap();
+        let reminders = repo.get_reminders().unwrap();
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].note_id, id);
+
+        // Opening again leaves the resolved time alone
+        repo.conn
+            .execute("UPDATE reminders SET due_at = 'kept'", [])
+            .unwrap();
+        repo.migrate().unwrap();
+        let due_at: String = repo
+            .conn
+            .query_row("SELECT due_at FROM reminders", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(due_at, "kept");
+    }
+
+    #[test]
+    fn enabling_e2e_keeps_the_list_order() {
+        let mut repo = repo();
+        repo.pull_upsert_notes(
+            vec![
+                pulled("older", "a", "2026-01-01T00:00:00Z"),
+                pulled("newer", "b", "2026-01-02T00:00:00Z"),
+            ],
+            "1",
+        )
+        .unwrap();
+        let order = |repo: &RepoInternal| -> Vec<(String, String)> {
+            repo.get_notes(false)
+                .unwrap()
+                .into_iter()
+                .map(|n| (n.id, n.updated_at))
+                .collect()
+        };
+        let before = order(&repo);
+        assert_eq!(before[0].0, "newer");
+
+        repo.set_notes_encrypted_status(1).unwrap();
+        assert_eq!(order(&repo), before);
+        // Still queued so the notes go up encrypted
+        assert_eq!(repo.get_unsynced_notes().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn read_only_repo_reads_but_never_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local.db");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        // A file risu never opened has no tables, and a read-only open
+        // doesn't create them
+        Connection::open(&path).unwrap();
+        let empty = Repo::open_read_only(&path).unwrap();
+        assert!(rt.block_on(empty.get_notes(false)).is_err());
+        drop(empty);
+
+        let writer = Repo::open(&path).unwrap();
+        let id = rt
+            .block_on(writer.save_note(None, "kept".to_string(), false, false))
+            .unwrap();
+        let reader = Repo::open_read_only(&path).unwrap();
+        let notes = rt.block_on(reader.get_notes(false)).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, id);
+        assert!(rt
+            .block_on(reader.save_note(None, "lost".to_string(), false, false))
+            .is_err());
+    }
+
+    #[test]
+    fn restoring_a_snapshot_brings_back_its_notes_and_frees_the_lease() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("daily-2026-01-01.db");
+        let mut repo = repo();
+        let kept = repo.save_note(None, "kept", false, false).unwrap();
+        repo.take_sync_lease("other", "2999-01-01T00:00:00Z")
+            .unwrap();
+        repo.snapshot_to(&snapshot).unwrap();
+
+        repo.delete_note(&kept).unwrap();
+        repo.save_note(None, "added later", false, false).unwrap();
+        repo.restore_from(&snapshot).unwrap();
+
+        let notes = repo.get_notes(false).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(
+            (notes[0].id.as_str(), notes[0].content.as_str()),
+            (kept.as_str(), "kept")
+        );
+        assert!(repo
+            .take_sync_lease("restorer", "2999-01-01T00:00:00Z")
+            .unwrap());
+    }
+}
@@ -0,0 +1,276 @@
+// Line-based diff (Myers' O(ND) algorithm), and a word-level one for lines
+// that changed.
+
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffOp<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+// Beyond this many cells of search trace the middle of the diff is reported
+// as a block replacement instead, which keeps memory bounded on huge rewrites.
+const MAX_TRACE_CELLS: usize = 4_000_000;
+
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffOp<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut ops: Vec<DiffOp> = a[..prefix].iter().map(|l| DiffOp::Same(l)).collect();
+    ops.extend(myers(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+    ));
+    ops.extend(a[a.len() - suffix..].iter().map(|l| DiffOp::Same(l)));
+    ops
+}
+
+/// `old` → `new` split into words, runs of whitespace and single punctuation
+/// marks, so a changed line can show which words changed.
+pub fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<DiffOp<'a>> {
+    myers(&tokens(old), &tokens(new))
+}
+
+#[derive(PartialEq)]
+enum TokenKind {
+    Word,
+    Space,
+    Other,
+}
+
+fn token_kind(c: char) -> TokenKind {
+    if c.is_alphanumeric() || c == '_' {
+        TokenKind::Word
+    } else if c.is_whitespace() {
+        TokenKind::Space
+    } else {
+        TokenKind::Other
+    }
+}
+
+fn tokens(line: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<TokenKind> = None;
+    let mut joined = false;
+    for (i, c) in line.char_indices() {
+        // Combining marks, variation selectors and whatever a zero-width
+        // joiner links stay with the character before them
+        if prev.is_some() && (joined || c.width() == Some(0)) {
+            joined = c == '\u{200d}';
+            continue;
+        }
+        let kind = token_kind(c);
+        // Punctuation stands alone; words and whitespace run on
+        if prev
+            .as_ref()
+            .is_some_and(|p| *p != kind || kind == TokenKind::Other)
+        {
+            out.push(&line[start..i]);
+            start = i;
+        }
+        prev = Some(kind);
+    }
+    if start < line.len() {
+        out.push(&line[start..]);
+    }
+    out
+}
+
+fn myers<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize;
+    let mut v = vec![0isize; 2 * max + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    // Forward pass: furthest x reached on each diagonal k for each edit count d
+    'search: for d in 0..=max as isize {
+        if (trace.len() + 1) * v.len() > MAX_TRACE_CELLS {
+            let mut ops: Vec<DiffOp> = a.iter().map(|l| DiffOp::Removed(l)).collect();
+            ops.extend(b.iter().map(|l| DiffOp::Added(l)));
+            return ops;
+        }
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk the trace back from the end to recover the edit script
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Same(a[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(DiffOp::Added(b[y as usize - 1]));
+                y -= 1;
+            } else {
+                ops.push(DiffOp::Removed(a[x as usize - 1]));
+                x -= 1;
+            }
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffOp::{Added, Removed, Same};
+
+    // Both sides put back together from the ops
+    fn sides(ops: &[DiffOp]) -> (String, String) {
+        let (mut old, mut new) = (String::new(), String::new());
+        for op in ops {
+            match op {
+                Same(t) => {
+                    old.push_str(t);
+                    new.push_str(t);
+                }
+                Removed(t) => old.push_str(t),
+                Added(t) => new.push_str(t),
+            }
+        }
+        (old, new)
+    }
+
+    #[test]
+    fn changed_words_are_picked_out() {
+        assert_eq!(
+            diff_words("the quick fox", "the slow fox"),
+            [
+                Same("the"),
+                Same(" "),
+                Removed("quick"),
+                Added("slow"),
+                Same(" "),
+                Same("fox")
+            ]
+        );
+        // Punctuation stands alone; whitespace runs on
+        assert_eq!(
+            diff_words("a(b)", "a(b))"),
+            [Same("a"), Same("("), Same("b"), Same(")"), Added(")")]
+        );
+        assert_eq!(
+            diff_words("x  y", "x y"),
+            [Same("x"), Removed("  "), Added(" "), Same("y")]
+        );
+        assert_eq!(diff_words("", "new"), [Added("new")]);
+        assert_eq!(diff_words("old", ""), [Removed("old")]);
+    }
+
+    #[test]
+    fn unicode_words_stay_whole() {
+        assert_eq!(
+            diff_words("日本語のテキスト", "日本語の文章"),
+            [Removed("日本語のテキスト"), Added("日本語の文章")]
+        );
+        assert_eq!(
+            diff_words("naïve café", "naive café"),
+            [Removed("naïve"), Added("naive"), Same(" "), Same("café")]
+        );
+        // A combining accent is part of its letter, not a change of its own
+        assert_eq!(
+            diff_words("cafe\u{301} au lait", "cafe au lait"),
+            [
+                Removed("cafe\u{301}"),
+                Added("cafe"),
+                Same(" "),
+                Same("au"),
+                Same(" "),
+                Same("lait")
+            ]
+        );
+        assert_eq!(
+            diff_words("e\u{301}\u{302}!", "e\u{301}?"),
+            [
+                Removed("e\u{301}\u{302}"),
+                Removed("!"),
+                Added("e\u{301}"),
+                Added("?")
+            ]
+        );
+        // Emoji sequences joined with ZWJ, and variation selectors
+        assert_eq!(
+            diff_words("hi 👩\u{200d}💻", "hi 👩"),
+            [Same("hi"), Same(" "), Removed("👩\u{200d}💻"), Added("👩")]
+        );
+        assert_eq!(
+            diff_words("❤\u{fe0f}", "❤"),
+            [Removed("❤\u{fe0f}"), Added("❤")]
+        );
+        // A mark with nothing before it still forms a token
+        assert_eq!(diff_words("\u{301}a", "a"), [Removed("\u{301}"), Same("a")]);
+    }
+
+    #[test]
+    fn word_diffs_rebuild_both_sides() {
+        let pairs = [
+            ("", ""),
+            ("same", "same"),
+            ("fn main() { println!(\"hi\"); }", "fn main() {\n}"),
+            ("ｆｕｌｌ　ｗｉｄｔｈ", "full width"),
+            ("a\tb  c", "a b\tc "),
+            ("ありがとう ございます", "ありがとう、ございます"),
+            ("Zalgo: z\u{336}\u{31b}a\u{35c}", "Zalgo: za"),
+            ("🇯🇵🇺🇸", "🇯🇵"),
+        ];
+        for (old, new) in pairs {
+            let ops = diff_words(old, new);
+            assert_eq!(sides(&ops), (old.to_string(), new.to_string()), "{:?}", ops);
+            // No token starts with a combining mark unless the line does
+            for op in &ops {
+                let (Same(t) | Removed(t) | Added(t)) = op;
+                let first = t.chars().next().unwrap();
+                assert!(
+                    first.width() != Some(0) || old.starts_with(t) || new.starts_with(t),
+                    "{:?}",
+                    t
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// What the user sees when risu exits with an error or panics: a short summary,
+// a guess at the cause for the failures people actually hit, and where the
+// full details went.
+
+use std::io;
+use std::panic::PanicHookInfo;
+
+use crate::logger;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cause {
+    DbLocked,
+    DirNotWritable,
+    Dns,
+}
+
+impl Cause {
+    fn explanation(self) -> &'static str {
+        match self {
+            Cause::DbLocked => {
+                "The local database is locked. Another risu process (or a tool holding \
+                 local.db open) is probably still running; close it and try again."
+            }
+            Cause::DirNotWritable => {
+                "risu can't write to its data directory. Check that it exists and that \
+                 you own it."
+            }
+            Cause::Dns => {
+                "The server's address couldn't be resolved. Check your network connection, \
+                 or set general.offline_mode = true to work offline."
+            }
+        }
+    }
+}
+
+/// Best guess at why `err` happened, from anywhere in its chain.
+pub fn classify(err: &anyhow::Error) -> Option<Cause> {
+    for cause in err.chain() {
+        if let Some(rusqlite::Error::SqliteFailure(e, _)) = cause.downcast_ref::<rusqlite::Error>()
+        {
+            match e.code {
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+                    return Some(Cause::DbLocked)
+                }
+                rusqlite::ErrorCode::ReadOnly | rusqlite::ErrorCode::CannotOpen => {
+                    return Some(Cause::DirNotWritable)
+                }
+                _ => {}
+            }
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+            ) {
+                return Some(Cause::DirNotWritable);
+            }
+        }
+        // reqwest and hyper only expose resolver failures through their messages
+        let msg = cause.to_string().to_lowercase();
+        if msg.contains("database is locked") {
+            return Some(Cause::DbLocked);
+        }
+        if msg.contains("dns error")
+            || msg.contains("failed to lookup address")
+            || msg.contains("name or service not known")
+            || msg.contains("nodename nor servname")
+        {
+            return Some(Cause::Dns);
+        }
+    }
+    None
+}
+
+/// Prints `err` for a person rather than a developer. Call after the terminal
+/// has been restored.
+pub fn report(err: &anyhow::Error) {
+    logger::log(&format!("Exiting with error: {:?}", err));
+
+    eprintln!("risu stopped because of an error:\n");
+    eprintln!("  {}", err);
+    for cause in err.chain().skip(1) {
+        eprintln!("  caused by: {}", cause);
+    }
+    if let Some(cause) = classify(err) {
+        eprintln!("\nProbable cause: {}", cause.explanation());
+    }
+    eprintln!("\nDetails were written to {}", logger::log_path().display());
+    eprintln!("Run `risu doctor` to check your setup.");
+}
+
+/// Records a panic in the log. The panic hook calls this before restoring the
+/// terminal, since the printed message can end up lost with the alternate screen.
+pub fn log_panic(info: &PanicHookInfo) {
+    let payload = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "(non-string payload)".to_string());
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+        .unwrap_or_else(|| "unknown location".to_string());
+    logger::log(&format!("Panic at {}: {}", location, payload));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn a_database_held_by_another_process_is_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local.db");
+        let holder = rusqlite::Connection::open(&path).unwrap();
+        holder
+            .execute_batch("CREATE TABLE notes (id TEXT); BEGIN EXCLUSIVE;")
+            .unwrap();
+        let other = rusqlite::Connection::open(&path).unwrap();
+        other.busy_timeout(std::time::Duration::ZERO).unwrap();
+        let busy = other
+            .execute("INSERT INTO notes VALUES ('a')", [])
+            .unwrap_err();
+
+        let err = anyhow::Error::new(busy).context("Failed to save note");
+        assert_eq!(classify(&err), Some(Cause::DbLocked));
+        // Flattened into a message somewhere along the way
+        let err = anyhow!("sync failed: database is locked");
+        assert_eq!(classify(&err), Some(Cause::DbLocked));
+    }
+
+    #[test]
+    fn unwritable_places_are_a_directory_problem() {
+        let denied: anyhow::Result<()> = Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to create ~/.risu");
+        assert_eq!(classify(&denied.unwrap_err()), Some(Cause::DirNotWritable));
+        let read_only = anyhow::Error::new(io::Error::from(io::ErrorKind::ReadOnlyFilesystem));
+        assert_eq!(classify(&read_only), Some(Cause::DirNotWritable));
+
+        let dir = tempfile::tempdir().unwrap();
+        let missing = rusqlite::Connection::open_with_flags(
+            dir.path().join("no").join("such").join("local.db"),
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+        )
+        .unwrap_err();
+        assert_eq!(
+            classify(&anyhow::Error::new(missing)),
+            Some(Cause::DirNotWritable)
+        );
+    }
+
+    #[test]
+    fn resolver_failures_are_dns() {
+        for msg in [
+            "error sending request: dns error: failed to lookup address information",
+            "Failed to lookup address information: Name or service not known",
+            "nodename nor servname provided, or not known",
+        ] {
+            let err = anyhow!(msg.to_string()).context("Could not reach the server");
+            assert_eq!(classify(&err), Some(Cause::Dns), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn other_errors_have_no_guess() {
+        assert_eq!(classify(&anyhow!("Pull failed: 500")), None);
+        let not_found = anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(classify(&not_found), None);
+        let constraint = rusqlite::Connection::open_in_memory()
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE t (id TEXT PRIMARY KEY); INSERT INTO t VALUES ('a'), ('a');",
+            )
+            .unwrap_err();
+        assert_eq!(classify(&anyhow::Error::new(constraint)), None);
+    }
+}
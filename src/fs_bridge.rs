@@ -0,0 +1,572 @@
+// Mirrors notes into a directory of markdown files (`general.notes_dir`) and
+// imports edits made there. The directory is polled by mtime; each file maps to
+// one note id, and the last seen mtime and note timestamp per file are kept in
+// the kv store so changes made while risu wasn't running are picked up too.
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use zeroize::Zeroizing;
+
+use crate::config;
+use crate::crypto;
+use crate::db::{Note, Repo};
+
+const STATE_KEY: &str = "notes_dir_state";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// File name stem length, before the extension and any dedup suffix
+const MAX_STEM_CHARS: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    id: String,
+    // File mtime (ms) when we last wrote or imported it
+    mtime: i64,
+    // Note `updated_at` when we last wrote or imported it
+    updated_at: String,
+}
+
+pub struct FsBridge {
+    dir: PathBuf,
+    repo: Repo,
+    crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+    // `general.notes_dir_deletes`: a file removed from the directory deletes
+    // its note. Off, the file is written back instead
+    deletes: bool,
+    // Pinged after notes were changed from disk
+    changed_tx: mpsc::Sender<()>,
+    sync_trigger: mpsc::Sender<()>,
+}
+
+/// `general.notes_dir` with `~/` expanded, or None when the mode is off.
+pub fn notes_dir(setting: &str) -> Option<PathBuf> {
+    let setting = setting.trim();
+    if setting.is_empty() {
+        return None;
+    }
+    match setting.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+        None => Some(PathBuf::from(setting)),
+    }
+}
+
+impl FsBridge {
+    pub fn new(
+        dir: PathBuf,
+        repo: Repo,
+        crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+        deletes: bool,
+        changed_tx: mpsc::Sender<()>,
+        sync_trigger: mpsc::Sender<()>,
+    ) -> Self {
+        Self {
+            dir,
+            repo,
+            crypto_key,
+            deletes,
+            changed_tx,
+            sync_trigger,
+        }
+    }
+
+    pub async fn start(self) {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            crate::logger::log(&format!(
+                "notes_dir: can't create {}: {}",
+                self.dir.display(),
+                e
+            ));
+            return;
+        }
+        crate::logger::log(&format!("notes_dir: mirroring {}", self.dir.display()));
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.scan().await {
+                Ok(true) => {
+                    let _ = self.changed_tx.try_send(());
+                    let _ = self.sync_trigger.try_send(());
+                }
+                Ok(false) => {}
+                Err(e) => crate::logger::log(&format!("notes_dir: scan failed: {:#}", e)),
+            }
+            if self.changed_tx.is_closed() {
+                return;
+            }
+        }
+    }
+
+    /// One reconciliation pass. Returns whether any note was changed from disk.
+    async fn scan(&self) -> Result<bool> {
+        let notes: HashMap<String, Note> = self
+            .repo
+            .get_notes(false)
+            .await?
+            .into_iter()
+            .map(|n| (n.id.clone(), n))
+            .collect();
+        let mut files = self.list_files()?;
+        let state = self.load_state().await?;
+        let mut new_state: BTreeMap<String, Entry> = BTreeMap::new();
+        let mut notes_changed = false;
+        let mut deleted = Vec::new();
+
+        // Files we know about
+        for (name, entry) in &state {
+            let note = notes.get(&entry.id);
+            let mtime = files.remove(name);
+            match (note, mtime) {
+                (None, None) => {}
+                (None, Some(mtime)) if mtime != entry.mtime => {
+                    // Edited on disk after the note was deleted: keep the edit
+                    crate::logger::log(&format!(
+                        "notes_dir: {} changed after its note was deleted; importing it as a new note",
+                        name
+                    ));
+                    files.insert(name.clone(), mtime);
+                }
+                (None, Some(_)) => {
+                    crate::logger::log(&format!("notes_dir: note deleted, removing {}", name));
+                    let _ = fs::remove_file(self.dir.join(name));
+                }
+                (Some(note), None) => {
+                    let renamed = self.find_rename(&mut files, note);
+                    if let Some((new_name, mtime)) = renamed {
+                        crate::logger::log(&format!("notes_dir: {} renamed to {}", name, new_name));
+                        new_state.insert(
+                            new_name,
+                            Entry {
+                                mtime,
+                                ..entry.clone()
+                            },
+                        );
+                    } else if note.updated_at != entry.updated_at {
+                        crate::logger::log(&format!(
+                            "notes_dir: {} was deleted but its note changed since; writing it back",
+                            name
+                        ));
+                        let mtime = self.write_file(name, &note.content)?;
+                        new_state.insert(name.clone(), self.entry(note, mtime));
+                    } else if !self.deletes {
+                        crate::logger::log(&format!(
+                            "notes_dir: {} was deleted; writing it back (notes_dir_deletes is off)",
+                            name
+                        ));
+                        let mtime = self.write_file(name, &note.content)?;
+                        new_state.insert(name.clone(), self.entry(note, mtime));
+                    } else {
+                        crate::logger::log(&format!(
+                            "notes_dir: {} deleted, deleting its note",
+                            name
+                        ));
+                        self.repo.delete_note(note.id.clone()).await?;
+                        deleted.push(note.id.as_str());
+                        notes_changed = true;
+                    }
+                }
+                (Some(note), Some(mtime)) => {
+                    let file_changed = mtime != entry.mtime;
+                    let note_changed = note.updated_at != entry.updated_at;
+                    let entry = if file_changed {
+                        let content = self.read_file(name)?;
+                        let file_wins = content != note.content
+                            && (!note_changed || {
+                                let note_ms = timestamp_ms(&note.updated_at);
+                                crate::logger::log(&format!(
+                                    "notes_dir: {} and its note both changed; keeping the {} one",
+                                    name,
+                                    if mtime >= note_ms { "file" } else { "in-app" }
+                                ));
+                                mtime >= note_ms
+                            });
+                        if file_wins {
+                            notes_changed = true;
+                            self.import(Some(note.id.clone()), content, mtime).await?
+                        } else if content != note.content {
+                            let mtime = self.write_file(name, &note.content)?;
+                            self.entry(note, mtime)
+                        } else {
+                            self.entry(note, mtime)
+                        }
+                    } else if note_changed {
+                        if crypto::looks_like_ciphertext(&note.content) {
+                            entry.clone()
+                        } else {
+                            let mtime = self.write_file(name, &note.content)?;
+                            self.entry(note, mtime)
+                        }
+                    } else {
+                        entry.clone()
+                    };
+                    new_state.insert(name.clone(), entry);
+                }
+            }
+        }
+
+        // Files that appeared on disk
+        for (name, mtime) in files {
+            let content = self.read_file(&name)?;
+            if content.trim().is_empty() {
+                continue;
+            }
+            crate::logger::log(&format!("notes_dir: importing new file {}", name));
+            let entry = self.import(None, content, mtime).await?;
+            new_state.insert(name, entry);
+            notes_changed = true;
+        }
+
+        // Notes that have no file yet
+        let mapped: Vec<&str> = new_state.values().map(|e| e.id.as_str()).collect();
+        let unmapped: Vec<&Note> = notes
+            .values()
+            .filter(|n| !mapped.contains(&n.id.as_str()) && !deleted.contains(&n.id.as_str()))
+            .filter(|n| !crypto::looks_like_ciphertext(&n.content))
+            .collect();
+        for note in unmapped {
+            let name = self.unique_name(&crate::derive_title(&note.content), &new_state);
+            let mtime = self.write_file(&name, &note.content)?;
+            new_state.insert(name, self.entry(note, mtime));
+        }
+
+        if state != new_state {
+            self.repo
+                .set_kv(STATE_KEY, &serde_json::to_string(&new_state)?)
+                .await?;
+        }
+        Ok(notes_changed)
+    }
+
+    // A new file holding exactly the content of `note` is taken as its rename
+    fn find_rename(&self, files: &mut BTreeMap<String, i64>, note: &Note) -> Option<(String, i64)> {
+        let name = files
+            .keys()
+            .find(|name| self.read_file(name).is_ok_and(|c| c == note.content))?
+            .clone();
+        files.remove_entry(&name)
+    }
+
+    async fn import(&self, id: Option<String>, content: String, mtime: i64) -> Result<Entry> {
+        let e2e_enabled = self.repo.get_salt().await?.is_some();
+        let locked = e2e_enabled && self.crypto_key.lock().unwrap().is_none();
+        let id = self
+            .repo
+            .save_note(id, content, e2e_enabled, locked)
+            .await?;
+        let updated_at = self
+            .repo
+            .get_note(id.clone())
+            .await?
+            .map(|n| n.updated_at)
+            .unwrap_or_default();
+        Ok(Entry {
+            id,
+            mtime,
+            updated_at,
+        })
+    }
+
+    fn entry(&self, note: &Note, mtime: i64) -> Entry {
+        Entry {
+            id: note.id.clone(),
+            mtime,
+            updated_at: note.updated_at.clone(),
+        }
+    }
+
+    async fn load_state(&self) -> Result<BTreeMap<String, Entry>> {
+        Ok(match self.repo.get_kv(STATE_KEY).await? {
+            Some(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                crate::logger::log(&format!("notes_dir: discarding unreadable state: {}", e));
+                BTreeMap::new()
+            }),
+            None => BTreeMap::new(),
+        })
+    }
+
+    // `*.md` files in the directory (not recursive), by name, with their mtime
+    fn list_files(&self) -> Result<BTreeMap<String, i64>> {
+        let mut files = BTreeMap::new();
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?;
+        for entry in entries.flatten() {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name.starts_with('.') || !name.ends_with(".md") {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_file() {
+                files.insert(name, mtime_ms(&meta));
+            }
+        }
+        Ok(files)
+    }
+
+    fn read_file(&self, name: &str) -> Result<String> {
+        let path = self.dir.join(name);
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    // Writes through a temp file so other tools never see a half-written note.
+    // Returns the new mtime.
+    fn write_file(&self, name: &str, content: &str) -> Result<i64> {
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        // Owner-only, like the database the notes come from
+        config::private_file_options()
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(fs::metadata(&path).map(|m| mtime_ms(&m)).unwrap_or(0))
+    }
+
+    fn unique_name(&self, title: &str, taken: &BTreeMap<String, Entry>) -> String {
+        let stem = file_stem(title);
+        let free = |name: &String| !taken.contains_key(name) && !self.dir.join(name).exists();
+        let name = format!("{}.md", stem);
+        if free(&name) {
+            return name;
+        }
+        (2..)
+            .map(|n| format!("{}-{}.md", stem, n))
+            .find(free)
+            .unwrap()
+    }
+}
+
+// File-name-safe version of a note title
+fn file_stem(title: &str) -> String {
+    let stem: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_STEM_CHARS)
+        .collect();
+    let stem = stem.trim_matches([' ', '-']).to_string();
+    if stem.is_empty() {
+        "untitled".to_string()
+    } else {
+        stem
+    }
+}
+
+fn mtime_ms(meta: &fs::Metadata) -> i64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+fn timestamp_ms(rfc3339: &str) -> i64 {
+    DateTime::parse_from_rfc3339(rfc3339).map_or(0, |dt| dt.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    // The database and a `notes` folder for it, removed on drop
+    fn temp_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("notes")).unwrap();
+        dir
+    }
+
+    fn bridge(dir: &TempDir, deletes: bool) -> FsBridge {
+        let repo = Repo::open(&dir.path().join("local.db")).unwrap();
+        let (changed_tx, _) = mpsc::channel(1);
+        let (sync_trigger, _) = mpsc::channel(1);
+        FsBridge::new(
+            dir.path().join("notes"),
+            repo,
+            Arc::new(Mutex::new(None)),
+            deletes,
+            changed_tx,
+            sync_trigger,
+        )
+    }
+
+    // Writes a file as another editor would, with an mtime the bridge can't
+    // have seen yet
+    fn edit(bridge: &FsBridge, name: &str, content: &str) {
+        let path = bridge.dir.join(name);
+        fs::write(&path, content).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+    }
+
+    async fn contents(bridge: &FsBridge) -> Vec<String> {
+        let mut notes: Vec<String> = bridge
+            .repo
+            .get_notes(false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.content)
+            .collect();
+        notes.sort();
+        notes
+    }
+
+    #[test]
+    fn file_stem_keeps_a_safe_readable_name() {
+        assert_eq!(file_stem("Groceries"), "Groceries");
+        assert_eq!(file_stem("Plan: Q3/Q4 (draft)"), "Plan- Q3-Q4 -draft");
+        assert_eq!(file_stem("../../etc/passwd"), "etc-passwd");
+        assert_eq!(file_stem("日本語のメモ"), "日本語のメモ");
+        assert_eq!(file_stem(" -?- "), "untitled");
+        assert_eq!(file_stem(""), "untitled");
+        assert_eq!(file_stem(&"x".repeat(200)).len(), MAX_STEM_CHARS);
+    }
+
+    #[tokio::test]
+    async fn scan_mirrors_notes_and_imports_new_files() {
+        let dir = temp_dir();
+        let bridge = bridge(&dir, false);
+        bridge
+            .repo
+            .save_note(None, "Groceries\nmilk".into(), false, false)
+            .await
+            .unwrap();
+
+        assert!(!bridge.scan().await.unwrap());
+        assert_eq!(
+            fs::read_to_string(bridge.dir.join("Groceries.md")).unwrap(),
+            "Groceries\nmilk"
+        );
+
+        edit(&bridge, "Ideas.md", "Ideas\nmore sleep");
+        edit(&bridge, "Blank.md", "  \n");
+        assert!(bridge.scan().await.unwrap());
+        assert_eq!(
+            contents(&bridge).await,
+            ["Groceries\nmilk", "Ideas\nmore sleep"]
+        );
+
+        edit(&bridge, "Groceries.md", "Groceries\nmilk\neggs");
+        assert!(bridge.scan().await.unwrap());
+        assert_eq!(
+            contents(&bridge).await,
+            ["Groceries\nmilk\neggs", "Ideas\nmore sleep"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn mirrored_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir();
+        let bridge = bridge(&dir, false);
+        bridge
+            .repo
+            .save_note(
+                None,
+                "Diary
+private"
+                    .into(),
+                false,
+                false,
+            )
+            .await
+            .unwrap();
+        bridge.scan().await.unwrap();
+        let mode = fs::metadata(bridge.dir.join("Diary.md"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn scan_follows_a_renamed_file() {
+        let dir = temp_dir();
+        let bridge = bridge(&dir, true);
+        let id = bridge
+            .repo
+            .save_note(None, "Groceries\nmilk".into(), false, false)
+            .await
+            .unwrap();
+        bridge.scan().await.unwrap();
+
+        fs::rename(
+            bridge.dir.join("Groceries.md"),
+            bridge.dir.join("Shopping.md"),
+        )
+        .unwrap();
+        assert!(!bridge.scan().await.unwrap());
+
+        let state = bridge.load_state().await.unwrap();
+        assert_eq!(state.keys().collect::<Vec<_>>(), ["Shopping.md"]);
+        assert_eq!(state["Shopping.md"].id, id);
+        assert_eq!(contents(&bridge).await, ["Groceries\nmilk"]);
+    }
+
+    #[tokio::test]
+    async fn scan_writes_back_a_removed_file_unless_deletes_are_on() {
+        let dir = temp_dir();
+        let bridge = bridge(&dir, false);
+        bridge
+            .repo
+            .save_note(None, "Groceries\nmilk".into(), false, false)
+            .await
+            .unwrap();
+        bridge.scan().await.unwrap();
+
+        fs::remove_file(bridge.dir.join("Groceries.md")).unwrap();
+        assert!(!bridge.scan().await.unwrap());
+        assert!(bridge.dir.join("Groceries.md").exists());
+        assert_eq!(contents(&bridge).await, ["Groceries\nmilk"]);
+
+        let bridge = FsBridge {
+            deletes: true,
+            ..bridge
+        };
+        fs::remove_file(bridge.dir.join("Groceries.md")).unwrap();
+        assert!(bridge.scan().await.unwrap());
+        assert!(contents(&bridge).await.is_empty());
+        assert!(!bridge.dir.join("Groceries.md").exists());
+    }
+
+    #[tokio::test]
+    async fn find_rename_matches_on_content_only() {
+        let dir = temp_dir();
+        let bridge = bridge(&dir, false);
+        let id = bridge
+            .repo
+            .save_note(None, "Groceries\nmilk".into(), false, false)
+            .await
+            .unwrap();
+        let note = bridge.repo.get_note(id).await.unwrap().unwrap();
+        edit(&bridge, "Other.md", "Groceries\nmilk and eggs");
+        edit(&bridge, "Shopping.md", "Groceries\nmilk");
+
+        let mut files = bridge.list_files().unwrap();
+        let (name, _) = bridge.find_rename(&mut files, &note).unwrap();
+        assert_eq!(name, "Shopping.md");
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["Other.md"]);
+        assert!(bridge.find_rename(&mut files, &note).is_none());
+    }
+}
@@ -0,0 +1,335 @@
+// A note as a standalone HTML page (`risu export-html`, `:html` in the
+// editor), rendered from the same tokens as the markdown preview.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::markdown::{self, Block, Inline};
+
+const STYLE: &str = "body{max-width:46rem;margin:2rem auto;padding:0 1rem;\
+font:16px/1.6 -apple-system,BlinkMacSystemFont,'Segoe UI',sans-serif;color:#222}\
+h1,h2,h3{line-height:1.25}h1{border-bottom:1px solid #ddd;padding-bottom:.3rem}\
+code{font:.9em ui-monospace,SFMono-Regular,Menlo,monospace;background:#f3f3f3;\
+padding:.1em .3em;border-radius:3px}pre{background:#f6f8fa;padding:.8rem;overflow:auto;\
+border-radius:4px}pre code{background:none;padding:0}blockquote{margin:0;padding:0 1rem;\
+color:#666;border-left:.25rem solid #ddd}table{border-collapse:collapse}\
+th,td{border:1px solid #ccc;padding:.3rem .6rem}th{background:#f3f3f3}\
+li.task{list-style:none;margin-left:-1.3rem}a{color:#0b62c4}\
+.k{color:#a626a4}.s{color:#50a14f}.c{color:#999;font-style:italic}.n{color:#986801}";
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "case", "class", "const", "continue", "def", "else", "enum",
+    "export", "false", "fn", "for", "from", "func", "function", "if", "impl", "import", "in",
+    "let", "match", "mut", "None", "null", "pub", "return", "self", "static", "struct", "switch",
+    "true", "type", "use", "var", "while",
+];
+
+#[derive(PartialEq)]
+enum Open {
+    Nothing,
+    Paragraph,
+    List,
+    Quote,
+    Table,
+    Code,
+}
+
+/// The whole page for `content`, titled after its first line.
+pub fn render_document(content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(&crate::derive_title(content)),
+        STYLE,
+        render_body(content)
+    )
+}
+
+fn render_body(content: &str) -> String {
+    let blocks = markdown::blocks(content);
+    let mut out = String::new();
+    let mut open = Open::Nothing;
+    let mut i = 0;
+    while i < blocks.len() {
+        let block = &blocks[i];
+        i += 1;
+        match *block {
+            Block::Fence(_) if open == Open::Code => close(&mut out, &mut open),
+            Block::Fence(line) => {
+                close(&mut out, &mut open);
+                let lang = line.trim_start_matches('`').trim();
+                if lang.is_empty() {
+                    out.push_str("<pre><code>");
+                } else {
+                    out.push_str(&format!("<pre><code class=\"language-{}\">", escape(lang)));
+                }
+                open = Open::Code;
+            }
+            Block::Code(line) => {
+                out.push_str(&highlight_code(line));
+                out.push('\n');
+            }
+            Block::Heading(level, text) => {
+                close(&mut out, &mut open);
+                out.push_str(&format!("<h{0}>{1}</h{0}>\n", level, inline_html(text)));
+            }
+            Block::Item { checked, text } => {
+                if open != Open::List {
+                    close(&mut out, &mut open);
+                    out.push_str("<ul>\n");
+                    open = Open::List;
+                }
+                match checked {
+                    Some(checked) => out.push_str(&format!(
+                        "<li class=\"task\"><input type=\"checkbox\" disabled{}> {}</li>\n",
+                        if checked { " checked" } else { "" },
+                        inline_html(text)
+                    )),
+                    None => out.push_str(&format!("<li>{}</li>\n", inline_html(text))),
+                }
+            }
+            Block::Quote(text) => {
+                if open != Open::Quote {
+                    close(&mut out, &mut open);
+                    out.push_str("<blockquote>\n");
+                    open = Open::Quote;
+                }
+                out.push_str(&format!("<p>{}</p>\n", inline_html(text)));
+            }
+            Block::TableRow(row) => {
+                if open != Open::Table {
+                    close(&mut out, &mut open);
+                    out.push_str("<table>\n");
+                    open = Open::Table;
+                    // A row with a rule under it is the header
+                    if let Some(Block::TableRule(_)) = blocks.get(i) {
+                        i += 1;
+                        out.push_str("<thead>\n");
+                        out.push_str(&table_row(row, "th"));
+                        out.push_str("</thead>\n<tbody>\n");
+                        continue;
+                    }
+                    out.push_str("<tbody>\n");
+                }
+                out.push_str(&table_row(row, "td"));
+            }
+            Block::TableRule(_) if open == Open::Table => {}
+            Block::TableRule(line) | Block::Text(line) => {
+                if line.trim().is_empty() {
+                    close(&mut out, &mut open);
+                } else if open == Open::Paragraph {
+                    out.push_str(&format!("<br>\n{}", inline_html(line)));
+                } else {
+                    close(&mut out, &mut open);
+                    out.push_str(&format!("<p>{}", inline_html(line)));
+                    open = Open::Paragraph;
+                }
+            }
+        }
+    }
+    close(&mut out, &mut open);
+    out
+}
+
+fn close(out: &mut String, open: &mut Open) {
+    out.push_str(match open {
+        Open::Nothing => "",
+        Open::Paragraph => "</p>\n",
+        Open::List => "</ul>\n",
+        Open::Quote => "</blockquote>\n",
+        Open::Table => "</tbody>\n</table>\n",
+        Open::Code => "</code></pre>\n",
+    });
+    *open = Open::Nothing;
+}
+
+fn table_row(row: &str, cell: &str) -> String {
+    let cells: String = markdown::table_cells(row)
+        .into_iter()
+        .map(|text| format!("<{0}>{1}</{0}>", cell, inline_html(text)))
+        .collect();
+    format!("<tr>{}</tr>\n", cells)
+}
+
+fn inline_html(text: &str) -> String {
+    markdown::inlines(text)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => escape(text),
+            Inline::Bold(text) => format!("<strong>{}</strong>", escape(text)),
+            Inline::Code(text) => format!("<code>{}</code>", escape(text)),
+            Inline::Link { text, url } if safe_url(url) => {
+                format!("<a href=\"{}\">{}</a>", escape(url), escape(text))
+            }
+            Inline::Link { text, .. } => escape(text),
+        })
+        .collect()
+}
+
+// Links that run script when clicked are shown as plain text
+fn safe_url(url: &str) -> bool {
+    let url = url.trim().to_ascii_lowercase();
+    !["javascript:", "vbscript:", "data:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Language-agnostic: keywords common to the usual languages, strings, numbers,
+// and `//` or leading `#` comments
+fn highlight_code(line: &str) -> String {
+    let span =
+        |class: &str, text: &str| format!("<span class=\"{}\">{}</span>", class, escape(text));
+    if line.trim_start().starts_with('#') {
+        return span("c", line);
+    }
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let len = if rest.starts_with("//") {
+            out.push_str(&span("c", rest));
+            rest.len()
+        } else if c == '"' {
+            let end = rest[1..]
+                .char_indices()
+                .scan(false, |escaped, (i, ch)| {
+                    let close = ch == '"' && !*escaped;
+                    *escaped = ch == '\\' && !*escaped;
+                    Some((i, close))
+                })
+                .find(|&(_, close)| close)
+                .map_or(rest.len(), |(i, _)| i + 2);
+            out.push_str(&span("s", &rest[..end]));
+            end
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '.' || ch == '_'))
+                .unwrap_or(rest.len());
+            out.push_str(&span("n", &rest[..end]));
+            end
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if KEYWORDS.contains(&word) {
+                out.push_str(&span("k", word));
+            } else {
+                out.push_str(&escape(word));
+            }
+            end
+        } else {
+            out.push_str(&escape(&rest[..c.len_utf8()]));
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Writes the page for `content` to `out`, or to a file in the temp directory
+/// named after `id` when no path is given. Returns where it went.
+pub fn export(content: &str, id: &str, out: Option<&Path>) -> io::Result<PathBuf> {
+    let path = match out {
+        Some(path) => path.to_path_buf(),
+        None => std::env::temp_dir().join(format!("risu-{}.html", id)),
+    };
+    std::fs::write(&path, render_document(content))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text a browser would show for `html`: tags dropped, entities decoded
+    fn shown_text(html: &str) -> String {
+        let mut text = String::new();
+        let mut in_tag = false;
+        for c in html.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => in_tag = false,
+                c if !in_tag => text.push(c),
+                _ => {}
+            }
+        }
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&")
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            render_body("a < b & \"c\" 'd' > e"),
+            "<p>a &lt; b &amp; &quot;c&quot; &#39;d&#39; &gt; e</p>\n"
+        );
+        assert_eq!(
+            render_body("# <script>alert(1)</script>"),
+            "<h1>&lt;script&gt;alert(1)&lt;/script&gt;</h1>\n"
+        );
+        // Already-escaped text is escaped again, not passed through
+        assert_eq!(render_body("&amp;"), "<p>&amp;amp;</p>\n");
+        assert!(render_document("<b>Title</b> & co\nbody")
+            .contains("<title>&lt;b&gt;Title&lt;/b&gt; &amp; co</title>"));
+    }
+
+    #[test]
+    fn inline_code_and_links_are_escaped() {
+        assert_eq!(
+            render_body("run `a<b && c=\"'d'\"` now"),
+            "<p>run <code>a&lt;b &amp;&amp; c=&quot;&#39;d&#39;&quot;</code> now</p>\n"
+        );
+        assert_eq!(
+            render_body("[x](https://example.com/?a=1&b=\"2\")"),
+            "<p><a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">x</a></p>\n"
+        );
+        assert_eq!(render_body("[click](JavaScript:void)"), "<p>click</p>\n");
+    }
+
+    #[test]
+    fn code_blocks_show_exactly_what_was_written() {
+        let code = [
+            "if a < b && s == \"x<y>\" { // <tag> & 'q'",
+            "    let n = 1.5e3; let s = \"\\\"quoted\\\" & <b>\";",
+            "# comment with <html> & \"quotes\"",
+            "</code></pre><script>alert(1)</script>",
+            "",
+            "日本語 'single' \"double\"",
+        ];
+        let note = format!("```rust\n{}\n```", code.join("\n"));
+        let html = render_body(&note);
+
+        let inner = html
+            .strip_prefix("<pre><code class=\"language-rust\">")
+            .and_then(|rest| rest.strip_suffix("</code></pre>\n"))
+            .expect("one code block");
+        assert_eq!(shown_text(inner), format!("{}\n", code.join("\n")));
+        // Nothing in the block opens or closes an element of its own
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<tag>"));
+        assert_eq!(html.matches("</code></pre>").count(), 1);
+
+        // The language is escaped too
+        assert!(render_body("```\"><x>\nx\n```")
+            .starts_with("<pre><code class=\"language-&quot;&gt;&lt;x&gt;\">"));
+    }
+}
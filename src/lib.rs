@@ -0,0 +1,59 @@
+//! Local storage, encryption and sync for risu, shared by the `risu` binary
+//! and tools that want to read notes or trigger a sync without the CLI.
+//!
+//! The database lives at [`db_path`] and is reached through [`Repo`], a handle
+//! to a worker thread that owns the SQLite connection. Several processes may
+//! open it at once, as the TUI and `risu sync --watch` already do; a tool that
+//! only reads should use [`Repo::open_read_only`], which never changes the file.
+//!
+//! ```
+//! # let dir = std::env::temp_dir().join(format!("risu-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&dir)?;
+//! # let path = dir.join("local.db");
+//! let rt = tokio::runtime::Runtime::new()?;
+//! # rt.block_on(risu::Repo::open(&path)?.save_note(None, "Groceries\nmilk".into(), false, false))?;
+//! // `risu::db_path()` for the current user's notes
+//! let repo = risu::Repo::open_read_only(&path)?;
+//! let notes = rt.block_on(repo.get_notes(false))?;
+//! for note in &notes {
+//!     println!("{}  {}", note.id, risu::derive_title(&note.content));
+//! }
+//! assert_eq!(risu::derive_title(&notes[0].content), "Groceries");
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Nothing is logged until `logger::init` is called by the binary, so a
+//! library user doesn't write to risu's log files.
+
+pub mod crypto;
+
+pub use config::get_config_dir;
+pub use db::{db_path, Note, Repo};
+pub use title::derive_title;
+
+/// The risu server's HTTP API.
+pub mod api {
+    pub use crate::sync::{
+        APIClient, AuthMeResponse, LoginSession, Plan, PollResult, PullResult, SyncCheck, SyncError,
+    };
+}
+
+// Shared with the risu binary. Not part of the library's API: it may change
+// in any release.
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod db;
+#[doc(hidden)]
+pub mod diff;
+#[doc(hidden)]
+pub mod logger;
+#[doc(hidden)]
+pub mod merge;
+#[doc(hidden)]
+pub mod reminder;
+#[doc(hidden)]
+pub mod sync;
+#[doc(hidden)]
+pub mod title;
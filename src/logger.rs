@@ -1,49 +1,288 @@
 use crate::config;
+use crate::db::{short_id, SHORT_ID_LEN};
+use serde::{Deserialize, Serialize};
 use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, OnceLock};
-
-#[cfg(unix)]
-use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
 
 static LOG_FILE: OnceLock<Mutex<std::fs::File>> = OnceLock::new();
 
+// Lines logged since the last flush. `log` only appends here, so a slow disk
+// doesn't hold up the caller; the flush thread does the writing.
+static LOG_BUFFER: Mutex<String> = Mutex::new(String::new());
+
+// How often the flush thread writes out the buffer
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Past this the caller flushes itself, so a burst can't grow the buffer
+// without bound
+const LOG_BUFFER_MAX: usize = 256 * 1024;
+
+// `general.redact_titles`: audit entries keep note ids only
+static REDACT_TITLES: AtomicBool = AtomicBool::new(false);
+
+// audit.log is set aside (never deleted) once it grows past this
+const AUDIT_ROTATE_BYTES: u64 = 1024 * 1024;
+
+pub fn log_path() -> PathBuf {
+    config::get_config_dir().join("logs").join("risu.log")
+}
+
+pub fn audit_path() -> PathBuf {
+    config::get_config_dir().join("logs").join("audit.log")
+}
+
+fn rotate(log_path: &Path, old_path: &Path) {
+    // Try to remove old log first to ensure rename succeeds (simple rotation)
+    if old_path.exists() {
+        let _ = fs::remove_file(old_path);
+    }
+    if fs::rename(log_path, old_path).is_err() {
+        // Windows won't rename a file another process has open: copy it
+        // aside and start the current one over instead
+        if fs::copy(log_path, old_path).is_ok() {
+            let _ = OpenOptions::new().write(true).truncate(true).open(log_path);
+        }
+    }
+}
+
 pub fn init() {
-    let mut log_dir = config::get_config_dir();
-    log_dir.push("logs");
+    let log_path = log_path();
+    let log_dir = log_path.parent().unwrap().to_path_buf();
     fs::create_dir_all(&log_dir).ok();
 
-    let mut log_path = log_dir.clone();
-    log_path.push("risu.log");
-
     // Rotate log file: move old log to risu.log.old
     if log_path.exists() {
-        let mut old_path = log_dir;
-        old_path.push("risu.log.old");
-        // Try to remove old log first to ensure rename succeeds (simple rotation)
-        if old_path.exists() {
-            let _ = fs::remove_file(&old_path);
+        rotate(&log_path, &log_dir.join("risu.log.old"));
+    }
+
+    let mut options = config::private_file_options();
+    options.truncate(false).append(true); // Append to new file
+
+    let file = options.open(log_path).expect("Failed to open log file");
+
+    if LOG_FILE.set(Mutex::new(file)).is_ok() {
+        let _ = std::thread::Builder::new()
+            .name("risu-log".to_string())
+            .spawn(|| loop {
+                std::thread::sleep(LOG_FLUSH_INTERVAL);
+                flush();
+            });
+    }
+
+    // The audit log spans sessions; a full one moves aside under its date
+    let audit_path = audit_path();
+    if fs::metadata(&audit_path).is_ok_and(|m| m.len() > AUDIT_ROTATE_BYTES) {
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let _ = fs::rename(
+            &audit_path,
+            audit_path.with_file_name(format!("audit-{}.log", stamp)),
+        );
+    }
+}
+
+pub fn log(msg: &str) {
+    if LOG_FILE.get().is_none() {
+        return;
+    }
+    let line = format!("[{}] {}\n", chrono::Local::now(), msg);
+    let full = match LOG_BUFFER.lock() {
+        Ok(mut buffer) => {
+            buffer.push_str(&line);
+            buffer.len() > LOG_BUFFER_MAX
+        }
+        Err(_) => return,
+    };
+    if full {
+        flush();
+    }
+}
+
+/// Writes out the buffered log lines. Runs on a timer, and from the panic
+/// hook and on exit so the last lines before a crash aren't lost.
+pub fn flush() {
+    let Some(mutex) = LOG_FILE.get() else {
+        return;
+    };
+    // The file lock is taken first so concurrent flushes keep the lines in order
+    let Ok(mut file) = mutex.lock() else {
+        return;
+    };
+    let pending = match LOG_BUFFER.lock() {
+        Ok(mut buffer) if !buffer.is_empty() => std::mem::take(&mut *buffer),
+        _ => return,
+    };
+    let _ = file.write_all(pending.as_bytes());
+}
+
+/// Where a destructive operation came from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Via {
+    Tui,
+    Cli,
+    /// A deletion pulled from another device
+    Sync,
+    /// A setting acting on its own, like `sync.tombstone_retention_days`
+    Auto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditNote {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl AuditNote {
+    pub fn of(note: &crate::db::Note) -> Self {
+        Self {
+            id: note.id.clone(),
+            title: Some(crate::title::derive_title(&note.content)),
         }
-        let _ = fs::rename(&log_path, old_path);
     }
+}
 
-    let mut options = OpenOptions::new();
-    options.create(true).append(true); // Append to new file
+/// An operation that destroys data or access to it, for `audit.log`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Delete { via: Via, notes: Vec<AuditNote> },
+    EmptyTrash { via: Via, count: usize },
+    ClearLocalData { via: Via },
+    ResetRemote { via: Via },
+    Logout { via: Via },
+    PassphraseChange { via: Via },
+}
 
-    #[cfg(unix)]
-    {
-        options.mode(0o600);
+impl std::fmt::Display for Via {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Via::Tui => "TUI",
+            Via::Cli => "CLI",
+            Via::Sync => "sync",
+            Via::Auto => "automatic",
+        })
     }
+}
 
-    let file = options.open(log_path).expect("Failed to open log file");
+impl AuditEvent {
+    /// One line for the status dialog, without the time.
+    pub fn describe(&self) -> String {
+        match self {
+            AuditEvent::Delete { via, notes } => {
+                let names: Vec<String> = notes
+                    .iter()
+                    .map(|n| {
+                        let id = short_id(&n.id, SHORT_ID_LEN);
+                        match &n.title {
+                            Some(title) => format!("\"{}\" ({})", title, id),
+                            None => id.to_string(),
+                        }
+                    })
+                    .collect();
+                format!("Deleted ({}): {}", via, names.join(", "))
+            }
+            AuditEvent::EmptyTrash { via, count } => {
+                format!("Emptied trash ({}): {} notes", via, count)
+            }
+            AuditEvent::ClearLocalData { via } => format!("Cleared local data ({})", via),
+            AuditEvent::ResetRemote { via } => format!("Reset server data ({})", via),
+            AuditEvent::Logout { via } => format!("Logged out ({})", via),
+            AuditEvent::PassphraseChange { via } => format!("Changed passphrase ({})", via),
+        }
+    }
+}
 
-    let _ = LOG_FILE.set(Mutex::new(file));
+#[derive(Serialize, Deserialize)]
+struct AuditEntry {
+    at: String,
+    #[serde(flatten)]
+    event: AuditEvent,
 }
 
-pub fn log(msg: &str) {
-    if let Some(mutex) = LOG_FILE.get() {
-        if let Ok(mut file) = mutex.lock() {
-            let _ = writeln!(file, "[{}] {}", chrono::Local::now(), msg);
+pub fn set_redact_titles(redact: bool) {
+    REDACT_TITLES.store(redact, Ordering::Relaxed);
+}
+
+/// Appends `event` to audit.log as one JSON line. Also noted in risu.log, so
+/// a failure to write here still leaves a trace.
+pub fn audit(mut event: AuditEvent) {
+    if REDACT_TITLES.load(Ordering::Relaxed) {
+        if let AuditEvent::Delete { notes, .. } = &mut event {
+            for note in notes {
+                note.title = None;
+            }
         }
     }
+    let entry = AuditEntry {
+        at: chrono::Local::now().to_rfc3339(),
+        event,
+    };
+    let Ok(line) = serde_json::to_string(&entry) else {
+        return;
+    };
+    log(&format!("Audit: {}", line));
+    let mut options = config::private_file_options();
+    options.truncate(false).append(true);
+    if let Ok(mut file) = options.open(audit_path()) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// The newest `limit` entries, oldest first, as (time, description). Lines
+/// that don't parse are skipped.
+pub fn recent_audit(limit: usize) -> Vec<(String, String)> {
+    let Ok(file) = fs::File::open(audit_path()) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<(String, String)> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+        .map(|entry| (entry.at, entry.event.describe()))
+        .collect();
+    let skip = entries.len().saturating_sub(limit);
+    entries.drain(..skip);
+    entries
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use std::os::windows::fs::OpenOptionsExt;
+    use tempfile::TempDir;
+
+    // FILE_SHARE_READ | FILE_SHARE_WRITE without FILE_SHARE_DELETE: the file
+    // can't be renamed while this handle is open
+    const SHARE_READ_WRITE: u32 = 0x1 | 0x2;
+
+    #[test]
+    fn a_log_held_open_is_copied_aside_and_started_over() {
+        let dir = TempDir::new().unwrap();
+        let log_path = dir.path().join("risu.log");
+        let old_path = dir.path().join("risu.log.old");
+        fs::write(&log_path, "last session\n").unwrap();
+        fs::write(&old_path, "the session before\n").unwrap();
+
+        let held = OpenOptions::new()
+            .read(true)
+            .share_mode(SHARE_READ_WRITE)
+            .open(&log_path)
+            .unwrap();
+        assert!(fs::rename(&log_path, dir.path().join("moved")).is_err());
+        rotate(&log_path, &old_path);
+        assert_eq!(fs::read_to_string(&old_path).unwrap(), "last session\n");
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+        drop(held);
+
+        // Nothing holds it now, so it's simply renamed
+        fs::write(&log_path, "this session\n").unwrap();
+        rotate(&log_path, &old_path);
+        assert!(!log_path.exists());
+        assert_eq!(fs::read_to_string(&old_path).unwrap(), "this session\n");
+    }
 }
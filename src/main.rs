@@ -1,10 +1,11 @@
-use anyhow::Result;
-use chrono::{DateTime, Local};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Local, Utc};
 use clap::{Parser, Subcommand};
 use crossterm::{
     event::{
         self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
-        Event, KeyCode, KeyEventKind,
+        Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind,
     },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
@@ -13,26 +14,46 @@ use ratatui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::time;
-use tui_textarea::{CursorMove, TextArea};
+use tui_textarea::{CursorMove, Scrolling, TextArea};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use zeroize::Zeroizing;
 
-mod config;
-mod crypto;
-mod db;
-mod logger;
+mod backup;
+mod block;
+mod error_report;
+mod fs_bridge;
+mod html;
 mod markdown;
-mod sync;
-
-use crate::db::{Note, Repo};
-use sync::{APIClient, SyncManager, SyncStatus};
+mod metrics;
+mod motion;
+mod normalize;
+mod palette;
+mod plain;
+mod timer;
+mod ui;
+mod viewer;
+
+use risu::title::{derive_title, LOCKED_NOTE_TITLE};
+use risu::{config, crypto, db, diff, logger, merge, sync};
+
+use crate::db::{short_id, Note, Repo, ResolvedId, SHORT_ID_LEN};
+use sync::{
+    APIClient, KeyCheck, PassphraseCheck, Plan, SyncEvent, SyncManager, SyncPhase, SyncStatus,
+    UnlockError,
+};
+use ui::{Action, Command};
 
 #[derive(PartialEq, Debug)]
 enum ActivePane {
@@ -45,6 +66,52 @@ enum ActivePane {
     StatusDialog,
     PassphraseInput,
     E2ESetup,
+    Recovery,
+    Diff,
+    ReuploadConfirm,
+    UnsavedConfirm,
+    CommandPalette,
+}
+
+/// Where end-to-end encryption stands for the signed-in account.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum E2EState {
+    /// Guest, offline, or a plan without sync
+    Disabled,
+    /// Sync-eligible account without a passphrase yet
+    SetupRequired,
+    Locked,
+    Unlocking,
+    Unlocked,
+    /// The server stopped accepting sync for this plan
+    UpgradeRequired,
+}
+
+impl E2EState {
+    /// New and edited notes are marked for encrypted upload
+    fn is_enabled(self) -> bool {
+        self != E2EState::Disabled
+    }
+
+    fn is_unlocked(self) -> bool {
+        self == E2EState::Unlocked
+    }
+
+    /// No key is loaded, so edits can't be checked against the server copy yet
+    fn is_locked(self) -> bool {
+        matches!(self, E2EState::Locked | E2EState::Unlocking)
+    }
+
+    fn display(self) -> &'static str {
+        match self {
+            E2EState::Disabled => "Disabled",
+            E2EState::SetupRequired => "Setup Required",
+            E2EState::Locked => "Locked",
+            E2EState::Unlocking => "Unlocking...",
+            E2EState::Unlocked => "Unlocked",
+            E2EState::UpgradeRequired => "Upgrade Required",
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -53,6 +120,8 @@ enum Mode {
     Insert,
     Visual,
     VisualLine,
+    VisualBlock,
+    Command,
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -61,20 +130,127 @@ enum PendingKey {
     D,
     Y,
     G,
+    // Awaiting a register letter for `q` (record) / `@` (replay)
+    Q,
+    At,
+    // Awaiting a mark letter for `m` (set), `` ` `` (exact jump) / `'` (line jump)
+    Mark,
+    MarkJump,
+    MarkLineJump,
+    Z,
+    // `Ctrl+w`, awaiting a direction
+    W,
+    // `c`, awaiting a motion; `ci` awaiting the `w` of `ciw`
+    C,
+    CI,
+    // `f`/`F`/`t`/`T` (true after `d`), awaiting the character to find
+    Find(char, bool),
+}
+
+// A move from the editor to another note, held while the unsaved-changes
+// prompt waits for an answer
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum NoteSwitch {
+    // Ctrl+o (back) / Ctrl+i through the jumplist
+    Jump { back: bool },
+    // n / N through the list's confirmed search
+    SearchMatch { forward: bool },
+}
+
+// Requested by `zt`/`zz`/`zb`; applied when the editor is next rendered
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum ViewAlign {
+    Top,
+    Center,
+    Bottom,
 }
 
 #[derive(Debug)]
 enum Message {
     Key(event::KeyEvent),
     Resize(u16, u16),
+    // Left click at (column, row)
+    Click(u16, u16),
     Paste(String),
-    SyncStatusUpdate(SyncStatus),
+    Sync(SyncEvent),
+    NotesDirChanged,
     Tick,
     PollingTick,
     SubscriptionCheck,
     AccountCheckResult(Result<sync::AuthMeResponse, String>),
+    PersistSession,
+    DiffReady(String, Vec<Line<'static>>),
 }
 
+// Plain keys read in quick succession that are treated as an unbracketed paste
+const TYPED_BURST_MIN: usize = 8;
+
+// Longest gap between two keys of an unbracketed paste. Typing, and even key
+// repeat, is an order of magnitude slower.
+const TYPED_BURST_GAP: Duration = Duration::from_millis(5);
+
+// Stops runaway replays, e.g. a macro that invokes itself
+const MACRO_KEY_LIMIT: usize = 10_000;
+
+// kv_store keys for restoring the last session on startup
+const SESSION_NOTE_KEY: &str = "session_note_id";
+const SESSION_PANE_KEY: &str = "session_pane";
+const SESSION_SELECTED_KEY: &str = "session_selected";
+
+// Idle time after the last editor key before unsaved edits go to a swap file
+const SWAP_IDLE: Duration = Duration::from_secs(3);
+
+// Idle time before the scratch note saves itself; it has no swap file
+const SCRATCH_SAVE_IDLE: Duration = Duration::from_millis(500);
+
+// A daily backup still running after this shows the footer spinner
+const BACKUP_SPINNER_DELAY: Duration = Duration::from_secs(1);
+
+// Display width of the badge in front of each list title, e.g. " \u{f249}  "
+const LIST_BADGE_WIDTH: usize = 4;
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+// How often the input thread wakes to check for shutdown
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+// How often `risu sync --watch` checks the database for outside changes
+const WATCH_POLL: Duration = Duration::from_secs(1);
+
+// Diffs of more lines than this run on a blocking thread
+const DIFF_INLINE_LINES: usize = 2_000;
+
+// A changed line longer than this many chars is cut down in the diff popup to
+// its changed words and WORD_DIFF_CONTEXT chars either side
+const WORD_DIFF_MAX_LINE: usize = 200;
+const WORD_DIFF_CONTEXT: usize = 40;
+
+// Terminals commonly cap OSC 52 clipboard payloads around this many base64 bytes
+const OSC52_MAX_ENCODED: usize = 100_000;
+
+// Lines longer than this (in bytes) make the editor crawl, so a note with one
+// is shown split into chunks of this size, read-only (`editor.keep_long_lines`)
+const LONG_LINE_LIMIT: usize = 4_096;
+
+// The preview is rebuilt every frame, so notes over this many bytes skip the
+// markdown parser and render as plain text
+const PREVIEW_MARKDOWN_LIMIT: usize = 256 * 1024;
+
+// How long the footer flashes when a focus block ends
+const TIMER_FLASH: Duration = Duration::from_secs(5);
+
+// Entries shown by the status dialog's audit log view
+const AUDIT_VIEW_LIMIT: usize = 20;
+
+// Oldest entries are dropped past this many
+const JUMPLIST_MAX: usize = 50;
+
+// kv_store key prefix for per-note marks, followed by the note id
+const MARKS_KEY_PREFIX: &str = "marks:";
+
+// Subscription polling gives up after this long without a plan change
+const SUBSCRIPTION_POLL_WINDOW: Duration = Duration::from_secs(15 * 60);
+
 const RISU_LOGO: &str = r###"   RISU NOTE
 ██████╗ ██╗███████╗██╗   ██╗
 ██╔══██╗██║██╔════╝██║   ██║
@@ -87,9 +263,25 @@ struct Model<'a> {
     repo: Repo,
     notes: Vec<Note>,
     filtered_notes: Vec<Note>,
+    // `is:` tokens in the search query that matched no known state
+    unknown_filters: Vec<String>,
+    // Note id prefix length that tells all notes apart (see `short_id`)
+    short_id_len: usize,
+    // Earliest reminder per note id (RFC 3339 UTC)
+    reminders: HashMap<String, String>,
+    // `u`: only notes with a reminder, soonest first
+    due_view: bool,
     list_state: ListState,
+    // Notes that fit in the list, and its scroll offset as last drawn while
+    // the list had focus; kept so refreshes and leaving the editor don't
+    // move the viewport
+    list_rows: usize,
+    list_offset: usize,
     textarea: TextArea<'a>,
     search_textarea: TextArea<'a>,
+    // The filter as of the last Enter in the search popup; `n`/`N` step
+    // through its matches while it is set
+    confirmed_query: Option<String>,
     passphrase_textarea: TextArea<'a>,
     passphrase_confirm_textarea: TextArea<'a>,
     clear_confirm_textarea: TextArea<'a>,
@@ -99,13 +291,19 @@ struct Model<'a> {
     current_note_id: Option<String>,
     sync_status: SyncStatus,
     sync_trigger: mpsc::Sender<()>,
-    status_rx: mpsc::Receiver<SyncStatus>,
-    status_tx: mpsc::Sender<SyncStatus>,
+    event_rx: mpsc::Receiver<SyncEvent>,
+    event_tx: mpsc::Sender<SyncEvent>,
 
     api_client: APIClient,
     login_session: Option<sync::LoginSession>,
     polling_login: bool,
-    polling_subscription: bool,
+    // Token pasted into the Login pane when the browser flow can't call back
+    login_token_textarea: TextArea<'a>,
+    login_token_error: Option<String>,
+    // Set while waiting for a checkout/portal change to show up on the account
+    subscription_poll: Option<SubscriptionPoll>,
+    // Server clock minus ours in seconds, from the last sync check
+    clock_skew: Option<i64>,
 
     note_to_delete: Option<Note>,
 
@@ -119,23 +317,179 @@ struct Model<'a> {
 
     show_preview: bool,
     preview_scroll: u16,
+    // Stored content the buffer was based on when a pull or the notes folder
+    // changed the note under unsaved edits; the next save merges against it
+    remote_changed_base: Option<String>,
+    // The selected note is ciphertext we can't decrypt; the editor shows a
+    // placeholder and refuses edits
+    editor_locked: bool,
+    // The buffer shows the note with its long lines split, so it refuses edits
+    // until `:raw` loads the note as it is
+    editor_split: bool,
 
     visual_anchor_row: Option<usize>,
+    visual_block_anchor: Option<(usize, usize)>,
+    visual_block_to_eol: bool,
+    block_insert: Option<BlockInsert>,
+    block_register: Option<Vec<String>>,
+    block_undo: Option<BlockUndo>,
+    // The lines before `o`/`O`, so the insert it starts undoes in one step
+    open_line_before: Option<Vec<String>>,
+    // Cursor positions inside pairs auto-pairs inserted, innermost last.
+    // Backspace removes a whole pair only there; any other key forgets them
+    auto_pairs_open: Vec<(usize, usize)>,
+    // Mirrors the textarea's scroll offset, which tui-textarea keeps private,
+    // so the block selection can be drawn over the rendered text.
+    editor_viewport: (u16, u16),
+    pending_view_align: Option<ViewAlign>,
+
+    pending_count: usize,
+    macros: HashMap<char, Vec<event::KeyEvent>>,
+    // The last `f`/`F`/`t`/`T` and its character, for `;` and `,`
+    last_find: Option<(char, char)>,
+    macro_recording: Option<(char, Vec<event::KeyEvent>)>,
+    macro_queue: VecDeque<event::KeyEvent>,
+    last_macro: Option<char>,
+    // Set when an editor command can't do anything; aborts macro replay
+    editor_beep: bool,
+
+    marks: HashMap<char, (usize, usize)>,
+    // Note the marks were loaded for; `None` until first use
+    marks_note_id: Option<Option<String>>,
+    // Position before the last jump, for `''` / ``` `` ```
+    jump_origin: Option<(usize, usize)>,
+
+    // Notes opened this session, oldest first; `jump_index` is the current one
+    jumplist: Vec<(String, (usize, usize))>,
+    jump_index: usize,
+    // Carried out once the unsaved-changes prompt is answered
+    pending_switch: Option<NoteSwitch>,
+
+    swap_due: Option<Instant>,
+    // Names the swap file of a new note until its first save
+    draft_id: String,
+    // Id of the scratch note once it has been loaded
+    scratch_id: Option<String>,
+    // While the scratch note is open from `S`, the note selected before it
+    scratch_return: Option<Option<String>>,
+    // Swap files left by a previous run, offered one at a time on startup
+    recovery_queue: Vec<config::SwapFile>,
+
+    // `:` command line in the editor
+    command_line: String,
+    // One-off feedback shown in the footer until the next key
+    editor_message: Option<String>,
+    diff_view: Option<(String, Vec<Line<'static>>)>,
+    diff_scroll: u16,
+
+    // For background work that reports back through the event loop
+    internal_tx: mpsc::UnboundedSender<Message>,
+    internal_rx: mpsc::UnboundedReceiver<Message>,
+    // Pings from the notes_dir mirror after it changed notes from disk
+    notes_dir_rx: Option<mpsc::Receiver<()>>,
 
     config: config::AppConfig,
+    // theme.color_mode with auto resolved against the terminal
+    color_mode: config::ColorMode,
+    // Some when `general.metrics` is on
+    metrics: Option<metrics::SessionMetrics>,
     token_source: Option<config::TokenSource>,
+    token_times: Option<config::TokenTimes>,
+    has_refresh_token: bool,
     user_email: Option<String>,
-    user_plan: Option<String>,
+    user_plan: Option<Plan>,
     user_subscription_status: Option<String>,
     user_subscription_end_date: Option<String>,
+    // Set while the plan fields above come from the startup cache
+    account_cached_at: Option<DateTime<Utc>>,
     last_error: Option<String>,
+    // Scroll offset while the full last error is open over the status dialog
+    error_popup_scroll: Option<u16>,
+    // The status dialog's audit log view: the newest entries, when open
+    audit_popup: Option<Vec<(String, String)>>,
+    // `:timer`: the running or paused focus block, mirrored to kv_store
+    focus_timer: Option<timer::FocusTimer>,
+    // The footer flashes in the warning color until then after a block ends
+    timer_flash_until: Option<Instant>,
 
     crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
-    e2e_status: String,
+    sync_cancel: Arc<AtomicBool>,
+    // Last connectivity probe from the SyncManager
+    network_online: Arc<AtomicBool>,
+    e2e_status: E2EState,
     is_loading: bool,
+    // The startup daily backup, while it runs, and when it started
+    backup_task: Option<(tokio::task::JoinHandle<()>, Instant)>,
+    unlock_in_progress: bool,
+    unlock_generation: Arc<AtomicU64>,
+    key_unverified: bool,
+    // Where the footer's E2E indicator was last drawn, for clicks
+    e2e_indicator_area: Option<ratatui::layout::Rect>,
+    rotation_in_progress: bool,
+    // Why the passphrase change last stopped short, for the status dialog
+    rotation_blocked: Option<String>,
 
     status_list_state: ListState,
-    e2e_setup_step: usize, // 0: Enter, 1: Confirm
+    palette_textarea: TextArea<'a>,
+    palette_state: ListState,
+    // Most recently run from the palette first
+    palette_recent: Vec<Command>,
+    e2e_setup_step: usize,  // 0: Enter, 1: Confirm
+    e2e_setup_rotate: bool, // Setup pane reused for changing the passphrase
+
+    // The DB actor is gone; only the failure screen is shown from then on
+    db_failed: bool,
+}
+
+/// Pending `I`/`A` from visual block mode. Text typed on the top row is copied
+/// to the remaining rows when Insert mode ends.
+struct BlockInsert {
+    top: usize,
+    bottom: usize,
+    col: usize,
+    edge: block::InsertEdge,
+    line_count: usize,
+}
+
+/// A block edit is a selection delete plus an insert in tui-textarea's history;
+/// this lets `u`/`r` step over both at once.
+struct BlockUndo {
+    before: Vec<String>,
+    after: Vec<String>,
+    steps: usize,
+}
+
+#[derive(Clone, Copy)]
+struct SubscriptionPoll {
+    started: Instant,
+    next_check: Instant,
+}
+
+/// Identifies one unlock attempt. Only the most recently spawned attempt may
+/// install its key or report a status, so overlapping attempts can't race.
+#[derive(Clone)]
+struct UnlockTicket {
+    latest: Arc<AtomicU64>,
+    id: u64,
+}
+
+impl UnlockTicket {
+    fn is_current(&self) -> bool {
+        self.latest.load(Ordering::SeqCst) == self.id
+    }
+}
+
+const UNVERIFIED_UNLOCK_NOTICE: &str = "Couldn't reach server — will validate when online";
+
+#[derive(Debug)]
+enum UnlockOutcome {
+    Unlocked,
+    WrongPassphrase,
+    NoSalt,
+    /// Key installed without validation because the server was unreachable
+    /// and no cached validator exists.
+    NetworkError(String),
+    Other(String),
 }
 
 async fn unlock_process(
@@ -143,54 +497,39 @@ async fn unlock_process(
     api_client: APIClient,
     passphrase: String,
     crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
-) -> Result<bool> {
-    if passphrase.is_empty() {
-        return Ok(false);
-    }
-
-    if let Some(salt) = repo.get_salt().await? {
-        let key = crypto::derive_key_async(passphrase, salt).await?;
-
-        // Validate passphrase if a validator exists on the server
-        match api_client.get_me().await {
-            Ok(me) => {
-                if let Some(validator) = me.encryption_validator {
-                    match crypto::decrypt(&validator, &key) {
-                        Ok(decrypted) if decrypted == "RISU-VALID" => {
-                            crate::logger::log("Passphrase validated successfully.");
-                        }
-                        _ => {
-                            crate::logger::log("Invalid passphrase: Validation failed.");
-                            return Ok(false);
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                crate::logger::log(&format!(
-                    "Warning: Could not fetch validator from server: {}",
-                    e
-                ));
-            }
-        }
+    ticket: &UnlockTicket,
+) -> UnlockOutcome {
+    let (key, network_error) = match sync::check_passphrase(&repo, &api_client, passphrase).await {
+        PassphraseCheck::Valid(key) => (key, None),
+        PassphraseCheck::Unverified(key, e) => (key, Some(e)),
+        PassphraseCheck::WrongPassphrase => return UnlockOutcome::WrongPassphrase,
+        PassphraseCheck::NoSalt => return UnlockOutcome::NoSalt,
+        PassphraseCheck::Failed(e) => return UnlockOutcome::Other(e),
+    };
 
-        let mut guard = crypto_key.lock().unwrap();
-        *guard = Some(key);
-        drop(guard);
+    let mut guard = crypto_key.lock().unwrap();
+    if !ticket.is_current() {
+        crate::logger::log("Unlock attempt superseded. Discarding derived key.");
+        return UnlockOutcome::Other("Superseded by a newer unlock attempt".to_string());
+    }
+    *guard = Some(key);
+    drop(guard);
 
-        return Ok(true);
+    match network_error {
+        Some(e) => UnlockOutcome::NetworkError(e),
+        None => UnlockOutcome::Unlocked,
     }
-    Ok(false)
 }
 
 impl<'a> Model<'a> {
     async fn new(
         repo: Repo,
         sync_trigger: mpsc::Sender<()>,
-        status_rx: mpsc::Receiver<SyncStatus>,
-        status_tx: mpsc::Sender<SyncStatus>,
+        event_rx: mpsc::Receiver<SyncEvent>,
+        event_tx: mpsc::Sender<SyncEvent>,
         config: config::AppConfig,
         crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+        sync_cancel: Arc<AtomicBool>,
     ) -> Result<Self> {
         let token_data = config::get_token_data();
         let initial_pane = ActivePane::List;
@@ -201,6 +540,8 @@ impl<'a> Model<'a> {
             None
         };
         let token_source = Some(token_data.source);
+        let token_times = config::get_token_times(&token_data.id_token).ok();
+        let has_refresh_token = !token_data.refresh_token.is_empty();
 
         let clipboard = arboard::Clipboard::new().ok();
 
@@ -238,13 +579,22 @@ impl<'a> Model<'a> {
                 .border_style(Style::default().fg(config.theme.sync_error)),
         );
 
+        let (internal_tx, internal_rx) = mpsc::unbounded_channel();
+
         let mut model = Self {
             repo,
             notes: Vec::new(),
             filtered_notes: Vec::new(),
+            unknown_filters: Vec::new(),
+            short_id_len: SHORT_ID_LEN,
+            reminders: HashMap::new(),
+            due_view: false,
             list_state: ListState::default(),
+            list_rows: 0,
+            list_offset: 0,
             textarea: TextArea::default(),
             search_textarea,
+            confirmed_query: None,
             passphrase_textarea,
             passphrase_confirm_textarea,
             clear_confirm_textarea,
@@ -254,12 +604,15 @@ impl<'a> Model<'a> {
             current_note_id: None,
             sync_status: SyncStatus::Offline,
             sync_trigger,
-            status_rx,
-            status_tx,
+            event_rx,
+            event_tx,
             api_client: APIClient::new(),
             login_session: None,
+            login_token_textarea: TextArea::default(),
+            login_token_error: None,
             polling_login: false,
-            polling_subscription: false,
+            subscription_poll: None,
+            clock_skew: None,
             note_to_delete: None,
             clipboard,
             saved_feedback_until: None,
@@ -268,60 +621,182 @@ impl<'a> Model<'a> {
             pending_sync_end: false,
             show_preview: false,
             preview_scroll: 0,
+            remote_changed_base: None,
+            editor_locked: false,
+            editor_split: false,
             visual_anchor_row: None,
+            visual_block_anchor: None,
+            visual_block_to_eol: false,
+            block_insert: None,
+            block_register: None,
+            block_undo: None,
+            open_line_before: None,
+            auto_pairs_open: Vec::new(),
+            editor_viewport: (0, 0),
+            pending_view_align: None,
+            pending_count: 0,
+            macros: HashMap::new(),
+            last_find: None,
+            macro_recording: None,
+            macro_queue: VecDeque::new(),
+            last_macro: None,
+            editor_beep: false,
+            marks: HashMap::new(),
+            marks_note_id: None,
+            jump_origin: None,
+            jumplist: Vec::new(),
+            jump_index: 0,
+            pending_switch: None,
+            swap_due: None,
+            draft_id: uuid::Uuid::new_v4().to_string(),
+            scratch_id: None,
+            scratch_return: None,
+            recovery_queue: Vec::new(),
+            command_line: String::new(),
+            editor_message: None,
+            diff_view: None,
+            diff_scroll: 0,
+            internal_tx,
+            internal_rx,
+            notes_dir_rx: None,
+            color_mode: config.theme.color_mode.resolve(),
+            metrics: config.general.metrics.then(metrics::SessionMetrics::new),
             config,
             token_source,
+            token_times,
+            has_refresh_token,
             user_email,
             user_plan: None,
             user_subscription_status: None,
             user_subscription_end_date: None,
+            account_cached_at: None,
             last_error: None,
+            error_popup_scroll: None,
+            audit_popup: None,
+            focus_timer: None,
+            timer_flash_until: None,
             crypto_key,
-            e2e_status: "Disabled".to_string(),
+            sync_cancel,
+            network_online: Arc::new(AtomicBool::new(true)),
+            e2e_status: E2EState::Disabled,
             is_loading: false,
+            backup_task: None,
+            unlock_in_progress: false,
+            unlock_generation: Arc::new(AtomicU64::new(0)),
+            key_unverified: false,
+            e2e_indicator_area: None,
+            rotation_in_progress: false,
+            rotation_blocked: None,
             status_list_state: ListState::default(),
+            palette_textarea: TextArea::default(),
+            palette_state: ListState::default(),
+            palette_recent: Vec::new(),
             e2e_setup_step: 0,
+            e2e_setup_rotate: false,
+            db_failed: false,
         };
         model.refresh_notes(true).await?;
         model.setup_textarea();
 
+        if model.config.general.restore_session {
+            model.restore_session().await;
+        }
+
+        model.recovery_queue = config::load_swap_files();
+        if !model.recovery_queue.is_empty()
+            && matches!(model.active_pane, ActivePane::List | ActivePane::Editor)
+        {
+            model.active_pane = ActivePane::Recovery;
+        }
+
+        model.rotation_in_progress = model.repo.get_key_rotation().await?.is_some();
+
+        // A block that ran out while risu was closed is logged on the first tick
+        model.focus_timer = model
+            .repo
+            .get_kv(timer::KV_KEY)
+            .await?
+            .and_then(|json| serde_json::from_str(&json).ok());
+
+        // Last-known plan, so the first frame is useful before /auth/me answers
+        if model.user_email.is_some() {
+            if let Some(cache) = model.repo.get_account_cache().await? {
+                model.user_plan = cache.plan.parse().ok();
+                model.user_subscription_status = Some(cache.subscription_status);
+                model.user_subscription_end_date = cache.subscription_end_date;
+                model.account_cached_at = DateTime::parse_from_rfc3339(&cache.fetched_at)
+                    .ok()
+                    .map(|dt| dt.with_timezone(&Utc));
+            }
+        }
+
         if model.repo.get_salt().await?.is_some() {
-            model.e2e_status = "Locked".to_string();
+            model.e2e_status = E2EState::Locked;
             if let Ok(Some(pass)) = config::get_passphrase() {
                 // Background unlock
-                let repo = model.repo.clone();
-                let client = APIClient::new();
-                let key_store = model.crypto_key.clone();
-                let tx = model.status_tx.clone();
-                let pass_clone = pass.clone();
-
-                tokio::spawn(async move {
-                    let _ = tx.send(SyncStatus::Unlocking).await;
-                    match unlock_process(repo, client, pass_clone, key_store).await {
-                        Ok(true) => {
-                            let _ = tx.send(SyncStatus::Unlocked).await;
-                        }
-                        Ok(false) => {
-                            let _ = tx.send(SyncStatus::Error).await;
-                        }
-                        Err(e) => {
-                            crate::logger::log(&format!("Unlock error: {}", e));
-                            let _ = tx.send(SyncStatus::Error).await;
-                        }
-                    }
-                });
+                model.spawn_unlock(pass, false);
             }
         }
 
         Ok(model)
     }
 
+    /// Derives the key in the background and reports Unlocking/Unlocked/Error.
+    /// Starting a new attempt supersedes any attempt still in flight.
+    fn spawn_unlock(&mut self, passphrase: String, remember: bool) {
+        let id = self.unlock_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let ticket = UnlockTicket {
+            latest: self.unlock_generation.clone(),
+            id,
+        };
+        self.unlock_in_progress = true;
+
+        let repo = self.repo.clone();
+        let client = APIClient::new();
+        let key_store = self.crypto_key.clone();
+        let tx = self.event_tx.clone();
+
+        tokio::spawn(async move {
+            let _ = tx
+                .send(SyncEvent::StatusChanged(SyncStatus::Unlocking))
+                .await;
+            let result = unlock_process(repo, client, passphrase.clone(), key_store, &ticket).await;
+
+            if !ticket.is_current() {
+                crate::logger::log("Unlock attempt superseded. Dropping its result.");
+                return;
+            }
+
+            let result = match result {
+                UnlockOutcome::Unlocked => Ok(KeyCheck::Verified),
+                UnlockOutcome::NetworkError(e) => {
+                    crate::logger::log(&format!("Unlocked without validation: {}", e));
+                    Ok(KeyCheck::Unverified)
+                }
+                UnlockOutcome::WrongPassphrase => Err(UnlockError::WrongPassphrase),
+                UnlockOutcome::NoSalt => Err(UnlockError::NoSalt),
+                UnlockOutcome::Other(e) => {
+                    crate::logger::log(&format!("Unlock error: {}", e));
+                    Err(UnlockError::Other)
+                }
+            };
+
+            if remember && result.is_ok() {
+                let _ = config::save_passphrase(&passphrase);
+            }
+            let _ = tx.send(SyncEvent::UnlockResult(result)).await;
+        });
+    }
+
     fn setup_textarea(&mut self) {
         let theme = &self.config.theme;
         self.textarea
             .set_cursor_line_style(Style::default().bg(theme.editor_cursor_line));
         self.textarea
             .set_block(Block::default().borders(Borders::ALL).title(" Editor "));
+        self.editor_viewport = (0, 0);
+        self.block_undo = None;
+        self.open_line_before = None;
     }
 
     fn setup_search_textarea(&mut self) {
@@ -344,16 +819,60 @@ impl<'a> Model<'a> {
         );
     }
 
+    /// `L` / clicking the padlock: ask for the passphrase when locked, drop the
+    /// key when unlocked.
+    fn toggle_e2e_lock(&mut self) {
+        match self.e2e_status {
+            E2EState::Locked => {
+                self.active_pane = ActivePane::PassphraseInput;
+                self.setup_unlock_passphrase_textarea_style();
+            }
+            E2EState::Unlocked if !self.rotation_in_progress => {
+                *self.crypto_key.lock().unwrap() = None;
+                self.e2e_status = E2EState::Locked;
+                self.key_unverified = false;
+                self.editor_message = Some(" E2E locked. Press L to unlock ".to_string());
+            }
+            _ => {}
+        }
+    }
+
     fn setup_unlock_passphrase_textarea_style(&mut self) {
         let theme = &self.config.theme;
+        let title = if self.rotation_in_progress {
+            " Enter NEW Passphrase (Change In Progress) "
+        } else {
+            " Enter Passphrase to Unlock "
+        };
         self.passphrase_textarea.set_block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(" Enter Passphrase to Unlock ")
+                .title(title)
                 .border_style(Style::default().fg(theme.border_active)),
         );
     }
 
+    fn reset_e2e_setup_inputs(&mut self) {
+        self.passphrase_textarea = TextArea::default();
+        self.passphrase_textarea.set_mask_char('•');
+        self.setup_passphrase_textarea_style();
+        self.passphrase_confirm_textarea = TextArea::default();
+        self.passphrase_confirm_textarea.set_mask_char('•');
+        self.setup_confirm_textarea_style();
+        self.e2e_setup_step = 0;
+    }
+
+    fn show_unlock_error(&mut self, msg: &str) {
+        self.passphrase_textarea = TextArea::default();
+        self.passphrase_textarea.set_mask_char('•');
+        self.passphrase_textarea.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" {} ", msg))
+                .border_style(Style::default().fg(self.config.theme.sync_error)),
+        );
+    }
+
     fn setup_confirm_textarea_style(&mut self) {
         let theme = &self.config.theme;
         self.passphrase_confirm_textarea.set_block(
@@ -365,18 +884,47 @@ impl<'a> Model<'a> {
     }
 
     async fn refresh_notes(&mut self, should_update_editor: bool) -> Result<()> {
-        self.notes = self.repo.get_notes().await?;
+        let selected_id = self.selected_note_id();
+        let offset = self.list_state.offset();
+        self.notes = self.repo.get_notes(false).await?;
+        self.reveal_ciphertext_notes();
+        if self.config.general.sort_by == config::NoteSort::Created {
+            // Timestamps are all RFC 3339 UTC, so they order as strings
+            self.notes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        }
+        self.short_id_len = self.repo.short_id_len().await?;
+        self.reminders = self
+            .repo
+            .get_reminders()
+            .await?
+            .into_iter()
+            .map(|r| (r.note_id, r.due_at))
+            .collect();
 
-        let query = self.search_textarea.lines()[0].to_lowercase();
-        self.filtered_notes = if query.is_empty() {
-            self.notes.clone()
-        } else {
-            self.notes
-                .iter()
-                .filter(|n| n.content.to_lowercase().contains(&query))
-                .cloned()
-                .collect()
-        };
+        let query = NoteQuery::parse(&self.search_textarea.lines()[0]);
+        self.filtered_notes = self
+            .notes
+            .iter()
+            .filter(|n| query.matches(n))
+            .filter(|n| !self.due_view || self.reminders.contains_key(&n.id))
+            .cloned()
+            .collect();
+        if self.due_view {
+            self.filtered_notes
+                .sort_by(|a, b| self.reminders[&a.id].cmp(&self.reminders[&b.id]));
+        }
+        // The scratch note is pinned on top of the unfiltered list, and while
+        // it's open from `S`
+        let show_scratch = self.config.general.show_scratch
+            && self.search_textarea.lines()[0].is_empty()
+            && !self.due_view;
+        if show_scratch || self.scratch_return.is_some() {
+            let scratch = self.repo.scratch_note().await?;
+            self.scratch_id = Some(scratch.id.clone());
+            self.notes.insert(0, scratch.clone());
+            self.filtered_notes.insert(0, scratch);
+        }
+        self.unknown_filters = query.unknown;
 
         if self.filtered_notes.is_empty() {
             self.list_state.select(None);
@@ -385,1844 +933,4220 @@ impl<'a> Model<'a> {
         {
             self.list_state.select(Some(0));
         }
+        // Emptying the list resets the offset; put it back if the same note
+        // is still selected
+        if selected_id.is_some() && self.selected_note_id() == selected_id {
+            self.restore_list_offset(offset);
+        }
 
-        if should_update_editor {
+        if should_update_editor || self.editor_locked {
             self.update_editor_from_selection();
         }
         Ok(())
     }
 
+    fn selected_note_id(&self) -> Option<String> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.filtered_notes.get(i))
+            .map(|n| n.id.clone())
+    }
+
+    /// Scrolls the list to `offset`, moved just enough to keep the selection
+    /// in view.
+    fn restore_list_offset(&mut self, offset: usize) {
+        *self.list_state.offset_mut() = clamp_list_offset(
+            offset,
+            self.list_state.selected(),
+            self.list_rows,
+            self.filtered_notes.len(),
+        );
+    }
+
+    // Notes that arrived as ciphertext (pulled while locked, or mislabeled as
+    // plaintext) are decrypted in memory once a key is available. The stored
+    // copy stays as is until the note is edited and saved.
+    fn reveal_ciphertext_notes(&mut self) {
+        let guard = self.crypto_key.lock().unwrap();
+        let Some(key) = guard.as_ref() else {
+            return;
+        };
+        for note in &mut self.notes {
+            if crypto::looks_like_ciphertext(&note.content) {
+                if let Ok(plaintext) = crypto::decrypt(note.content.trim(), key) {
+                    note.content = plaintext;
+                }
+            }
+        }
+    }
+
     fn update_editor_from_selection(&mut self) {
         if let Some(note) = self
             .list_state
             .selected()
             .and_then(|i| self.filtered_notes.get(i))
         {
-            if self.current_note_id.as_deref() != Some(&note.id) {
-                self.textarea = TextArea::from(note.content.lines());
+            let locked = crypto::looks_like_ciphertext(&note.content);
+            // A locked note is reloaded once it can be decrypted
+            if self.current_note_id.as_deref() != Some(&note.id) || self.editor_locked != locked {
+                (self.textarea, self.editor_split) = if locked {
+                    (TextArea::default(), false)
+                } else {
+                    note_textarea(&note.content, !self.config.editor.keep_long_lines)
+                };
                 self.current_note_id = Some(note.id.clone());
+                self.editor_locked = locked;
                 self.preview_scroll = 0;
+                self.remote_changed_base = None;
                 self.setup_textarea();
             }
             return;
         }
         self.textarea = TextArea::default();
         self.current_note_id = None;
+        self.editor_locked = false;
+        self.editor_split = false;
+        self.remote_changed_base = None;
         self.setup_textarea();
     }
 
-    async fn save_current_note(&mut self) -> Result<()> {
-        let content = self.textarea.lines().join("\n");
-        if content.trim().is_empty() {
-            if let Some(id) = &self.current_note_id {
-                self.repo.delete_note(id.clone()).await?;
-                self.current_note_id = None;
-                let _ = self.sync_trigger.try_send(());
-            }
-            self.refresh_notes(true).await?;
+    /// Refreshes after notes changed behind the editor's back (a pull, or the
+    /// notes folder). A clean buffer follows the stored note in place; unsaved
+    /// edits are left alone, and the next save merges them with the change.
+    async fn refresh_notes_under_editor(&mut self, should_update_editor: bool) -> Result<()> {
+        let id = self.current_note_id.clone();
+        let before = self.stored_content();
+        let was_dirty = self.editor_is_dirty();
+        self.refresh_notes(should_update_editor).await?;
+        if self.editor_locked || self.current_note_id != id {
             return Ok(());
         }
-
-        // Check for changes before saving
-        if let Some(id) = &self.current_note_id {
-            if let Some(original_note) = self.notes.iter().find(|n| &n.id == id) {
-                if original_note.content == content {
-                    return Ok(());
-                }
-            }
+        let (Some(before), Some(after)) = (before, self.stored_content()) else {
+            return Ok(());
+        };
+        if before == after {
+            return Ok(());
+        }
+        if was_dirty {
+            // Several changes in a row still merge against what was edited
+            self.remote_changed_base.get_or_insert(before);
+            self.editor_message = Some(
+                " This note changed on another device — saving will merge the changes ".to_string(),
+            );
+        } else {
+            self.reload_editor_in_place(&after);
         }
+        Ok(())
+    }
 
-        let is_e2e_enabled = self.e2e_status != "Disabled";
-        let id = self
+    // The stored content of the note in the editor, if it has been saved
+    fn stored_content(&self) -> Option<String> {
+        let id = self.current_note_id.as_ref()?;
+        self.notes
+            .iter()
+            .find(|n| &n.id == id)
+            .map(|n| n.content.clone())
+    }
+
+    // Replaces the buffer, keeping the cursor where it was as far as it fits
+    fn reload_editor_in_place(&mut self, content: &str) {
+        let (row, col) = self.textarea.cursor();
+        // A note loaded with `:raw` stays that way
+        let raw = !self.editor_split
+            && self
+                .textarea
+                .lines()
+                .iter()
+                .any(|l| l.len() > LONG_LINE_LIMIT);
+        (self.textarea, self.editor_split) =
+            note_textarea(content, !raw && !self.config.editor.keep_long_lines);
+        self.setup_textarea();
+        self.textarea.move_cursor(CursorMove::Jump(
+            row.min(u16::MAX as usize) as u16,
+            col.min(u16::MAX as usize) as u16,
+        ));
+    }
+
+    async fn restore_session(&mut self) {
+        let note_id = self.repo.get_kv(SESSION_NOTE_KEY).await.unwrap_or(None);
+        let pane = self.repo.get_kv(SESSION_PANE_KEY).await.unwrap_or(None);
+        let selected = self
             .repo
-            .save_note(self.current_note_id.clone(), content, is_e2e_enabled)
-            .await?;
-        self.current_note_id = Some(id);
+            .get_kv(SESSION_SELECTED_KEY)
+            .await
+            .unwrap_or(None)
+            .and_then(|v| v.parse::<usize>().ok());
+
+        let index = match note_id {
+            Some(id) => match self.filtered_notes.iter().position(|n| n.id == id) {
+                Some(i) => i,
+                None => {
+                    crate::logger::log(
+                        "restore_session: Last opened note no longer exists. Using defaults.",
+                    );
+                    return;
+                }
+            },
+            None => match selected {
+                Some(i) if i < self.filtered_notes.len() => i,
+                _ => return,
+            },
+        };
 
-        self.saved_feedback_until = Some(Instant::now() + Duration::from_secs(1));
+        self.list_state.select(Some(index));
+        self.update_editor_from_selection();
 
-        self.refresh_notes(true).await?;
-        if !self.notes.is_empty() {
-            self.list_state.select(Some(0));
-            self.update_editor_from_selection();
+        if pane.as_deref() == Some("Editor") {
+            self.active_pane = ActivePane::Editor;
+            self.mode = Mode::Normal;
+            self.record_note_visit();
         }
-
-        let _ = self.sync_trigger.try_send(());
-        Ok(())
     }
 
-    async fn start_login(&mut self) -> Result<()> {
-        let session = self.api_client.start_login_session().await?;
-        open_browser(&session.url);
-        self.login_session = Some(session);
-        self.polling_login = true;
-        Ok(())
-    }
+    async fn persist_session(&self) {
+        if !self.config.general.restore_session {
+            return;
+        }
 
-    async fn poll_login(&mut self) -> Result<bool> {
-        if let Some(session) = &self.login_session {
-            let res = self
-                .api_client
-                .poll_login_session(&session.session_id)
-                .await?;
-            if res.status == "success" {
-                config::save_token_data(&res.token, &res.refresh_token)?;
-                self.polling_login = false;
-                self.login_session = None;
-                self.user_email = config::get_user_email_from_token(&res.token).ok();
+        let pane = if self.active_pane == ActivePane::Editor {
+            "Editor"
+        } else {
+            "List"
+        };
 
-                self.is_loading = true;
-                match self.api_client.get_me().await {
-                    Ok(me) => {
-                        self.user_plan = Some(me.plan.clone());
-                        self.user_subscription_status = Some(me.subscription_status.clone());
-                        self.user_subscription_end_date = me.subscription_end_date.clone();
-                        let is_eligible = me.plan == "pro" || me.plan == "dev";
-                        if is_eligible {
-                            if let Some(salt) = me.encryption_salt {
-                                self.repo.set_salt(&salt).await?;
-                                self.e2e_status = "Locked".to_string();
-
-                                let pass_opt = config::get_passphrase().unwrap_or(None);
-                                if let Some(pass) = pass_opt {
-                                    // Background unlock
-                                    let repo = self.repo.clone();
-                                    let client = APIClient::new();
-                                    let key_store = self.crypto_key.clone();
-                                    let tx = self.status_tx.clone();
-                                    let pass_clone = pass.clone();
-
-                                    tokio::spawn(async move {
-                                        let _ = tx.send(SyncStatus::Unlocking).await;
-                                        match unlock_process(repo, client, pass_clone, key_store)
-                                            .await
-                                        {
-                                            Ok(true) => {
-                                                let _ = tx.send(SyncStatus::Unlocked).await;
-                                            }
-                                            Ok(false) => {
-                                                // This means passphrase exists but invalid for new account? Or just wrong.
-                                                // UI should probably prompt.
-                                                let _ = tx.send(SyncStatus::Error).await;
-                                            }
-                                            Err(_) => {
-                                                let _ = tx.send(SyncStatus::Error).await;
-                                            }
-                                        }
-                                    });
-                                    // We don't wait here, but we default to List view.
-                                    // If unlock fails, user will see Error status or "Locked".
-                                    self.active_pane = ActivePane::List;
-                                } else {
-                                    self.active_pane = ActivePane::PassphraseInput;
-                                    self.passphrase_textarea = TextArea::default();
-                                    self.passphrase_textarea.set_mask_char('•');
-                                    self.setup_unlock_passphrase_textarea_style();
-                                }
-                            } else {
-                                // Eligible but no E2E setup -> Go to Setup
-                                self.e2e_status = "Setup Required".to_string();
-                                self.active_pane = ActivePane::E2ESetup;
-                            }
-                        } else {
-                            self.e2e_status = "Disabled".to_string();
-                            self.active_pane = ActivePane::List;
-                            if self.repo.get_salt().await.unwrap_or(None).is_some() {
-                                crate::logger::log("poll_login: Free plan detected but local salt exists. Cleaning up.");
-                                let _ = self.repo.delete_salt().await;
-                                let _ = config::delete_passphrase();
-                                {
-                                    let mut guard = self.crypto_key.lock().unwrap();
-                                    *guard = None;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        crate::logger::log(&format!("Failed to get user info: {}", e));
-                        self.active_pane = ActivePane::List;
-                    }
-                }
-                self.is_loading = false;
+        let _ = match &self.current_note_id {
+            Some(id) => self.repo.set_kv(SESSION_NOTE_KEY, id).await,
+            None => self.repo.delete_kv(SESSION_NOTE_KEY).await,
+        };
+        let _ = self.repo.set_kv(SESSION_PANE_KEY, pane).await;
+        let _ = match self.list_state.selected() {
+            Some(i) => self.repo.set_kv(SESSION_SELECTED_KEY, &i.to_string()).await,
+            None => self.repo.delete_kv(SESSION_SELECTED_KEY).await,
+        };
+    }
 
-                let _ = self.sync_trigger.send(()).await;
-                return Ok(true);
-            } else if res.status == "not_found" {
-                self.polling_login = false;
-                self.login_session = None;
-                return Err(anyhow::anyhow!("Login session expired"));
+    // The buffer differs from the stored note (or is a non-empty new note)
+    fn editor_is_dirty(&self) -> bool {
+        if self.editor_locked || self.editor_split {
+            return false;
+        }
+        let content = self.textarea.lines().join("\n");
+        match &self.current_note_id {
+            Some(id) => self
+                .notes
+                .iter()
+                .find(|n| &n.id == id)
+                .is_none_or(|n| n.content != content),
+            None => !content.trim().is_empty(),
+        }
+    }
+
+    /// Esc from the editor, or focus moving off it: saves, discards or asks
+    /// first as `editor.esc_behavior` says (focus never discards; see
+    /// `focus_pane`).
+    async fn request_leave_editor(&mut self) {
+        // The scratch note always keeps what was typed
+        if self.editing_scratch() {
+            self.leave_editor(true).await;
+            return;
+        }
+        match self.config.editor.esc_behavior {
+            config::EscBehavior::Save => self.leave_editor(true).await,
+            config::EscBehavior::Discard => self.leave_editor(false).await,
+            config::EscBehavior::Prompt if self.editor_is_dirty() => {
+                self.active_pane = ActivePane::UnsavedConfirm;
+                self.pending_key = PendingKey::None;
             }
+            config::EscBehavior::Prompt => self.leave_editor(true).await,
         }
-        Ok(false)
     }
 
-    async fn delete_note(&mut self) -> Result<()> {
-        if let Some(note) = &self.note_to_delete {
-            self.repo.delete_note(note.id.clone()).await?;
-            self.refresh_notes(true).await?;
-            let _ = self.sync_trigger.try_send(());
+    /// Moves focus between the list and the editor area (the textarea or the
+    /// preview, whichever is showing).
+    async fn focus_pane(&mut self, pane: ActivePane) {
+        self.pending_key = PendingKey::None;
+        match (&self.active_pane, pane) {
+            (ActivePane::List, ActivePane::Editor) => {
+                self.active_pane = ActivePane::Editor;
+                self.mode = Mode::Normal;
+                self.record_note_visit();
+            }
+            // Moving focus is too easy to do by accident to throw edits away,
+            // so the discard policy asks instead
+            (ActivePane::Editor, ActivePane::List)
+                if self.config.editor.esc_behavior == config::EscBehavior::Discard
+                    && self.editor_is_dirty()
+                    && !self.editing_scratch() =>
+            {
+                self.active_pane = ActivePane::UnsavedConfirm;
+            }
+            (ActivePane::Editor, ActivePane::List) => self.request_leave_editor().await,
+            _ => {}
         }
-        self.active_pane = ActivePane::List;
-        self.note_to_delete = None;
-        self.saved_feedback_until = None;
-        Ok(())
     }
 
-    async fn handle_key_event(&mut self, key: event::KeyEvent) -> Result<bool> {
-        match self.active_pane {
-            ActivePane::List => match key.code {
-                KeyCode::Char('q') => return Ok(true),
-                KeyCode::Esc => {
-                    if !self.search_textarea.lines()[0].is_empty() {
-                        self.search_textarea = TextArea::default();
-                        self.setup_search_textarea();
-                        self.refresh_notes(true).await?;
-                    }
-                }
-                KeyCode::Char('j') | KeyCode::Down => self.move_list_selection(1),
-                KeyCode::Char('k') | KeyCode::Up => self.move_list_selection(-1),
-                KeyCode::Char('r') => {
-                    let _ = self.sync_trigger.try_send(());
-                }
-                KeyCode::Char('d') => {
-                    if let Some(note) = self
-                        .list_state
-                        .selected()
-                        .and_then(|i| self.filtered_notes.get(i))
-                    {
-                        self.note_to_delete = Some(note.clone());
-                        self.active_pane = ActivePane::DeleteConfirm;
-                    }
-                }
-                KeyCode::Enter | KeyCode::Tab => {
-                    self.active_pane = ActivePane::Editor;
-                    self.mode = Mode::Normal;
-                }
-                KeyCode::Char('g') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
-                    self.active_pane = ActivePane::StatusDialog;
-                    self.status_list_state.select(Some(0));
-                }
-                KeyCode::Char('i') => {
-                    self.active_pane = ActivePane::Editor;
-                    self.mode = Mode::Insert;
-                    self.textarea.move_cursor(CursorMove::Bottom);
-                    self.textarea.move_cursor(CursorMove::End);
-                }
-                KeyCode::Char('n') => {
-                    self.current_note_id = None;
-                    self.textarea = TextArea::default();
-                    self.setup_textarea();
-                    self.active_pane = ActivePane::Editor;
-                    self.mode = Mode::Insert;
-                }
-                KeyCode::Char('/') => {
-                    self.active_pane = ActivePane::Search;
-                    self.setup_search_textarea();
-                }
-                KeyCode::Char('L') if self.e2e_status == "Locked" => {
-                    self.active_pane = ActivePane::PassphraseInput;
-                    self.setup_unlock_passphrase_textarea_style();
-                }
-                _ => {}
-            },
-            ActivePane::Search => match key.code {
-                KeyCode::Esc | KeyCode::Enter => {
-                    self.active_pane = ActivePane::List;
-                }
-                _ => {
-                    if self.search_textarea.input(key) {
-                        self.refresh_notes(true).await?;
-                    }
-                }
-            },
-            ActivePane::StatusDialog => match key.code {
-                KeyCode::Esc | KeyCode::Char('q') => {
-                    self.active_pane = ActivePane::List;
-                }
-                KeyCode::Char('j') | KeyCode::Down => {
-                    let items = self.get_status_menu_items();
-                    let i = match self.status_list_state.selected() {
-                        Some(i) => {
-                            if i >= items.len() - 1 {
-                                0
-                            } else {
-                                i + 1
-                            }
-                        }
-                        None => 0,
-                    };
-                    self.status_list_state.select(Some(i));
-                }
-                KeyCode::Char('k') | KeyCode::Up => {
-                    let items = self.get_status_menu_items();
-                    let i = match self.status_list_state.selected() {
-                        Some(i) => {
-                            if i == 0 {
-                                items.len() - 1
-                            } else {
-                                i - 1
-                            }
-                        }
-                        None => 0,
-                    };
-                    self.status_list_state.select(Some(i));
-                }
-                KeyCode::Enter => {
-                    if let Some(i) = self.status_list_state.selected() {
-                        let items = self.get_status_menu_items();
-                        if let Some(action) = items.get(i) {
-                            match *action {
-                                "Sync Now" => {
-                                    let _ = self.sync_trigger.try_send(());
-                                    self.active_pane = ActivePane::List;
-                                }
-                                "Login" => {
-                                    let _ = self.start_login().await;
-                                    self.active_pane = ActivePane::Login;
-                                }
-                                "Select Plan" => {
-                                    if let Ok(url) = self.api_client.get_checkout_url().await {
-                                        open_browser(&url);
-                                    }
-                                    self.active_pane = ActivePane::List;
-                                    self.polling_subscription = true;
-                                }
-                                "Manage Subscription" => {
-                                    if let Ok(url) = self.api_client.get_portal_url().await {
-                                        open_browser(&url);
-                                    }
-                                    self.active_pane = ActivePane::List;
-                                    self.polling_subscription = true;
-                                }
-                                "Logout" => {
-                                    let _ = self.perform_logout().await;
-                                    self.active_pane = ActivePane::List;
-                                }
-                                "Clear All Data" => {
-                                    self.clear_confirm_textarea = TextArea::default();
-                                    self.clear_confirm_textarea.set_block(
-                                        Block::default()
-                                            .borders(Borders::ALL)
-                                            .title(" Confirm Clear (Type 'ClearAllData') ")
-                                            .border_style(
-                                                Style::default().fg(self.config.theme.sync_error),
-                                            ),
-                                    );
-                                    self.active_pane = ActivePane::ClearConfirm;
-                                }
-                                "Close" => {
-                                    self.active_pane = ActivePane::List;
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                _ => {}
-            },
-            ActivePane::ClearConfirm => match key.code {
-                KeyCode::Esc => {
-                    self.active_pane = ActivePane::StatusDialog;
+    // Leaves the editor for the list, saving or reverting the buffer first. A
+    // failed save keeps the editor open with the edits.
+    async fn leave_editor(&mut self, save: bool) {
+        if save {
+            if !self.keep_buffer().await {
+                self.active_pane = ActivePane::Editor;
+                return;
+            }
+        } else {
+            self.revert_buffer();
+        }
+        if let Some(previous) = self.scratch_return.take() {
+            let _ = self.refresh_notes(false).await;
+            match previous {
+                Some(id) => {
+                    let _ = self.select_note_by_id(&id).await;
                 }
-                KeyCode::Enter => {
-                    let input = if self.clear_confirm_textarea.lines().is_empty() {
-                        ""
-                    } else {
-                        self.clear_confirm_textarea.lines()[0].trim()
-                    };
+                None => self.update_editor_from_selection(),
+            }
+        }
+        self.active_pane = ActivePane::List;
+        self.mode = Mode::Normal;
+        self.pending_key = PendingKey::None;
+        self.show_preview = false;
+        self.restore_list_offset(self.list_offset);
+    }
 
-                    if input == "ClearAllData" {
-                        self.perform_clear_all_data().await?;
-                        self.active_pane = ActivePane::List;
-                    } else {
-                        self.active_pane = ActivePane::StatusDialog;
-                    }
-                }
-                _ => {
-                    self.clear_confirm_textarea.input(key);
-                }
-            },
-            ActivePane::PassphraseInput => match key.code {
-                KeyCode::Esc => {
-                    self.active_pane = ActivePane::List;
-                }
-                KeyCode::Enter => {
-                    let passphrase = self.passphrase_textarea.lines()[0].clone();
-                    if !passphrase.is_empty() {
-                        self.is_loading = true;
-
-                        // Spawn unlock task
-                        let repo = self.repo.clone();
-                        let client = APIClient::new();
-                        let key_store = self.crypto_key.clone();
-                        let tx = self.status_tx.clone();
-                        let pass_clone = passphrase.clone();
-
-                        tokio::spawn(async move {
-                            let _ = tx.send(SyncStatus::Unlocking).await;
-                            match unlock_process(repo, client, pass_clone.clone(), key_store).await
-                            {
-                                Ok(true) => {
-                                    let _ = config::save_passphrase(&pass_clone);
-                                    let _ = tx.send(SyncStatus::Unlocked).await;
-                                }
-                                Ok(false) => {
-                                    let _ = tx.send(SyncStatus::Error).await;
-                                }
-                                Err(_) => {
-                                    let _ = tx.send(SyncStatus::Error).await;
-                                }
-                            }
-                        });
+    /// Saves the buffer. A failure is shown in the status line and returns
+    /// false, so the caller doesn't move on from the edits.
+    async fn keep_buffer(&mut self) -> bool {
+        match self.save_current_note().await {
+            Ok(()) => true,
+            Err(e) => {
+                crate::logger::log(&format!("Save failed: {:?}", e));
+                self.editor_message = Some(format!(" Couldn't save: {} ", e));
+                false
+            }
+        }
+    }
 
-                        self.passphrase_textarea = TextArea::default();
-                        self.passphrase_textarea.set_mask_char('•');
+    // Puts the stored note back in the editor
+    fn revert_buffer(&mut self) {
+        self.swap_due = None;
+        let _ = config::delete_swap_file(self.swap_owner());
+        // Forces a reload from the stored note
+        self.current_note_id = None;
+        self.update_editor_from_selection();
+    }
+
+    /// Before the editor moves to another note: saves or reverts the buffer as
+    /// `editor.esc_behavior` says. With `prompt` and unsaved edits it asks
+    /// first and returns false; the answer carries out `switch`.
+    async fn settle_buffer(&mut self, switch: NoteSwitch) -> bool {
+        if self.editing_scratch() {
+            return self.keep_buffer().await;
+        }
+        match self.config.editor.esc_behavior {
+            config::EscBehavior::Save => self.keep_buffer().await,
+            config::EscBehavior::Discard => {
+                self.revert_buffer();
+                true
+            }
+            config::EscBehavior::Prompt if self.editor_is_dirty() => {
+                self.pending_switch = Some(switch);
+                self.active_pane = ActivePane::UnsavedConfirm;
+                self.pending_key = PendingKey::None;
+                false
+            }
+            config::EscBehavior::Prompt => self.keep_buffer().await,
+        }
+    }
+
+    /// The unsaved-changes prompt was answered with save (`true`) or discard.
+    pub(crate) async fn answer_unsaved_prompt(&mut self, save: bool) -> Result<()> {
+        let Some(switch) = self.pending_switch.take() else {
+            self.leave_editor(save).await;
+            return Ok(());
+        };
+        self.active_pane = ActivePane::Editor;
+        if save {
+            if !self.keep_buffer().await {
+                return Ok(());
+            }
+        } else {
+            self.revert_buffer();
+        }
+        match switch {
+            NoteSwitch::Jump { back } => self.walk_jumplist(back).await,
+            NoteSwitch::SearchMatch { forward } => {
+                self.step_search_match(forward);
+                Ok(())
+            }
+        }
+    }
+
+    fn editing_scratch(&self) -> bool {
+        self.scratch_id.is_some() && self.current_note_id == self.scratch_id
+    }
+
+    /// Opens the scratch note (`S` in the list, Ctrl+` from the editor) for
+    /// typing. It never syncs and saves itself shortly after each edit.
+    pub(crate) async fn open_scratch(&mut self) -> Result<()> {
+        if self.active_pane == ActivePane::Editor {
+            if self.editing_scratch() {
+                return Ok(());
+            }
+            self.request_leave_editor().await;
+            // Unsaved edits to answer for first
+            if self.active_pane != ActivePane::List {
+                return Ok(());
+            }
+        }
+        if self.scratch_return.is_none() {
+            self.scratch_return = Some(self.selected_note_id());
+        }
+        self.refresh_notes(false).await?;
+        let index = self
+            .filtered_notes
+            .iter()
+            .position(|n| Some(&n.id) == self.scratch_id.as_ref());
+        self.list_state.select(index);
+        self.update_editor_from_selection();
+        self.active_pane = ActivePane::Editor;
+        self.mode = Mode::Insert;
+        self.textarea.move_cursor(CursorMove::Bottom);
+        self.textarea.move_cursor(CursorMove::End);
+        Ok(())
+    }
+
+    /// Writes the scratch buffer straight to the database, without the save
+    /// feedback or a list refresh.
+    async fn autosave_scratch(&mut self) -> Result<()> {
+        if !self.editor_is_dirty() {
+            return Ok(());
+        }
+        let content = self.textarea.lines().join("\n");
+        let id = self
+            .repo
+            .save_note(self.current_note_id.clone(), content.clone(), false, false)
+            .await?;
+        for note in self.notes.iter_mut().chain(self.filtered_notes.iter_mut()) {
+            if note.id == id {
+                note.content = content.clone();
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_current_note(&mut self) -> Result<()> {
+        if self.editor_locked || self.editor_split {
+            return Ok(());
+        }
+        // Cleaned in the buffer, before the dirty check, so a note that only
+        // differs by whitespace the cleanup removes isn't saved again
+        if self.config.editor.normalize_on_save {
+            let content = self.textarea.lines().join("\n");
+            let normalized = normalize::note(&content);
+            if normalized != content {
+                let cursor = self.textarea.cursor();
+                let last = self.textarea.lines().len() - 1;
+                self.replace_rows(0, last, normalized.split('\n').map(String::from).collect());
+                self.textarea
+                    .move_cursor(CursorMove::Jump(cursor.0 as u16, cursor.1 as u16));
+            }
+        }
+        let content = self.textarea.lines().join("\n");
+        self.swap_due = None;
+        if content.trim().is_empty() && !self.editing_scratch() {
+            let _ = config::delete_swap_file(self.swap_owner());
+            if let Some(id) = &self.current_note_id {
+                let title = self
+                    .notes
+                    .iter()
+                    .find(|n| &n.id == id)
+                    .map(|n| derive_title(&n.content));
+                self.repo.delete_note(id.clone()).await?;
+                logger::audit(logger::AuditEvent::Delete {
+                    via: logger::Via::Tui,
+                    notes: vec![logger::AuditNote {
+                        id: id.clone(),
+                        title,
+                    }],
+                });
+                self.current_note_id = None;
+                let _ = self.sync_trigger.try_send(());
+            }
+            self.refresh_notes(true).await?;
+            return Ok(());
+        }
+
+        // Check for changes before saving
+        if self.current_note_id.is_some() && !self.editor_is_dirty() {
+            let _ = config::delete_swap_file(self.swap_owner());
+            return Ok(());
+        }
+
+        // The note changed under these edits; fold the change in rather than
+        // overwrite it
+        let content = match self.remote_changed_base.take() {
+            Some(base) => {
+                let stored = self.stored_content().unwrap_or_default();
+                let merged = match merge::merge3(&base, &content, &stored) {
+                    merge::MergeResult::Clean(text) => {
+                        self.editor_message =
+                            Some(" Merged with the changes from another device ".to_string());
+                        text
                     }
-                }
-                _ => {
-                    self.passphrase_textarea.input(key);
-                }
-            },
-            ActivePane::E2ESetup => match key.code {
-                KeyCode::Esc => {
-                    self.active_pane = ActivePane::List;
-                    self.e2e_setup_step = 0;
-                    self.passphrase_textarea = TextArea::default();
-                    self.passphrase_textarea.set_mask_char('•');
-                    self.setup_passphrase_textarea_style(); // Helper to reset style
-                    self.passphrase_confirm_textarea = TextArea::default();
-                    self.passphrase_confirm_textarea.set_mask_char('•');
-                    self.setup_confirm_textarea_style();
-                }
-                KeyCode::Tab | KeyCode::Down | KeyCode::Up => {
-                    // Toggle focus
-                    self.e2e_setup_step = 1 - self.e2e_setup_step;
-                }
-                KeyCode::Enter => {
-                    let p1 = self.passphrase_textarea.lines()[0].clone();
-                    let p2 = self.passphrase_confirm_textarea.lines()[0].clone();
-
-                    if p1.is_empty() {
-                        self.e2e_setup_step = 0;
-                        return Ok(false);
+                    merge::MergeResult::Conflicted(text) => {
+                        self.editor_message = Some(
+                            " Merged with conflicts — resolve the marked sections ".to_string(),
+                        );
+                        text
                     }
+                };
+                self.reload_editor_in_place(&merged);
+                merged
+            }
+            None => content,
+        };
 
-                    if self.e2e_setup_step == 0 {
-                        self.e2e_setup_step = 1;
-                    } else {
-                        // Submit
-                        if p1 != p2 {
-                            // Mismatch - reset confirm
-                            self.passphrase_confirm_textarea = TextArea::default();
-                            self.passphrase_confirm_textarea.set_mask_char('•');
-                            self.setup_confirm_textarea_style();
-                            crate::logger::log("Passphrases do not match");
-                            return Ok(false);
-                        }
+        let id = self
+            .repo
+            .save_note(
+                self.current_note_id.clone(),
+                content,
+                self.e2e_status.is_enabled(),
+                self.e2e_status.is_locked(),
+            )
+            .await?;
+        let _ = config::delete_swap_file(self.swap_owner());
 
-                        self.is_loading = true;
-
-                        // 1. Generate Salt locally
-                        let salt = crypto::generate_salt();
-
-                        // 2. Derive key and create Validator
-                        match crypto::derive_key_async(p1.clone(), salt.clone()).await {
-                            Ok(key) => {
-                                match crypto::encrypt("RISU-VALID", &key) {
-                                    Ok(validator) => {
-                                        // 3. Send Salt + Validator atomically
-                                        match self
-                                            .api_client
-                                            .e2e_enable(Some(&salt), Some(&validator))
-                                            .await
-                                        {
-                                            Ok(_returned_salt) => {
-                                                // Should match our salt
-                                                self.repo.set_salt(&salt).await?;
-                                                config::save_passphrase(&p1)?;
-                                                self.repo.set_notes_encrypted_status(1).await?;
-
-                                                // Unlock immediately
-                                                let mut guard = self.crypto_key.lock().unwrap();
-                                                *guard = Some(key); // Key is already derived
-                                                drop(guard);
-
-                                                self.e2e_status = "Unlocked".to_string();
-                                                self.active_pane = ActivePane::List;
-                                                let _ = self.sync_trigger.try_send(());
-                                            }
-                                            Err(e) => {
-                                                crate::logger::log(&format!(
-                                                    "Failed to enable E2E: {}",
-                                                    e
-                                                ));
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        crate::logger::log(&format!(
-                                            "Failed to encrypt validator: {}",
-                                            e
-                                        ));
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                crate::logger::log(&format!("Failed to derive key: {}", e));
-                            }
-                        }
+        // Marks set before a new note's first save move over to its id
+        if self.marks_note_id == Some(None) {
+            self.marks_note_id = Some(Some(id.clone()));
+            self.persist_marks().await;
+        }
+        self.current_note_id = Some(id.clone());
+        if let Some(metrics) = &mut self.metrics {
+            metrics.notes_saved += 1;
+        }
+
+        self.saved_feedback_until = Some(Instant::now() + Duration::from_secs(1));
 
-                        self.is_loading = false;
+        self.refresh_notes(true).await?;
+        if !self.notes.is_empty() {
+            // The saved note is the newest, just below a pinned scratch note
+            let index = self.filtered_notes.iter().position(|n| n.id == id);
+            self.list_state.select(Some(index.unwrap_or(0)));
+            self.update_editor_from_selection();
+        }
 
-                        // Cleanup textareas
+        let _ = self.sync_trigger.try_send(());
+        Ok(())
+    }
 
-                        self.passphrase_textarea = TextArea::default();
-                        self.passphrase_textarea.set_mask_char('•');
-                        self.setup_passphrase_textarea_style();
-                        self.passphrase_confirm_textarea = TextArea::default();
-                        self.passphrase_confirm_textarea.set_mask_char('•');
-                        self.setup_confirm_textarea_style();
-                        self.e2e_setup_step = 0;
+    /// `:timer [minutes|pause|resume|cancel]`. Bare `:timer` starts a block of
+    /// `timer.minutes`, or says how long the current one has left.
+    async fn timer_command(&mut self, arg: &str) {
+        let now = Utc::now();
+        let message = match (arg, self.focus_timer.as_mut()) {
+            ("pause", Some(t)) => {
+                t.pause(now);
+                "Focus timer paused".to_string()
+            }
+            ("resume", Some(t)) => {
+                t.resume(now);
+                "Focus timer resumed".to_string()
+            }
+            ("cancel", Some(_)) => {
+                self.focus_timer = None;
+                "Focus timer cancelled".to_string()
+            }
+            ("pause" | "resume" | "cancel", None) => "No focus timer is running".to_string(),
+            ("", Some(t)) => format!(
+                "{}m focus block: {} left{}",
+                t.minutes,
+                t.label(now).trim(),
+                if t.is_paused() { " (paused)" } else { "" }
+            ),
+            (arg, _) => {
+                let minutes = if arg.is_empty() {
+                    Some(self.config.timer.minutes)
+                } else {
+                    arg.parse::<u64>().ok()
+                };
+                match minutes.filter(|m| (1..=24 * 60).contains(m)) {
+                    Some(minutes) => {
+                        self.focus_timer = Some(timer::FocusTimer::start(minutes, now));
+                        format!("Focus timer started: {}m", minutes)
                     }
-                }
-                _ => {
-                    if self.e2e_setup_step == 0 {
-                        self.passphrase_textarea.input(key);
-                    } else {
-                        self.passphrase_confirm_textarea.input(key);
+                    None => {
+                        self.editor_beep = true;
+                        "Usage: :timer [minutes|pause|resume|cancel]".to_string()
                     }
                 }
-            },
-            ActivePane::Editor => match self.mode {
-                Mode::Normal => match key.code {
-                    KeyCode::Esc => {
-                        let _ = self.save_current_note().await;
-                        self.active_pane = ActivePane::List;
-                        self.pending_key = PendingKey::None;
-                        self.show_preview = false;
-                    }
-                    KeyCode::Char('i') => {
-                        self.mode = Mode::Insert;
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('h') | KeyCode::Left => {
-                        self.textarea.move_cursor(CursorMove::Back);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('j') | KeyCode::Down => {
-                        if self.show_preview {
-                            self.preview_scroll = self.preview_scroll.saturating_add(1);
-                        } else {
-                            self.textarea.move_cursor(CursorMove::Down);
-                        }
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('k') | KeyCode::Up => {
-                        if self.show_preview {
-                            self.preview_scroll = self.preview_scroll.saturating_sub(1);
-                        } else {
-                            self.textarea.move_cursor(CursorMove::Up);
-                        }
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('l') | KeyCode::Right => {
-                        self.textarea.move_cursor(CursorMove::Forward);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('w') => {
-                        self.textarea.move_cursor(CursorMove::WordForward);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('b') => {
-                        self.textarea.move_cursor(CursorMove::WordBack);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('e') => {
-                        self.textarea.move_cursor(CursorMove::WordForward);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('0') => {
-                        self.textarea.move_cursor(CursorMove::Head);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('$') => {
-                        self.textarea.move_cursor(CursorMove::End);
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('u') => {
-                        self.textarea.undo();
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('r') => {
-                        self.textarea.redo();
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('m') => {
-                        self.show_preview = !self.show_preview;
-                        self.preview_scroll = 0;
-                        self.pending_key = PendingKey::None;
-                    }
+            }
+        };
+        self.editor_message = Some(format!(" {} ", message));
+        self.persist_focus_timer().await;
+    }
 
-                    KeyCode::Char('g') => {
-                        if self.pending_key == PendingKey::G {
-                            self.textarea.move_cursor(CursorMove::Top);
-                            self.pending_key = PendingKey::None;
-                        } else {
-                            self.pending_key = PendingKey::G;
-                        }
-                    }
-                    KeyCode::Char('G') => {
-                        self.textarea.move_cursor(CursorMove::Bottom);
-                        self.pending_key = PendingKey::None;
-                    }
+    async fn persist_focus_timer(&mut self) {
+        let json = self
+            .focus_timer
+            .as_ref()
+            .and_then(|t| serde_json::to_string(t).ok());
+        let _ = match json {
+            Some(json) => self.repo.set_kv(timer::KV_KEY, &json).await,
+            None => self.repo.delete_kv(timer::KV_KEY).await,
+        };
+    }
 
-                    KeyCode::Char('d') => {
-                        if self.pending_key == PendingKey::D {
-                            let (row, _) = self.textarea.cursor();
-                            let line = self.textarea.lines()[row].clone();
-                            self.copy_to_clipboard(&format!("{}\n", line));
-                            self.textarea.move_cursor(CursorMove::Head);
-                            self.textarea.delete_line_by_end();
-                            if !self.textarea.delete_next_char() {
-                                self.textarea.move_cursor(CursorMove::Back);
-                                self.textarea.delete_next_char();
-                            }
-                            self.pending_key = PendingKey::None;
-                        } else {
-                            self.pending_key = PendingKey::D;
-                        }
-                    }
+    /// A block ran out: flash the footer and log it to `timer.log_note`.
+    async fn finish_focus_timer(&mut self) -> Result<()> {
+        let Some(finished) = self.focus_timer.take() else {
+            return Ok(());
+        };
+        self.persist_focus_timer().await;
+        self.timer_flash_until = Some(Instant::now() + TIMER_FLASH);
+        let entry = finished.log_entry(Local::now());
+        let title = self.config.timer.log_note.trim().to_string();
+        if title.is_empty() {
+            self.editor_message = Some(format!(" {}m focus block done ", finished.minutes));
+            return Ok(());
+        }
 
-                    KeyCode::Char('y') => {
-                        if self.pending_key == PendingKey::Y {
-                            let (row, _) = self.textarea.cursor();
-                            let line = self.textarea.lines()[row].clone();
-                            self.copy_to_clipboard(&format!("{}\n", line));
-                            self.pending_key = PendingKey::None;
-                        } else {
-                            self.pending_key = PendingKey::Y;
-                        }
-                    }
+        let log_note = self
+            .notes
+            .iter()
+            .find(|n| n.is_deleted == 0 && derive_title(&n.content) == title)
+            .map(|n| (n.id.clone(), n.content.clone()));
+        if let Some((id, _)) = &log_note {
+            // Unsaved edits to the log note itself: add the line to the buffer
+            if self.current_note_id.as_ref() == Some(id) && self.editor_is_dirty() {
+                self.textarea.move_cursor(CursorMove::Bottom);
+                self.textarea.move_cursor(CursorMove::End);
+                self.textarea.insert_newline();
+                self.textarea.insert_str(&entry);
+                self.editor_message = Some(format!(
+                    " {}m focus block done — added to the open note, save to keep it ",
+                    finished.minutes
+                ));
+                return Ok(());
+            }
+        }
+        let (id, content) = match log_note {
+            Some((id, content)) => (Some(id), format!("{}\n{}", content.trim_end(), entry)),
+            None => (None, format!("# {}\n\n{}", title, entry)),
+        };
+        self.repo
+            .save_note(
+                id,
+                content,
+                self.e2e_status.is_enabled(),
+                self.e2e_status.is_locked(),
+            )
+            .await?;
+        self.refresh_notes_under_editor(false).await?;
+        let _ = self.sync_trigger.try_send(());
+        self.editor_message = Some(format!(
+            " {}m focus block done — logged to \"{}\" ",
+            finished.minutes, title
+        ));
+        Ok(())
+    }
 
-                    KeyCode::Char('p') => {
-                        if let Some(text) = self.get_from_clipboard() {
-                            self.textarea.insert_str(&text);
-                        }
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('v') => {
-                        self.mode = Mode::Visual;
-                        self.textarea.start_selection();
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('V') => {
-                        self.mode = Mode::VisualLine;
-                        let (row, _) = self.textarea.cursor();
-                        self.visual_anchor_row = Some(row);
-                        self.textarea.move_cursor(CursorMove::Head);
-                        self.textarea.start_selection();
-                        self.textarea.move_cursor(CursorMove::End);
-                        self.pending_key = PendingKey::None;
-                    }
+    // Opens `url` in the browser, reporting a failure in the footer
+    fn open_link(&mut self, url: &str) {
+        if let Err(e) = open_browser(url, &self.config.general.browser_command) {
+            logger::log(&format!("Couldn't open {}: {:#}", url, e));
+            self.editor_message = Some(format!(" Couldn't open the browser: {} ", e));
+        }
+    }
 
-                    KeyCode::Char('s') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
-                        let _ = self.save_current_note().await;
-                        self.pending_key = PendingKey::None;
-                    }
-                    _ => {
-                        self.pending_key = PendingKey::None;
-                    }
-                },
-                Mode::Insert => match key.code {
-                    KeyCode::Esc => self.mode = Mode::Normal,
-                    KeyCode::Char('s') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
-                        let _ = self.save_current_note().await;
-                    }
-                    _ => {
-                        self.textarea.input(key);
-                    }
-                },
-                Mode::Visual => match key.code {
-                    KeyCode::Esc => {
-                        self.mode = Mode::Normal;
-                        self.textarea.cancel_selection();
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('y') => {
-                        self.textarea.copy();
-                        let text = self.textarea.yank_text();
-                        self.copy_to_clipboard(&text);
-                        self.mode = Mode::Normal;
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('d') => {
-                        self.textarea.cut();
-                        let text = self.textarea.yank_text();
-                        self.copy_to_clipboard(&text);
-                        self.mode = Mode::Normal;
-                        self.pending_key = PendingKey::None;
-                    }
-                    KeyCode::Char('g') => {
-                        if self.pending_key == PendingKey::G {
-                            self.textarea.move_cursor(CursorMove::Top);
-                            self.pending_key = PendingKey::None;
+    async fn start_login(&mut self) -> Result<()> {
+        let session = self.api_client.start_login_session().await?;
+        self.open_link(&session.url);
+        self.login_session = Some(session);
+        self.polling_login = true;
+        Ok(())
+    }
+
+    /// Everything after a token is in hand, whether from the browser flow or
+    /// pasted by hand: store it, fetch the account, and move on to unlock or
+    /// E2E setup as the plan requires.
+    async fn complete_login(&mut self, token: &str, refresh_token: &str) -> Result<()> {
+        config::save_token_data(token, refresh_token)?;
+        self.polling_login = false;
+        self.login_session = None;
+        self.login_token_textarea = TextArea::default();
+        self.login_token_error = None;
+        self.user_email = config::get_user_email_from_token(token).ok();
+
+        self.is_loading = true;
+        match self.api_client.get_me().await {
+            Ok(me) => {
+                self.store_account_info(&me).await;
+                if me.plan.is_sync_eligible() {
+                    if let Some(salt) = me.encryption_salt {
+                        self.repo.set_salt(&salt).await?;
+                        self.e2e_status = E2EState::Locked;
+
+                        let pass_opt = config::get_passphrase().unwrap_or(None);
+                        if let Some(pass) = pass_opt {
+                            // Background unlock
+                            self.spawn_unlock(pass, false);
+                            // We don't wait here, but we default to List view.
+                            // If unlock fails, user will see Error status or "Locked".
+                            self.active_pane = ActivePane::List;
                         } else {
-                            self.pending_key = PendingKey::G;
+                            self.active_pane = ActivePane::PassphraseInput;
+                            self.passphrase_textarea = TextArea::default();
+                            self.passphrase_textarea.set_mask_char('•');
+                            self.setup_unlock_passphrase_textarea_style();
                         }
+                    } else {
+                        // Eligible but no E2E setup -> Go to Setup
+                        self.e2e_status = E2EState::SetupRequired;
+                        self.active_pane = ActivePane::E2ESetup;
                     }
-                    KeyCode::Char('G') => {
-                        self.textarea.move_cursor(CursorMove::Bottom);
-                        self.pending_key = PendingKey::None;
+                } else {
+                    self.e2e_status = E2EState::Disabled;
+                    self.active_pane = ActivePane::List;
+                    if self.repo.get_salt().await.unwrap_or(None).is_some() {
+                        crate::logger::log(
+                            "complete_login: Free plan detected but local salt exists. Cleaning up.",
+                        );
+                        let _ = self.repo.delete_salt().await;
+                        let _ = config::delete_passphrase();
+                        {
+                            let mut guard = self.crypto_key.lock().unwrap();
+                            *guard = None;
+                        }
                     }
-                    KeyCode::Char('h') | KeyCode::Left => {
-                        self.textarea.move_cursor(CursorMove::Back);
-                        self.pending_key = PendingKey::None;
+                }
+            }
+            Err(e) => {
+                crate::logger::log(&format!("Failed to get user info: {}", e));
+                self.active_pane = ActivePane::List;
+            }
+        }
+        self.is_loading = false;
+
+        let _ = self.sync_trigger.send(()).await;
+        Ok(())
+    }
+
+    /// Enter in the Login pane with a pasted token.
+    async fn submit_pasted_token(&mut self) {
+        let token = self
+            .login_token_textarea
+            .lines()
+            .join("")
+            .trim()
+            .to_string();
+        if let Err(e) = config::validate_token(&token) {
+            self.login_token_error = Some(e.to_string());
+            return;
+        }
+        // Tokens copied by hand come without a refresh token; the session
+        // lasts until this one expires
+        if let Err(e) = self.complete_login(&token, "").await {
+            self.login_token_error = Some(format!("Login failed: {}", e));
+        }
+    }
+
+    async fn poll_login(&mut self) -> Result<bool> {
+        if let Some(session) = &self.login_session {
+            let res = self
+                .api_client
+                .poll_login_session(&session.session_id)
+                .await?;
+            if res.status == "success" {
+                self.complete_login(&res.token, &res.refresh_token).await?;
+                return Ok(true);
+            } else if res.status == "not_found" {
+                self.polling_login = false;
+                self.login_session = None;
+                return Err(anyhow::anyhow!("Login session expired"));
+            }
+        }
+        Ok(false)
+    }
+
+    async fn delete_note(&mut self) -> Result<()> {
+        if let Some(note) = self
+            .note_to_delete
+            .take_if(|n| Some(&n.id) == self.scratch_id.as_ref())
+        {
+            // The scratch note is emptied rather than deleted
+            self.repo
+                .save_note(Some(note.id.clone()), String::new(), false, false)
+                .await?;
+            if self.current_note_id.as_ref() == Some(&note.id) {
+                self.current_note_id = None;
+            }
+            self.refresh_notes(true).await?;
+            self.active_pane = ActivePane::List;
+            return Ok(());
+        }
+        if let Some(note) = &self.note_to_delete {
+            self.repo.delete_note(note.id.clone()).await?;
+            logger::audit(logger::AuditEvent::Delete {
+                via: logger::Via::Tui,
+                notes: vec![logger::AuditNote::of(note)],
+            });
+            let _ = config::delete_swap_file(config::SwapOwner::Note(&note.id));
+            let _ = self
+                .repo
+                .delete_kv(&format!("{}{}", MARKS_KEY_PREFIX, note.id))
+                .await;
+            self.refresh_notes(true).await?;
+            let _ = self.sync_trigger.try_send(());
+        }
+        self.active_pane = ActivePane::List;
+        self.note_to_delete = None;
+        self.saved_feedback_until = None;
+        Ok(())
+    }
+
+    /// Routes a key to the focused pane's handler in `ui`.
+    async fn handle_key_event(&mut self, key: event::KeyEvent) -> Result<Action> {
+        if let Some(digit) = self.count_digit(key) {
+            self.pending_count = self.pending_count.saturating_mul(10).saturating_add(digit);
+            return Ok(Action::None);
+        }
+        let count = std::mem::take(&mut self.pending_count).max(1);
+
+        if self.active_pane == ActivePane::Editor
+            && key.code == KeyCode::Char('`')
+            && key.modifiers.contains(event::KeyModifiers::CONTROL)
+        {
+            self.open_scratch().await?;
+            return Ok(Action::None);
+        }
+
+        match self.active_pane {
+            ActivePane::List => self.handle_list_key(key).await,
+            ActivePane::Search => self.handle_search_key(key).await,
+            ActivePane::StatusDialog => self.handle_status_dialog_key(key).await,
+            ActivePane::ClearConfirm => self.handle_clear_confirm_key(key).await,
+            ActivePane::PassphraseInput => self.handle_passphrase_key(key).await,
+            ActivePane::E2ESetup => self.handle_e2e_setup_key(key).await,
+            ActivePane::Editor => self.handle_editor_key(key, count).await,
+            ActivePane::Login => self.handle_login_key(key).await,
+            ActivePane::Diff => self.handle_diff_key(key).await,
+            ActivePane::Recovery => self.handle_recovery_key(key).await,
+            ActivePane::UnsavedConfirm => self.handle_unsaved_confirm_key(key).await,
+            ActivePane::DeleteConfirm => self.handle_delete_confirm_key(key).await,
+            ActivePane::ReuploadConfirm => self.handle_reupload_confirm_key(key).await,
+            ActivePane::CommandPalette => self.handle_palette_key(key).await,
+        }
+    }
+
+    /// Starts re-encrypting every note under a new passphrase. The job is persisted
+    /// before the key is swapped, so it resumes after a restart until the sync
+    /// manager confirms every note is on the new key.
+    async fn start_key_rotation(&mut self, passphrase: String) -> Result<()> {
+        let old_key = {
+            let guard = self.crypto_key.lock().unwrap();
+            guard.clone()
+        }
+        .ok_or_else(|| anyhow::anyhow!("Unlock before changing the passphrase"))?;
+
+        let salt = crypto::generate_salt();
+        let new_key = crypto::derive_key_async(passphrase.clone(), salt.clone()).await?;
+        let rotation = db::KeyRotation {
+            target_version: self.repo.get_key_version().await? + 1,
+            new_salt: salt,
+            new_validator: crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &new_key)?,
+            wrapped_old_key: crypto::wrap_key(&old_key, &new_key)?,
+            server_updated: false,
+        };
+
+        self.repo.begin_key_rotation(&rotation).await?;
+        config::save_passphrase(&passphrase)?;
+        {
+            let mut guard = self.crypto_key.lock().unwrap();
+            *guard = Some(new_key);
+        }
+
+        crate::logger::log(&format!(
+            "Key rotation started (target key version {})",
+            rotation.target_version
+        ));
+        logger::audit(logger::AuditEvent::PassphraseChange {
+            via: logger::Via::Tui,
+        });
+        self.rotation_in_progress = true;
+        let _ = self.sync_trigger.try_send(());
+        Ok(())
+    }
+
+    /// Goes back to the old passphrase while the server still has it. The
+    /// saved passphrase is the new one, so it's forgotten rather than kept.
+    async fn cancel_key_rotation(&mut self) -> Result<()> {
+        sync::cancel_key_rotation(&self.repo, &self.crypto_key).await?;
+        config::delete_passphrase()?;
+        crate::logger::log("Key rotation cancelled by the user");
+        self.rotation_in_progress = false;
+        self.rotation_blocked = None;
+        let _ = self.sync_trigger.try_send(());
+        Ok(())
+    }
+
+    /// Another device completed E2E setup first: use its salt and ask for that passphrase.
+    async fn adopt_server_salt(&mut self, salt: &str) -> Result<()> {
+        self.repo.set_salt(salt).await?;
+        // Local notes were never pushed under this account's key
+        self.repo.set_notes_encrypted_status(1).await?;
+        {
+            let mut guard = self.crypto_key.lock().unwrap();
+            *guard = None;
+        }
+
+        self.e2e_status = E2EState::Locked;
+        self.active_pane = ActivePane::PassphraseInput;
+        self.passphrase_textarea = TextArea::default();
+        self.passphrase_textarea.set_mask_char('•');
+        self.passphrase_textarea.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" E2E Already Set Up Elsewhere - Enter That Passphrase ")
+                .border_style(Style::default().fg(self.config.theme.border_active)),
+        );
+        Ok(())
+    }
+
+    async fn store_account_info(&mut self, me: &sync::AuthMeResponse) {
+        self.user_plan = Some(me.plan.clone());
+        self.user_subscription_status = Some(me.subscription_status.clone());
+        self.user_subscription_end_date = me.subscription_end_date.clone();
+        self.account_cached_at = None;
+
+        let cache = db::AccountCache {
+            plan: me.plan.as_str().to_string(),
+            subscription_status: me.subscription_status.clone(),
+            subscription_end_date: me.subscription_end_date.clone(),
+            fetched_at: Utc::now().to_rfc3339(),
+        };
+        if let Err(e) = self.repo.set_account_cache(&cache).await {
+            crate::logger::log(&format!("Failed to cache account info: {}", e));
+        }
+    }
+
+    async fn apply_account_info(&mut self, me: sync::AuthMeResponse) -> Result<()> {
+        self.store_account_info(&me).await;
+        if me.plan.is_sync_eligible() {
+            if let Some(salt) = me.encryption_salt {
+                let local_salt = self.repo.get_salt().await?;
+                if !self.rotation_in_progress && local_salt.is_some_and(|l| l != salt) {
+                    crate::logger::log(
+                        "apply_account_info: Server salt changed (passphrase changed on another device).",
+                    );
+                    self.repo.replace_salt(&salt).await?;
+                    let _ = config::delete_passphrase();
+                    {
+                        let mut guard = self.crypto_key.lock().unwrap();
+                        *guard = None;
+                    }
+                    self.key_unverified = false;
+                    self.e2e_status = E2EState::Locked;
+                    self.active_pane = ActivePane::PassphraseInput;
+                    self.show_unlock_error("Passphrase Changed On Another Device");
+                    return Ok(());
+                }
+
+                self.repo.set_salt(&salt).await?;
+
+                let is_unlocked = {
+                    let guard = self.crypto_key.lock().unwrap();
+                    guard.is_some()
+                };
+
+                if let Some(validator) = &me.encryption_validator {
+                    self.repo.set_validator(validator).await?;
+                }
+
+                let verification_failed = is_unlocked
+                    && self.key_unverified
+                    && me.encryption_validator.as_deref().is_some_and(|v| {
+                        let guard = self.crypto_key.lock().unwrap();
+                        guard
+                            .as_ref()
+                            .is_some_and(|key| !crypto::verify_validator(v, key))
+                    });
+                self.key_unverified = false;
+
+                if verification_failed {
+                    crate::logger::log(
+                        "apply_account_info: Key unlocked offline failed validation. Locking.",
+                    );
+                    {
+                        let mut guard = self.crypto_key.lock().unwrap();
+                        *guard = None;
+                    }
+                    let _ = config::delete_passphrase();
+                    self.e2e_status = E2EState::Locked;
+                    self.last_error = None;
+                    self.active_pane = ActivePane::PassphraseInput;
+                    self.show_unlock_error("Invalid Passphrase! Try Again");
+                } else if is_unlocked {
+                    self.e2e_status = E2EState::Unlocked;
+                    if self.last_error.as_deref() == Some(UNVERIFIED_UNLOCK_NOTICE) {
+                        self.last_error = None;
                     }
-                    KeyCode::Char('j') | KeyCode::Down => {
-                        self.textarea.move_cursor(CursorMove::Down);
-                        self.pending_key = PendingKey::None;
+                    crate::logger::log("apply_account_info: E2E already unlocked");
+                    let _ = self.sync_trigger.try_send(());
+                } else {
+                    self.e2e_status = E2EState::Locked;
+                    if let Ok(Some(pass)) = config::get_passphrase() {
+                        // Background unlock
+                        self.spawn_unlock(pass, false);
+                    } else {
+                        self.active_pane = ActivePane::PassphraseInput;
+                        self.passphrase_textarea = TextArea::default();
+                        self.passphrase_textarea.set_mask_char('•');
+                        self.setup_unlock_passphrase_textarea_style();
                     }
-                    KeyCode::Char('k') | KeyCode::Up => {
-                        self.textarea.move_cursor(CursorMove::Up);
-                        self.pending_key = PendingKey::None;
+                }
+            } else {
+                // Eligible but no salt -> Setup needed
+                self.e2e_status = E2EState::SetupRequired;
+                self.active_pane = ActivePane::E2ESetup;
+            }
+        } else {
+            self.e2e_status = E2EState::Disabled;
+            if self.repo.get_salt().await.unwrap_or(None).is_some() {
+                crate::logger::log(
+                    "apply_account_info: Free plan detected but local salt exists. Cleaning up.",
+                );
+                let _ = self.repo.delete_salt().await;
+                let _ = config::delete_passphrase();
+                {
+                    let mut guard = self.crypto_key.lock().unwrap();
+                    *guard = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn update(&mut self, msg: Message) -> Result<bool> {
+        match msg {
+            Message::Key(key) if self.db_failed => {
+                return Ok(matches!(
+                    key.code,
+                    KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter
+                ));
+            }
+            Message::Key(key) => {
+                if matches!(key.kind, KeyEventKind::Press | KeyEventKind::Repeat) {
+                    self.editor_message = None;
+                    if let Some((_, keys)) = &mut self.macro_recording {
+                        keys.push(key);
                     }
-                    KeyCode::Char('l') | KeyCode::Right => {
-                        self.textarea.move_cursor(CursorMove::Forward);
-                        self.pending_key = PendingKey::None;
+                    if self.handle_key_event(key).await? == Action::Quit {
+                        return Ok(true);
                     }
-                    KeyCode::Char('w') => {
-                        self.textarea.move_cursor(CursorMove::WordForward);
-                        self.pending_key = PendingKey::None;
+                    if self.active_pane == ActivePane::Editor {
+                        self.swap_due = Some(Instant::now() + self.swap_idle());
                     }
-                    KeyCode::Char('b') => {
-                        self.textarea.move_cursor(CursorMove::WordBack);
-                        self.pending_key = PendingKey::None;
+                    return self.run_macro_queue().await;
+                }
+            }
+            Message::Resize(_w, _h) => {}
+            Message::Click(column, row) => {
+                let position = ratatui::layout::Position::new(column, row);
+                if self
+                    .e2e_indicator_area
+                    .is_some_and(|a| a.contains(position))
+                    && self.active_pane == ActivePane::List
+                {
+                    self.toggle_e2e_lock();
+                }
+            }
+            Message::Paste(text) => {
+                // Single-line fields drop the trailing newline password managers add
+                let line = text
+                    .trim_end_matches(['\r', '\n'])
+                    .replace("\r\n", " ")
+                    .replace(['\r', '\n'], " ");
+                match self.active_pane {
+                    ActivePane::Editor if self.editor_locked || self.editor_split => {}
+                    ActivePane::Editor => {
+                        let text = text.replace('\r', "");
+                        self.textarea.insert_str(text);
+                        self.swap_due = Some(Instant::now() + self.swap_idle());
                     }
-                    KeyCode::Char('e') => {
-                        self.textarea.move_cursor(CursorMove::WordForward);
-                        self.pending_key = PendingKey::None;
+                    ActivePane::Search => {
+                        self.search_textarea.insert_str(line);
+                        self.refresh_notes(true).await?;
                     }
-                    KeyCode::Char('0') => {
-                        self.textarea.move_cursor(CursorMove::Head);
-                        self.pending_key = PendingKey::None;
+                    ActivePane::PassphraseInput => {
+                        self.passphrase_textarea.insert_str(line);
                     }
-                    KeyCode::Char('$') => {
-                        self.textarea.move_cursor(CursorMove::End);
-                        self.pending_key = PendingKey::None;
+                    ActivePane::Login if self.polling_login => {
+                        self.login_token_textarea.insert_str(line);
+                        self.login_token_error = None;
                     }
-                    _ => {
-                        self.pending_key = PendingKey::None;
+                    ActivePane::E2ESetup if self.e2e_setup_step == 0 => {
+                        self.passphrase_textarea.insert_str(line);
                     }
-                },
-                Mode::VisualLine => match key.code {
-                    KeyCode::Esc => {
-                        self.mode = Mode::Normal;
-                        self.textarea.cancel_selection();
-                        self.visual_anchor_row = None;
-                        self.pending_key = PendingKey::None;
+                    ActivePane::E2ESetup => {
+                        self.passphrase_confirm_textarea.insert_str(line);
                     }
-                    KeyCode::Char('y') => {
-                        self.textarea.copy();
-                        let text = self.textarea.yank_text();
-                        self.copy_to_clipboard(&text);
-                        self.mode = Mode::Normal;
-                        self.visual_anchor_row = None;
-                        self.pending_key = PendingKey::None;
+                    ActivePane::ClearConfirm => {
+                        self.clear_confirm_textarea.insert_str(line);
                     }
-                    KeyCode::Char('d') => {
-                        self.textarea.cut();
-                        let text = self.textarea.yank_text();
-                        self.copy_to_clipboard(&text);
-                        self.mode = Mode::Normal;
-                        self.visual_anchor_row = None;
-                        self.pending_key = PendingKey::None;
+                    _ => {}
+                }
+            }
+            Message::Sync(event) => self.handle_sync_event(event).await?,
+            Message::NotesDirChanged => {
+                self.refresh_notes_under_editor(self.active_pane != ActivePane::Editor)
+                    .await?;
+            }
+            Message::Tick => {
+                self.spinner_index = (self.spinner_index + 1) % 4;
+                if self.swap_due.is_some_and(|due| Instant::now() >= due) {
+                    self.swap_due = None;
+                    if self.editing_scratch() {
+                        self.autosave_scratch().await?;
+                    } else {
+                        self.write_swap_file();
                     }
-                    _ => {
-                        match key.code {
-                            KeyCode::Char('j') | KeyCode::Down => {
-                                self.textarea.move_cursor(CursorMove::Down);
-                                self.pending_key = PendingKey::None;
-                            }
-                            KeyCode::Char('k') | KeyCode::Up => {
-                                self.textarea.move_cursor(CursorMove::Up);
-                                self.pending_key = PendingKey::None;
-                            }
-                            KeyCode::Char('g') => {
-                                if self.pending_key == PendingKey::G {
-                                    self.textarea.move_cursor(CursorMove::Top);
-                                    self.pending_key = PendingKey::None;
-                                } else {
-                                    self.pending_key = PendingKey::G;
-                                    return Ok(false);
-                                }
-                            }
-                            KeyCode::Char('G') => {
-                                self.textarea.move_cursor(CursorMove::Bottom);
-                                self.pending_key = PendingKey::None;
-                            }
-                            _ => {
-                                self.pending_key = PendingKey::None;
-                            }
-                        }
+                }
+                if self
+                    .focus_timer
+                    .as_ref()
+                    .is_some_and(|t| t.is_done(Utc::now()))
+                {
+                    self.finish_focus_timer().await?;
+                }
+                if self.timer_flash_until.is_some_and(|t| Instant::now() >= t) {
+                    self.timer_flash_until = None;
+                }
+                if self
+                    .backup_task
+                    .as_ref()
+                    .is_some_and(|(task, _)| task.is_finished())
+                {
+                    self.backup_task = None;
+                }
+            }
+            Message::PollingTick => {
+                if self.polling_login {
+                    let _ = self.poll_login().await;
+                }
+            }
+            Message::SubscriptionCheck => {
+                let Some(poll) = self.subscription_poll.as_mut() else {
+                    return Ok(false);
+                };
+                let now = Instant::now();
+                let elapsed = now.duration_since(poll.started);
+                if elapsed >= SUBSCRIPTION_POLL_WINDOW {
+                    crate::logger::log("Subscription polling window expired");
+                    self.subscription_poll = None;
+                    self.editor_message = Some(
+                        " Stopped waiting for subscription. Use Sync Now or reopen the status dialog (Ctrl+g) to check again "
+                            .to_string(),
+                    );
+                } else if now >= poll.next_check {
+                    poll.next_check = now + subscription_poll_delay(elapsed);
+                    if let Ok(me) = self.api_client.get_me().await {
+                        let is_paid_now = me.plan.is_sync_eligible();
+                        let was_free = self.user_plan.as_ref().is_none_or(|p| *p == Plan::Free);
 
-                        if let Some(anchor) = self.visual_anchor_row {
-                            let (current_row, _) = self.textarea.cursor();
-                            self.textarea.cancel_selection();
-
-                            if current_row < anchor {
-                                self.textarea
-                                    .move_cursor(CursorMove::Jump(anchor as u16, 0));
-                                self.textarea.move_cursor(CursorMove::End);
-                                self.textarea.start_selection();
-                                self.textarea
-                                    .move_cursor(CursorMove::Jump(current_row as u16, 0));
-                                self.textarea.move_cursor(CursorMove::Head);
-                            } else {
-                                self.textarea
-                                    .move_cursor(CursorMove::Jump(anchor as u16, 0));
-                                self.textarea.move_cursor(CursorMove::Head);
-                                self.textarea.start_selection();
-                                self.textarea
-                                    .move_cursor(CursorMove::Jump(current_row as u16, 0));
-                                self.textarea.move_cursor(CursorMove::End);
-                            }
+                        let _ = self.apply_account_info(me).await;
+
+                        if was_free && is_paid_now {
+                            crate::logger::log("Subscription upgrade detected!");
+                            self.subscription_poll = None;
                         }
                     }
-                },
-            },
-            ActivePane::Login => match key.code {
-                KeyCode::Char('q') => return Ok(true),
-                KeyCode::Esc => {
-                    self.active_pane = ActivePane::List;
                 }
-                KeyCode::Enter => {
-                    if !self.polling_login {
-                        let _ = self.start_login().await;
-                    }
+            }
+            Message::PersistSession => {
+                self.persist_session().await;
+            }
+            Message::DiffReady(title, lines) => {
+                self.is_loading = false;
+                // Dropped if the user left the editor while it was computing
+                if self.active_pane == ActivePane::Editor {
+                    self.show_diff(title, lines);
                 }
-                _ => {}
-            },
-            ActivePane::DeleteConfirm => match key.code {
-                KeyCode::Char('y') | KeyCode::Enter => {
-                    let _ = self.delete_note().await;
+            }
+            Message::AccountCheckResult(result) => {
+                self.is_loading = false;
+                match result {
+                    Ok(me) => {
+                        let _ = self.apply_account_info(me).await;
+                    }
+                    Err(e) => {
+                        let msg = format!("AccountCheck: Failed to get user info: {}", e);
+                        crate::logger::log(&msg);
+                        self.last_error = Some(msg);
+                    }
                 }
-                KeyCode::Char('n') | KeyCode::Esc => {
+            }
+        }
+        Ok(false)
+    }
+
+    async fn handle_sync_event(&mut self, event: SyncEvent) -> Result<()> {
+        match event {
+            SyncEvent::StatusChanged(status) => self.handle_sync_status(status).await?,
+            SyncEvent::Progress {
+                phase: SyncPhase::Encrypting,
+                done,
+                total,
+            } => {
+                self.sync_status = SyncStatus::Encrypting(done, total);
+                self.pending_sync_end = false;
+            }
+            SyncEvent::UnlockResult(Ok(check)) => {
+                self.key_unverified = check == KeyCheck::Unverified;
+                self.e2e_status = E2EState::Unlocked;
+                self.sync_status = SyncStatus::Synced; // Or idle
+                self.is_loading = false;
+                self.unlock_in_progress = false;
+                self.pending_sync_end = true; // Show synced momentarily
+
+                // Trigger sync once unlocked
+                let _ = self.sync_trigger.try_send(());
+                // Swap in notes that were only readable as ciphertext
+                self.refresh_notes(false).await?;
+
+                // If we were on PassphraseInput, go to List
+                if self.active_pane == ActivePane::PassphraseInput {
                     self.active_pane = ActivePane::List;
-                    self.note_to_delete = None;
+                    self.last_error = None;
                 }
-                _ => {}
-            },
+
+                if self.key_unverified {
+                    self.last_error = Some(UNVERIFIED_UNLOCK_NOTICE.to_string());
+                }
+            }
+            SyncEvent::UnlockResult(Err(e)) => {
+                let msg = e.message().to_string();
+                self.e2e_status = E2EState::Locked;
+                self.sync_status = SyncStatus::UnlockFailed(msg.clone());
+                self.is_loading = false;
+                self.unlock_in_progress = false;
+                self.last_error = Some(msg.clone());
+
+                if self.active_pane == ActivePane::PassphraseInput {
+                    self.show_unlock_error(&msg);
+                }
+            }
+            SyncEvent::Conflicts(count) => {
+                crate::logger::log(&format!("Sync left {} note(s) with conflicts", count));
+                self.sync_status = SyncStatus::Warning("Merge Conflict".to_string());
+                self.is_loading = false;
+                self.pending_sync_end = false;
+            }
+            SyncEvent::NoteError { id, msg } => {
+                crate::logger::log(&format!("Note {}: {}", short_id(&id, SHORT_ID_LEN), msg));
+                self.last_error = Some(msg);
+            }
+            SyncEvent::ClockSkew(skew) => self.clock_skew = Some(skew),
+            SyncEvent::RotationBlocked(reason) => self.rotation_blocked = Some(reason),
         }
-        Ok(false)
+        Ok(())
+    }
+
+    async fn handle_sync_status(&mut self, status: SyncStatus) -> Result<()> {
+        match status {
+            SyncStatus::Syncing => {
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.syncs_attempted += 1;
+                }
+                self.sync_start_time = Some(Instant::now());
+                self.sync_status = status;
+                self.pending_sync_end = false;
+            }
+            SyncStatus::Synced => {
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.syncs_succeeded += 1;
+                }
+                let should_update_editor = self.active_pane != ActivePane::Editor;
+                self.refresh_notes_under_editor(should_update_editor)
+                    .await?;
+                if self.rotation_in_progress {
+                    self.rotation_in_progress = self.repo.get_key_rotation().await?.is_some();
+                    if !self.rotation_in_progress {
+                        self.rotation_blocked = None;
+                    }
+                }
+                self.pending_sync_end = true;
+                self.sync_status = status;
+            }
+            SyncStatus::Unlocking => {
+                self.e2e_status = E2EState::Unlocking;
+                self.sync_status = status;
+            }
+            SyncStatus::Error(ref msg) => {
+                self.last_error = Some(msg.clone());
+                self.sync_status = status;
+                self.is_loading = false;
+            }
+            SyncStatus::SessionExpired => {
+                self.sync_status = status;
+                self.is_loading = false;
+                self.last_error = Some("Session expired".to_string());
+                // Don't pull the user out of an open note
+                if self.active_pane == ActivePane::List {
+                    self.active_pane = ActivePane::Login;
+                }
+            }
+            SyncStatus::PaymentRequired => {
+                self.sync_status = status;
+                self.is_loading = false;
+                self.e2e_status = E2EState::UpgradeRequired;
+                // Auto-open status dialog to prompt upgrade?
+                self.active_pane = ActivePane::StatusDialog;
+                // Pre-select "Upgrade to Pro" if possible (simple hack: set selection index)
+                // But list items are dynamic. Just opening dialog is good enough.
+            }
+            SyncStatus::Warning(_) => {
+                self.sync_status = status;
+                self.is_loading = false;
+                self.pending_sync_end = false;
+            }
+            _ => {
+                self.sync_status = status;
+                self.sync_start_time = None;
+                self.pending_sync_end = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn spawn_account_check(&mut self) {
+        if self.config.general.offline_mode || self.user_email.is_none() {
+            return;
+        }
+        self.is_loading = true;
+        let tx_clone = self.internal_tx.clone();
+        tokio::spawn(async move {
+            let client = APIClient::new();
+            match client.get_me().await {
+                Ok(me) => {
+                    let _ = tx_clone.send(Message::AccountCheckResult(Ok(me)));
+                }
+                Err(e) => {
+                    let _ = tx_clone.send(Message::AccountCheckResult(Err(e.to_string())));
+                }
+            }
+        });
+    }
+
+    fn start_subscription_poll(&mut self) {
+        let now = Instant::now();
+        self.subscription_poll = Some(SubscriptionPoll {
+            started: now,
+            next_check: now + subscription_poll_delay(Duration::ZERO),
+        });
+    }
+
+    async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        // Initial Account Check (Background)
+        self.spawn_account_check();
+
+        let mut input = InputReader::spawn(tx);
+        let result = self.run_loop(terminal, &mut rx, &mut input).await;
+        input.stop();
+        result
+    }
+
+    async fn run_loop<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        rx: &mut mpsc::UnboundedReceiver<(Event, Instant)>,
+        input: &mut InputReader,
+    ) -> Result<()> {
+        let mut poll_interval = time::interval(Duration::from_secs(2));
+        let mut spinner_interval = time::interval(Duration::from_millis(100));
+        let mut sub_poll_interval = time::interval(Duration::from_secs(3));
+        let mut session_interval = time::interval(Duration::from_secs(30));
+
+        let mut should_render = true;
+
+        loop {
+            if self.pending_sync_end {
+                let can_show = if let Some(start) = self.sync_start_time {
+                    start.elapsed() >= Duration::from_millis(700)
+                } else {
+                    true
+                };
+
+                if can_show {
+                    self.sync_status = SyncStatus::Synced;
+                    self.sync_start_time = None;
+                    self.pending_sync_end = false;
+                    should_render = true;
+                }
+            }
+
+            if let Some(until) = self.saved_feedback_until {
+                if Instant::now() >= until {
+                    self.saved_feedback_until = None;
+                    should_render = true;
+                }
+            }
+
+            if !self.db_failed && !self.repo.is_alive() {
+                self.enter_db_failure();
+                should_render = true;
+            }
+
+            if should_render {
+                let started = self.metrics.is_some().then(Instant::now);
+                terminal.draw(|f| {
+                    self.ui(f);
+                    palette::apply(f.buffer_mut(), self.color_mode, &self.config.theme);
+                })?;
+                if let (Some(metrics), Some(started)) = (&mut self.metrics, started) {
+                    metrics.record_draw(started.elapsed());
+                }
+                should_render = false;
+            }
+
+            let mut messages = Vec::new();
+            let mut input_closed = false;
+            tokio::select! {
+                event = rx.recv() => match event {
+                    None => input_closed = true,
+                    Some((event, read_at)) => {
+                        let process_event = |e| match e {
+                            Event::Key(key) => Some(Message::Key(key)),
+                            Event::Resize(w, h) => Some(Message::Resize(w, h)),
+                            Event::Mouse(m) if m.kind == MouseEventKind::Down(MouseButton::Left) => {
+                                Some(Message::Click(m.column, m.row))
+                            }
+                            Event::Paste(text) => Some(Message::Paste(text)),
+                            _ => None,
+                        };
+                        if let Some(m) = process_event(event) {
+                            messages.push((m, Some(read_at)));
+                        }
+                        while let Ok((e, read_at)) = rx.try_recv() {
+                            if let Some(m) = process_event(e) {
+                                messages.push((m, Some(read_at)));
+                            }
+                        }
+                    }
+                },
+                Some(msg) = self.internal_rx.recv() => messages.push((msg, None)),
+                Some(event) = self.event_rx.recv() => messages.push((Message::Sync(event), None)),
+                Some(()) = async { self.notes_dir_rx.as_mut().unwrap().recv().await }, if self.notes_dir_rx.is_some() => messages.push((Message::NotesDirChanged, None)),
+                _ = spinner_interval.tick() => messages.push((Message::Tick, None)),
+                _ = poll_interval.tick(), if self.polling_login => messages.push((Message::PollingTick, None)),
+                _ = sub_poll_interval.tick(), if self.subscription_poll.is_some() => messages.push((Message::SubscriptionCheck, None)),
+                _ = session_interval.tick() => messages.push((Message::PersistSession, None)),
+            }
+            if input_closed {
+                // The reader died (e.g. the pty went away). Try once more before giving up.
+                match input.restart() {
+                    Some(new_rx) => *rx = new_rx,
+                    None => {
+                        self.persist_session().await;
+                        return Err(anyhow::anyhow!(
+                            "Keyboard input stopped: the terminal is no longer delivering events"
+                        ));
+                    }
+                }
+            }
+
+            // Everything queued is applied before the next draw, so a burst of
+            // events costs one render
+            let mut messages = VecDeque::from(messages);
+            while let Some((mut msg, read_at)) = messages.pop_front() {
+                if let Message::Key(key) = &msg {
+                    if let Some(metrics) = &mut self.metrics {
+                        metrics.keys += 1;
+                    }
+                    if let Some(text) = self.take_typed_burst(key, read_at, &mut messages) {
+                        msg = Message::Paste(text);
+                    }
+                }
+                let quit = match self.update(msg).await {
+                    Ok(quit) => quit,
+                    Err(err) => {
+                        self.handle_update_error(err)?;
+                        false
+                    }
+                };
+                if quit {
+                    self.persist_session().await;
+                    if let Some(metrics) = &self.metrics {
+                        logger::log(&metrics.summary(self.repo.queue_high_water()));
+                    }
+                    return Ok(());
+                }
+                should_render = true;
+            }
+        }
+    }
+
+    /// A database that's stuck or gone shouldn't end the session halfway
+    /// through a keystroke: a timeout becomes a toast (and the status dialog's
+    /// last error), a dead actor the failure screen. Other errors are returned.
+    fn handle_update_error(&mut self, err: anyhow::Error) -> Result<()> {
+        match err.downcast_ref::<db::DbError>() {
+            Some(db::DbError::NotResponding(_)) => {
+                logger::log(&format!("{:#}", err));
+                self.editor_message = Some(format!(" {} — try again in a moment ", err));
+                self.last_error = Some(format!("{:#}", err));
+                Ok(())
+            }
+            Some(db::DbError::Shutdown) => {
+                self.enter_db_failure();
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    fn enter_db_failure(&mut self) {
+        if self.db_failed {
+            return;
+        }
+        logger::log("DB: actor is no longer running; showing the failure screen");
+        // The swap file is how unsaved edits survive into the next session
+        self.write_swap_file();
+        self.db_failed = true;
+    }
+
+    /// Terminals without bracketed paste deliver a paste as one key event per
+    /// character. A run of at least `TYPED_BURST_MIN` plain key presses, each
+    /// read within `TYPED_BURST_GAP` of the one before, in Insert mode is taken
+    /// out of `rest` and returned as text to insert in one edit, bypassing
+    /// auto-pairs like a real paste would. Keys queued up behind a slow update
+    /// keep their read times, so fast typing is never mistaken for a paste.
+    fn take_typed_burst(
+        &self,
+        first: &event::KeyEvent,
+        read_at: Option<Instant>,
+        rest: &mut VecDeque<(Message, Option<Instant>)>,
+    ) -> Option<String> {
+        // Held keys repeat, and a paste never does
+        fn typed_char(key: &event::KeyEvent) -> Option<char> {
+            if key.kind != KeyEventKind::Press
+                || !(key.modifiers - event::KeyModifiers::SHIFT).is_empty()
+            {
+                return None;
+            }
+            match key.code {
+                KeyCode::Char(c) => Some(c),
+                KeyCode::Enter => Some('\n'),
+                _ => None,
+            }
+        }
+
+        if self.active_pane != ActivePane::Editor
+            || self.mode != Mode::Insert
+            || self.editor_locked
+            || self.editor_split
+            // Recorded macros replay keys, so they have to see each one
+            || self.macro_recording.is_some()
+        {
+            return None;
+        }
+        let first = typed_char(first)?;
+        let mut last = read_at?;
+        let run = rest
+            .iter()
+            .take_while(|(m, at)| match (m, at) {
+                (Message::Key(k), Some(at))
+                    if typed_char(k).is_some()
+                        && at.saturating_duration_since(last) <= TYPED_BURST_GAP =>
+                {
+                    last = *at;
+                    true
+                }
+                _ => false,
+            })
+            .count();
+        if run + 1 < TYPED_BURST_MIN {
+            return None;
+        }
+        let mut text = String::with_capacity(run + 1);
+        text.push(first);
+        for (msg, _) in rest.drain(..run) {
+            if let Message::Key(key) = msg {
+                text.extend(typed_char(&key));
+            }
+        }
+        Some(text)
+    }
+
+    async fn run_editor_command(&mut self, command: &str) {
+        match command {
+            "" => {}
+            "w" => {
+                self.keep_buffer().await;
+            }
+            "raw" if !self.editor_split => {
+                self.editor_message = Some(" The note is already shown as is ".to_string());
+            }
+            "raw" => {
+                let content = self.stored_content().unwrap_or_default();
+                self.textarea = TextArea::from(content.lines());
+                self.editor_split = false;
+                self.setup_textarea();
+                self.editor_message =
+                    Some(" Loaded as is; long lines may make the editor slow ".to_string());
+            }
+            // A note pulled with invalid UTF-8 only syncs back once reviewed
+            "accept" => {
+                let Some(id) = self.current_note_id.clone() else {
+                    return;
+                };
+                self.editor_message = Some(match self.repo.accept_garbled(id).await {
+                    Ok(true) => {
+                        let _ = self.sync_trigger.try_send(());
+                        " Accepted; this note syncs again as shown ".to_string()
+                    }
+                    Ok(false) => " This note isn't held back from sync ".to_string(),
+                    Err(e) => {
+                        self.editor_beep = true;
+                        format!(" Accept failed: {} ", e)
+                    }
+                });
+            }
+            "diff" => {
+                let stored = self
+                    .current_note_id
+                    .as_ref()
+                    .and_then(|id| self.notes.iter().find(|n| &n.id == id))
+                    .map(|n| n.content.clone())
+                    .unwrap_or_default();
+                let buffer = if self.editor_split {
+                    stored.clone()
+                } else {
+                    self.textarea.lines().join("\n")
+                };
+                self.open_diff(" Diff: Saved → Buffer ".to_string(), stored, buffer);
+            }
+            // Both sides of the buffer's conflict markers
+            "diff conflict" => {
+                let buffer = self.textarea.lines().join("\n");
+                match merge::conflict_sides(&buffer) {
+                    Some((local, remote)) => {
+                        self.open_diff(" Conflict: Local → Remote ".to_string(), local, remote)
+                    }
+                    None => {
+                        self.editor_message = Some(" No conflict markers in this note ".to_string())
+                    }
+                }
+            }
+            // `:html` opens the buffer in the browser, `:html <path>` writes it there
+            timer if timer == "timer" || timer.starts_with("timer ") => {
+                self.timer_command(timer[5..].trim()).await;
+            }
+            html if html == "html" || html.starts_with("html ") => {
+                let out = html[4..].trim();
+                let out = (!out.is_empty()).then(|| PathBuf::from(out));
+                let id = self.current_note_id.as_deref().unwrap_or("new");
+                let content = match self.stored_content() {
+                    Some(stored) if self.editor_split => stored,
+                    _ => self.textarea.lines().join("\n"),
+                };
+                self.editor_message = Some(
+                    match html::export(&content, short_id(id, SHORT_ID_LEN), out.as_deref()) {
+                        Ok(path) => {
+                            let opened = match out {
+                                Some(_) => Ok(()),
+                                None => open_file_in_browser(
+                                    &path,
+                                    &self.config.general.browser_command,
+                                ),
+                            };
+                            match opened {
+                                Ok(()) => format!(" Exported to {} ", path.display()),
+                                Err(e) => {
+                                    self.editor_beep = true;
+                                    format!(
+                                        " Exported to {}, but couldn't open it: {} ",
+                                        path.display(),
+                                        e
+                                    )
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            self.editor_beep = true;
+                            format!(" Export failed: {} ", e)
+                        }
+                    },
+                );
+            }
+            _ => {
+                self.editor_message = Some(format!(" Not an editor command: {} ", command));
+                self.editor_beep = true;
+            }
+        }
+    }
+
+    /// Diffs `old` against `new` and opens the result in the diff popup. Large
+    /// notes are diffed on a blocking thread while the spinner runs.
+    fn open_diff(&mut self, title: String, old: String, new: String) {
+        if old.lines().count() + new.lines().count() <= DIFF_INLINE_LINES {
+            let lines = diff_text(&old, &new);
+            self.show_diff(title, lines);
+            return;
+        }
+
+        self.is_loading = true;
+        let tx = self.internal_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let lines = diff_text(&old, &new);
+            let _ = tx.send(Message::DiffReady(title, lines));
+        });
+    }
+
+    fn show_diff(&mut self, title: String, lines: Vec<Line<'static>>) {
+        self.diff_view = Some((title, lines));
+        self.diff_scroll = 0;
+        self.active_pane = ActivePane::Diff;
+    }
+
+    // SWAP_IDLE, or SCRATCH_SAVE_IDLE for the scratch note
+    fn swap_idle(&self) -> Duration {
+        if self.editing_scratch() {
+            SCRATCH_SAVE_IDLE
+        } else {
+            SWAP_IDLE
+        }
+    }
+
+    /// Writes the editor buffer to a swap file when it differs from the stored
+    /// note, or removes a stale one when it doesn't.
+    fn write_swap_file(&self) {
+        let content = self.textarea.lines().join("\n");
+        let stored = self
+            .current_note_id
+            .as_ref()
+            .and_then(|id| self.notes.iter().find(|n| &n.id == id))
+            .map(|n| n.content.as_str());
+        let result = if stored == Some(content.as_str()) || content.trim().is_empty() {
+            config::delete_swap_file(self.swap_owner())
+        } else {
+            config::write_swap_file(self.swap_owner(), &content)
+        };
+        if let Err(e) = result {
+            crate::logger::log(&format!("Failed to update swap file: {}", e));
+        }
+    }
+
+    fn swap_owner(&self) -> config::SwapOwner<'_> {
+        match &self.current_note_id {
+            Some(id) => config::SwapOwner::Note(id),
+            None => config::SwapOwner::Draft(&self.draft_id),
+        }
+    }
+
+    /// Restores (`restore`) or discards the swap file at the head of the
+    /// recovery queue, then moves on to the next one.
+    async fn resolve_recovery(&mut self, restore: bool) -> Result<()> {
+        if self.recovery_queue.is_empty() {
+            self.active_pane = ActivePane::List;
+            return Ok(());
+        }
+        let swap = self.recovery_queue.remove(0);
+
+        if restore {
+            // A note deleted since the crash comes back as a new note
+            let id = swap
+                .note_id
+                .clone()
+                .filter(|id| self.notes.iter().any(|n| &n.id == id));
+            let id = self
+                .repo
+                .save_note(
+                    id,
+                    swap.content.clone(),
+                    self.e2e_status.is_enabled(),
+                    self.e2e_status.is_locked(),
+                )
+                .await?;
+            crate::logger::log(&format!(
+                "Recovered unsaved changes into note {}",
+                short_id(&id, SHORT_ID_LEN)
+            ));
+            self.refresh_notes(false).await?;
+            self.select_note_by_id(&id).await?;
+            let _ = self.sync_trigger.try_send(());
+        }
+        if let Err(e) = std::fs::remove_file(&swap.path) {
+            crate::logger::log(&format!("Failed to remove swap file: {}", e));
+        }
+
+        if self.recovery_queue.is_empty() {
+            self.active_pane = ActivePane::List;
+        }
+        Ok(())
+    }
+
+    /// Digits typed in Normal mode build a count for the next command. A
+    /// leading `0` is still the line-start motion.
+    fn count_digit(&self, key: event::KeyEvent) -> Option<usize> {
+        if self.active_pane != ActivePane::Editor
+            || self.mode != Mode::Normal
+            || self.pending_key != PendingKey::None
+            || !key
+                .modifiers
+                .difference(event::KeyModifiers::SHIFT)
+                .is_empty()
+        {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char(c @ '1'..='9') => c.to_digit(10).map(|d| d as usize),
+            KeyCode::Char('0') if self.pending_count > 0 => Some(0),
+            _ => None,
+        }
+    }
+
+    fn replay_macro(&mut self, register: char, count: usize) {
+        let register = if register == '@' {
+            match self.last_macro {
+                Some(r) => r,
+                None => {
+                    self.editor_beep = true;
+                    return;
+                }
+            }
+        } else {
+            register
+        };
+        let Some(keys) = self.macros.get(&register).filter(|k| !k.is_empty()) else {
+            self.editor_beep = true;
+            return;
+        };
+        self.last_macro = Some(register);
+
+        // Queued ahead of anything pending so nested `@` runs inline
+        let replay: Vec<_> = std::iter::repeat_n(keys, count)
+            .flatten()
+            .copied()
+            .collect();
+        for key in replay.into_iter().rev() {
+            self.macro_queue.push_front(key);
+        }
+    }
+
+    /// Feeds queued macro keys through `handle_key_event`. Replay stops at the
+    /// first command that fails, when the editor is left, or after
+    /// `MACRO_KEY_LIMIT` keys.
+    async fn run_macro_queue(&mut self) -> Result<bool> {
+        let mut replayed = 0;
+        while let Some(key) = self.macro_queue.pop_front() {
+            self.editor_beep = false;
+            match self.handle_key_event(key).await {
+                Ok(Action::Quit) => return Ok(true),
+                Ok(Action::None) => {}
+                Err(e) => {
+                    crate::logger::log(&format!("Macro replay aborted: {}", e));
+                    self.editor_beep = true;
+                }
+            }
+            replayed += 1;
+            if self.editor_beep
+                || self.active_pane != ActivePane::Editor
+                || replayed >= MACRO_KEY_LIMIT
+            {
+                self.macro_queue.clear();
+            }
+        }
+        Ok(false)
+    }
+
+    /// Insert-mode pairing for brackets, quotes and emphasis markers. Returns
+    /// false when the key should go to the textarea unchanged.
+    fn auto_pair_input(&mut self, key: event::KeyEvent) -> bool {
+        let mut open = std::mem::take(&mut self.auto_pairs_open);
+        if key
+            .modifiers
+            .intersects(event::KeyModifiers::CONTROL | event::KeyModifiers::ALT)
+        {
+            return false;
+        }
+        let (row, col) = self.textarea.cursor();
+        let line: Vec<char> = self.textarea.lines()[row].chars().collect();
+        let prev = col.checked_sub(1).and_then(|i| line.get(i)).copied();
+        let next = line.get(col).copied();
+
+        match key.code {
+            KeyCode::Char(c) => {
+                // Step over a closer that is already there
+                if next == Some(c) && (auto_pair_close(c) == Some(c) || is_pair_closer(c)) {
+                    self.textarea.move_cursor(CursorMove::Forward);
+                    return true;
+                }
+                let Some(close) = auto_pair_close(c) else {
+                    return false;
+                };
+                if next.is_some_and(|n| n.is_alphanumeric()) {
+                    return false;
+                }
+                if close == c {
+                    // Apostrophes in words, and `*` starting a list item
+                    if prev.is_some_and(|p| p.is_alphanumeric()) {
+                        return false;
+                    }
+                    if c == '*' && line[..col].iter().all(|p| p.is_whitespace()) {
+                        return false;
+                    }
+                }
+                self.textarea.insert_char(c);
+                self.textarea.insert_char(close);
+                self.textarea.move_cursor(CursorMove::Back);
+                // Only pairs typed one inside the other stay removable
+                if open.last() != Some(&(row, col)) {
+                    open.clear();
+                }
+                open.push(self.textarea.cursor());
+                self.auto_pairs_open = open;
+                true
+            }
+            KeyCode::Backspace => match (prev, next) {
+                (Some(p), Some(n))
+                    if auto_pair_close(p) == Some(n) && open.last() == Some(&(row, col)) =>
+                {
+                    self.textarea.delete_next_char();
+                    self.textarea.delete_char();
+                    open.pop();
+                    self.auto_pairs_open = open;
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Adds the current note to the jumplist when it was opened by other means
+    /// than Ctrl+o / Ctrl+i. Like browser history, entries ahead of the current
+    /// one are discarded.
+    fn record_note_visit(&mut self) {
+        let Some(id) = self.current_note_id.clone() else {
+            return;
+        };
+        if self
+            .jumplist
+            .get(self.jump_index)
+            .is_some_and(|(note_id, _)| *note_id == id)
+        {
+            return;
+        }
+        self.jumplist.truncate(self.jump_index + 1);
+        self.jumplist.push((id, self.textarea.cursor()));
+        if self.jumplist.len() > JUMPLIST_MAX {
+            self.jumplist.remove(0);
+        }
+        self.jump_index = self.jumplist.len() - 1;
+    }
+
+    /// Reopens the previous (`back`) or next note in the jumplist, dropping
+    /// entries whose note has been deleted since.
+    async fn walk_jumplist(&mut self, back: bool) -> Result<()> {
+        // Remember where the cursor was in the note being left
+        if let (Some(id), Some(entry)) = (
+            self.current_note_id.as_ref(),
+            self.jumplist.get_mut(self.jump_index),
+        ) {
+            if entry.0 == *id {
+                entry.1 = self.textarea.cursor();
+            }
+        }
+
+        loop {
+            let next = if back {
+                self.jump_index.checked_sub(1)
+            } else {
+                Some(self.jump_index + 1).filter(|i| *i < self.jumplist.len())
+            };
+            let Some(next) = next else {
+                self.editor_beep = true;
+                return Ok(());
+            };
+
+            let (id, (row, col)) = self.jumplist[next].clone();
+            if self.notes.iter().any(|n| n.id == id) {
+                // The buffer is settled first, and the jump waits for the
+                // prompt if there is one
+                if self.active_pane == ActivePane::Editor
+                    && self.current_note_id.as_ref() != Some(&id)
+                    && !self.settle_buffer(NoteSwitch::Jump { back }).await
+                {
+                    return Ok(());
+                }
+                self.jump_index = next;
+                self.select_note_by_id(&id).await?;
+                self.textarea
+                    .move_cursor(CursorMove::Jump(row as u16, col as u16));
+                return Ok(());
+            }
+            self.jumplist.remove(next);
+            if back {
+                self.jump_index -= 1;
+            }
+        }
+    }
+
+    async fn select_note_by_id(&mut self, id: &str) -> Result<()> {
+        if !self.filtered_notes.iter().any(|n| n.id == id) {
+            // Hidden by the search filter
+            self.search_textarea = TextArea::default();
+            self.confirmed_query = None;
+            self.setup_search_textarea();
+            self.refresh_notes(false).await?;
+        }
+        if let Some(i) = self.filtered_notes.iter().position(|n| n.id == id) {
+            self.list_state.select(Some(i));
+            self.update_editor_from_selection();
+        }
+        Ok(())
+    }
+
+    /// Loads the current note's marks from kv_store when the note changed
+    /// since they were last loaded. Marks past the end of the note are dropped.
+    async fn ensure_marks_loaded(&mut self) {
+        if self.marks_note_id.as_ref() == Some(&self.current_note_id) {
+            return;
+        }
+        self.marks.clear();
+        self.jump_origin = None;
+        if let Some(id) = &self.current_note_id {
+            let key = format!("{}{}", MARKS_KEY_PREFIX, id);
+            if let Ok(Some(json)) = self.repo.get_kv(&key).await {
+                self.marks = serde_json::from_str(&json).unwrap_or_default();
+            }
+        }
+        let line_count = self.textarea.lines().len();
+        self.marks.retain(|_, (row, _)| *row < line_count);
+        self.marks_note_id = Some(self.current_note_id.clone());
+    }
+
+    async fn persist_marks(&mut self) {
+        let Some(Some(id)) = &self.marks_note_id else {
+            return;
+        };
+        let key = format!("{}{}", MARKS_KEY_PREFIX, id);
+        let result = if self.marks.is_empty() {
+            self.repo.delete_kv(&key).await
+        } else {
+            match serde_json::to_string(&self.marks) {
+                Ok(json) => self.repo.set_kv(&key, &json).await,
+                Err(e) => Err(e.into()),
+            }
+        };
+        if let Err(e) = result {
+            crate::logger::log(&format!("Failed to save marks: {}", e));
+        }
+    }
+
+    async fn set_mark(&mut self, mark: char) {
+        self.ensure_marks_loaded().await;
+        self.marks.insert(mark, self.textarea.cursor());
+        self.persist_marks().await;
+    }
+
+    /// `` `a `` / `'a` jumps to mark `a`; the backtick and apostrophe marks
+    /// return to where the last jump started.
+    async fn jump_to_mark(&mut self, mark: char, exact: bool) {
+        self.ensure_marks_loaded().await;
+        let target = match mark {
+            '`' | '\'' => self.jump_origin,
+            _ => self.marks.get(&mark).copied(),
+        };
+        let Some((row, col)) = target else {
+            self.editor_beep = true;
+            return;
+        };
+        if row >= self.textarea.lines().len() {
+            // The note shrank below the mark
+            if self.marks.remove(&mark).is_some() {
+                self.persist_marks().await;
+            }
+            self.editor_beep = true;
+            return;
+        }
+
+        self.jump_origin = Some(self.textarea.cursor());
+        let col = if exact { col } else { 0 };
+        self.textarea
+            .move_cursor(CursorMove::Jump(row as u16, col as u16));
+    }
+
+    fn jump_to_matching_bracket(&mut self) {
+        if let Some((row, col)) =
+            motion::bracket_jump(self.textarea.lines(), self.textarea.cursor())
+        {
+            self.textarea
+                .move_cursor(CursorMove::Jump(row as u16, col as u16));
+        } else {
+            self.editor_beep = true;
+        }
+    }
+
+    /// `dw`, `de` or `db`: cuts from the cursor to the motion's target on the
+    /// same line, onto the clipboard like `dd`.
+    fn delete_word_motion(&mut self, motion: char) {
+        let (row, col) = self.textarea.cursor();
+        let line = &self.textarea.lines()[row];
+        let (start, end) = match motion {
+            'w' => (col, motion::word_forward_end(line, col)),
+            'e' => (col, motion::word_end_end(line, col)),
+            _ => (motion::word_back_start(line, col), col),
+        };
+        if start >= end {
+            self.editor_beep = true;
+            return;
+        }
+        self.cut_in_line(row, start, end);
+    }
+
+    /// `f`/`F`/`t`/`T` (`kind`) to `target` on the cursor's line, or with
+    /// `delete` the text from the cursor through where it lands, like `dw`.
+    /// Stays put when `target` isn't on the line.
+    fn find_char(&mut self, kind: char, target: char, delete: bool, repeat: bool) {
+        let (row, col) = self.textarea.cursor();
+        let Some(to) = motion::find_char(&self.textarea.lines()[row], col, kind, target, repeat)
+        else {
+            self.editor_beep = true;
+            return;
+        };
+        if !delete {
+            self.textarea
+                .move_cursor(CursorMove::Jump(row as u16, to as u16));
+        } else if to >= col {
+            self.cut_in_line(row, col, to + 1);
+        } else {
+            self.cut_in_line(row, to, col);
+        }
+    }
+
+    /// `;` repeats the last find, `,` (`reverse`) repeats it the other way.
+    fn repeat_find(&mut self, reverse: bool) {
+        let Some((kind, target)) = self.last_find else {
+            return;
+        };
+        let kind = match (kind, reverse) {
+            (_, false) => kind,
+            ('f', true) => 'F',
+            ('F', true) => 'f',
+            ('t', true) => 'T',
+            _ => 't',
+        };
+        self.find_char(kind, target, false, true);
+    }
+
+    /// `cw`, `ce`, `ciw` (`i`) or `c$`/`C`: cuts like `delete_word_motion`,
+    /// then Insert. An empty range, as at the end of a line, only enters
+    /// Insert.
+    fn change_motion(&mut self, motion: char) {
+        let (row, col) = self.textarea.cursor();
+        let line = &self.textarea.lines()[row];
+        let (start, end) = match motion {
+            'w' => (col, motion::change_word_end(line, col)),
+            'e' => (col, motion::word_end_end(line, col)),
+            'i' => motion::word_under(line, col),
+            _ => (col, line.chars().count()),
+        };
+        if start < end {
+            self.cut_in_line(row, start, end);
+        }
+        self.mode = Mode::Insert;
+    }
+
+    /// `x` (`forward`) or `X`: deletes up to `count` characters on the
+    /// cursor's line. Lines are never joined, and the yank and the clipboard
+    /// are left alone. Like Vim, `x` on the last character lands on the new
+    /// last one, so repeating it keeps deleting.
+    fn delete_chars(&mut self, forward: bool, count: usize) {
+        let (row, col) = self.textarea.cursor();
+        let len = self.textarea.lines()[row].chars().count();
+        let n = if forward {
+            count.min(len.saturating_sub(col))
+        } else {
+            count.min(col)
+        };
+        if n == 0 {
+            self.editor_beep = true;
+            return;
+        }
+        let yank = self.textarea.yank_text();
+        if !forward {
+            self.textarea
+                .move_cursor(CursorMove::Jump(row as u16, (col - n) as u16));
+        }
+        self.textarea.delete_str(n);
+        self.textarea.set_yank_text(yank);
+        let (_, col) = self.textarea.cursor();
+        if forward && col > 0 && col == len - n {
+            self.textarea.move_cursor(CursorMove::Back);
+        }
+    }
+
+    /// Cuts `start..end` of `row` onto the clipboard, like `dd`.
+    fn cut_in_line(&mut self, row: usize, start: usize, end: usize) {
+        self.textarea
+            .move_cursor(CursorMove::Jump(row as u16, start as u16));
+        self.textarea.start_selection();
+        self.textarea
+            .move_cursor(CursorMove::Jump(row as u16, end as u16));
+        self.textarea.cut();
+        let text = self.textarea.yank_text();
+        self.copy_to_clipboard(&text);
+    }
+
+    fn visual_block(&self) -> Option<block::BlockSelection> {
+        self.visual_block_anchor.map(|anchor| {
+            block::BlockSelection::new(anchor, self.textarea.cursor(), self.visual_block_to_eol)
+        })
+    }
+
+    fn exit_visual_block(&mut self) {
+        self.mode = Mode::Normal;
+        self.visual_block_anchor = None;
+        self.visual_block_to_eol = false;
+        self.pending_key = PendingKey::None;
+    }
+
+    fn yank_block(&mut self, block: &block::BlockSelection) {
+        let pieces = block::yank(self.textarea.lines(), block);
+        self.copy_to_clipboard(&pieces.join("\n"));
+        self.block_register = Some(pieces);
+    }
+
+    /// Swaps rows `top..=bottom` for `rows` by replacing a selection, so the
+    /// change stays in the textarea's undo history.
+    fn replace_rows(&mut self, top: usize, bottom: usize, rows: Vec<String>) {
+        let before = self.textarea.lines().to_vec();
+        let old_empty = top == bottom && before[top].is_empty();
+        let new_text = rows.join("\n");
+
+        self.textarea.cancel_selection();
+        self.textarea.move_cursor(CursorMove::Jump(top as u16, 0));
+        self.textarea.start_selection();
+        self.textarea
+            .move_cursor(CursorMove::Jump(bottom as u16, u16::MAX));
+        self.textarea.insert_str(&new_text);
+
+        self.block_undo = Some(BlockUndo {
+            before,
+            after: self.textarea.lines().to_vec(),
+            steps: usize::from(!old_empty) + usize::from(!new_text.is_empty()),
+        });
+    }
+
+    /// `o` (`below`) or `O`: opens an empty line and enters Insert.
+    fn open_line(&mut self, below: bool) {
+        self.open_line_before = Some(self.textarea.lines().to_vec());
+        if below {
+            self.textarea.move_cursor(CursorMove::End);
+            self.textarea.insert_newline();
+        } else {
+            self.textarea.move_cursor(CursorMove::Head);
+            self.textarea.insert_newline();
+            self.textarea.move_cursor(CursorMove::Up);
+        }
+        self.mode = Mode::Insert;
+    }
+
+    /// Folds the new line and everything typed after `o`/`O` into one undo
+    /// step: steps back to the lines before it, then redoes the change as a
+    /// single row replacement. If the textarea's history doesn't reach that
+    /// far, the steps are put back and stay separate.
+    fn finish_open_line(&mut self) {
+        let Some(before) = self.open_line_before.take() else {
+            return;
+        };
+        let after = self.textarea.lines().to_vec();
+        let cursor = self.textarea.cursor();
+        let mut undone = 0;
+        while self.textarea.lines() != before.as_slice() && self.textarea.undo() {
+            undone += 1;
+        }
+        if self.textarea.lines() != before.as_slice() {
+            for _ in 0..undone {
+                self.textarea.redo();
+            }
+            return;
+        }
+
+        // Keep at least one row on each side, so the replacement has a range
+        let shorter = before.len().min(after.len());
+        let prefix = before
+            .iter()
+            .zip(&after)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(shorter - 1);
+        let suffix = before
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(shorter - 1 - prefix);
+        self.replace_rows(
+            prefix,
+            before.len() - 1 - suffix,
+            after[prefix..after.len() - suffix].to_vec(),
+        );
+        self.textarea
+            .move_cursor(CursorMove::Jump(cursor.0 as u16, cursor.1 as u16));
+    }
+
+    fn undo_edit(&mut self) {
+        match &self.block_undo {
+            Some(u) if self.textarea.lines() == u.after.as_slice() => {
+                for _ in 0..u.steps {
+                    self.textarea.undo();
+                }
+            }
+            _ => {
+                self.textarea.undo();
+            }
+        }
+    }
+
+    fn redo_edit(&mut self) {
+        match &self.block_undo {
+            Some(u) if self.textarea.lines() == u.before.as_slice() => {
+                for _ in 0..u.steps {
+                    self.textarea.redo();
+                }
+            }
+            _ => {
+                self.textarea.redo();
+            }
+        }
+    }
+
+    fn paste_block(&mut self, pieces: &[String]) {
+        let (row, col) = self.textarea.cursor();
+        let last = self.textarea.lines().len() - 1;
+        let rows = block::paste(self.textarea.lines(), row, col, pieces);
+        let bottom = (row + pieces.len() - 1).min(last);
+        self.replace_rows(row, bottom, rows);
+        self.textarea
+            .move_cursor(CursorMove::Jump(row as u16, col as u16));
+    }
+
+    fn begin_block_insert(&mut self, append: bool) {
+        let Some(block) = self.visual_block() else {
+            return;
+        };
+        self.exit_visual_block();
+
+        let edge = if !append {
+            block::InsertEdge::Left(block.left)
+        } else if block.to_eol {
+            block::InsertEdge::LineEnd
+        } else {
+            block::InsertEdge::Right(block.right + 1)
+        };
+
+        let len = self.textarea.lines()[block.top].chars().count();
+        let col = match edge {
+            block::InsertEdge::Left(c) => c.min(len),
+            block::InsertEdge::Right(c) => {
+                if len < c {
+                    self.textarea
+                        .move_cursor(CursorMove::Jump(block.top as u16, len as u16));
+                    self.textarea.insert_str(" ".repeat(c - len));
+                }
+                c
+            }
+            block::InsertEdge::LineEnd => len,
+        };
+        self.textarea
+            .move_cursor(CursorMove::Jump(block.top as u16, col as u16));
+
+        self.block_insert = Some(BlockInsert {
+            top: block.top,
+            bottom: block.bottom,
+            col,
+            edge,
+            line_count: self.textarea.lines().len(),
+        });
+        self.mode = Mode::Insert;
+    }
+
+    /// Repeats the text typed on the block's top row on every other row. Like
+    /// vim, nothing is repeated if the insert left the row or added lines.
+    fn finish_block_insert(&mut self) {
+        let Some(insert) = self.block_insert.take() else {
+            return;
+        };
+        let (row, col) = self.textarea.cursor();
+        if insert.bottom == insert.top
+            || row != insert.top
+            || col <= insert.col
+            || self.textarea.lines().len() != insert.line_count
+        {
+            return;
+        }
+
+        let typed: String = self.textarea.lines()[row]
+            .chars()
+            .skip(insert.col)
+            .take(col - insert.col)
+            .collect();
+        let rows = block::insert(
+            &self.textarea.lines()[insert.top + 1..=insert.bottom],
+            insert.edge,
+            &typed,
+        );
+        self.replace_rows(insert.top + 1, insert.bottom, rows);
+        self.textarea
+            .move_cursor(CursorMove::Jump(insert.top as u16, insert.col as u16));
+    }
+
+    /// Returns false when the text went out over OSC 52 and is larger than
+    /// terminals reliably accept.
+    fn copy_to_clipboard(&mut self, text: &str) -> bool {
+        if let Some(cb) = &mut self.clipboard {
+            if cb.set_text(text.to_string()).is_ok() {
+                return true;
+            }
+        }
+        // No system clipboard (e.g. over SSH): ask the terminal to set it
+        let encoded = STANDARD.encode(text);
+        let mut out = io::stdout();
+        let _ = write!(out, "\x1b]52;c;{}\x07", encoded);
+        let _ = out.flush();
+        encoded.len() <= OSC52_MAX_ENCODED
+    }
+
+    fn overdue_count(&self) -> usize {
+        let now = Utc::now().to_rfc3339();
+        self.reminders.values().filter(|due| **due < now).count()
+    }
+
+    /// `y` in the status dialog copies the whole last error, untruncated.
+    fn yank_last_error(&mut self) {
+        let Some(error) = self.last_error.clone() else {
+            return;
+        };
+        self.editor_message = Some(if self.copy_to_clipboard(&error) {
+            " Copied last error ".to_string()
+        } else {
+            " Copied last error, over the OSC 52 size limit: the terminal may truncate it "
+                .to_string()
+        });
+    }
+
+    /// `Y` in the list copies the selected note, `yy` only its title line.
+    fn yank_selected_note(&mut self, title_only: bool) {
+        let Some(note) = self
+            .list_state
+            .selected()
+            .and_then(|i| self.filtered_notes.get(i))
+        else {
+            return;
+        };
+        let (what, text) = if title_only {
+            (
+                "title",
+                note.content.lines().next().unwrap_or("").to_string(),
+            )
+        } else {
+            ("note", note.content.clone())
+        };
+        self.textarea.set_yank_text(text.clone());
+        let bytes = text.len();
+        self.editor_message = Some(if self.copy_to_clipboard(&text) {
+            format!(" Copied {} ({} bytes) ", what, bytes)
+        } else {
+            format!(
+                " Copied {} ({} bytes), over the OSC 52 size limit: the terminal may truncate it ",
+                what, bytes
+            )
+        });
     }
 
-    async fn apply_account_info(&mut self, me: sync::AuthMeResponse) -> Result<()> {
-        self.user_plan = Some(me.plan.clone());
-        self.user_subscription_status = Some(me.subscription_status.clone());
-        self.user_subscription_end_date = me.subscription_end_date.clone();
-        let is_eligible = me.plan == "pro" || me.plan == "dev";
-        if is_eligible {
-            if let Some(salt) = me.encryption_salt {
-                self.repo.set_salt(&salt).await?;
-
-                let is_unlocked = {
-                    let guard = self.crypto_key.lock().unwrap();
-                    guard.is_some()
-                };
+    fn get_from_clipboard(&mut self) -> Option<String> {
+        self.clipboard.as_mut().and_then(|cb| cb.get_text().ok())
+    }
 
-                if is_unlocked {
-                    self.e2e_status = "Unlocked".to_string();
-                    crate::logger::log("apply_account_info: E2E already unlocked");
-                    let _ = self.sync_trigger.try_send(());
+    fn move_list_selection(&mut self, delta: i32) {
+        self.saved_feedback_until = None;
+        if self.filtered_notes.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                let next = i as i32 + delta;
+                if next < 0 {
+                    0
+                } else if next >= self.filtered_notes.len() as i32 {
+                    self.filtered_notes.len() - 1
                 } else {
-                    self.e2e_status = "Locked".to_string();
-                    if let Ok(Some(pass)) = config::get_passphrase() {
-                        // Background unlock
-                        let repo = self.repo.clone();
-                        let client = APIClient::new();
-                        let key_store = self.crypto_key.clone();
-                        let tx = self.status_tx.clone();
-                        let pass_clone = pass.clone();
-
-                        tokio::spawn(async move {
-                            let _ = tx.send(SyncStatus::Unlocking).await;
-                            match unlock_process(repo, client, pass_clone, key_store).await {
-                                Ok(true) => {
-                                    let _ = tx.send(SyncStatus::Unlocked).await;
-                                }
-                                Ok(false) => {
-                                    let _ = tx.send(SyncStatus::Error).await;
-                                }
-                                Err(_) => {
-                                    let _ = tx.send(SyncStatus::Error).await;
-                                }
-                            }
-                        });
-                    } else {
-                        self.active_pane = ActivePane::PassphraseInput;
-                        self.passphrase_textarea = TextArea::default();
-                        self.passphrase_textarea.set_mask_char('•');
-                        self.setup_unlock_passphrase_textarea_style();
-                    }
-                }
-            } else {
-                // Eligible but no salt -> Setup needed
-                self.e2e_status = "Setup Required".to_string();
-                self.active_pane = ActivePane::E2ESetup;
-            }
-        } else {
-            self.e2e_status = "Disabled".to_string();
-            if self.repo.get_salt().await.unwrap_or(None).is_some() {
-                crate::logger::log(
-                    "apply_account_info: Free plan detected but local salt exists. Cleaning up.",
-                );
-                let _ = self.repo.delete_salt().await;
-                let _ = config::delete_passphrase();
-                {
-                    let mut guard = self.crypto_key.lock().unwrap();
-                    *guard = None;
+                    next as usize
                 }
             }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+        self.update_editor_from_selection();
+    }
+
+    fn ui(&mut self, f: &mut Frame) {
+        if self.db_failed {
+            self.render_db_failure(f, f.area());
+            return;
         }
-        Ok(())
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(8),
+                Constraint::Min(1),
+                Constraint::Length(2),
+            ])
+            .split(f.area());
+        let main_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+            .split(chunks[1]);
+
+        self.render_header(f, chunks[0]);
+        self.render_list(f, main_chunks[0]);
+        self.render_editor(f, main_chunks[1]);
+        self.render_dialog(f, chunks[1]);
+        self.render_footer(f, chunks[2]);
     }
 
-    async fn update(&mut self, msg: Message) -> Result<bool> {
-        match msg {
-            Message::Key(key) => {
-                if matches!(key.kind, KeyEventKind::Press | KeyEventKind::Repeat) {
-                    return self.handle_key_event(key).await;
-                }
-            }
-            Message::Resize(_w, _h) => {}
-            Message::Paste(text) => {
-                if self.active_pane == ActivePane::Editor {
-                    let text = text.replace('\r', "");
-                    self.textarea.insert_str(text);
-                }
-            }
-            Message::SyncStatusUpdate(status) => {
-                match status {
-                    SyncStatus::Syncing => {
-                        self.sync_start_time = Some(Instant::now());
-                        self.sync_status = status;
-                        self.pending_sync_end = false;
-                    }
-                    SyncStatus::Synced => {
-                        let should_update_editor = self.active_pane != ActivePane::Editor;
-                        self.refresh_notes(should_update_editor).await?;
-                        self.pending_sync_end = true;
-                        self.sync_status = status;
-                    }
-                    SyncStatus::Unlocking => {
-                        self.e2e_status = "Unlocking...".to_string();
-                        self.sync_status = status;
-                    }
-                    SyncStatus::Unlocked => {
-                        self.e2e_status = "Unlocked".to_string();
-                        self.sync_status = SyncStatus::Synced; // Or idle
-                        self.is_loading = false;
-                        self.pending_sync_end = true; // Show synced momentarily
+    /// The list highlight and footer flash green for a moment after a save.
+    fn showing_saved_feedback(&self) -> bool {
+        self.saved_feedback_until
+            .is_some_and(|t| Instant::now() < t)
+    }
 
-                        // Trigger sync once unlocked
-                        let _ = self.sync_trigger.try_send(());
+    /// The daily backup has been running long enough to show in the footer.
+    fn showing_backup(&self) -> bool {
+        self.backup_task
+            .as_ref()
+            .is_some_and(|(_, started)| started.elapsed() >= BACKUP_SPINNER_DELAY)
+    }
 
-                        // If we were on PassphraseInput, go to List
-                        if self.active_pane == ActivePane::PassphraseInput {
-                            self.active_pane = ActivePane::List;
-                            self.last_error = None;
-                        }
-                    }
-                    SyncStatus::Error => {
-                        self.sync_status = status;
-                        self.is_loading = false;
+    fn load_session_info(&mut self) {
+        let token_data = config::get_token_data();
+        self.token_times = config::get_token_times(&token_data.id_token).ok();
+        self.has_refresh_token = !token_data.refresh_token.is_empty();
+        self.token_source = Some(token_data.source);
+    }
 
-                        if self.active_pane == ActivePane::PassphraseInput {
-                            // Assume error means invalid passphrase here if we were inputting it
-                            self.passphrase_textarea = TextArea::default();
-                            self.passphrase_textarea.set_mask_char('•');
-                            self.passphrase_textarea.set_block(
-                                Block::default()
-                                    .borders(Borders::ALL)
-                                    .title(" Invalid Passphrase! Try Again ")
-                                    .border_style(
-                                        Style::default().fg(self.config.theme.sync_error),
-                                    ),
-                            );
-                        }
-                    }
-                    SyncStatus::PaymentRequired => {
-                        self.sync_status = status;
-                        self.is_loading = false;
-                        self.e2e_status = "Upgrade Required".to_string();
-                        // Auto-open status dialog to prompt upgrade?
-                        self.active_pane = ActivePane::StatusDialog;
-                        // Pre-select "Upgrade to Pro" if possible (simple hack: set selection index)
-                        // But list items are dynamic. Just opening dialog is good enough.
-                    }
-                    SyncStatus::Warning(_) => {
-                        self.sync_status = status;
-                        self.is_loading = false;
-                        self.pending_sync_end = false;
-                    }
-                    _ => {
-                        self.sync_status = status;
-                        self.sync_start_time = None;
-                        self.pending_sync_end = false;
-                    }
-                }
+    fn get_status_menu_items(&self) -> Vec<&str> {
+        let mut items = vec!["Sync Now"];
+
+        if self.user_email.is_some() {
+            if self
+                .user_plan
+                .as_ref()
+                .is_some_and(|p| p.is_sync_eligible())
+            {
+                items.push("Manage Subscription");
+            } else if self.user_plan == Some(Plan::Free) {
+                items.push("Select Plan");
             }
-            Message::Tick => {
-                self.spinner_index = (self.spinner_index + 1) % 4;
+            if self.e2e_status.is_unlocked() && !self.rotation_in_progress {
+                items.push("Change Passphrase");
+                items.push("Re-upload All Notes Encrypted");
+            } else if self.e2e_status.is_unlocked() {
+                items.push("Cancel Passphrase Change");
             }
-            Message::PollingTick => {
-                if self.polling_login {
-                    let _ = self.poll_login().await;
-                }
+            if self.sync_status == SyncStatus::SessionExpired {
+                items.push("Login");
+            } else {
+                items.push("Refresh Session");
             }
-            Message::SubscriptionCheck => {
-                if self.polling_subscription {
-                    if let Ok(me) = self.api_client.get_me().await {
-                        let new_plan = me.plan.clone();
-                        let current_plan = self.user_plan.clone().unwrap_or("free".to_string());
-
-                        let _ = self.apply_account_info(me).await;
+            items.push("Logout");
+        } else {
+            items.push("Login");
+        }
 
-                        let is_paid_now = new_plan == "pro" || new_plan == "dev";
-                        let was_free = current_plan == "free";
+        match &self.focus_timer {
+            None => items.push("Start Focus Timer"),
+            Some(t) if t.is_paused() => items.push("Resume Focus Timer"),
+            Some(_) => items.push("Pause Focus Timer"),
+        }
+        if self.focus_timer.is_some() {
+            items.push("Cancel Focus Timer");
+        }
+        items.push("Audit Log");
+        items.push("Clear All Data");
+        items.push("Close");
+        items
+    }
 
-                        if was_free && is_paid_now {
-                            crate::logger::log("Subscription upgrade detected!");
-                            self.polling_subscription = false;
-                        }
-                    }
-                }
+    async fn perform_clear_all_data(&mut self) -> Result<()> {
+        let token = config::get_token();
+        if !token.is_empty() {
+            // Logged in: Try to clear remote first
+            if let Err(e) = self.api_client.reset_remote().await {
+                logger::log(&format!("Failed to clear remote data: {}", e));
+            } else {
+                logger::log("Remote data cleared successfully.");
+                logger::audit(logger::AuditEvent::ResetRemote {
+                    via: logger::Via::Tui,
+                });
             }
-            Message::AccountCheckResult(result) => {
-                self.is_loading = false;
-                match result {
-                    Ok(me) => {
-                        let _ = self.apply_account_info(me).await;
-                    }
-                    Err(e) => {
-                        let msg = format!("AccountCheck: Failed to get user info: {}", e);
-                        crate::logger::log(&msg);
-                        self.last_error = Some(msg);
-                    }
-                }
+        }
+
+        // Clear local data
+        self.repo.clear_all_data().await?;
+        logger::audit(logger::AuditEvent::ClearLocalData {
+            via: logger::Via::Tui,
+        });
+        self.refresh_notes(true).await?;
+
+        // Restore account state (re-fetch salt, check plan, etc.)
+        if !self.config.general.offline_mode && self.user_email.is_some() {
+            if let Ok(me) = self.api_client.get_me().await {
+                self.apply_account_info(me).await?;
+            } else {
+                logger::log("Failed to refresh account info after clear.");
             }
         }
-        Ok(false)
+
+        logger::log("All data cleared.");
+        Ok(())
     }
 
-    async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
-        let mut poll_interval = time::interval(Duration::from_secs(2));
-        let mut spinner_interval = time::interval(Duration::from_millis(100));
-        let mut sub_poll_interval = time::interval(Duration::from_secs(3));
+    async fn perform_logout(&mut self) -> Result<()> {
+        let _ = config::delete_token_data();
+        let _ = config::delete_passphrase();
+        logger::audit(logger::AuditEvent::Logout {
+            via: logger::Via::Tui,
+        });
 
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        let (internal_tx, mut internal_rx) = mpsc::unbounded_channel();
+        self.user_email = None;
+        self.token_source = None;
+        self.token_times = None;
+        self.has_refresh_token = false;
+        self.user_plan = None;
+        self.account_cached_at = None;
+        let _ = self.repo.delete_account_cache().await;
+        self.e2e_status = E2EState::Disabled;
+        self.sync_status = SyncStatus::Offline;
 
-        // Initial Account Check (Background)
-        if !self.config.general.offline_mode && self.user_email.is_some() {
-            self.is_loading = true;
-            let tx_clone = internal_tx.clone();
-            tokio::spawn(async move {
-                let client = APIClient::new();
-                match client.get_me().await {
-                    Ok(me) => {
-                        let _ = tx_clone.send(Message::AccountCheckResult(Ok(me)));
-                    }
-                    Err(e) => {
-                        let _ = tx_clone.send(Message::AccountCheckResult(Err(e.to_string())));
-                    }
-                }
-            });
+        // Clear cached keys
+        {
+            let mut guard = self.crypto_key.lock().unwrap();
+            *guard = None;
         }
 
-        let _input_handle = std::thread::spawn(move || {
-            while let Ok(evt) = event::read() {
-                if tx.send(evt).is_err() {
-                    break;
-                }
-            }
-        });
+        // Clear sensitive UI fields
+        self.passphrase_textarea = TextArea::default();
+        self.passphrase_textarea.set_mask_char('•');
+        self.setup_passphrase_textarea_style();
+        self.passphrase_confirm_textarea = TextArea::default();
+        self.passphrase_confirm_textarea.set_mask_char('•');
+        self.setup_confirm_textarea_style();
 
-        let mut should_render = true;
+        // Refresh notes as guest/offline user
+        self.refresh_notes(true).await?;
+        Ok(())
+    }
+}
 
-        loop {
-            if self.pending_sync_end {
-                let can_show = if let Some(start) = self.sync_start_time {
-                    start.elapsed() >= Duration::from_millis(700)
-                } else {
-                    true
-                };
+// Checks back off as the wait drags on: 3s for the first minute, then 10s,
+// then 30s after five minutes
+fn subscription_poll_delay(elapsed: Duration) -> Duration {
+    if elapsed < Duration::from_secs(60) {
+        Duration::from_secs(3)
+    } else if elapsed < Duration::from_secs(300) {
+        Duration::from_secs(10)
+    } else {
+        Duration::from_secs(30)
+    }
+}
 
-                if can_show {
-                    self.sync_status = SyncStatus::Synced;
-                    self.sync_start_time = None;
-                    self.pending_sync_end = false;
-                    should_render = true;
-                }
-            }
+/// "3h behind" or "5m ahead": the local clock against the server's, or None
+/// when it's within `CLOCK_SKEW_WARN_SECS`.
+fn clock_skew_text(skew: Option<i64>) -> Option<String> {
+    let skew = skew.filter(|s| s.abs() > sync::CLOCK_SKEW_WARN_SECS)?;
+    Some(format!(
+        "{} {}",
+        format_duration_short(skew.abs()),
+        if skew > 0 { "behind" } else { "ahead" }
+    ))
+}
 
-            if let Some(until) = self.saved_feedback_until {
-                if Instant::now() >= until {
-                    self.saved_feedback_until = None;
-                    should_render = true;
-                }
-            }
+// Largest whole unit only: "45s", "12m", "3h", "2d"
+fn format_duration_short(secs: i64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+// Sync/encryption states selectable with `is:` tokens in the search query
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NoteState {
+    Unsynced,
+    Encrypted,
+    Conflict,
+    Locked,
+}
+
+impl NoteState {
+    const ALL: [NoteState; 4] = [
+        NoteState::Unsynced,
+        NoteState::Encrypted,
+        NoteState::Conflict,
+        NoteState::Locked,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            NoteState::Unsynced => "unsynced",
+            NoteState::Encrypted => "encrypted",
+            NoteState::Conflict => "conflict",
+            NoteState::Locked => "locked",
+        }
+    }
+
+    fn matches(&self, note: &Note) -> bool {
+        match self {
+            NoteState::Unsynced => note.is_synced == 0,
+            NoteState::Encrypted => note.is_encrypted != 0,
+            NoteState::Conflict => note.has_conflict != 0,
+            NoteState::Locked => note.modified_while_locked != 0,
+        }
+    }
+}
 
-            if should_render {
-                terminal.draw(|f| self.ui(f))?;
-                should_render = false;
-            }
+// Color labels, cycled with `c` in the list and matched with `label:` tokens
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Label {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+}
 
-            let mut messages = Vec::new();
-            tokio::select! {
-                Some(event) = rx.recv() => {
-                    let process_event = |e| match e {
-                        Event::Key(key) => Some(Message::Key(key)),
-                        Event::Resize(w, h) => Some(Message::Resize(w, h)),
-                        Event::Paste(text) => Some(Message::Paste(text)),
-                        _ => None,
-                    };
-                    if let Some(m) = process_event(event) {
-                        messages.push(m);
-                    }
-                    while let Ok(e) = rx.try_recv() {
-                        if let Some(m) = process_event(e) {
-                            messages.push(m);
-                        }
-                    }
-                }
-                Some(msg) = internal_rx.recv() => messages.push(msg),
-                Some(status) = self.status_rx.recv() => messages.push(Message::SyncStatusUpdate(status)),
-                _ = spinner_interval.tick() => messages.push(Message::Tick),
-                _ = poll_interval.tick(), if self.polling_login => messages.push(Message::PollingTick),
-                _ = sub_poll_interval.tick(), if self.polling_subscription => messages.push(Message::SubscriptionCheck),
-            }
+impl Label {
+    const ALL: [Label; 4] = [Label::Red, Label::Yellow, Label::Green, Label::Blue];
 
-            for msg in messages {
-                if self.update(msg).await? {
-                    return Ok(());
-                }
-                should_render = true;
-            }
+    fn as_str(&self) -> &'static str {
+        match self {
+            Label::Red => "red",
+            Label::Yellow => "yellow",
+            Label::Green => "green",
+            Label::Blue => "blue",
         }
     }
 
-    fn copy_to_clipboard(&mut self, text: &str) {
-        if let Some(cb) = &mut self.clipboard {
-            let _ = cb.set_text(text.to_string());
+    fn parse(name: &str) -> Option<Self> {
+        Label::ALL
+            .into_iter()
+            .find(|l| l.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// The label after `label` in the `c` cycle, which passes through none.
+    fn next(label: Option<Label>) -> Option<Label> {
+        match label {
+            None => Some(Label::ALL[0]),
+            Some(l) => {
+                let i = Label::ALL.iter().position(|x| *x == l).unwrap_or(0);
+                Label::ALL.get(i + 1).copied()
+            }
         }
     }
 
-    fn get_from_clipboard(&mut self) -> Option<String> {
-        self.clipboard.as_mut().and_then(|cb| cb.get_text().ok())
+    fn of(note: &Note) -> Option<Label> {
+        note.label.as_deref().and_then(Label::parse)
     }
 
-    fn move_list_selection(&mut self, delta: i32) {
-        self.saved_feedback_until = None;
-        if self.filtered_notes.is_empty() {
-            return;
+    fn color(&self, theme: &config::ThemeConfig) -> Color {
+        match self {
+            Label::Red => theme.label_red,
+            Label::Yellow => theme.label_yellow,
+            Label::Green => theme.label_green,
+            Label::Blue => theme.label_blue,
         }
-        let i = match self.list_state.selected() {
-            Some(i) => {
-                let next = i as i32 + delta;
-                if next < 0 {
-                    0
-                } else if next >= self.filtered_notes.len() as i32 {
-                    self.filtered_notes.len() - 1
-                } else {
-                    next as usize
-                }
-            }
-            None => 0,
-        };
-        self.list_state.select(Some(i));
-        self.update_editor_from_selection();
     }
+}
 
-    fn ui(&mut self, f: &mut Frame) {
-        let theme = self.config.theme.clone();
-
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(8),
-                Constraint::Min(1),
-                Constraint::Length(2),
-            ])
-            .split(f.area());
+// Search query split into `is:` state and `label:` filters and the remaining
+// free text
+struct NoteQuery {
+    text: String,
+    states: Vec<NoteState>,
+    labels: Vec<Label>,
+    unknown: Vec<String>,
+}
 
-        let mode_text = if self.config.general.offline_mode {
-            "Offline Mode".to_string()
-        } else {
-            let token = config::get_token();
-            if !token.is_empty() {
-                match config::get_user_id_from_token(&token) {
-                    Ok(uid) => format!("User: {}", uid),
-                    Err(_) => "Session Invalid".to_string(),
+impl NoteQuery {
+    fn parse(query: &str) -> Self {
+        let mut words = Vec::new();
+        let mut states = Vec::new();
+        let mut labels = Vec::new();
+        let mut unknown = Vec::new();
+        for word in query.split_whitespace() {
+            if let Some(name) = word.strip_prefix("label:") {
+                match Label::parse(name) {
+                    Some(label) if !labels.contains(&label) => labels.push(label),
+                    Some(_) => {}
+                    None if name.is_empty() => {}
+                    None => unknown.push(word.to_string()),
                 }
-            } else {
-                "Guest Mode (Local Only)".to_string()
+                continue;
             }
-        };
-        let header_content = format!("{}\n {} • {}", RISU_LOGO, config::APP_VERSION, mode_text);
-        let header = Paragraph::new(header_content)
-            .alignment(ratatui::layout::Alignment::Center)
-            .style(Style::default().fg(theme.logo).add_modifier(Modifier::BOLD));
-        f.render_widget(header, chunks[0]);
-
-        let main_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
-            .split(chunks[1]);
+            let Some(name) = word.strip_prefix("is:") else {
+                words.push(word);
+                continue;
+            };
+            match NoteState::ALL
+                .iter()
+                .find(|s| s.as_str().eq_ignore_ascii_case(name))
+            {
+                Some(state) if !states.contains(state) => states.push(*state),
+                Some(_) => {}
+                // Still being typed
+                None if name.is_empty() => {}
+                None => unknown.push(word.to_string()),
+            }
+        }
+        Self {
+            text: words.join(" ").to_lowercase(),
+            states,
+            labels,
+            unknown,
+        }
+    }
 
-        let selected_index = self.list_state.selected();
-        let items: Vec<ListItem> = self
-            .filtered_notes
-            .iter()
-            .enumerate()
-            .map(|(i, n)| {
-                let raw_title = n.content.lines().next().unwrap_or("No Content");
-                let title = sanitize_title(raw_title);
-                let is_selected = Some(i) == selected_index;
-
-                let date_str = DateTime::parse_from_rfc3339(&n.updated_at)
-                    .map(|dt| {
-                        dt.with_timezone(&Local)
-                            .format("%Y-%m-%d %H:%M")
-                            .to_string()
-                    })
-                    .unwrap_or_else(|_| n.updated_at.clone());
+    // Several `label:` tokens match a note with any of them
+    fn matches(&self, note: &Note) -> bool {
+        self.states.iter().all(|s| s.matches(note))
+            && (self.labels.is_empty() || Label::of(note).is_some_and(|l| self.labels.contains(&l)))
+            && (self.text.is_empty() || note.content.to_lowercase().contains(&self.text))
+    }
+}
 
-                let date_line = if is_selected {
-                    ratatui::text::Line::from(format!("    Updated: {}", date_str))
-                } else {
-                    ratatui::text::Line::from(ratatui::text::Span::styled(
-                        format!("    Updated: {}", date_str),
-                        Style::default().fg(Color::DarkGray),
-                    ))
-                };
+// The editor buffer for `content`. With `split`, lines over LONG_LINE_LIMIT
+// are cut into chunks of that size (at char boundaries); the bool says whether
+// any were.
+fn note_textarea<'a>(content: &str, split: bool) -> (TextArea<'a>, bool) {
+    if !split || content.lines().all(|l| l.len() <= LONG_LINE_LIMIT) {
+        return (TextArea::from(content.lines()), false);
+    }
+    let mut lines = Vec::new();
+    for mut line in content.lines() {
+        while line.len() > LONG_LINE_LIMIT {
+            let mut end = LONG_LINE_LIMIT;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            lines.push(line[..end].to_string());
+            line = &line[end..];
+        }
+        lines.push(line.to_string());
+    }
+    (TextArea::new(lines), true)
+}
 
-                let lines = vec![
-                    ratatui::text::Line::from(format!("   {}", title)),
-                    date_line,
-                ];
+/// Thread forwarding terminal events to the main loop, each with the time it
+/// was read. It polls so shutdown never waits on a blocked `event::read()`.
+struct InputReader {
+    handle: std::thread::JoinHandle<()>,
+    shutdown: Arc<AtomicBool>,
+    restarted: bool,
+}
 
-                ListItem::new(lines)
-            })
-            .collect();
+impl InputReader {
+    fn spawn(tx: mpsc::UnboundedSender<(Event, Instant)>) -> Self {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        Self {
+            handle: Self::start(tx, Arc::clone(&shutdown)),
+            shutdown,
+            restarted: false,
+        }
+    }
 
-        let query = self.search_textarea.lines()[0].clone();
-        let list_title = if query.is_empty() {
-            " Notes ".to_string()
-        } else {
-            let display_query = if query.len() > 15 {
-                format!("{}..", &query[0..12])
-            } else {
-                query.clone()
-            };
-            format!(" Notes (Filter: \"{}\") ", display_query)
-        };
+    fn start(
+        tx: mpsc::UnboundedSender<(Event, Instant)>,
+        shutdown: Arc<AtomicBool>,
+    ) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                let evt = match event::poll(INPUT_POLL) {
+                    Ok(false) => continue,
+                    Ok(true) => event::read(),
+                    Err(e) => Err(e),
+                };
+                match evt {
+                    Ok(evt) => {
+                        if tx.send((evt, Instant::now())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        crate::logger::log(&format!("Input reader failed: {}", e));
+                        break;
+                    }
+                }
+            }
+        })
+    }
 
-        let mut list_block = Block::default().borders(Borders::ALL).title(list_title);
-        if let ActivePane::List = self.active_pane {
-            list_block = list_block.border_style(Style::default().fg(theme.border_active));
-        } else if let ActivePane::Search = self.active_pane {
-            list_block = list_block.border_style(Style::default().fg(theme.border_inactive));
-        } else {
-            list_block = list_block.border_style(Style::default().fg(theme.border_inactive));
+    /// Replaces a dead reader, once. Returns the new event receiver.
+    fn restart(&mut self) -> Option<mpsc::UnboundedReceiver<(Event, Instant)>> {
+        if self.restarted {
+            return None;
         }
+        self.restarted = true;
+        crate::logger::log("Input reader stopped. Restarting it.");
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.handle = Self::start(tx, Arc::clone(&self.shutdown));
+        Some(rx)
+    }
 
-        let show_feedback = self
-            .saved_feedback_until
-            .is_some_and(|t| Instant::now() < t);
-        let highlight_style = if show_feedback {
-            Style::default()
-                .bg(theme.sync_synced)
-                .fg(Color::Black)
-                .add_modifier(Modifier::BOLD)
-        } else {
-            Style::default()
-                .bg(theme.selection_bg)
-                .fg(theme.selection_fg)
-                .add_modifier(Modifier::BOLD)
-        };
-
-        let list = List::new(items)
-            .block(list_block)
-            .highlight_style(highlight_style)
-            .highlight_symbol(">>");
+    fn stop(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.handle.join();
+    }
+}
 
-        f.render_stateful_widget(list, main_chunks[0], &mut self.list_state);
+// Cuts `text` to at most `width` terminal columns, marking the cut with `…`
+fn truncate_to_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    if width == 0 {
+        return out;
+    }
+    // Per-character widths miss some sequences (an emoji variation selector
+    // widens the character before it), so measure what's actually shown
+    out.push('…');
+    while out.width() > width {
+        out.pop();
+        out.pop();
+        out.push('…');
+    }
+    out
+}
 
-        if self.show_preview {
-            let content = self.textarea.lines().join("\n");
-            let markdown_text = markdown::parse_markdown(&content);
-            let mut preview_block = Block::default()
-                .borders(Borders::ALL)
-                .title(" Preview (Markdown) ");
-            if let ActivePane::Editor = self.active_pane {
-                preview_block =
-                    preview_block.border_style(Style::default().fg(theme.border_active));
-            } else {
-                preview_block =
-                    preview_block.border_style(Style::default().fg(theme.border_inactive));
-            }
-            let paragraph = Paragraph::new(markdown_text)
-                .block(preview_block)
-                .wrap(Wrap { trim: false })
-                .scroll((self.preview_scroll, 0));
-            f.render_widget(paragraph, main_chunks[1]);
-        } else {
-            let mut editor_block = Block::default().borders(Borders::ALL);
-            if let ActivePane::Editor = self.active_pane {
-                let (color, title) = match self.mode {
-                    Mode::Normal => (theme.mode_normal, " Editor (Normal) "),
-                    Mode::Insert => (theme.mode_insert, " Editor (Insert) "),
-                    Mode::Visual => (theme.mode_normal, " Editor (Visual) "),
-                    Mode::VisualLine => (theme.mode_normal, " Editor (Visual Line) "),
-                };
-                editor_block = editor_block
-                    .border_style(Style::default().fg(color))
-                    .title(title);
-            } else {
-                editor_block = editor_block
-                    .border_style(Style::default().fg(theme.border_inactive))
-                    .title(" Editor ");
-                // Hide cursor and disable cursor line highlight when not in editor pane
-                self.textarea.set_cursor_style(Style::default());
-                self.textarea.set_cursor_line_style(Style::default());
-            }
+// RFC 3339 timestamp in local time, or as stored if it doesn't parse
+fn format_note_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|dt| {
+            dt.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
 
-            if let ActivePane::Editor = self.active_pane {
-                // Restore cursor style and cursor line highlight when active
-                self.textarea
-                    .set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
-                self.textarea
-                    .set_cursor_line_style(Style::default().bg(theme.editor_cursor_line));
+// `old` → `new` as styled lines: removals red, additions green. A removed
+// line followed by an added one is diffed word by word, with the removed
+// words struck through and the added ones in bold.
+fn diff_text(old: &str, new: &str) -> Vec<Line<'static>> {
+    if old == new {
+        return Vec::new();
+    }
+    let ops = diff::diff_lines(old, new);
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < ops.len() {
+        if let diff::DiffOp::Same(l) = ops[i] {
+            lines.push(Line::from(format!("  {}", l)));
+            i += 1;
+            continue;
+        }
+        let end = ops[i..]
+            .iter()
+            .position(|op| matches!(op, diff::DiffOp::Same(_)))
+            .map_or(ops.len(), |p| i + p);
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for op in &ops[i..end] {
+            match *op {
+                diff::DiffOp::Removed(l) => removed.push(l),
+                diff::DiffOp::Added(l) => added.push(l),
+                diff::DiffOp::Same(_) => {}
             }
-
-            self.textarea.set_block(editor_block);
-            f.render_widget(&self.textarea, main_chunks[1]);
         }
-
-        if self.active_pane == ActivePane::Login {
-            self.render_login(f, chunks[1]);
-        } else if self.active_pane == ActivePane::DeleteConfirm {
-            self.render_delete_confirm(f, chunks[1]);
-        } else if self.active_pane == ActivePane::Search {
-            let area = centered_rect(60, 20, f.area());
-            let area = ratatui::layout::Rect {
-                x: area.x,
-                y: area.y,
-                width: area.width,
-                height: 3,
-            };
-            f.render_widget(ratatui::widgets::Clear, area);
-            f.render_widget(&self.search_textarea, area);
-        } else if self.active_pane == ActivePane::StatusDialog {
-            self.render_status_dialog(f, chunks[1]);
-        } else if self.active_pane == ActivePane::PassphraseInput {
-            self.render_passphrase_input(f, chunks[1]);
-        } else if self.active_pane == ActivePane::E2ESetup {
-            self.render_e2e_setup(f, chunks[1]);
-        } else if self.active_pane == ActivePane::ClearConfirm {
-            let area = centered_rect(60, 20, f.area());
-            let area = ratatui::layout::Rect {
-                x: area.x,
-                y: area.y,
-                width: area.width,
-                height: 3,
-            };
-            f.render_widget(ratatui::widgets::Clear, area);
-            f.render_widget(&self.clear_confirm_textarea, area);
+        // The k-th removed line is taken to have become the k-th added one
+        let pairs: Vec<Option<(Line<'static>, Line<'static>)>> = removed
+            .iter()
+            .zip(&added)
+            .map(|(old, new)| word_diff_lines(old, new))
+            .collect();
+        for (k, l) in removed.iter().enumerate() {
+            lines.push(match pairs.get(k) {
+                Some(Some((line, _))) => line.clone(),
+                _ => Line::from(Span::styled(
+                    format!("- {}", l),
+                    Style::default().fg(Color::Red),
+                )),
+            });
+        }
+        for (k, l) in added.iter().enumerate() {
+            lines.push(match pairs.get(k) {
+                Some(Some((_, line))) => line.clone(),
+                _ => Line::from(Span::styled(
+                    format!("+ {}", l),
+                    Style::default().fg(Color::Green),
+                )),
+            });
         }
+        i = end;
+    }
+    lines
+}
 
-        let sync_color = if show_feedback {
-            theme.sync_synced
-        } else if self.config.general.offline_mode {
-            theme.sync_offline
-        } else {
-            match &self.sync_status {
-                SyncStatus::Synced => theme.sync_synced,
-                SyncStatus::Syncing => theme.sync_syncing,
-                SyncStatus::Offline => theme.sync_offline,
-                SyncStatus::Error => theme.sync_error,
-                SyncStatus::PaymentRequired => theme.sync_payment_required,
-                SyncStatus::Unlocking => theme.sync_syncing,
-                SyncStatus::Unlocked => theme.sync_synced,
-                SyncStatus::Warning(_) => Color::Yellow,
+// The removed and added line of a changed pair with the changed words
+// highlighted; None when no word survived, as a word diff would be all noise
+fn word_diff_lines(old: &str, new: &str) -> Option<(Line<'static>, Line<'static>)> {
+    let ops = diff::diff_words(old, new);
+    if !ops
+        .iter()
+        .any(|op| matches!(op, diff::DiffOp::Same(t) if !t.trim().is_empty()))
+    {
+        return None;
+    }
+    // One side of the pair: the unchanged words plain, the changed ones in
+    // `changed_style`
+    let side = |keep_removed: bool, marker: Span<'static>, changed_style: Style| {
+        let mut runs: Vec<(String, bool)> = Vec::new();
+        for op in &ops {
+            let (text, changed) = match *op {
+                diff::DiffOp::Same(t) => (t, false),
+                diff::DiffOp::Removed(t) if keep_removed => (t, true),
+                diff::DiffOp::Added(t) if !keep_removed => (t, true),
+                _ => continue,
+            };
+            match runs.last_mut() {
+                Some((last, c)) if *c == changed => last.push_str(text),
+                _ => runs.push((text.to_string(), changed)),
             }
-        };
-
-        let sync_indicator = if show_feedback {
-            " Saved! ".to_string()
-        } else if self.config.general.offline_mode {
-            " Offline Mode ".to_string()
-        } else if matches!(self.sync_status, SyncStatus::Syncing) || self.is_loading {
-            let spinner = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
-            let s = spinner[self.spinner_index % spinner.len()];
-            if self.is_loading {
-                format!(" {} Loading... ", s)
+        }
+        let mut spans = vec![marker];
+        spans.extend(elide_word_runs(runs).into_iter().map(|(text, changed)| {
+            if changed {
+                Span::styled(text, changed_style)
             } else {
-                format!(" {} Syncing... ", s)
+                Span::raw(text)
             }
-        } else {
-            format!(" {} ", self.sync_status.as_str())
-        };
-
-        let mut help_text = match self.active_pane {
-            ActivePane::List => {
-                let query = self.search_textarea.lines()[0].clone();
-                if query.is_empty() {
-                    " j/k: Move  •  Enter: Open  •  i: Edit  •  n: New  •  d: Delete  •  r: Sync  •  Ctrl+g: Info  •  q: Quit ".to_string()
-                } else {
-                    " j/k: Move  •  Enter: Open  •  i: Edit  •  /: Filter  •  Esc: Clear Filter  •  q: Quit ".to_string()
-                }
-            },
-            ActivePane::Editor => match self.mode {
-                Mode::Normal => " i: Insert  •  v: Visual  •  V: V-Line  •  m: Preview  •  Esc: Back(Save)  •  Ctrl+S: Save \n dd: DelLine  •  yy: CopyLine  •  p: Paste ".to_string(),
-                Mode::Insert => " Esc: Normal Mode  •  Ctrl+S: Save ".to_string(),
-                Mode::Visual | Mode::VisualLine => " y: Yank  •  d: Delete  •  Esc: Normal Mode \n Move: h/j/k/l ".to_string(),
-            },
-            ActivePane::Login => " Enter: Login  •  Esc: Skip(Offline)  •  q: Quit ".to_string(),
-            ActivePane::DeleteConfirm => " y: Confirm  •  n: Cancel ".to_string(),
-            ActivePane::Search => " Enter/Esc: Close ".to_string(),
-            ActivePane::StatusDialog => " Esc/Enter/q: Close ".to_string(),
-            ActivePane::PassphraseInput => " Enter: Unlock  •  Esc: Cancel ".to_string(),
-            ActivePane::E2ESetup => " Tab: Switch Field  •  Enter: Submit  •  Esc: Cancel ".to_string(),
-            ActivePane::ClearConfirm => " Type 'ClearAllData' + Enter: Confirm  •  Esc: Cancel ".to_string(),
-        };
+        }));
+        Line::from(spans)
+    };
+    let removed = side(
+        true,
+        Span::styled("- ", Style::default().fg(Color::Red)),
+        Style::default()
+            .fg(Color::Red)
+            .add_modifier(Modifier::CROSSED_OUT),
+    );
+    let added = side(
+        false,
+        Span::styled("+ ", Style::default().fg(Color::Green)),
+        Style::default()
+            .fg(Color::Green)
+            .add_modifier(Modifier::BOLD),
+    );
+    Some((removed, added))
+}
 
-        if self.pending_key != PendingKey::None {
-            let pending_char = match self.pending_key {
-                PendingKey::D => "d",
-                PendingKey::Y => "y",
-                PendingKey::G => "g",
-                _ => "",
-            };
-            help_text = format!("(Pending: {}) {}", help_text, pending_char);
+// Cuts the unchanged text before the first and after the last changed run of
+// a long line down to WORD_DIFF_CONTEXT chars, marking each cut with `…`
+fn elide_word_runs(mut runs: Vec<(String, bool)>) -> Vec<(String, bool)> {
+    let len: usize = runs.iter().map(|(t, _)| t.chars().count()).sum();
+    if len <= WORD_DIFF_MAX_LINE {
+        return runs;
+    }
+    let last = runs.len() - 1;
+    if let Some((text, false)) = runs.first_mut() {
+        let count = text.chars().count();
+        if count > WORD_DIFF_CONTEXT && last > 0 {
+            *text = format!(
+                "…{}",
+                text.chars()
+                    .skip(count - WORD_DIFF_CONTEXT)
+                    .collect::<String>()
+            );
+        }
+    }
+    if let Some((text, false)) = runs.last_mut() {
+        if text.chars().count() > WORD_DIFF_CONTEXT && last > 0 {
+            *text = format!(
+                "{}…",
+                text.chars().take(WORD_DIFF_CONTEXT).collect::<String>()
+            );
         }
+    }
+    runs
+}
 
-        let footer_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Length(12), Constraint::Min(1)])
-            .split(chunks[2]);
+// Lines added and removed going from `old` to `new`, ignoring moves
+fn line_diff_counts(old: &str, new: &str) -> (usize, usize) {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in old.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    for line in new.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    counts.values().fold((0, 0), |(added, removed), &n| {
+        if n > 0 {
+            (added + n as usize, removed)
+        } else {
+            (added, removed + n.unsigned_abs())
+        }
+    })
+}
 
-        f.render_widget(
-            Paragraph::new(sync_indicator)
-                .style(Style::default().fg(sync_color).add_modifier(Modifier::BOLD)),
-            footer_chunks[0],
-        );
-        f.render_widget(
-            Paragraph::new(help_text)
-                .style(Style::default().fg(theme.border_inactive))
-                .wrap(Wrap { trim: true }),
-            footer_chunks[1],
-        );
+// The list offset closest to `offset` that shows `selected` among `rows`
+// visible notes without leaving blank rows at the end
+fn clamp_list_offset(offset: usize, selected: Option<usize>, rows: usize, len: usize) -> usize {
+    let rows = rows.max(1);
+    let offset = offset.min(len.saturating_sub(rows));
+    match selected {
+        Some(selected) if selected < offset => selected,
+        Some(selected) if selected >= offset + rows => selected + 1 - rows,
+        _ => offset,
     }
+}
 
-    fn render_login(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let theme = &self.config.theme;
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .title(" Authentication Required ")
-            .border_style(Style::default().fg(theme.border_active));
+// tui-textarea's rule for keeping the cursor inside the viewport
+fn next_scroll_top(prev_top: u16, cursor: u16, len: u16) -> u16 {
+    if cursor < prev_top {
+        cursor
+    } else if prev_top + len <= cursor {
+        cursor + 1 - len
+    } else {
+        prev_top
+    }
+}
 
-        let text = if self.polling_login {
-            "\n  Browser opened. Waiting for login...\n"
-        } else {
-            "\n  You need to login to sync your notes.\n\n  Press [Enter] to login with Google\n  Press [Esc] to start in Offline Mode\n"
-        };
+fn auto_pair_close(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '`' | '*' => Some(c),
+        _ => None,
+    }
+}
 
-        let p = Paragraph::new(text)
-            .block(block)
-            .alignment(ratatui::layout::Alignment::Center);
+fn is_pair_closer(c: char) -> bool {
+    matches!(c, ')' | ']' | '}')
+}
 
-        let login_area = centered_rect(50, 30, area);
-        f.render_widget(ratatui::widgets::Clear, login_area);
-        f.render_widget(p, login_area);
+/// A popup centered in `r`: `percent` of its width and height, kept within
+/// `min` and `max` cells so it neither sprawls on wide terminals nor loses its
+/// content on small ones, and never larger than `r` itself.
+fn popup_rect(
+    r: ratatui::layout::Rect,
+    percent: (u16, u16),
+    min: (u16, u16),
+    max: (u16, u16),
+) -> ratatui::layout::Rect {
+    let axis = |len: u16, percent: u16, min: u16, max: u16| {
+        ((len as u32 * percent as u32 / 100) as u16)
+            .clamp(min, max)
+            .min(len)
+    };
+    let width = axis(r.width, percent.0, min.0, max.0);
+    let height = axis(r.height, percent.1, min.1, max.1);
+    ratatui::layout::Rect {
+        x: r.x + (r.width - width) / 2,
+        y: r.y + (r.height - height) / 2,
+        width,
+        height,
     }
+}
 
-    fn render_delete_confirm(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let theme = &self.config.theme;
-        let note_title = self
-            .note_to_delete
-            .as_ref()
-            .map(|n| n.content.lines().next().unwrap_or("No Content"))
-            .unwrap_or("");
+/// Opens an http(s) link with `general.browser_command`, or the system
+/// browser when that is empty. Other schemes are refused before anything runs.
+fn open_browser(url: &str, command: &str) -> Result<()> {
+    let lower = url.trim_start().to_ascii_lowercase();
+    if !(lower.starts_with("https://") || lower.starts_with("http://")) {
+        return Err(anyhow::anyhow!(
+            "Refusing to open {}: only http(s) links open in the browser",
+            url
+        ));
+    }
+    launch_browser(url, command)
+}
 
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .title(" Delete Note? ")
-            .border_style(Style::default().fg(theme.sync_error));
+/// Opens a file risu wrote itself (an HTML export) the same way.
+fn open_file_in_browser(path: &Path, command: &str) -> Result<()> {
+    launch_browser(&path.to_string_lossy(), command)
+}
 
-        let text = format!(
-            "\n  Are you sure you want to delete this note?\n\n  \"{}\"\n\n  (y/n)",
-            note_title
-        );
-        let p = Paragraph::new(text)
-            .block(block)
-            .alignment(ratatui::layout::Alignment::Center);
+fn launch_browser(target: &str, command: &str) -> Result<()> {
+    if command.trim().is_empty() {
+        return webbrowser::open(target).context("No browser could be opened");
+    }
+    let mut args = split_command(command)?;
+    // Substituted after splitting, so the target is always one argument
+    if args.iter().any(|a| a.contains("{url}")) {
+        for arg in &mut args {
+            *arg = arg.replace("{url}", target);
+        }
+    } else {
+        args.push(target.to_string());
+    }
+    std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("Couldn't run {}", args[0]))?;
+    Ok(())
+}
 
-        let confirm_area = centered_rect(40, 30, area);
-        f.render_widget(ratatui::widgets::Clear, confirm_area);
-        f.render_widget(p, confirm_area);
+// Splits a command line into words the way a POSIX shell would for quotes and
+// backslashes, without expanding anything
+fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow::anyhow!("Unclosed ' in {}", command)),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(anyhow::anyhow!("Unclosed \" in {}", command)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow::anyhow!("Unclosed \" in {}", command)),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        args.push(word);
     }
+    if args.is_empty() {
+        return Err(anyhow::anyhow!("general.browser_command is blank"));
+    }
+    Ok(args)
+}
 
-    fn render_status_dialog(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let theme = &self.config.theme;
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .title(" Risu System Status ")
-            .border_style(Style::default().fg(theme.border_active));
+fn handle_cli_doctor(config_path: Option<&Path>) {
+    let loaded = config::load_config_layers(config_path);
 
-        let token_source_str = self
-            .token_source
-            .as_ref()
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "Unknown".to_string());
-
-        let account_str = self.user_email.as_deref().unwrap_or("Not Logged In");
-        let plan_raw = self.user_plan.as_deref().unwrap_or("Unknown");
-        let plan_str = match plan_raw {
-            "dev" => "Early bird",
-            "pro" => "Pro",
-            _ => plan_raw,
-        };
-        let sub_status = self.user_subscription_status.as_deref().unwrap_or("None");
-        let sub_end = self.user_subscription_end_date.as_deref().unwrap_or("N/A");
-
-        let online_mode = if self.config.general.offline_mode {
-            "Offline (Manual)".to_string()
-        } else if self.user_email.is_none() {
-            "Offline (Guest)".to_string()
-        } else if self
-            .user_plan
-            .as_deref()
-            .unwrap_or("")
-            .trim()
-            .eq_ignore_ascii_case("free")
-        {
-            "Offline (Free Plan)".to_string()
-        } else {
-            "Online (Local-First)".to_string()
-        };
+    println!("Risu {}", config::APP_VERSION);
+    println!("Config dir: {}", config::get_config_dir().display());
+    println!("Log file: {}", logger::log_path().display());
 
-        let e2e_display = match self.e2e_status.as_str() {
-            "Unlocked" => "Active (Unlocked)".to_string(),
-            "Locked" => "Inactive (Locked)".to_string(),
-            _ => "Disabled".to_string(),
-        };
+    println!("\nConfig files (later wins):");
+    if loaded.files.is_empty() {
+        println!("  (none, using defaults)");
+    }
+    for path in &loaded.files {
+        println!("  {}", path.display());
+    }
 
-        let error_str = self.last_error.as_deref().unwrap_or("None");
+    let source = |key: &str| {
+        loaded
+            .sources
+            .get(key)
+            .map(String::as_str)
+            .unwrap_or("default")
+    };
+    let general = &loaded.config.general;
+    let editor = &loaded.config.editor;
+    let settings = [
+        ("general.offline_mode", general.offline_mode.to_string()),
+        (
+            "general.restore_session",
+            general.restore_session.to_string(),
+        ),
+        ("editor.match_brackets", editor.match_brackets.to_string()),
+        ("editor.auto_pairs", editor.auto_pairs.to_string()),
+        ("editor.scrolloff", editor.scrolloff.to_string()),
+        ("editor.keep_long_lines", editor.keep_long_lines.to_string()),
+        (
+            "theme.color_mode",
+            loaded.config.theme.color_mode.as_str().to_string(),
+        ),
+    ];
+    println!("\nEffective settings:");
+    for (key, value) in settings {
+        println!("  {:<24} {:<6} ({})", key, value, source(key));
+    }
 
-        let text = format!(
-            "  Account:      {}\n  Plan:         {}\n  Sub Status:   {} ({})\n  Token Store:  {}\n  Network:      {}\n  E2E Encrypt:  {}\n\n  Last Error:   {}",
-            account_str, plan_str, sub_status, sub_end, token_source_str, online_mode, e2e_display, error_str
-        );
+    // Themes are usually set as a whole, so list every layer that touched one
+    let mut theme_sources: Vec<&str> = loaded
+        .sources
+        .iter()
+        .filter(|(key, _)| key.starts_with("theme."))
+        .map(|(_, source)| source.as_str())
+        .collect();
+    theme_sources.sort();
+    theme_sources.dedup();
+    println!("  {:<24} {:<6} ({})", "theme", "", theme_sources.join(", "));
+}
 
-        let menu_items_list = self.get_status_menu_items();
-        let menu_items_count = menu_items_list.len() as u16;
+/// Returns false if any problem was found.
+fn handle_cli_config_check(config_path: Option<&Path>) -> bool {
+    if let Some(path) = config_path.filter(|p| !p.exists()) {
+        eprintln!("{}: file not found", path.display());
+        return false;
+    }
 
-        // Dynamic Height Calculation
-        // Info text is about 8-9 lines. Menu is variable.
-        // We need at least: 9 (info) + menu_count + 2 (border) + 1 (spacing)
-        let min_height = 10 + menu_items_count + 2;
+    let (checked, issues) = config::check_config(config_path);
+    for issue in &issues {
+        eprintln!("{}", issue);
+    }
+    if issues.is_empty() {
+        println!("Config OK ({} file(s) checked)", checked.len());
+        true
+    } else {
+        eprintln!(
+            "\n{} problem(s) in {} file(s) checked. Invalid values fall back to defaults.",
+            issues.len(),
+            checked.len()
+        );
+        false
+    }
+}
 
-        let available_height = area.height;
-        let dialog_height = if available_height < min_height {
-            available_height.saturating_sub(2).max(10)
-        } else {
-            let target = std::cmp::max(available_height * 50 / 100, min_height);
-            std::cmp::min(target, available_height.saturating_sub(2))
-        };
+async fn logout(_repo: Repo) -> Result<()> {
+    if config::get_token().is_empty() {
+        println!("Already logged out.");
+        return Ok(());
+    }
 
-        // Vertical Centering
-        let v_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length((available_height.saturating_sub(dialog_height)) / 2),
-                Constraint::Length(dialog_height),
-                Constraint::Min(0),
-            ])
-            .split(area);
+    // repo.clear_all_data().await?; // Phase 7: Keep local data, only discard keys
+    let _ = config::delete_token_data();
+    let _ = config::delete_passphrase(); // Delete E2E passphrase too
+    logger::audit(logger::AuditEvent::Logout {
+        via: logger::Via::Cli,
+    });
+    println!("Logged out successfully. Local data preserved but access keys removed.");
+    Ok(())
+}
+
+fn restore_terminal() -> Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
+    Ok(())
+}
 
-        let dialog_area_v = v_layout[1];
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Extra config file layered over ~/.risu/config.toml and config.local.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
 
-        // Horizontal Centering (60% width)
-        let h_layout = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([
-                Constraint::Percentage(20),
-                Constraint::Percentage(60),
-                Constraint::Percentage(20),
-            ])
-            .split(dialog_area_v);
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
 
-        let dialog_area = h_layout[1];
+#[derive(Subcommand)]
+enum Commands {
+    /// Start the TUI application (default)
+    Tui {
+        /// Line-by-line prompt for screen readers, with notes edited in $EDITOR
+        #[arg(long)]
+        plain: bool,
+        /// Draw in the normal screen buffer instead of the alternate screen
+        #[arg(long)]
+        no_altscreen: bool,
+    },
+    /// Login to Risu Cloud
+    Login,
+    /// Logout from Risu Cloud
+    Logout,
+    /// Reset local database (Forces full re-sync)
+    ResetLocal,
+    /// Show the account and local note counts
+    Status,
+    /// Sync state for scripts and shell prompts, or a sync run to completion
+    Sync {
+        /// Print the unsynced and conflict counts, last sync time and E2E lock
+        /// state on one line, from local data only
+        #[arg(
+            long,
+            conflicts_with_all = ["wait", "watch"],
+            required_unless_present_any = ["wait", "watch"]
+        )]
+        status: bool,
+        /// Sync now and wait until it's done; exits non-zero if it fails
+        #[arg(long, conflicts_with = "watch")]
+        wait: bool,
+        /// Keep running: sync at start, periodically, and whenever the local
+        /// database changes, until Ctrl+C or SIGTERM
+        #[arg(long)]
+        watch: bool,
+        /// Seconds between periodic syncs in --watch
+        #[arg(long, default_value_t = 300, requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// Exit non-zero if the first --watch sync fails, instead of watching
+        #[arg(long, requires = "watch")]
+        once_then_watch: bool,
+        /// Format of the --status line
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Open a read-only, auto-refreshing view of one note (e.g. in a second terminal)
+    View {
+        /// Note id, or any prefix of it that only one note has
+        id: String,
+    },
+    /// Write a note as a standalone HTML page, or open it in the browser
+    ExportHtml {
+        /// Note id, or any prefix of it that only one note has
+        id: String,
+        /// Where to write the page; without it, a temporary file is opened
+        out: Option<PathBuf>,
+    },
+    /// Search note contents with a regular expression
+    Grep {
+        pattern: String,
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Lines of context to show around each match
+        #[arg(short = 'C', long, default_value_t = 0)]
+        context: usize,
+        /// Print one JSON object per matching note
+        #[arg(long)]
+        json: bool,
+        /// Only list the id and title of matching notes
+        #[arg(short = 'l', long)]
+        files_with_matches: bool,
+    },
+    /// Show where the effective configuration comes from
+    Doctor,
+    /// Inspect configuration files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// End-to-end encryption maintenance
+    E2e {
+        #[command(subcommand)]
+        command: E2eCommands,
+    },
+    /// Create notes from scripts
+    Note {
+        #[command(subcommand)]
+        command: NoteCommands,
+    },
+    /// Local database maintenance
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+}
 
-        f.render_widget(ratatui::widgets::Clear, dialog_area);
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
 
-        // Layout splitting: Top for Info, Bottom for Menu
-        let inner_area = block.inner(dialog_area);
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(8), Constraint::Length(menu_items_count)])
-            .split(inner_area);
+#[derive(Subcommand)]
+enum E2eCommands {
+    /// Encrypt and upload every note again, replacing plaintext server copies
+    Reupload {
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
 
-        f.render_widget(block, dialog_area); // Render outer border
+#[derive(Subcommand)]
+enum NoteCommands {
+    /// Create a note and print its id. The body comes from --body, --file, or
+    /// stdin when it isn't a terminal
+    New {
+        /// First line of the note
+        #[arg(long)]
+        title: Option<String>,
+        /// Appended as a `#tag` line; repeat for more tags
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// The note's text after the title
+        #[arg(long, conflicts_with = "file", allow_hyphen_values = true)]
+        body: Option<String>,
+        /// Read the body from a file
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+        /// Print the id, or the whole note as JSON
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+}
 
-        // Info Paragraph
-        let p = Paragraph::new(text).alignment(ratatui::layout::Alignment::Left);
-        f.render_widget(p, chunks[0]);
+#[derive(Subcommand)]
+enum DbCommands {
+    /// List the daily snapshots in ~/.risu/backups, oldest first
+    ListSnapshots,
+    /// Replace the local database with the snapshot from DATE (YYYY-MM-DD)
+    RestoreSnapshot {
+        date: String,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
 
-        // Menu List
-        let menu_items: Vec<ListItem> = menu_items_list
-            .iter()
-            .map(|i| ListItem::new(format!("  {}", i)))
-            .collect();
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Report unknown keys and invalid values with their locations
+    Check,
+    /// Print the effective value of a setting, e.g. general.offline_mode
+    Get { key: String },
+    /// Change a setting in config.toml (or the --config file)
+    Set { key: String, value: String },
+}
 
-        let menu = List::new(menu_items)
-            .highlight_style(Style::default().fg(Color::Black).bg(theme.selection_bg))
-            .highlight_symbol("> ");
+// ...
 
-        f.render_stateful_widget(menu, chunks[1], &mut self.status_list_state);
+async fn handle_cli_status(repo: Repo) -> Result<()> {
+    let token = config::get_token();
+    match config::get_user_email_from_token(&token) {
+        Ok(email) if !token.is_empty() => println!("Account:    {}", email),
+        _ => println!("Account:    Not logged in"),
     }
+    if let Some(cache) = repo.get_account_cache().await? {
+        let Ok(plan) = cache.plan.parse::<Plan>();
+        println!(
+            "Plan:       {} ({}, as of {})",
+            plan.display_name(),
+            cache.subscription_status,
+            format_note_time(&cache.fetched_at)
+        );
+    }
+    let counts = repo.count_notes().await?;
+    println!("Notes:      {} ({} unsynced)", counts.live, counts.unsynced);
+    println!("Tombstones: {}", counts.tombstones);
+    Ok(())
+}
 
-    fn render_passphrase_input(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let area = centered_rect(50, 20, area);
-        let area = ratatui::layout::Rect {
-            x: area.x,
-            y: area.y,
-            width: area.width,
-            height: 3,
+fn handle_cli_list_snapshots() -> Result<()> {
+    let snapshots = backup::list()?;
+    if snapshots.is_empty() {
+        println!("No snapshots in {}", backup::backups_dir().display());
+        return Ok(());
+    }
+    for snapshot in snapshots {
+        let notes = match snapshot.notes {
+            Some(n) => format!("{} note{}", n, if n == 1 { "" } else { "s" }),
+            None => "unreadable".to_string(),
         };
-        f.render_widget(ratatui::widgets::Clear, area);
-        f.render_widget(&self.passphrase_textarea, area);
+        println!(
+            "{}  {:>9}  {:>10}  {}",
+            snapshot.date,
+            format!("{:.1} KiB", snapshot.size as f64 / 1024.0),
+            notes,
+            snapshot.path.display()
+        );
     }
+    Ok(())
+}
 
-    fn render_e2e_setup(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let area = centered_rect(60, 40, area);
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .title(" Setup E2E Encryption ")
-            .border_style(Style::default().fg(self.config.theme.border_active));
-
-        f.render_widget(ratatui::widgets::Clear, area);
-        f.render_widget(block, area);
-
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(2), // Info text
-                Constraint::Length(3), // Input 1
-                Constraint::Length(1), // Spacer
-                Constraint::Length(3), // Input 2
-                Constraint::Min(1),
-            ])
-            .margin(2)
-            .split(area);
-
-        let info = Paragraph::new(
-            "Set a passphrase to encrypt your notes.\nThis passphrase cannot be recovered if lost.",
-        )
-        .alignment(ratatui::layout::Alignment::Center)
-        .style(Style::default().fg(self.config.theme.foreground));
-        f.render_widget(info, chunks[0]);
-
-        // Highlight active input
-        if self.e2e_setup_step == 0 {
-            self.passphrase_textarea
-                .set_style(Style::default().fg(Color::Yellow));
-            self.passphrase_confirm_textarea
-                .set_style(Style::default().fg(Color::DarkGray));
-        } else {
-            self.passphrase_textarea
-                .set_style(Style::default().fg(Color::DarkGray));
-            self.passphrase_confirm_textarea
-                .set_style(Style::default().fg(Color::Yellow));
+async fn handle_cli_restore_snapshot(repo: Repo, date: &str, yes: bool) -> Result<()> {
+    let snapshot = backup::find(date)?;
+    let current = repo.count_notes().await?.live;
+    if !yes {
+        print!(
+            "Replace the {} notes in {} with the {} from {}? [y/N] ",
+            current,
+            db::db_path().display(),
+            match snapshot.notes {
+                Some(n) => format!("{} in the snapshot", n),
+                None => "snapshot".to_string(),
+            },
+            snapshot.date
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled.");
+            return Ok(());
         }
-
-        // Ensure styles are set correctly (borders)
-        self.setup_passphrase_textarea_style();
-        self.setup_confirm_textarea_style();
-
-        f.render_widget(&self.passphrase_textarea, chunks[1]);
-        f.render_widget(&self.passphrase_confirm_textarea, chunks[3]);
     }
+    backup::restore(&repo, &snapshot).await?;
+    logger::log(&format!("Restored the database from {:?}", snapshot.path));
+    println!("Restored the snapshot from {}.", snapshot.date);
+    println!("Notes changed on the server since then are pulled again on the next sync.");
+    Ok(())
+}
 
-    fn get_status_menu_items(&self) -> Vec<&str> {
-        let mut items = vec!["Sync Now"];
+/// `risu sync --status`: kept to a few local reads so a prompt can call it on
+/// every render. Locked means E2E is set up but no passphrase is saved.
+async fn handle_cli_sync_status(repo: Repo, output: OutputFormat) -> Result<()> {
+    let counts = repo.count_notes().await?;
+    let last_sync = repo.get_last_sync_time().await?;
+    let locked = repo.get_salt().await?.is_some() && config::get_passphrase()?.is_none();
+    match output {
+        OutputFormat::Text => println!(
+            "unsynced={} conflicts={} last_sync={} locked={}",
+            counts.unsynced,
+            counts.conflicts,
+            last_sync.as_deref().unwrap_or("never"),
+            locked
+        ),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "unsynced": counts.unsynced,
+                "conflicts": counts.conflicts,
+                "last_sync": last_sync,
+                "locked": locked,
+            })
+        ),
+    }
+    Ok(())
+}
 
-        if self.user_email.is_some() {
-            if self.user_plan.as_deref() == Some("pro") || self.user_plan.as_deref() == Some("dev")
-            {
-                items.push("Manage Subscription");
-            } else if self.user_plan.as_deref() == Some("free") {
-                items.push("Select Plan");
+/// `risu sync --wait`: one full sync in this process, unlocked with the saved
+/// passphrase when E2E is set up.
+async fn handle_cli_sync_wait(repo: Repo, settings: config::SyncConfig) -> Result<()> {
+    let crypto_key = cli_sync_key(&repo).await?;
+
+    let (event_tx, mut event_rx) = mpsc::channel(10);
+    let (_trigger_tx, trigger_rx) = mpsc::channel(1);
+    let sync_manager = SyncManager::new(
+        repo.clone(),
+        event_tx,
+        trigger_rx,
+        crypto_key,
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(true)),
+        settings,
+    );
+    let collector = tokio::spawn(async move {
+        let mut last = None;
+        let mut conflicts = 0;
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                SyncEvent::StatusChanged(status) => last = Some(status),
+                SyncEvent::Conflicts(count) => conflicts = count,
+                _ => {}
             }
-            items.push("Logout");
-        } else {
-            items.push("Login");
         }
+        (last, conflicts)
+    });
+    sync_manager.sync_once().await;
+    drop(sync_manager);
+    let (last, conflicts) = collector.await?;
+
+    // A sync that ends in conflicts is done; it just doesn't report Synced
+    if conflicts > 0 {
+        eprintln!(
+            "Synced. {} note{} need{} merging.",
+            conflicts,
+            if conflicts == 1 { "" } else { "s" },
+            if conflicts == 1 { "s" } else { "" }
+        );
+        return Ok(());
+    }
+    match last {
+        Some(SyncStatus::Synced) => Ok(()),
+        Some(SyncStatus::Error(msg)) => anyhow::bail!("Sync failed: {}", msg),
+        Some(status) => anyhow::bail!("Sync did not complete: {}", status.as_str()),
+        None => anyhow::bail!("Sync did not run"),
+    }
+}
 
-        items.push("Clear All Data");
-        items.push("Close");
-        items
+// Checks a CLI sync can run, and loads the key when E2E is set up
+async fn cli_sync_key(repo: &Repo) -> Result<Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>> {
+    if config::get_token().is_empty() {
+        anyhow::bail!("Not logged in. Run `risu login` first.");
     }
+    let crypto_key = Arc::new(Mutex::new(None));
+    if repo.get_salt().await?.is_some() {
+        let Some(passphrase) = config::get_passphrase()? else {
+            anyhow::bail!("End-to-end encryption is locked. Unlock once in the TUI first.");
+        };
+        unlock_for_cli(repo, passphrase, &crypto_key).await?;
+    }
+    Ok(crypto_key)
+}
 
-    async fn perform_clear_all_data(&mut self) -> Result<()> {
-        let token = config::get_token();
-        if !token.is_empty() {
-            // Logged in: Try to clear remote first
-            if let Err(e) = self.api_client.reset_remote().await {
-                logger::log(&format!("Failed to clear remote data: {}", e));
-            } else {
-                logger::log("Remote data cleared successfully.");
+/// `risu sync --watch`: the TUI's background sync without the TUI. Syncs at
+/// start, every `interval`, and whenever another process (a TUI, `risu note
+/// new`) changes the database. One line per finished pass goes to stdout.
+/// SIGINT/SIGTERM let a running pass finish, then exit 0. With `fail_first`,
+/// a first pass that doesn't sync exits non-zero instead of watching.
+async fn handle_cli_sync_watch(
+    repo: Repo,
+    settings: config::SyncConfig,
+    interval: Duration,
+    fail_first: bool,
+) -> Result<()> {
+    let crypto_key = cli_sync_key(&repo).await?;
+
+    let (event_tx, mut event_rx) = mpsc::channel(10);
+    let (trigger_tx, trigger_rx) = mpsc::channel(1);
+    let sync_manager = SyncManager::new(
+        repo.clone(),
+        event_tx,
+        trigger_rx,
+        crypto_key,
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(true)),
+        settings,
+    );
+    let (first_tx, mut first_rx) = tokio::sync::oneshot::channel();
+    let reporter = tokio::spawn(async move {
+        let mut first_tx = Some(first_tx);
+        while let Some(event) = event_rx.recv().await {
+            let stamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+            let (line, ok) = match event {
+                SyncEvent::NoteError { id, msg } => {
+                    println!("{} note {}: {}", stamp, short_id(&id, SHORT_ID_LEN), msg);
+                    continue;
+                }
+                SyncEvent::RotationBlocked(reason) => {
+                    println!("{} passphrase change blocked: {}", stamp, reason);
+                    continue;
+                }
+                // Ends a pass that left notes to merge
+                SyncEvent::Conflicts(count) => (format!("synced, {} to merge", count), true),
+                SyncEvent::StatusChanged(
+                    SyncStatus::Syncing | SyncStatus::Encrypting(_, _) | SyncStatus::Unlocking,
+                ) => continue,
+                SyncEvent::StatusChanged(SyncStatus::Error(msg)) => {
+                    (format!("error: {}", msg), false)
+                }
+                SyncEvent::StatusChanged(status) => {
+                    (status.as_str().to_lowercase(), status == SyncStatus::Synced)
+                }
+                _ => continue,
+            };
+            println!("{} {}", stamp, line);
+            if let Some(tx) = first_tx.take() {
+                let _ = tx.send(ok);
+            }
+        }
+    });
+    let manager = tokio::spawn(sync_manager.start());
+    println!(
+        "Watching for changes, syncing every {}s. Ctrl+C stops.",
+        interval.as_secs()
+    );
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut ticker = time::interval(interval);
+    ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    // The first tick is immediate; the manager already syncs at start
+    ticker.tick().await;
+    let mut poll = time::interval(WATCH_POLL);
+    let mut version = repo.data_version().await?;
+    let mut waiting_first = fail_first;
+    let mut first_failed = false;
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            ok = &mut first_rx, if waiting_first => {
+                waiting_first = false;
+                if ok != Ok(true) {
+                    first_failed = true;
+                    break;
+                }
+            }
+            _ = ticker.tick() => {
+                let _ = trigger_tx.try_send(());
+            }
+            _ = poll.tick() => {
+                let current = repo.data_version().await?;
+                if current != version {
+                    version = current;
+                    let _ = trigger_tx.try_send(());
+                }
             }
         }
+    }
 
-        // Clear local data
-        self.repo.clear_all_data().await?;
-        self.refresh_notes(true).await?;
+    // The manager stops once its trigger is gone, after any pass in flight
+    drop(trigger_tx);
+    manager.await?;
+    reporter.await?;
+    if first_failed {
+        anyhow::bail!("The first sync didn't complete; not watching");
+    }
+    println!("Stopped.");
+    Ok(())
+}
 
-        // Restore account state (re-fetch salt, check plan, etc.)
-        if !self.config.general.offline_mode && self.user_email.is_some() {
-            if let Ok(me) = self.api_client.get_me().await {
-                self.apply_account_info(me).await?;
-            } else {
-                logger::log("Failed to refresh account info after clear.");
+// Resolves on Ctrl+C, or SIGTERM where there is one
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
             }
+            return;
         }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
 
-        logger::log("All data cleared.");
-        Ok(())
+// Loads the key for a CLI command that syncs. Without a network the cached
+// validator is trusted, as the TUI does.
+async fn unlock_for_cli(
+    repo: &Repo,
+    passphrase: String,
+    crypto_key: &Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+) -> Result<()> {
+    let ticket = UnlockTicket {
+        latest: Arc::new(AtomicU64::new(1)),
+        id: 1,
+    };
+    match unlock_process(
+        repo.clone(),
+        APIClient::new(),
+        passphrase,
+        Arc::clone(crypto_key),
+        &ticket,
+    )
+    .await
+    {
+        UnlockOutcome::Unlocked | UnlockOutcome::NetworkError(_) => Ok(()),
+        UnlockOutcome::WrongPassphrase => anyhow::bail!("The saved passphrase is no longer valid."),
+        UnlockOutcome::NoSalt => anyhow::bail!("End-to-end encryption is not set up."),
+        UnlockOutcome::Other(e) => anyhow::bail!("Unlock failed: {}", e),
     }
+}
 
-    async fn perform_logout(&mut self) -> Result<()> {
-        let _ = config::delete_token_data();
-        let _ = config::delete_passphrase();
+/// Where `Ctrl+w` followed by `key` moves focus from `from`. The list is left
+/// of the editor and nothing is above or below, so `j`/`k` stay put.
+fn window_target(from: ActivePane, key: char) -> Option<ActivePane> {
+    match (key, from) {
+        ('h', _) => Some(ActivePane::List),
+        ('l', _) => Some(ActivePane::Editor),
+        ('w', ActivePane::List) => Some(ActivePane::Editor),
+        ('w', _) => Some(ActivePane::List),
+        _ => None,
+    }
+}
 
-        self.user_email = None;
-        self.token_source = None;
-        self.user_plan = None;
-        self.e2e_status = "Disabled".to_string();
-        self.sync_status = SyncStatus::Offline;
+/// Turns an id or id prefix from the command line into a note id.
+async fn resolve_note_id(repo: &Repo, prefix: &str) -> Result<String> {
+    match repo.resolve_id(prefix.to_string(), false).await? {
+        ResolvedId::Unique(id) => Ok(id),
+        ResolvedId::NotFound => Err(anyhow::anyhow!("No note with id {}", prefix)),
+        ResolvedId::Ambiguous(ids) => {
+            let len = repo.short_id_len().await?;
+            let mut msg = format!("Id {} matches {} notes:", prefix, ids.len());
+            for id in ids {
+                let title = repo
+                    .get_note(id.clone())
+                    .await?
+                    .map(|n| derive_title(&n.content))
+                    .unwrap_or_default();
+                msg.push_str(&format!("\n  {}  {}", short_id(&id, len), title));
+            }
+            Err(anyhow::anyhow!(msg))
+        }
+    }
+}
 
-        // Clear cached keys
-        {
-            let mut guard = self.crypto_key.lock().unwrap();
-            *guard = None;
+async fn handle_cli_export_html(
+    repo: Repo,
+    id: String,
+    out: Option<PathBuf>,
+    browser_command: &str,
+) -> Result<()> {
+    let note = repo
+        .get_note(id.clone())
+        .await?
+        .filter(|n| n.is_deleted == 0)
+        .ok_or_else(|| anyhow::anyhow!("No note with id {}", id))?;
+    if crypto::looks_like_ciphertext(&note.content) {
+        return Err(anyhow::anyhow!(
+            "Note {} is stored encrypted; unlock it in risu and use :html from the editor",
+            short_id(&id, SHORT_ID_LEN)
+        ));
+    }
+    let path = html::export(&note.content, short_id(&id, SHORT_ID_LEN), out.as_deref())?;
+    println!("Wrote {}", path.display());
+    if out.is_none() {
+        if let Err(e) = open_file_in_browser(&path, browser_command) {
+            eprintln!("Couldn't open it in a browser: {:#}", e);
         }
+    }
+    Ok(())
+}
 
-        // Clear sensitive UI fields
-        self.passphrase_textarea = TextArea::default();
-        self.passphrase_textarea.set_mask_char('•');
-        self.setup_passphrase_textarea_style();
-        self.passphrase_confirm_textarea = TextArea::default();
-        self.passphrase_confirm_textarea.set_mask_char('•');
-        self.setup_confirm_textarea_style();
+/// `risu note new`: the title line, the body and a line of `#tags`, saved as
+/// a new note the next TUI or `risu sync --wait` pushes.
+async fn handle_cli_note_new(
+    repo: Repo,
+    title: Option<String>,
+    tags: Vec<String>,
+    body: Option<String>,
+    file: Option<PathBuf>,
+    output: OutputFormat,
+) -> Result<()> {
+    use std::io::{IsTerminal, Read};
+    let body = match (body, file) {
+        (Some(body), _) => body,
+        (None, Some(path)) => std::fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?,
+        (None, None) if !io::stdin().is_terminal() => {
+            let mut body = String::new();
+            io::stdin()
+                .read_to_string(&mut body)
+                .context("Couldn't read the body from stdin")?;
+            body
+        }
+        (None, None) => String::new(),
+    };
 
-        // Refresh notes as guest/offline user
-        self.refresh_notes(true).await?;
-        Ok(())
+    let mut tag_line = Vec::new();
+    for tag in &tags {
+        let name = tag.trim().trim_start_matches('#');
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(anyhow::anyhow!(
+                "Invalid tag {:?}: tags are single words, with or without a leading #",
+                tag
+            ));
+        }
+        tag_line.push(format!("#{}", name));
     }
-}
 
-fn sanitize_title(input: &str) -> String {
-    let sanitized: String = input
-        .chars()
-        .map(|c| if c.is_control() { ' ' } else { c })
-        .collect();
+    let mut parts = Vec::new();
+    if let Some(title) = title.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        parts.push(title.to_string());
+    }
+    let body = body.trim_end();
+    if !body.trim().is_empty() {
+        parts.push(body.to_string());
+    }
+    if !tag_line.is_empty() {
+        parts.push(tag_line.join(" "));
+    }
+    let content = parts.join("\n");
+    if content.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "The note would be empty; give --title, --body, --file or pipe the body on stdin"
+        ));
+    }
 
-    // Collapse multiple spaces
-    let result = sanitized.split_whitespace().collect::<Vec<_>>().join(" ");
-    if result.is_empty() {
-        "No Content".to_string()
-    } else {
-        result
+    // Saved like a TUI save: encrypted on push when E2E is set up
+    let e2e = repo.get_salt().await?.is_some();
+    let locked = e2e && config::get_passphrase()?.is_none();
+    let id = repo.save_note(None, content, e2e, locked).await?;
+    match output {
+        OutputFormat::Text => println!("{}", id),
+        OutputFormat::Json => {
+            let note = repo
+                .get_note(id.clone())
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Note {} vanished after saving", id))?;
+            println!(
+                "{}",
+                serde_json::json!({
+                    "id": note.id,
+                    "title": derive_title(&note.content),
+                    "content": note.content,
+                    "tags": tag_line,
+                    "created_at": note.created_at,
+                    "updated_at": note.updated_at,
+                })
+            );
+        }
     }
+    Ok(())
 }
 
-fn centered_rect(
-    percent_x: u16,
-    percent_y: u16,
-    r: ratatui::layout::Rect,
-) -> ratatui::layout::Rect {
-    let popup_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Percentage((100 - percent_y) / 2),
-            Constraint::Percentage(percent_y),
-            Constraint::Percentage((100 - percent_y) / 2),
-        ])
-        .split(r);
+/// Takes the day's database snapshot in the background, so a large
+/// database doesn't hold up the first frame, then compacts old tombstones:
+/// the snapshot still has them.
+fn spawn_daily_backup(
+    repo: Repo,
+    keep: usize,
+    tombstone_retention_days: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        match backup::run_daily(&repo, keep).await {
+            Ok(Some(path)) => logger::log(&format!("Backup: wrote {:?}", path)),
+            Ok(None) => {}
+            Err(e) => logger::log(&format!("Backup: daily snapshot failed: {:?}", e)),
+        }
+        sync::compact_tombstones(&repo, tombstone_retention_days).await;
+    })
+}
 
-    Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Percentage((100 - percent_x) / 2),
-            Constraint::Percentage(percent_x),
-            Constraint::Percentage((100 - percent_x) / 2),
-        ])
-        .split(popup_layout[1])[1]
+#[derive(Clone, Copy)]
+enum GrepOutput {
+    Lines { context: usize },
+    Json,
+    FilesWithMatches,
 }
 
-fn open_browser(url: &str) {
-    let _ = webbrowser::open(url);
+// Notes bigger than this are skipped by `risu grep`
+const GREP_MAX_NOTE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Prints matches note by note as they're read. Returns whether anything matched.
+async fn handle_cli_grep(repo: Repo, regex: &regex::Regex, output: GrepOutput) -> Result<bool> {
+    use std::io::IsTerminal;
+    let color = io::stdout().is_terminal() && !matches!(output, GrepOutput::Json);
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let id_len = repo.short_id_len().await?;
+    let mut notes = repo.stream_notes(false)?;
+    let mut stdout = io::stdout().lock();
+    let mut matched_any = false;
+    while let Some(note) = notes.recv().await {
+        let note = note?;
+        if note.content.len() > GREP_MAX_NOTE_BYTES {
+            eprintln!(
+                "risu grep: skipping note {} (too large)",
+                short_id(&note.id, id_len)
+            );
+            continue;
+        }
+        if note.content.contains('\0') || crypto::looks_like_ciphertext(&note.content) {
+            eprintln!(
+                "risu grep: skipping note {} (not text)",
+                short_id(&note.id, id_len)
+            );
+            continue;
+        }
+
+        let lines: Vec<&str> = note.content.lines().collect();
+        let hits: Vec<usize> = (0..lines.len())
+            .filter(|&i| regex.is_match(lines[i]))
+            .collect();
+        if hits.is_empty() {
+            continue;
+        }
+        matched_any = true;
+        let title = derive_title(&note.content);
+
+        match output {
+            GrepOutput::FilesWithMatches => {
+                writeln!(
+                    stdout,
+                    "{}  {}",
+                    paint("35", short_id(&note.id, id_len)),
+                    title
+                )?;
+            }
+            GrepOutput::Json => {
+                let matches: Vec<serde_json::Value> = hits
+                    .iter()
+                    .map(|&i| {
+                        serde_json::json!({
+                            "line": i + 1,
+                            "text": lines[i],
+                            "ranges": regex
+                                .find_iter(lines[i])
+                                .map(|m| [m.start(), m.end()])
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect();
+                let record = serde_json::json!({
+                    "id": note.id,
+                    "title": title,
+                    "label": note.label,
+                    "matches": matches,
+                });
+                writeln!(stdout, "{}", record)?;
+            }
+            GrepOutput::Lines { context } => {
+                writeln!(
+                    stdout,
+                    "{} {}",
+                    paint("1", &title),
+                    paint("35", &format!("({})", short_id(&note.id, id_len)))
+                )?;
+                let mut last_printed: Option<usize> = None;
+                for &hit in &hits {
+                    let start = hit.saturating_sub(context);
+                    let end = (hit + context).min(lines.len() - 1);
+                    let from = match last_printed {
+                        Some(last) if last >= end => continue,
+                        Some(last) if last + 1 >= start => last + 1,
+                        Some(_) => {
+                            writeln!(stdout, "{}", paint("36", "--"))?;
+                            start
+                        }
+                        None => start,
+                    };
+                    for (i, line) in lines.iter().enumerate().take(end + 1).skip(from) {
+                        let is_hit = regex.is_match(line);
+                        let text = if color && is_hit {
+                            regex
+                                .replace_all(line, |c: &regex::Captures| paint("1;31", &c[0]))
+                                .into_owned()
+                        } else {
+                            line.to_string()
+                        };
+                        let sep = if is_hit { ':' } else { '-' };
+                        writeln!(
+                            stdout,
+                            "{}{}{}",
+                            paint("32", &(i + 1).to_string()),
+                            sep,
+                            text
+                        )?;
+                    }
+                    last_printed = Some(end);
+                }
+                writeln!(stdout)?;
+            }
+        }
+    }
+    Ok(matched_any)
 }
 
-async fn logout(_repo: Repo) -> Result<()> {
+async fn handle_cli_e2e_reupload(
+    repo: Repo,
+    settings: config::SyncConfig,
+    yes: bool,
+) -> Result<()> {
     if config::get_token().is_empty() {
-        println!("Already logged out.");
-        return Ok(());
+        anyhow::bail!("Not logged in. Run `risu login` first.");
     }
+    let Some(passphrase) = config::get_passphrase()? else {
+        anyhow::bail!("No saved passphrase. Unlock once in the TUI first.");
+    };
 
-    // repo.clear_all_data().await?; // Phase 7: Keep local data, only discard keys
-    let _ = config::delete_token_data();
-    let _ = config::delete_passphrase(); // Delete E2E passphrase too
-    println!("Logged out successfully. Local data preserved but access keys removed.");
-    Ok(())
-}
-
-fn restore_terminal() -> Result<()> {
-    disable_raw_mode()?;
-    execute!(
-        io::stdout(),
-        LeaveAlternateScreen,
-        DisableMouseCapture,
-        DisableBracketedPaste
-    )?;
-    Ok(())
-}
+    let resuming = repo.is_reupload_pending().await?;
+    let count = if resuming {
+        repo.get_unsynced_notes().await?.len()
+    } else {
+        repo.get_notes(false).await?.len()
+    };
+    if resuming {
+        println!("Resuming an interrupted re-upload: {} notes left.", count);
+    } else if !yes {
+        print!(
+            "{} notes will be encrypted and uploaded again, replacing any plaintext copies on the server. Continue? [y/N] ",
+            count
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    }
 
-#[derive(Parser)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[command(subcommand)]
-    command: Option<Commands>,
-}
+    let crypto_key = Arc::new(Mutex::new(None));
+    unlock_for_cli(&repo, passphrase, &crypto_key).await?;
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Start the TUI application (default)
-    Tui,
-    /// Login to Risu Cloud
-    Login,
-    /// Logout from Risu Cloud
-    Logout,
-    /// Reset local database (Forces full re-sync)
-    ResetLocal,
-}
+    repo.begin_reupload().await?;
 
-// ...
+    let (event_tx, mut event_rx) = mpsc::channel(10);
+    let (_trigger_tx, trigger_rx) = mpsc::channel(1);
+    let sync_manager = SyncManager::new(
+        repo.clone(),
+        event_tx,
+        trigger_rx,
+        crypto_key,
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(true)),
+        settings,
+    );
+    let printer = tokio::spawn(async move {
+        let mut last = None;
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                SyncEvent::Progress { done, total, .. } => {
+                    print!("\rUploading {}/{}", done, total);
+                    let _ = io::stdout().flush();
+                }
+                SyncEvent::StatusChanged(status) => last = Some(status),
+                _ => {}
+            }
+        }
+        last
+    });
+    sync_manager.sync_once().await;
+    drop(sync_manager);
+    let last = printer.await?;
+    println!();
+
+    if repo.is_reupload_pending().await? {
+        let status = last.map(|s| s.as_str().to_string()).unwrap_or_default();
+        anyhow::bail!(
+            "Re-upload incomplete ({}). Run `risu e2e reupload` again to resume.",
+            status
+        );
+    }
+    println!("All notes are stored encrypted on the server.");
+    Ok(())
+}
 
-async fn handle_cli_login(repo: Repo) -> Result<()> {
+async fn handle_cli_login(repo: Repo, browser_command: &str) -> Result<()> {
     let client = APIClient::new();
 
     // Check if already logged in
@@ -2231,12 +5155,11 @@ async fn handle_cli_login(repo: Repo) -> Result<()> {
         if let Ok(me) = client.get_me().await {
             if let Ok(email) = config::get_user_email_from_token(&token) {
                 println!("Already logged in as: {}", email);
-                let display_plan = match me.plan.as_str() {
-                    "dev" => "Early bird",
-                    "pro" => "Pro",
-                    _ => &me.plan,
-                };
-                println!("Plan: {} ({})", display_plan, me.subscription_status);
+                println!(
+                    "Plan: {} ({})",
+                    me.plan.display_name(),
+                    me.subscription_status
+                );
 
                 // Ensure salt is synced even if already logged in
                 if let Some(salt) = me.encryption_salt {
@@ -2255,7 +5178,9 @@ async fn handle_cli_login(repo: Repo) -> Result<()> {
             println!("Please open the following URL in your browser to login:");
             println!("{}", session.url);
 
-            open_browser(&session.url);
+            if let Err(e) = open_browser(&session.url, browser_command) {
+                eprintln!("Couldn't open a browser: {:#}", e);
+            }
 
             print!("Waiting for authentication... ");
             io::stdout().flush()?;
@@ -2317,46 +5242,239 @@ async fn handle_cli_login(repo: Repo) -> Result<()> {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     let default_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
+        error_report::log_panic(info);
+        logger::flush();
         let _ = restore_terminal();
         default_hook(info);
+        eprintln!("Details were written to {}", logger::log_path().display());
     }));
 
     logger::init();
-    let repo = Repo::new()?;
+    let result = run_app().await;
+    if let Err(err) = &result {
+        error_report::report(err);
+    }
+    logger::flush();
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+async fn run_app() -> Result<()> {
+    let mut repo = Repo::new()?;
 
     let args = Args::parse();
+    logger::set_redact_titles(
+        config::load_config_layers(args.config.as_deref())
+            .config
+            .general
+            .redact_titles,
+    );
 
     match args.command {
         Some(Commands::Login) => {
-            return handle_cli_login(repo).await;
+            let general = config::load_config_layers(args.config.as_deref())
+                .config
+                .general;
+            return handle_cli_login(repo, &general.browser_command).await;
         }
         Some(Commands::Logout) => {
             return logout(repo).await;
         }
+        Some(Commands::Doctor) => {
+            handle_cli_doctor(args.config.as_deref());
+            return Ok(());
+        }
+        Some(Commands::Config {
+            command: ConfigCommands::Check,
+        }) => {
+            if !handle_cli_config_check(args.config.as_deref()) {
+                logger::flush();
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::Config {
+            command: ConfigCommands::Get { key },
+        }) => {
+            println!(
+                "{}",
+                config::get_config_value(args.config.as_deref(), &key)?
+            );
+            return Ok(());
+        }
+        Some(Commands::Config {
+            command: ConfigCommands::Set { key, value },
+        }) => {
+            let path = config::set_config_value(args.config.as_deref(), &key, &value)?;
+            println!("Set {} = {} in {}", key, value.trim(), path.display());
+            let loaded = config::load_config_layers(args.config.as_deref());
+            if let Some(source) = loaded
+                .sources
+                .get(&key)
+                .filter(|s| **s != path.display().to_string())
+            {
+                println!("Note: overridden by {}", source);
+            }
+            return Ok(());
+        }
+        Some(Commands::E2e {
+            command: E2eCommands::Reupload { yes },
+        }) => {
+            let settings = config::load_config_layers(args.config.as_deref())
+                .config
+                .sync;
+            return handle_cli_e2e_reupload(repo, settings, yes).await;
+        }
+        Some(Commands::Note {
+            command:
+                NoteCommands::New {
+                    title,
+                    tags,
+                    body,
+                    file,
+                    output,
+                },
+        }) => {
+            return handle_cli_note_new(repo, title, tags, body, file, output).await;
+        }
+        Some(Commands::Db {
+            command: DbCommands::ListSnapshots,
+        }) => {
+            handle_cli_list_snapshots()?;
+            return Ok(());
+        }
+        Some(Commands::Db {
+            command: DbCommands::RestoreSnapshot { date, yes },
+        }) => {
+            return handle_cli_restore_snapshot(repo, &date, yes).await;
+        }
+        Some(Commands::Status) => {
+            return handle_cli_status(repo).await;
+        }
+        Some(Commands::Sync {
+            status: true,
+            output,
+            ..
+        }) => {
+            return handle_cli_sync_status(repo, output).await;
+        }
+        Some(Commands::Sync {
+            watch: true,
+            interval,
+            once_then_watch,
+            ..
+        }) => {
+            let settings = config::load_config_layers(args.config.as_deref())
+                .config
+                .sync;
+            return handle_cli_sync_watch(
+                repo,
+                settings,
+                Duration::from_secs(interval),
+                once_then_watch,
+            )
+            .await;
+        }
+        Some(Commands::Sync { .. }) => {
+            let settings = config::load_config_layers(args.config.as_deref())
+                .config
+                .sync;
+            return handle_cli_sync_wait(repo, settings).await;
+        }
+        Some(Commands::View { id }) => {
+            let theme = config::load_config_layers(args.config.as_deref())
+                .config
+                .theme;
+            let id = resolve_note_id(&repo, &id).await?;
+            return viewer::run(repo, id, theme).await;
+        }
+        Some(Commands::ExportHtml { id, out }) => {
+            let id = resolve_note_id(&repo, &id).await?;
+            let general = config::load_config_layers(args.config.as_deref())
+                .config
+                .general;
+            return handle_cli_export_html(repo, id, out, &general.browser_command).await;
+        }
+        Some(Commands::Grep {
+            pattern,
+            ignore_case,
+            context,
+            json,
+            files_with_matches,
+        }) => {
+            let output = if json {
+                GrepOutput::Json
+            } else if files_with_matches {
+                GrepOutput::FilesWithMatches
+            } else {
+                GrepOutput::Lines { context }
+            };
+            let regex = regex::RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()?;
+            if !handle_cli_grep(repo, &regex, output).await? {
+                logger::flush();
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
         Some(Commands::ResetLocal) => {
             repo.clear_all_data().await?;
+            logger::audit(logger::AuditEvent::ClearLocalData {
+                via: logger::Via::Cli,
+            });
             println!("Local database reset successfully.");
             println!("When you start Risu next time, it will perform a full sync from the server.");
             return Ok(());
         }
-        None | Some(Commands::Tui) => {
+        Some(Commands::Tui { plain: true, .. }) => {
+            let loaded = config::load_config_layers(args.config.as_deref()).config;
+            repo.set_timeout(loaded.database.timeout());
+            return plain::run(repo, loaded).await;
+        }
+        None | Some(Commands::Tui { .. }) => {
             // Proceed to TUI
         }
     }
+    let alt_screen = !matches!(
+        args.command,
+        Some(Commands::Tui {
+            no_altscreen: true,
+            ..
+        })
+    );
 
     let (sync_trigger_tx, sync_trigger_rx) = mpsc::channel(1);
-    let (status_tx, status_rx) = mpsc::channel(10);
+    let (event_tx, event_rx) = mpsc::channel(10);
     let crypto_key = Arc::new(Mutex::new(None));
-    let app_config = config::load_config();
+    let sync_cancel = Arc::new(AtomicBool::new(false));
+    let network_online = Arc::new(AtomicBool::new(true));
+    let loaded_config = config::load_config_layers(args.config.as_deref());
+    let config_issues = loaded_config.issues.len();
+    let app_config = loaded_config.config;
+    repo.set_timeout(app_config.database.timeout());
+    let backup_task = spawn_daily_backup(
+        repo.clone(),
+        app_config.general.daily_backups,
+        app_config.sync.tombstone_retention_days,
+    );
 
     let sync_handle = if !app_config.general.offline_mode {
         let sync_repo = repo.clone();
         let sync_key = Arc::clone(&crypto_key);
-        let sync_manager =
-            SyncManager::new(sync_repo, status_tx.clone(), sync_trigger_rx, sync_key);
+        let sync_manager = SyncManager::new(
+            sync_repo,
+            event_tx.clone(),
+            sync_trigger_rx,
+            sync_key,
+            Arc::clone(&sync_cancel),
+            Arc::clone(&network_online),
+            app_config.sync.clone(),
+        );
         Some(tokio::spawn(async move { sync_manager.start().await }))
     } else {
         None
@@ -2364,32 +5482,1502 @@ async fn main() -> Result<()> {
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(
-        stdout,
-        EnterAlternateScreen,
-        EnableMouseCapture,
-        EnableBracketedPaste
-    )?;
+    if alt_screen {
+        execute!(stdout, EnterAlternateScreen)?;
+    }
+    execute!(stdout, EnableMouseCapture, EnableBracketedPaste)?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
 
-    let mut model = Model::new(
-        repo,
-        sync_trigger_tx,
-        status_rx,
-        status_tx.clone(),
-        app_config,
-        crypto_key,
-    )
-    .await?;
-    let model_result = model.run(&mut terminal).await;
+    // Any error past this point has to restore the terminal before it's printed
+    let model_result = async {
+        let mut model = Model::new(
+            repo,
+            sync_trigger_tx,
+            event_rx,
+            event_tx.clone(),
+            app_config,
+            crypto_key,
+            sync_cancel,
+        )
+        .await?;
+        model.network_online = network_online;
+        model.backup_task = Some((backup_task, Instant::now()));
+        if let Some(dir) = fs_bridge::notes_dir(&model.config.general.notes_dir) {
+            let (changed_tx, changed_rx) = mpsc::channel(1);
+            let bridge = fs_bridge::FsBridge::new(
+                dir,
+                model.repo.clone(),
+                Arc::clone(&model.crypto_key),
+                model.config.general.notes_dir_deletes,
+                changed_tx,
+                model.sync_trigger.clone(),
+            );
+            tokio::spawn(bridge.start());
+            model.notes_dir_rx = Some(changed_rx);
+        }
+        let overdue = model.overdue_count();
+        if overdue > 0 {
+            model.editor_message = Some(format!(
+                " {} overdue reminder{} — press u to see them ",
+                overdue,
+                if overdue == 1 { "" } else { "s" }
+            ));
+        }
+        if config_issues > 0 {
+            model.editor_message = Some(format!(
+                " Config has {} warning{} — run risu config check ",
+                config_issues,
+                if config_issues == 1 { "" } else { "s" }
+            ));
+        }
+        model.run(&mut terminal).await
+    }
+    .await;
 
-    drop(model);
     if let Some(handle) = sync_handle {
         let _ = tokio::time::timeout(Duration::from_secs(1), handle).await;
     }
     let _ = restore_terminal();
-    if let Err(err) = model_result {
-        eprintln!("Error: {:?}", err);
+    model_result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use ratatui::backend::TestBackend;
+    use ratatui::layout::Rect;
+    use ratatui::widgets::{Block, Clear};
+    use std::fs;
+    use std::sync::OnceLock;
+
+    // A model on an in-memory database. The config directory is a temp dir
+    // shared by every test, so nothing is read from or written to ~/.risu
+    async fn model() -> Model<'static> {
+        static HOME: OnceLock<PathBuf> = OnceLock::new();
+        HOME.get_or_init(|| {
+            let home = tempfile::tempdir().unwrap().keep();
+            std::env::set_var("HOME", &home);
+            home
+        });
+        let repo = Repo::open(std::path::Path::new(":memory:")).unwrap();
+        let (sync_trigger, _) = mpsc::channel(1);
+        let (event_tx, event_rx) = mpsc::channel(100);
+        Model::new(
+            repo,
+            sync_trigger,
+            event_rx,
+            event_tx,
+            config::AppConfig::default(),
+            Arc::new(Mutex::new(None)),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn popup_rect_is_clamped_and_centered() {
+        // Percent of a big screen, capped at the maximum
+        assert_eq!(
+            popup_rect(Rect::new(0, 0, 200, 60), (50, 30), (44, 8), (70, 10)),
+            Rect::new(65, 25, 70, 10)
+        );
+        // Raised to the minimum, but never past the screen
+        assert_eq!(
+            popup_rect(Rect::new(0, 0, 40, 10), (50, 30), (44, 8), (70, 10)),
+            Rect::new(0, 1, 40, 8)
+        );
+        assert_eq!(
+            popup_rect(Rect::new(0, 0, 10, 2), (50, 30), (44, 8), (70, 10)),
+            Rect::new(0, 0, 10, 2)
+        );
+        // Centered within an area that doesn't start at the origin
+        assert_eq!(
+            popup_rect(Rect::new(5, 3, 100, 40), (60, 40), (68, 14), (90, 16)),
+            Rect::new(21, 15, 68, 16)
+        );
+        assert_eq!(
+            popup_rect(Rect::new(0, 0, 0, 0), (50, 30), (44, 8), (70, 10)),
+            Rect::new(0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn popup_rect_renders_inside_any_terminal() {
+        for (width, height) in [(1, 1), (20, 5), (80, 24), (300, 100)] {
+            let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+            let mut popup = Rect::default();
+            terminal
+                .draw(|f| {
+                    popup = popup_rect(f.area(), (60, 40), (30, 3), (90, 16));
+                    f.render_widget(Clear, popup);
+                    f.render_widget(Block::bordered(), popup);
+                })
+                .unwrap();
+            assert!(popup.right() <= width && popup.bottom() <= height);
+            if popup.width >= 2 && popup.height >= 2 {
+                let buffer = terminal.backend().buffer();
+                assert_eq!(buffer[(popup.x, popup.y)].symbol(), "┌");
+                assert_eq!(
+                    buffer[(popup.right() - 1, popup.bottom() - 1)].symbol(),
+                    "┘"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn durations_are_shown_in_their_largest_unit() {
+        assert_eq!(format_duration_short(0), "0s");
+        assert_eq!(format_duration_short(59), "59s");
+        assert_eq!(format_duration_short(60), "1m");
+        assert_eq!(format_duration_short(3599), "59m");
+        assert_eq!(format_duration_short(3600), "1h");
+        assert_eq!(format_duration_short(86399), "23h");
+        assert_eq!(format_duration_short(86400 * 3 + 5), "3d");
+    }
+
+    #[test]
+    fn list_offsets_keep_the_selection_in_view_without_blank_rows() {
+        // (offset, selected, rows, notes) -> offset
+        let cases = [
+            // Already fine
+            ((0, Some(3), 5, 20), 0),
+            ((6, Some(10), 5, 20), 6),
+            ((6, Some(6), 5, 20), 6),
+            ((6, Some(10), 5, 11), 6),
+            // The selection is above or below the viewport
+            ((6, Some(2), 5, 20), 2),
+            ((6, Some(11), 5, 20), 7),
+            ((0, Some(19), 5, 20), 15),
+            // Fewer notes than before: no blank rows at the end
+            ((15, Some(8), 5, 12), 7),
+            ((15, None, 5, 12), 7),
+            ((3, None, 5, 4), 0),
+            ((3, None, 5, 0), 0),
+            // Not drawn yet: a row of one
+            ((4, Some(2), 0, 20), 2),
+            ((4, None, 0, 20), 4),
+        ];
+        for ((offset, selected, rows, len), expected) in cases {
+            assert_eq!(
+                clamp_list_offset(offset, selected, rows, len),
+                expected,
+                "offset {} with {:?} selected, {} rows, {} notes",
+                offset,
+                selected,
+                rows,
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_titles_never_pass_the_width() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        // Narrow, wide, zero-width and emoji-presentation characters
+        const CHARS: &[char] = &[
+            'a',
+            'Z',
+            ' ',
+            '.',
+            'é',
+            '日',
+            '本',
+            '한',
+            'ｗ',
+            '😀',
+            '☺',
+            '\u{301}',
+            '\u{200b}',
+            '\u{200d}',
+            '\u{fe0f}',
+            '\u{1f1ef}',
+            '\u{1f1f5}',
+        ];
+        let mut rng = StdRng::seed_from_u64(696);
+        for _ in 0..5000 {
+            let len = rng.random_range(0..40);
+            let text: String = (0..len)
+                .map(|_| CHARS[rng.random_range(0..CHARS.len())])
+                .collect();
+            let width = rng.random_range(0..30);
+            let shown = truncate_to_width(&text, width);
+            assert!(
+                shown.width() <= width,
+                "{:?} at {} columns became {:?}",
+                text,
+                width,
+                shown
+            );
+            if text.width() <= width {
+                assert_eq!(shown, text);
+            } else if width > 0 {
+                let kept = shown.strip_suffix('…').unwrap();
+                assert!(text.starts_with(kept));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_list_says_why_it_is_empty() {
+        let mut model = model().await;
+        assert_eq!(
+            model.empty_list_text(),
+            format!(
+                "No notes yet — press {} to create one, {} for commands",
+                Command::New.key_label(),
+                Command::Palette.key_label()
+            )
+        );
+
+        model.due_view = true;
+        assert!(model.empty_list_text().starts_with("Nothing due"));
+
+        model.search_textarea = TextArea::new(vec!["groceries".to_string()]);
+        assert_eq!(
+            model.empty_list_text(),
+            "No notes match \"groceries\" — Esc to clear"
+        );
+
+        let rows = model.getting_started();
+        assert!(rows.contains(&(Command::New.key_label(), Command::New.title())));
+        assert!(rows
+            .iter()
+            .all(|(key, title)| !key.is_empty() && !title.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn a_change_under_the_editor_reloads_a_clean_buffer_only() {
+        let mut model = model().await;
+        let id = model
+            .repo
+            .save_note(None, "one\ntwo".to_string(), false, false)
+            .await
+            .unwrap();
+        model.refresh_notes(false).await.unwrap();
+        let index = model.filtered_notes.iter().position(|n| n.id == id);
+        model.list_state.select(index);
+        model.update_editor_from_selection();
+        model.textarea.move_cursor(CursorMove::Jump(1, 2));
+
+        // Clean: the buffer follows the note and the cursor stays put
+        let pulled = "one\nTWO\nthree".to_string();
+        model
+            .repo
+            .save_note(Some(id.clone()), pulled.clone(), false, false)
+            .await
+            .unwrap();
+        model.refresh_notes_under_editor(false).await.unwrap();
+        assert_eq!(model.textarea.lines().join("\n"), pulled);
+        assert_eq!(model.textarea.cursor(), (1, 2));
+        assert!(model.remote_changed_base.is_none());
+
+        // Dirty: the edits stay and the save merges against what was edited
+        model.textarea.insert_str("!");
+        let edited = model.textarea.lines().join("\n");
+        for content in ["one\nTWO\nthree\nfour", "zero\none\nTWO\nthree\nfour"] {
+            model
+                .repo
+                .save_note(Some(id.clone()), content.to_string(), false, false)
+                .await
+                .unwrap();
+            model.refresh_notes_under_editor(false).await.unwrap();
+        }
+        assert_eq!(model.textarea.lines().join("\n"), edited);
+        assert_eq!(model.remote_changed_base, Some(pulled));
+        assert!(model.editor_message.is_some());
+    }
+
+    #[tokio::test]
+    async fn adopting_the_server_salt_locks_until_its_passphrase_is_entered() {
+        let mut model = model().await;
+        *model.crypto_key.lock().unwrap() = Some(Zeroizing::new([7; 32]));
+        model.e2e_status = E2EState::SetupRequired;
+        let id = model
+            .repo
+            .save_note(None, "local note".to_string(), false, false)
+            .await
+            .unwrap();
+
+        model.adopt_server_salt("server-salt").await.unwrap();
+        assert_eq!(
+            model.repo.get_salt().await.unwrap().as_deref(),
+            Some("server-salt")
+        );
+        assert!(model.crypto_key.lock().unwrap().is_none());
+        assert_eq!(model.e2e_status, E2EState::Locked);
+        assert_eq!(model.active_pane, ActivePane::PassphraseInput);
+        // Pushed again once unlocked, encrypted under the account's key
+        let note = model.repo.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.is_encrypted, 1);
+        assert_eq!(note.is_synced, 0);
+    }
+
+    #[tokio::test]
+    async fn enter_is_ignored_while_a_key_is_being_derived() {
+        let mut model = model().await;
+        model.active_pane = ActivePane::PassphraseInput;
+        let press = |code| event::KeyEvent::new(code, event::KeyModifiers::NONE);
+        for c in "secret".chars() {
+            model
+                .handle_passphrase_key(press(KeyCode::Char(c)))
+                .await
+                .unwrap();
+        }
+        model
+            .handle_passphrase_key(press(KeyCode::Enter))
+            .await
+            .unwrap();
+        assert!(model.unlock_in_progress);
+        assert_eq!(model.unlock_generation.load(Ordering::SeqCst), 1);
+
+        // A second submit neither starts another attempt nor loses the input
+        model
+            .handle_passphrase_key(press(KeyCode::Char('x')))
+            .await
+            .unwrap();
+        model
+            .handle_passphrase_key(press(KeyCode::Enter))
+            .await
+            .unwrap();
+        assert_eq!(model.unlock_generation.load(Ordering::SeqCst), 1);
+        assert_eq!(model.passphrase_textarea.lines()[0], "x");
+    }
+
+    // Saves notes with these contents and opens each in the editor in turn,
+    // as picking them from the list does
+    async fn open_notes(model: &mut Model<'_>, contents: &[&str]) -> Vec<String> {
+        let mut ids = Vec::new();
+        for content in contents {
+            let id = model
+                .repo
+                .save_note(None, content.to_string(), false, false)
+                .await
+                .unwrap();
+            model.refresh_notes(false).await.unwrap();
+            model.select_note_by_id(&id).await.unwrap();
+            model.active_pane = ActivePane::Editor;
+            model.record_note_visit();
+            ids.push(id);
+        }
+        ids
+    }
+
+    fn press(code: KeyCode, modifiers: event::KeyModifiers) -> event::KeyEvent {
+        event::KeyEvent::new(code, modifiers)
+    }
+
+    #[tokio::test]
+    async fn jumping_back_follows_the_leave_policy() {
+        let ctrl_o = press(KeyCode::Char('o'), event::KeyModifiers::CONTROL);
+        let mut model = model().await;
+        model.config.editor.esc_behavior = config::EscBehavior::Prompt;
+        let ids = open_notes(&mut model, &["first", "second"]).await;
+
+        // Asked first; the jump waits for the answer
+        model.textarea.insert_str("edited ");
+        model.handle_key_event(ctrl_o).await.unwrap();
+        assert_eq!(model.active_pane, ActivePane::UnsavedConfirm);
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[1]));
+        model
+            .handle_key_event(press(KeyCode::Char('d'), event::KeyModifiers::NONE))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::Editor);
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[0]));
+        let second = model.repo.get_note(ids[1].clone()).await.unwrap().unwrap();
+        assert_eq!(second.content, "second");
+
+        // Discard never writes
+        model.config.editor.esc_behavior = config::EscBehavior::Discard;
+        model.textarea.insert_str("edited ");
+        model
+            .handle_key_event(press(KeyCode::Char('i'), event::KeyModifiers::CONTROL))
+            .await
+            .unwrap();
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[1]));
+        let first = model.repo.get_note(ids[0].clone()).await.unwrap().unwrap();
+        assert_eq!(first.content, "first");
+    }
+
+    #[tokio::test]
+    async fn stepping_through_matches_only_settles_the_buffer_when_leaving_it() {
+        let n = press(KeyCode::Char('n'), event::KeyModifiers::NONE);
+        let mut model = model().await;
+        model.config.editor.esc_behavior = config::EscBehavior::Discard;
+        let ids = open_notes(&mut model, &["apple pie", "pear tart", "apple jam"]).await;
+        model.confirmed_query = Some("apple".to_string());
+
+        // The match is another note: the edit is dropped, not written
+        model.textarea.insert_str("edited ");
+        model.handle_key_event(n).await.unwrap();
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[0]));
+        let jam = model.repo.get_note(ids[2].clone()).await.unwrap().unwrap();
+        assert_eq!(jam.content, "apple jam");
+
+        // The only match is this note: nothing is saved or thrown away
+        model.confirmed_query = Some("pie".to_string());
+        model.textarea.insert_str("edited ");
+        model.handle_key_event(n).await.unwrap();
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[0]));
+        assert_eq!(model.textarea.lines(), ["edited apple pie"]);
+        let pie = model.repo.get_note(ids[0].clone()).await.unwrap().unwrap();
+        assert_eq!(pie.content, "apple pie");
+    }
+
+    #[tokio::test]
+    async fn switching_panes_never_discards_edits() {
+        let mut model = model().await;
+        model.config.editor.esc_behavior = config::EscBehavior::Discard;
+        let ids = open_notes(&mut model, &["first"]).await;
+
+        model.textarea.insert_str("edited ");
+        model
+            .handle_key_event(press(KeyCode::BackTab, event::KeyModifiers::SHIFT))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::UnsavedConfirm);
+        assert_eq!(model.textarea.lines(), ["edited first"]);
+        model
+            .handle_key_event(press(KeyCode::Char('s'), event::KeyModifiers::NONE))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::List);
+        let first = model.repo.get_note(ids[0].clone()).await.unwrap().unwrap();
+        assert_eq!(first.content, "edited first");
+
+        // A clean buffer just moves
+        model.active_pane = ActivePane::Editor;
+        model
+            .handle_key_event(press(KeyCode::BackTab, event::KeyModifiers::SHIFT))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::List);
+    }
+
+    // Key presses for `text`, read `gap` apart
+    fn typed(text: &str, gap: Duration) -> VecDeque<(Message, Option<Instant>)> {
+        let start = Instant::now();
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let code = if c == '\n' {
+                    KeyCode::Enter
+                } else {
+                    KeyCode::Char(c)
+                };
+                let key = press(code, event::KeyModifiers::NONE);
+                (Message::Key(key), Some(start + gap * i as u32))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn only_keys_read_in_quick_succession_are_taken_for_a_paste() {
+        let mut model = model().await;
+        open_notes(&mut model, &[""]).await;
+        model.mode = Mode::Insert;
+        let burst = |model: &Model<'_>, mut keys: VecDeque<(Message, Option<Instant>)>| {
+            let (Message::Key(first), read_at) = keys.pop_front().unwrap() else {
+                unreachable!()
+            };
+            model.take_typed_burst(&first, read_at, &mut keys)
+        };
+
+        let pasted = "fn main() {\n}";
+        assert_eq!(
+            burst(&model, typed(pasted, Duration::from_micros(10))).as_deref(),
+            Some(pasted)
+        );
+        // Fast typing, even if it queued up behind a slow update
+        assert_eq!(
+            burst(&model, typed(pasted, Duration::from_millis(30))),
+            None
+        );
+
+        // A held key repeats; a paste never does
+        let mut keys = typed("aaaaaaaaaaaa", Duration::from_micros(10));
+        for (message, _) in keys.iter_mut().skip(1) {
+            if let Message::Key(key) = message {
+                key.kind = KeyEventKind::Repeat;
+            }
+        }
+        assert_eq!(burst(&model, keys), None);
+    }
+
+    #[tokio::test]
+    async fn a_large_unbracketed_paste_costs_a_few_draws() {
+        let mut model = model().await;
+        open_notes(&mut model, &[""]).await;
+        model.config.editor.auto_pairs = true;
+        model.mode = Mode::Insert;
+        model.metrics = Some(metrics::SessionMetrics::new());
+        let line = "let pair = (key, value); // [x] {y}\n";
+        let text = line.repeat(100 * 1024 / line.len());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        for (i, c) in text.chars().enumerate() {
+            let code = if c == '\n' {
+                KeyCode::Enter
+            } else {
+                KeyCode::Char(c)
+            };
+            let key = press(code, event::KeyModifiers::NONE);
+            let read_at = start + Duration::from_micros(i as u64);
+            tx.send((Event::Key(key), read_at)).unwrap();
+        }
+        drop(tx);
+        // Input ends after the paste, and isn't restarted
+        let mut input = InputReader {
+            handle: std::thread::spawn(|| {}),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            restarted: true,
+        };
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        assert!(model
+            .run_loop(&mut terminal, &mut rx, &mut input)
+            .await
+            .is_err());
+        input.stop();
+
+        // Auto-pairs were bypassed, so the text is exactly what was pasted
+        assert_eq!(model.textarea.lines().join("\n"), text);
+        let renders = model.metrics.as_ref().unwrap().renders();
+        assert!(renders <= 8, "{} draws", renders);
+    }
+
+    #[tokio::test]
+    async fn a_paste_lands_in_the_focused_field() {
+        let mut model = model().await;
+        let ids = open_notes(&mut model, &["milk and eggs", "call the bank"]).await;
+        let paste = |text: &str| Message::Paste(text.to_string());
+
+        // The list has no field to take it
+        model.active_pane = ActivePane::List;
+        model.update(paste("bank")).await.unwrap();
+        assert_eq!(model.search_textarea.lines(), [""]);
+        assert_eq!(model.textarea.lines(), ["call the bank"]);
+
+        // The editor keeps the lines, minus the carriage returns
+        model.active_pane = ActivePane::Editor;
+        model.mode = Mode::Insert;
+        model.textarea.move_cursor(CursorMove::End);
+        model.update(paste(" today\r\nor")).await.unwrap();
+        assert_eq!(model.textarea.lines(), ["call the bank today", "or"]);
+        assert!(model.swap_due.is_some());
+        model.textarea = TextArea::new(vec!["call the bank".to_string()]);
+        model.mode = Mode::Normal;
+
+        // Search takes one line and filters as it goes
+        model.active_pane = ActivePane::Search;
+        model.update(paste("milk\nand\n")).await.unwrap();
+        assert_eq!(model.search_textarea.lines(), ["milk and"]);
+        let shown: Vec<_> = model.filtered_notes.iter().map(|n| &n.id).collect();
+        assert_eq!(shown, [&ids[0]]);
+
+        // A passphrase from a password manager, trailing newline and all
+        model.active_pane = ActivePane::PassphraseInput;
+        model.update(paste("hunter2 hunter2\n")).await.unwrap();
+        assert_eq!(model.passphrase_textarea.lines(), ["hunter2 hunter2"]);
+        assert_eq!(model.passphrase_textarea.mask_char(), Some('•'));
+
+        model.active_pane = ActivePane::E2ESetup;
+        model.e2e_setup_step = 1;
+        model.update(paste("hunter2 hunter2\r\n")).await.unwrap();
+        assert_eq!(
+            model.passphrase_confirm_textarea.lines(),
+            ["hunter2 hunter2"]
+        );
+        assert_eq!(model.passphrase_textarea.lines(), ["hunter2 hunter2"]);
+
+        model.active_pane = ActivePane::ClearConfirm;
+        model.update(paste("DELETE\n")).await.unwrap();
+        assert_eq!(model.clear_confirm_textarea.lines(), ["DELETE"]);
+
+        // A locked editor takes nothing
+        model.active_pane = ActivePane::Editor;
+        model.editor_locked = true;
+        let before = model.textarea.lines().to_vec();
+        model.update(paste("more")).await.unwrap();
+        assert_eq!(model.textarea.lines(), before);
+    }
+
+    // Sends plain keys, as typed, through the pane handlers
+    async fn keys(model: &mut Model<'_>, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\u{1b}' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            let modifiers = if c.is_ascii_uppercase() {
+                event::KeyModifiers::SHIFT
+            } else {
+                event::KeyModifiers::NONE
+            };
+            model
+                .handle_key_event(press(code, modifiers))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn dd_and_yy_wait_for_their_second_key() {
+        let mut model = model().await;
+        open_notes(&mut model, &["one\ntwo\nthree"]).await;
+        model.clipboard = None;
+        model.textarea.move_cursor(CursorMove::Jump(1, 2));
+
+        keys(&mut model, "d").await;
+        assert_eq!(model.pending_key, PendingKey::D);
+        assert_eq!(model.textarea.lines(), ["one", "two", "three"]);
+        keys(&mut model, "d").await;
+        assert_eq!(model.pending_key, PendingKey::None);
+        assert_eq!(model.textarea.lines(), ["one", "three"]);
+        assert_eq!(model.textarea.cursor(), (1, 0));
+
+        // On the last line, the line before takes the cursor
+        keys(&mut model, "dd").await;
+        assert_eq!(model.textarea.lines(), ["one"]);
+        assert_eq!(model.textarea.cursor().0, 0);
+
+        // yy leaves the text alone, and the other's key starts over
+        keys(&mut model, "yy").await;
+        assert_eq!(model.pending_key, PendingKey::None);
+        assert_eq!(model.textarea.lines(), ["one"]);
+        keys(&mut model, "dy").await;
+        assert_eq!(model.pending_key, PendingKey::Y);
+        keys(&mut model, "d").await;
+        assert_eq!(model.pending_key, PendingKey::D);
+        assert_eq!(model.textarea.lines(), ["one"]);
+    }
+
+    #[tokio::test]
+    async fn visual_line_selects_whole_lines_either_side_of_the_anchor() {
+        let mut model = model().await;
+        open_notes(&mut model, &["a1\nb22\nc333\nd4444\ne5"]).await;
+        model.clipboard = None;
+        model.textarea.move_cursor(CursorMove::Jump(2, 1));
+
+        keys(&mut model, "V").await;
+        assert_eq!(model.mode, Mode::VisualLine);
+        assert_eq!(model.visual_anchor_row, Some(2));
+        assert_eq!(model.textarea.selection_range(), Some(((2, 0), (2, 4))));
+
+        // Above the anchor: from the top line's start to the anchor's end
+        keys(&mut model, "kk").await;
+        assert_eq!(model.textarea.selection_range(), Some(((0, 0), (2, 4))));
+        assert_eq!(model.textarea.cursor(), (0, 0));
+        // Back past it: from the anchor's start to the bottom line's end
+        keys(&mut model, "jjj").await;
+        assert_eq!(model.textarea.selection_range(), Some(((2, 0), (3, 5))));
+        assert_eq!(model.textarea.cursor(), (3, 5));
+        keys(&mut model, "G").await;
+        assert_eq!(model.textarea.selection_range(), Some(((2, 0), (4, 2))));
+        keys(&mut model, "gg").await;
+        assert_eq!(model.textarea.selection_range(), Some(((0, 0), (2, 4))));
+
+        keys(&mut model, "jd").await;
+        assert_eq!(model.mode, Mode::Normal);
+        assert_eq!(model.visual_anchor_row, None);
+        assert_eq!(model.textarea.lines(), ["a1", "", "d4444", "e5"]);
+    }
+
+    #[tokio::test]
+    async fn e2e_setup_asks_twice_and_starts_the_confirmation_over_on_a_mismatch() {
+        let mut model = model().await;
+        model.active_pane = ActivePane::E2ESetup;
+        model.reset_e2e_setup_inputs();
+
+        // Nothing typed: Enter stays on the first field
+        keys(&mut model, "\n").await;
+        assert_eq!(model.e2e_setup_step, 0);
+        keys(&mut model, "open sesame\n").await;
+        assert_eq!(model.e2e_setup_step, 1);
+        assert_eq!(model.passphrase_textarea.lines(), ["open sesame"]);
+
+        keys(&mut model, "open sesamy\n").await;
+        assert_eq!(model.active_pane, ActivePane::E2ESetup);
+        assert_eq!(model.e2e_setup_step, 1);
+        assert_eq!(model.passphrase_confirm_textarea.lines(), [""]);
+        assert_eq!(model.passphrase_confirm_textarea.mask_char(), Some('•'));
+        assert_eq!(model.passphrase_textarea.lines(), ["open sesame"]);
+
+        // Tab moves between the fields, typing goes to the focused one
+        keys(&mut model, "\tX").await;
+        assert_eq!(model.e2e_setup_step, 0);
+        assert_eq!(model.passphrase_textarea.lines(), ["open sesameX"]);
+        keys(&mut model, "\t").await;
+        assert_eq!(model.e2e_setup_step, 1);
+
+        // Esc backs out and forgets both
+        keys(&mut model, "abc\u{1b}").await;
+        assert_eq!(model.active_pane, ActivePane::List);
+        assert_eq!(model.e2e_setup_step, 0);
+        assert_eq!(model.passphrase_textarea.lines(), [""]);
+        assert_eq!(model.passphrase_confirm_textarea.lines(), [""]);
+    }
+
+    #[tokio::test]
+    async fn sync_events_change_what_the_ui_shows() {
+        let mut model = model().await;
+        model.e2e_status = E2EState::Unlocking;
+        model.active_pane = ActivePane::PassphraseInput;
+        model.is_loading = true;
+        let sync = |event| Message::Sync(event);
+
+        model
+            .update(sync(SyncEvent::UnlockResult(Err(
+                UnlockError::WrongPassphrase,
+            ))))
+            .await
+            .unwrap();
+        assert_eq!(model.e2e_status, E2EState::Locked);
+        let wrong = UnlockError::WrongPassphrase.message().to_string();
+        assert_eq!(model.sync_status, SyncStatus::UnlockFailed(wrong.clone()));
+        assert_eq!(model.last_error, Some(wrong));
+        assert_eq!(model.active_pane, ActivePane::PassphraseInput);
+        assert!(!model.is_loading);
+
+        model
+            .update(sync(SyncEvent::UnlockResult(Ok(KeyCheck::Verified))))
+            .await
+            .unwrap();
+        assert_eq!(model.e2e_status, E2EState::Unlocked);
+        assert_eq!(model.active_pane, ActivePane::List);
+        assert_eq!(model.last_error, None);
+        assert!(!model.key_unverified);
+        // Offline with nothing cached: unlocked, with a warning
+        model
+            .update(sync(SyncEvent::UnlockResult(Ok(KeyCheck::Unverified))))
+            .await
+            .unwrap();
+        assert!(model.key_unverified);
+        assert_eq!(model.last_error.as_deref(), Some(UNVERIFIED_UNLOCK_NOTICE));
+
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::Syncing)))
+            .await
+            .unwrap();
+        assert!(model.sync_start_time.is_some());
+        model
+            .update(sync(SyncEvent::Progress {
+                phase: SyncPhase::Encrypting,
+                done: 3,
+                total: 10,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(model.sync_status, SyncStatus::Encrypting(3, 10));
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::Synced)))
+            .await
+            .unwrap();
+        assert_eq!(model.sync_status, SyncStatus::Synced);
+        assert!(model.pending_sync_end);
+
+        model.update(sync(SyncEvent::Conflicts(2))).await.unwrap();
+        assert_eq!(
+            model.sync_status,
+            SyncStatus::Warning("Merge Conflict".to_string())
+        );
+        assert!(!model.pending_sync_end);
+        model
+            .update(sync(SyncEvent::NoteError {
+                id: "0123456789abcdef".to_string(),
+                msg: "Note too large".to_string(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(model.last_error.as_deref(), Some("Note too large"));
+        model.update(sync(SyncEvent::ClockSkew(-90))).await.unwrap();
+        assert_eq!(model.clock_skew, Some(-90));
+        model
+            .update(sync(SyncEvent::RotationBlocked("refused".to_string())))
+            .await
+            .unwrap();
+        assert_eq!(model.rotation_blocked.as_deref(), Some("refused"));
+
+        // Statuses that move the user somewhere else
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::SessionExpired)))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::Login);
+        assert_eq!(model.last_error.as_deref(), Some("Session expired"));
+        model.active_pane = ActivePane::Editor;
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::SessionExpired)))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::Editor);
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::PaymentRequired)))
+            .await
+            .unwrap();
+        assert_eq!(model.e2e_status, E2EState::UpgradeRequired);
+        assert_eq!(model.active_pane, ActivePane::StatusDialog);
+        model
+            .update(sync(SyncEvent::StatusChanged(SyncStatus::Error(
+                "boom".to_string(),
+            ))))
+            .await
+            .unwrap();
+        assert_eq!(model.sync_status, SyncStatus::Error("boom".to_string()));
+        assert_eq!(model.last_error.as_deref(), Some("boom"));
+    }
+
+    // Draws the whole screen as the event loop does
+    fn draw(model: &mut Model<'_>, width: u16, height: u16) -> ratatui::buffer::Buffer {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| model.ui(f)).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    // Row `y` as text; a wide character's second cell isn't repeated
+    fn row_text(buffer: &ratatui::buffer::Buffer, y: u16) -> String {
+        let mut text = String::new();
+        let mut x = 0;
+        while x < buffer.area.width {
+            let symbol = buffer[(x, y)].symbol();
+            text.push_str(symbol);
+            x += symbol.width().max(1) as u16;
+        }
+        text
+    }
+
+    // The footer's first line, and the color it starts in
+    fn footer_line(model: &mut Model<'_>) -> (String, Color) {
+        let buffer = draw(model, 120, 24);
+        (row_text(&buffer, 22), buffer[(1, 22)].fg)
+    }
+
+    #[tokio::test]
+    async fn the_footer_names_each_sync_status() {
+        let mut model = model().await;
+        model.user_email = Some("test@example.com".to_string());
+        model.user_plan = Some(Plan::Pro);
+        model.e2e_status = E2EState::Unlocked;
+        let theme = model.config.theme.clone();
+
+        for (status, text, color) in [
+            (SyncStatus::Synced, " Synced ", theme.sync_synced),
+            (SyncStatus::Syncing, " ⠋ Syncing... ", theme.sync_syncing),
+            (
+                SyncStatus::Encrypting(2, 5),
+                " ⠋ Encrypting 2/5 ",
+                theme.sync_syncing,
+            ),
+            (SyncStatus::Offline, " Online ", theme.sync_synced),
+            (
+                SyncStatus::Error("boom".to_string()),
+                " Error ",
+                theme.sync_error,
+            ),
+            (SyncStatus::Unlocking, " Unlocking... ", theme.sync_syncing),
+            (
+                SyncStatus::UnlockFailed("Wrong passphrase".to_string()),
+                " Locked ",
+                theme.sync_error,
+            ),
+            (
+                SyncStatus::PaymentRequired,
+                " Upgrade Required ",
+                theme.sync_payment_required,
+            ),
+            (
+                SyncStatus::SessionExpired,
+                " Session Expired ",
+                theme.sync_error,
+            ),
+            (
+                SyncStatus::Warning("Sync Incomplete".to_string()),
+                " Sync Incomplete ",
+                Color::Yellow,
+            ),
+        ] {
+            model.sync_status = status;
+            assert_footer(&mut model, text, color);
+        }
+
+        // Why sync isn't running takes the place of the last result
+        let offline = theme.sync_offline;
+        model.sync_status = SyncStatus::Offline;
+        model.e2e_status = E2EState::Locked;
+        assert_footer(&mut model, " Locked ", offline);
+        model.network_online.store(false, Ordering::SeqCst);
+        assert_footer(&mut model, " Offline (no network) ", offline);
+        model.user_plan = Some(Plan::Free);
+        assert_footer(&mut model, " Free (local only) ", offline);
+        model.user_email = None;
+        assert_footer(&mut model, " Guest ", offline);
+        model.config.general.offline_mode = true;
+        assert_footer(&mut model, " Offline Mode ", offline);
+    }
+
+    fn assert_footer(model: &mut Model<'_>, text: &str, color: Color) {
+        let (line, fg) = footer_line(model);
+        assert!(line.starts_with(text), "{:?}", line);
+        assert_eq!(fg, color, "{:?}", line);
+    }
+
+    #[tokio::test]
+    async fn the_footer_padlock_follows_the_e2e_state() {
+        let mut model = model().await;
+        let theme = model.config.theme.clone();
+        model.user_plan = Some(Plan::Pro);
+        model.e2e_status = E2EState::Unlocked;
+        // Guests have no padlock
+        assert!(!footer_line(&mut model).0.contains("E2E"));
+
+        model.user_email = Some("test@example.com".to_string());
+        for (state, shown) in [
+            (E2EState::Unlocked, Some((" 🔓 E2E ", theme.sync_synced))),
+            (E2EState::Unlocking, Some((" ⠋ E2E ", theme.sync_syncing))),
+            (E2EState::Locked, Some((" 🔒 E2E ", theme.sync_error))),
+            (
+                E2EState::SetupRequired,
+                Some((" 🔒 E2E? ", theme.sync_offline)),
+            ),
+            (E2EState::Disabled, None),
+            (E2EState::UpgradeRequired, None),
+        ] {
+            model.e2e_status = state;
+            let buffer = draw(&mut model, 120, 24);
+            let line = row_text(&buffer, 22);
+            match shown {
+                Some((text, color)) => {
+                    assert!(line.contains(text), "{:?}: {:?}", state, line);
+                    let area = model.e2e_indicator_area.unwrap();
+                    assert_eq!(buffer[(area.x + 1, area.y)].fg, color, "{:?}", state);
+                }
+                None => {
+                    assert!(!line.contains("E2E"), "{:?}: {:?}", state, line);
+                    assert_eq!(model.e2e_indicator_area, None);
+                }
+            }
+        }
+        model.e2e_status = E2EState::Unlocked;
+        model.key_unverified = true;
+        assert!(footer_line(&mut model).0.contains(" 🔓 E2E* "));
+    }
+
+    #[tokio::test]
+    async fn e2e_state_follows_the_account_through_its_transitions() {
+        fn account(plan: Plan, salt: Option<&str>) -> sync::AuthMeResponse {
+            sync::AuthMeResponse {
+                _id: "user-1".to_string(),
+                plan,
+                subscription_status: "active".to_string(),
+                subscription_end_date: None,
+                encryption_salt: salt.map(str::to_string),
+                encryption_validator: None,
+            }
+        }
+        enum Step {
+            Account(sync::AuthMeResponse),
+            Sync(SyncEvent),
+            Logout,
+        }
+        use E2EState::*;
+        let salt = crypto::generate_salt();
+        let mut model = model().await;
+        model.user_email = Some("test@example.com".to_string());
+        assert_eq!(model.e2e_status, Disabled);
+
+        // Each step, then the state and pane it leaves, and whether new notes
+        // are marked for encryption
+        let steps = [
+            // Signed in on a Pro account with E2E set up elsewhere
+            (
+                Step::Account(account(Plan::Pro, Some(&salt))),
+                Locked,
+                ActivePane::PassphraseInput,
+                true,
+            ),
+            (
+                Step::Sync(SyncEvent::UnlockResult(Err(UnlockError::WrongPassphrase))),
+                Locked,
+                ActivePane::PassphraseInput,
+                true,
+            ),
+            (
+                Step::Sync(SyncEvent::StatusChanged(SyncStatus::Unlocking)),
+                Unlocking,
+                ActivePane::PassphraseInput,
+                true,
+            ),
+            (
+                Step::Sync(SyncEvent::UnlockResult(Ok(KeyCheck::Verified))),
+                Unlocked,
+                ActivePane::List,
+                true,
+            ),
+            // A later account check keeps the unlocked key
+            (
+                Step::Account(account(Plan::Pro, Some(&salt))),
+                Unlocked,
+                ActivePane::List,
+                true,
+            ),
+            (
+                Step::Sync(SyncEvent::StatusChanged(SyncStatus::PaymentRequired)),
+                UpgradeRequired,
+                ActivePane::StatusDialog,
+                true,
+            ),
+            // Downgraded: sync stops and the local salt goes
+            (
+                Step::Account(account(Plan::Free, Some(&salt))),
+                Disabled,
+                ActivePane::StatusDialog,
+                false,
+            ),
+            // Upgraded again, on an account without E2E
+            (
+                Step::Account(account(Plan::Pro, None)),
+                SetupRequired,
+                ActivePane::E2ESetup,
+                true,
+            ),
+            (Step::Logout, Disabled, ActivePane::E2ESetup, false),
+        ];
+        for (i, (step, state, pane, encrypts)) in steps.into_iter().enumerate() {
+            match step {
+                Step::Account(me) => model.apply_account_info(me).await.unwrap(),
+                Step::Sync(event) => {
+                    if matches!(event, SyncEvent::UnlockResult(Ok(_))) {
+                        *model.crypto_key.lock().unwrap() = Some(Zeroizing::new([7; 32]));
+                    }
+                    model.update(Message::Sync(event)).await.unwrap();
+                }
+                Step::Logout => model.perform_logout().await.unwrap(),
+            }
+            assert_eq!(model.e2e_status, state, "step {}", i);
+            assert_eq!(model.active_pane, pane, "step {}", i);
+            assert_eq!(model.e2e_status.is_enabled(), encrypts, "step {}", i);
+        }
+        assert_eq!(model.repo.get_salt().await.unwrap(), None);
+        assert!(model.crypto_key.lock().unwrap().is_none());
+        assert_eq!(model.user_email, None);
+    }
+
+    #[tokio::test]
+    async fn the_main_screen_in_mono_uses_attributes_only() {
+        let mut model = model().await;
+        let notes: Vec<db::Note> = serde_json::from_value(serde_json::json!([
+            {
+                "id": "a1",
+                "content": "Groceries\n- milk\n- eggs",
+                "updated_at": "2026-03-01T09:30:00+00:00",
+                "is_deleted": 0
+            },
+            {
+                "id": "b2",
+                "content": "# Trip\npack socks",
+                "updated_at": "2026-02-01T18:00:00+00:00",
+                "is_deleted": 0
+            },
+        ]))
+        .unwrap();
+        model
+            .repo
+            .pull_upsert_notes(notes, String::new())
+            .await
+            .unwrap();
+        model.refresh_notes(false).await.unwrap();
+        model.select_note_by_id("a1").await.unwrap();
+        model.color_mode = config::ColorMode::Mono;
+
+        let mut terminal = Terminal::new(TestBackend::new(64, 20)).unwrap();
+        terminal
+            .draw(|f| {
+                model.ui(f);
+                palette::apply(f.buffer_mut(), model.color_mode, &model.config.theme);
+            })
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        assert!(buffer
+            .content
+            .iter()
+            .all(|c| c.fg == Color::Reset && c.bg == Color::Reset));
+
+        // Each row, then what marks it: Bold, Reversed, Underlined
+        let marks = |y: u16| -> String {
+            (0..64)
+                .map(|x| {
+                    let modifier = buffer[(x, y)].modifier;
+                    if modifier.contains(Modifier::REVERSED) {
+                        'R'
+                    } else if modifier.contains(Modifier::UNDERLINED) {
+                        'U'
+                    } else if modifier.contains(Modifier::BOLD) {
+                        'B'
+                    } else {
+                        ' '
+                    }
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        };
+        let mut shown: Vec<String> = (0..20)
+            .flat_map(|y| [row_text(buffer, y), marks(y)])
+            .collect();
+        // The version changes every release; redact it
+        let version = format!("v{} • Guest Mode (Local Only)", env!("CARGO_PKG_VERSION"));
+        assert_eq!(shown[14].trim(), version);
+        shown[14] = "<version>".to_string();
+        let bold = "B".repeat(64);
+        let expected = [
+            "                           \u{eb58}  RISU NOTE                         ",
+            &bold,
+            "                  ██████╗ ██╗███████╗██╗   ██╗                  ",
+            &bold,
+            "                  ██╔══██╗██║██╔════╝██║   ██║                  ",
+            &bold,
+            "                  ██████╔╝██║███████╗██║   ██║                  ",
+            &bold,
+            "                  ██╔══██╗██║╚════██║██║   ██║                  ",
+            &bold,
+            "                  ██║  ██║██║███████║╚██████╔╝                  ",
+            &bold,
+            "                  ╚═╝  ╚═╝╚═╝╚══════╝ ╚═════╝                   ",
+            &bold,
+            "<version>",
+            &bold,
+            "┌ Notes ──────────┐┌ Editor ───────────────────────────────────┐",
+            "BBBBBBBBBBBBBBBBBBB",
+            "│>>   Groceries   ││Groceries                                  │",
+            "BRRRRRRRRRRRRRRRRRB",
+            "│      Updated: 2…││- milk                                     │",
+            "BRRRRRRRRRRRRRRRRRB",
+            "│     Trip        ││- eggs                                     │",
+            "B                 B",
+            "│      Updated: 2…││                                           │",
+            "B                 B",
+            "│                 ││                                           │",
+            "B                 B",
+            "│                 ││                                           │",
+            "B                 B",
+            "│                 ││                                           │",
+            "B                 B",
+            "│                 ││                                           │",
+            "B                 B",
+            "└─────────────────┘└───────────────────────────────────────────┘",
+            "BBBBBBBBBBBBBBBBBBB",
+            " Guest      j/k: Move  •  Enter: Open  •  i: Edit  •  n: New  • ",
+            "BBBBBBBBBBBB",
+            "            S: Scratch  •  d: Delete  •  c: Label  •  u: Due  • ",
+            "BBBBBBBBBBBB",
+        ];
+        assert_eq!(shown, expected);
+    }
+
+    // A drawn screen as text: the rows, then each run of cells that isn't
+    // plain, as `row:first-last fg bg modifiers`. The row with the version
+    // in it is left out, so releases don't churn the snapshots.
+    fn screen_snapshot(buffer: &ratatui::buffer::Buffer) -> String {
+        let version = format!("v{}", env!("CARGO_PKG_VERSION"));
+        let area = buffer.area;
+        let mut rows = Vec::new();
+        let mut styles = Vec::new();
+        for y in 0..area.height {
+            let text = row_text(buffer, y);
+            if text.contains(&version) {
+                rows.push("<version>".to_string());
+                continue;
+            }
+            rows.push(text.trim_end().to_string());
+            let mut x = 0;
+            while x < area.width {
+                let cell = &buffer[(x, y)];
+                let style = (cell.fg, cell.bg, cell.modifier);
+                let start = x;
+                while x < area.width && {
+                    let next = &buffer[(x, y)];
+                    (next.fg, next.bg, next.modifier) == style
+                } {
+                    x += 1;
+                }
+                if style != (Color::Reset, Color::Reset, Modifier::empty()) {
+                    styles.push(format!(
+                        "{}:{}-{} {:?} {:?} {:?}",
+                        y,
+                        start,
+                        x - 1,
+                        style.0,
+                        style.1,
+                        style.2
+                    ));
+                }
+            }
+        }
+        format!("{}\n--- styles\n{}\n", rows.join("\n"), styles.join("\n"))
+    }
+
+    // Screens a rendering change shouldn't move, checked against
+    // tests/snapshots. Run with RISU_UPDATE_SNAPSHOTS=1 to accept a change
+    // on purpose.
+    #[tokio::test]
+    async fn screens_match_their_snapshots() {
+        let mut model = model().await;
+        // Shown in local time, so written in it too
+        let at = |month, day| {
+            Local
+                .with_ymd_and_hms(2026, month, day, 9, 30, 0)
+                .unwrap()
+                .to_rfc3339()
+        };
+        let notes: Vec<db::Note> = serde_json::from_value(serde_json::json!([
+            {
+                "id": "a1",
+                "content": "Groceries\n- milk\n- eggs\n```rust\nfn main() {}\n```",
+                "updated_at": at(3, 1),
+                "created_at": at(3, 1),
+                "is_deleted": 0
+            },
+            {
+                "id": "b2",
+                "content": "# Trip\npack socks\n**bold** and `code`",
+                "updated_at": at(2, 1),
+                "created_at": at(2, 1),
+                "is_deleted": 0
+            },
+            {
+                "id": "c3",
+                "content": "日本語のメモ 😀 with a title too long for the list",
+                "updated_at": at(1, 1),
+                "created_at": at(1, 1),
+                "is_deleted": 0
+            },
+        ]))
+        .unwrap();
+        model
+            .repo
+            .pull_upsert_notes(notes, String::new())
+            .await
+            .unwrap();
+        model.refresh_notes(false).await.unwrap();
+        model.select_note_by_id("a1").await.unwrap();
+
+        let mut screens = Vec::new();
+        let mut shoot = |model: &mut Model<'_>, name: &str, width, height| {
+            screens.push((
+                name.to_string(),
+                screen_snapshot(&draw(model, width, height)),
+            ));
+        };
+        shoot(&mut model, "list", 100, 30);
+        shoot(&mut model, "list_narrow", 40, 14);
+        model.active_pane = ActivePane::Editor;
+        model.mode = Mode::Insert;
+        model.textarea.move_cursor(CursorMove::Down);
+        shoot(&mut model, "editor_insert", 100, 30);
+        model.mode = Mode::VisualLine;
+        model.visual_anchor_row = Some(1);
+        model.textarea.start_selection();
+        model.textarea.move_cursor(CursorMove::Down);
+        shoot(&mut model, "editor_visual_line", 100, 30);
+        model.textarea.cancel_selection();
+        model.mode = Mode::Normal;
+        model.visual_anchor_row = None;
+        model.active_pane = ActivePane::Search;
+        model.search_textarea.insert_str("trip");
+        model.refresh_notes(true).await.unwrap();
+        shoot(&mut model, "search", 100, 30);
+        model.search_textarea = TextArea::default();
+        model.refresh_notes(false).await.unwrap();
+        for (pane, name) in [
+            (ActivePane::PassphraseInput, "passphrase"),
+            (ActivePane::E2ESetup, "e2e_setup"),
+            (ActivePane::DeleteConfirm, "delete_confirm"),
+        ] {
+            model.active_pane = pane;
+            shoot(&mut model, name, 100, 30);
+        }
+        model.active_pane = ActivePane::List;
+        model.user_email = Some("test@example.com".to_string());
+        model.e2e_status = E2EState::Unlocked;
+        model.sync_status = SyncStatus::Error("boom".to_string());
+        shoot(&mut model, "sync_error", 100, 30);
+
+        check_snapshots(screens);
+    }
+
+    #[tokio::test]
+    async fn empty_states_match_their_snapshots() {
+        let mut model = model().await;
+        model.refresh_notes(false).await.unwrap();
+
+        let mut screens = Vec::new();
+        let mut shoot = |model: &mut Model<'_>, name: &str, width, height| {
+            screens.push((
+                name.to_string(),
+                screen_snapshot(&draw(model, width, height)),
+            ));
+        };
+        // A fresh install: the list says how to start, the editor lists keys
+        shoot(&mut model, "empty", 100, 30);
+        shoot(&mut model, "empty_narrow", 40, 20);
+
+        model
+            .repo
+            .save_note(None, "Groceries\nmilk".to_string(), false, false)
+            .await
+            .unwrap();
+        model.active_pane = ActivePane::Search;
+        keys(&mut model, "socks\n").await;
+        assert_eq!(model.active_pane, ActivePane::List);
+        shoot(&mut model, "empty_no_match", 100, 30);
+
+        model.search_textarea = TextArea::default();
+        model.due_view = true;
+        model.refresh_notes(true).await.unwrap();
+        shoot(&mut model, "empty_nothing_due", 100, 30);
+
+        check_snapshots(screens);
+    }
+
+    // Compares each (name, snapshot) with tests/snapshots/<name>.txt, or
+    // writes them there with RISU_UPDATE_SNAPSHOTS set
+    fn check_snapshots(screens: Vec<(String, String)>) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+        let update = std::env::var_os("RISU_UPDATE_SNAPSHOTS").is_some();
+        let mut changed = Vec::new();
+        for (name, shown) in screens {
+            let path = dir.join(format!("{}.txt", name));
+            if update {
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(&path, &shown).unwrap();
+            } else if fs::read_to_string(&path).ok().as_deref() != Some(shown.as_str()) {
+                eprintln!("{} now draws as:\n{}", name, shown);
+                changed.push(name);
+            }
+        }
+        assert!(changed.is_empty(), "screens changed: {:?}", changed);
+    }
+
+    #[tokio::test]
+    async fn the_list_keeps_its_scroll_offset_across_refreshes_and_the_editor() {
+        let mut model = model().await;
+        let mut ids = Vec::new();
+        for i in 0..20 {
+            let id = model
+                .repo
+                .save_note(None, format!("Note {:02}", i), false, false)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        model.refresh_notes(false).await.unwrap();
+
+        // Scrolled down to the 13th note, then back up two
+        model.list_state.select(Some(12));
+        draw(&mut model, 120, 24);
+        let rows = model.list_rows;
+        assert!(rows > 2 && rows < 12, "{} rows", rows);
+        let offset = 13 - rows;
+        assert_eq!(model.list_state.offset(), offset);
+        keys(&mut model, "kk").await;
+        let screen = screen_snapshot(&draw(&mut model, 120, 24));
+        assert_eq!(model.list_offset, offset);
+
+        // A refresh with nothing changed leaves the viewport where it was
+        model.refresh_notes(false).await.unwrap();
+        assert_eq!(model.list_state.offset(), offset);
+        assert_eq!(screen_snapshot(&draw(&mut model, 120, 24)), screen);
+
+        // Into the editor and out again, after something reset the offset
+        keys(&mut model, "\n").await;
+        assert_eq!(model.active_pane, ActivePane::Editor);
+        draw(&mut model, 120, 24);
+        model.list_state.select(None);
+        model.list_state.select(Some(10));
+        assert_eq!(model.list_state.offset(), 0);
+        keys(&mut model, "\u{1b}").await;
+        assert_eq!(model.active_pane, ActivePane::List);
+        assert_eq!(model.list_state.offset(), offset);
+        assert_eq!(screen_snapshot(&draw(&mut model, 120, 24)), screen);
+
+        // The oldest notes go: the viewport moves up just enough to leave no
+        // blank rows, with the same note still selected
+        let selected = model.selected_note_id();
+        for id in &ids[..20 - 11] {
+            model.repo.delete_note(id.clone()).await.unwrap();
+        }
+        model.refresh_notes(false).await.unwrap();
+        assert_eq!(model.selected_note_id(), selected);
+        assert_eq!(model.list_state.offset(), 11 - rows);
+    }
+
+    #[tokio::test]
+    async fn a_failed_save_keeps_the_editor_on_its_note() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local.db");
+        let mut model = model().await;
+        model.repo = Repo::open(&path).unwrap();
+        let ids = open_notes(&mut model, &["first", "second"]).await;
+        model.repo = Repo::open_read_only(&path).unwrap();
+
+        model.textarea.insert_str("edited ");
+        model
+            .handle_key_event(press(KeyCode::Char('o'), event::KeyModifiers::CONTROL))
+            .await
+            .unwrap();
+        assert_eq!(model.current_note_id.as_ref(), Some(&ids[1]));
+        assert_eq!(model.textarea.lines(), ["edited second"]);
+        assert!(model
+            .editor_message
+            .as_ref()
+            .is_some_and(|m| m.contains("Couldn't save")));
+
+        model
+            .handle_key_event(press(KeyCode::Esc, event::KeyModifiers::NONE))
+            .await
+            .unwrap();
+        assert_eq!(model.active_pane, ActivePane::Editor);
+        assert_eq!(model.textarea.lines(), ["edited second"]);
+    }
+
+    // Keys in insert mode with auto-pairs on, as the editor routes them
+    fn type_keys(model: &mut Model, keys: &[KeyCode]) {
+        for &code in keys {
+            let key = event::KeyEvent::new(code, event::KeyModifiers::NONE);
+            if !model.auto_pair_input(key) {
+                model.textarea.input(key);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn backspace_removes_only_pairs_auto_pairs_just_inserted() {
+        use KeyCode::{Backspace, Char, Left};
+        let mut model = model().await;
+
+        type_keys(&mut model, &[Char('('), Char('[')]);
+        assert_eq!(model.textarea.lines(), ["([])"]);
+        type_keys(&mut model, &[Backspace]);
+        assert_eq!(model.textarea.lines(), ["()"]);
+        type_keys(&mut model, &[Backspace]);
+        assert_eq!(model.textarea.lines(), [""]);
+
+        // Typed inside, or walked back into: the pair is just text now
+        type_keys(&mut model, &[Char('('), Char('x'), Backspace, Backspace]);
+        assert_eq!(model.textarea.lines(), [")"]);
+        model.textarea = TextArea::new(vec![String::new()]);
+        type_keys(&mut model, &[Char('"'), Char('"'), Left, Backspace]);
+        assert_eq!(model.textarea.lines(), ["\""]);
+
+        // Text that was already there
+        model.textarea = TextArea::new(vec!["f()".to_string()]);
+        model.textarea.move_cursor(CursorMove::Jump(0, 2));
+        type_keys(&mut model, &[Backspace]);
+        assert_eq!(model.textarea.lines(), ["f)"]);
     }
-    Ok(())
 }
@@ -1,124 +1,245 @@
+// The markdown risu understands, as line blocks and inline spans. The preview
+// below and the HTML export (`html`) both render from these tokens.
+
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
 
-pub fn parse_markdown(content: &str) -> Text<'_> {
-    let mut lines = Vec::new();
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block<'a> {
+    /// A ``` line opening or closing a code block, kept whole
+    Fence(&'a str),
+    Code(&'a str),
+    Heading(usize, &'a str),
+    Item {
+        checked: Option<bool>,
+        text: &'a str,
+    },
+    Quote(&'a str),
+    /// A `| a | b |` line; `TableRule` is the `|---|---|` under a header
+    TableRow(&'a str),
+    TableRule(&'a str),
+    Text(&'a str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline<'a> {
+    Text(&'a str),
+    Bold(&'a str),
+    Code(&'a str),
+    Link { text: &'a str, url: &'a str },
+}
+
+pub fn blocks(content: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
     let mut in_code_block = false;
 
     for line in content.lines() {
         if line.starts_with("```") {
             in_code_block = !in_code_block;
-            let style = Style::default().fg(Color::DarkGray);
-            lines.push(Line::from(Span::styled(line.to_string(), style)));
-            continue;
+            blocks.push(Block::Fence(line));
+        } else if in_code_block {
+            blocks.push(Block::Code(line));
+        } else if let Some((level, rest)) = heading(line) {
+            blocks.push(Block::Heading(level, rest));
+        } else if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            let (checked, text) = match rest.get(..4) {
+                Some("[ ] ") => (Some(false), &rest[4..]),
+                Some("[x] ") | Some("[X] ") => (Some(true), &rest[4..]),
+                _ => (None, rest),
+            };
+            blocks.push(Block::Item { checked, text });
+        } else if let Some(rest) = line.strip_prefix("> ") {
+            blocks.push(Block::Quote(rest));
+        } else if is_table_row(line) {
+            let rule = table_cells(line)
+                .iter()
+                .all(|c| !c.is_empty() && c.chars().all(|ch| matches!(ch, '-' | ':')));
+            blocks.push(if rule {
+                Block::TableRule(line)
+            } else {
+                Block::TableRow(line)
+            });
+        } else {
+            blocks.push(Block::Text(line));
         }
+    }
+    blocks
+}
 
-        if in_code_block {
-            lines.push(Line::from(Span::styled(
-                line.to_string(),
-                Style::default().fg(Color::Magenta),
-            )));
-            continue;
+// `#` to `######` followed by a space
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    line[level..].strip_prefix(' ').map(|rest| (level, rest))
+}
+
+fn is_table_row(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 2 && line.starts_with('|') && line.ends_with('|')
+}
+
+/// The trimmed cells of a table row, without the outer pipes.
+pub fn table_cells(row: &str) -> Vec<&str> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = row.strip_suffix('|').unwrap_or(row);
+    row.split('|').map(str::trim).collect()
+}
+
+/// `**bold**`, `` `code` `` and `[text](url)`. An unclosed marker is text.
+pub fn inlines(line: &str) -> Vec<Inline<'_>> {
+    let mut spans = Vec::new();
+    let mut current = line;
+
+    while !current.is_empty() {
+        let bold_start = current.find("**");
+        // ``` is handled as a fence, not inline code
+        let code_start = current
+            .find('`')
+            .filter(|&i| !current[i..].starts_with("```"));
+        let link_start = current
+            .match_indices('[')
+            .map(|(i, _)| i)
+            .find(|&i| link_at(&current[i..]).is_some());
+        let Some(start) = [bold_start, code_start, link_start]
+            .into_iter()
+            .flatten()
+            .min()
+        else {
+            spans.push(Inline::Text(current));
+            break;
+        };
+        if start > 0 {
+            spans.push(Inline::Text(&current[..start]));
         }
+        let rest = &current[start..];
 
-        if let Some(rest) = line.strip_prefix("# ") {
-            lines.push(Line::from(Span::styled(
+        if Some(start) == link_start {
+            let (text, url, len) = link_at(rest).unwrap();
+            spans.push(Inline::Link { text, url });
+            current = &rest[len..];
+        } else if Some(start) == code_start {
+            match rest[1..].find('`') {
+                Some(end) => {
+                    spans.push(Inline::Code(&rest[1..1 + end]));
+                    current = &rest[1 + end + 1..];
+                }
+                None => {
+                    spans.push(Inline::Text("`"));
+                    current = &rest[1..];
+                }
+            }
+        } else {
+            match rest[2..].find("**") {
+                Some(end) => {
+                    spans.push(Inline::Bold(&rest[2..2 + end]));
+                    current = &rest[2 + end + 2..];
+                }
+                None => {
+                    spans.push(Inline::Text("**"));
+                    current = &rest[2..];
+                }
+            }
+        }
+    }
+    spans
+}
+
+// `[text](url)` at the start of `s`: the text, the url, and the bytes used
+fn link_at(s: &str) -> Option<(&str, &str, usize)> {
+    let close = s.find("](")?;
+    let text = &s[1..close];
+    if text.is_empty() || text.contains('[') {
+        return None;
+    }
+    let rest = &s[close + 2..];
+    let end = rest.find(')')?;
+    let url = &rest[..end];
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((text, url, close + 2 + end + 1))
+}
+
+pub fn parse_markdown(content: &str) -> Text<'_> {
+    let lines: Vec<Line> = blocks(content)
+        .into_iter()
+        .map(|block| match block {
+            Block::Fence(line) => Line::from(Span::styled(
+                line.to_string(),
+                Style::default().fg(Color::DarkGray),
+            )),
+            Block::Code(line) => Line::from(Span::styled(
+                line.to_string(),
+                Style::default().fg(Color::Magenta),
+            )),
+            Block::Heading(1, rest) => Line::from(Span::styled(
                 format!("{} ", rest),
                 Style::default()
                     .fg(Color::Yellow)
                     .add_modifier(Modifier::BOLD)
                     .add_modifier(Modifier::UNDERLINED),
-            )));
-        } else if let Some(rest) = line.strip_prefix("## ") {
-            lines.push(Line::from(Span::styled(
+            )),
+            Block::Heading(2, rest) => Line::from(Span::styled(
                 rest.to_string(),
                 Style::default()
                     .fg(Color::Cyan)
                     .add_modifier(Modifier::BOLD),
-            )));
-        } else if let Some(rest) = line.strip_prefix("### ") {
-            lines.push(Line::from(Span::styled(
+            )),
+            Block::Heading(_, rest) => Line::from(Span::styled(
                 rest.to_string(),
                 Style::default()
                     .fg(Color::Green)
                     .add_modifier(Modifier::BOLD),
-            )));
-        } else if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
-            lines.push(Line::from(vec![
-                Span::styled("  • ", Style::default().fg(Color::Cyan)),
-                Span::raw(rest.to_string()),
-            ]));
-        } else if let Some(rest) = line.strip_prefix("> ") {
-            lines.push(Line::from(Span::styled(
+            )),
+            Block::Item { checked, text } => {
+                let checkbox = match checked {
+                    Some(true) => "[x] ",
+                    Some(false) => "[ ] ",
+                    None => "",
+                };
+                Line::from(vec![
+                    Span::styled("  • ", Style::default().fg(Color::Cyan)),
+                    Span::raw(format!("{}{}", checkbox, text)),
+                ])
+            }
+            Block::Quote(rest) => Line::from(Span::styled(
                 format!("  ┃ {}", rest),
                 Style::default().fg(Color::DarkGray),
-            )));
-        } else {
-            // Basic inline styling: **bold**, `code`
-            let mut spans = Vec::new();
-            let mut current = line;
-
-            while !current.is_empty() {
-                let bold_start = current.find("**");
-                let code_start = current.find("`").filter(|&i| {
-                    // check if it's not part of ``` (which should be handled above)
-                    !current[i..].starts_with("```")
-                });
-
-                match (bold_start, code_start) {
-                    (Some(b), Some(c)) if b < c => {
-                        spans.push(Span::raw(current[..b].to_string()));
-                        if let Some(end) = current[b + 2..].find("**") {
-                            spans.push(Span::styled(
-                                current[b + 2..b + 2 + end].to_string(),
-                                Style::default()
-                                    .add_modifier(Modifier::BOLD)
-                                    .fg(Color::LightYellow),
-                            ));
-                            current = &current[b + 2 + end + 2..];
-                        } else {
-                            spans.push(Span::raw("**"));
-                            current = &current[b + 2..];
-                        }
-                    }
-                    (_, Some(c)) => {
-                        spans.push(Span::raw(current[..c].to_string()));
-                        if let Some(end) = current[c + 1..].find("`") {
-                            spans.push(Span::styled(
-                                current[c + 1..c + 1 + end].to_string(),
-                                Style::default()
-                                    .bg(Color::Rgb(40, 44, 52))
-                                    .fg(Color::LightCyan),
-                            ));
-                            current = &current[c + 1 + end + 1..];
-                        } else {
-                            spans.push(Span::raw("`"));
-                            current = &current[c + 1..];
-                        }
-                    }
-                    (Some(b), None) => {
-                        spans.push(Span::raw(current[..b].to_string()));
-                        if let Some(end) = current[b + 2..].find("**") {
-                            spans.push(Span::styled(
-                                current[b + 2..b + 2 + end].to_string(),
-                                Style::default()
-                                    .add_modifier(Modifier::BOLD)
-                                    .fg(Color::LightYellow),
-                            ));
-                            current = &current[b + 2 + end + 2..];
-                        } else {
-                            spans.push(Span::raw("**"));
-                            current = &current[b + 2..];
-                        }
-                    }
-                    (None, None) => {
-                        spans.push(Span::raw(current.to_string()));
-                        break;
-                    }
-                }
+            )),
+            Block::TableRow(line) | Block::TableRule(line) | Block::Text(line) => {
+                Line::from(inline_spans(line))
             }
-            lines.push(Line::from(spans));
-        }
-    }
+        })
+        .collect();
     Text::from(lines)
 }
+
+fn inline_spans(line: &str) -> Vec<Span<'static>> {
+    inlines(line)
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => Span::raw(text.to_string()),
+            Inline::Bold(text) => Span::styled(
+                text.to_string(),
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::LightYellow),
+            ),
+            Inline::Code(text) => Span::styled(
+                text.to_string(),
+                Style::default()
+                    .bg(Color::Rgb(40, 44, 52))
+                    .fg(Color::LightCyan),
+            ),
+            Inline::Link { text, .. } => Span::styled(
+                text.to_string(),
+                Style::default()
+                    .fg(Color::LightBlue)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+        })
+        .collect()
+}
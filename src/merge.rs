@@ -0,0 +1,230 @@
+// Line-level three-way merge of note content against the last synced version.
+
+use crate::diff::{self, DiffOp};
+
+pub const CONFLICT_LOCAL: &str = "<<<<<<< local";
+pub const CONFLICT_SEPARATOR: &str = "=======";
+pub const CONFLICT_REMOTE: &str = ">>>>>>> remote";
+
+#[derive(Debug, PartialEq)]
+pub enum MergeResult {
+    Clean(String),
+    // Overlapping edits, written out between conflict markers
+    Conflicted(String),
+}
+
+// Base lines `start..end` replaced by `lines`
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn hunks<'a>(base: &'a str, other: &'a str) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut pos = 0;
+    let mut open = false;
+    for op in diff::diff_lines(base, other) {
+        match op {
+            DiffOp::Same(_) => {
+                pos += 1;
+                open = false;
+            }
+            DiffOp::Removed(_) | DiffOp::Added(_) => {
+                if !open {
+                    hunks.push(Hunk {
+                        start: pos,
+                        end: pos,
+                        lines: Vec::new(),
+                    });
+                    open = true;
+                }
+                let hunk = hunks.last_mut().unwrap();
+                match op {
+                    DiffOp::Removed(_) => {
+                        pos += 1;
+                        hunk.end = pos;
+                    }
+                    DiffOp::Added(line) => hunk.lines.push(line),
+                    DiffOp::Same(_) => unreachable!(),
+                }
+            }
+        }
+    }
+    hunks
+}
+
+// `base[start..end]` with `hunks` (all inside that range) applied
+fn apply<'a>(base: &[&'a str], start: usize, end: usize, hunks: &[&Hunk<'a>]) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        out.extend_from_slice(&base[pos..hunk.start]);
+        out.extend(hunk.lines.iter().copied());
+        pos = hunk.end;
+    }
+    out.extend_from_slice(&base[pos..end]);
+    out
+}
+
+pub fn merge3(base: &str, local: &str, remote: &str) -> MergeResult {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let local_hunks = hunks(base, local);
+    let remote_hunks = hunks(base, remote);
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut conflicted = false;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < local_hunks.len() || j < remote_hunks.len() {
+        // Start a group at the earliest hunk, then pull in every hunk from
+        // either side that overlaps or touches it. Adjacent edits conflict,
+        // as in diff3.
+        let start = match (local_hunks.get(i), remote_hunks.get(j)) {
+            (Some(l), Some(r)) => l.start.min(r.start),
+            (Some(l), None) => l.start,
+            (None, Some(r)) => r.start,
+            (None, None) => unreachable!(),
+        };
+        let mut end = start;
+        let (mut group_local, mut group_remote) = (Vec::new(), Vec::new());
+        loop {
+            if let Some(h) = local_hunks.get(i).filter(|h| h.start <= end) {
+                end = end.max(h.end);
+                group_local.push(h);
+                i += 1;
+            } else if let Some(h) = remote_hunks.get(j).filter(|h| h.start <= end) {
+                end = end.max(h.end);
+                group_remote.push(h);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        out.extend_from_slice(&base_lines[pos..start]);
+        let local_side = apply(&base_lines, start, end, &group_local);
+        let remote_side = apply(&base_lines, start, end, &group_remote);
+        if group_remote.is_empty() || local_side == remote_side {
+            out.extend(local_side);
+        } else if group_local.is_empty() {
+            out.extend(remote_side);
+        } else {
+            conflicted = true;
+            out.push(CONFLICT_LOCAL);
+            out.extend(local_side);
+            out.push(CONFLICT_SEPARATOR);
+            out.extend(remote_side);
+            out.push(CONFLICT_REMOTE);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&base_lines[pos..]);
+
+    let merged = out.join("\n");
+    if conflicted {
+        MergeResult::Conflicted(merged)
+    } else {
+        MergeResult::Clean(merged)
+    }
+}
+
+pub fn has_conflict_markers(content: &str) -> bool {
+    content.lines().any(|l| l == CONFLICT_LOCAL) && content.lines().any(|l| l == CONFLICT_REMOTE)
+}
+
+/// The local and remote versions a conflicted note was merged from: text
+/// outside the markers goes to both, each block to its own side. None when
+/// the note has no markers.
+pub fn conflict_sides(content: &str) -> Option<(String, String)> {
+    if !has_conflict_markers(content) {
+        return None;
+    }
+    let (mut local, mut remote) = (Vec::new(), Vec::new());
+    // (in the local block, in the remote block)
+    let mut block = (false, false);
+    for line in content.lines() {
+        match line {
+            CONFLICT_LOCAL => block = (true, false),
+            CONFLICT_SEPARATOR if block.0 => block = (false, true),
+            CONFLICT_REMOTE if block.1 => block = (false, false),
+            _ => {
+                if !block.1 {
+                    local.push(line);
+                }
+                if !block.0 {
+                    remote.push(line);
+                }
+            }
+        }
+    }
+    Some((local.join("\n"), remote.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "a\nb\nc\nd\ne";
+
+    #[test]
+    fn edits_far_apart_merge_cleanly() {
+        assert_eq!(
+            merge3(BASE, "a\nB\nc\nd\ne", "a\nb\nc\nD\ne"),
+            MergeResult::Clean("a\nB\nc\nD\ne".to_string())
+        );
+        assert_eq!(
+            merge3("a\nb\nc", "a\nb", "z\na\nb\nc"),
+            MergeResult::Clean("z\na\nb".to_string())
+        );
+    }
+
+    #[test]
+    fn one_sided_and_identical_edits_merge_cleanly() {
+        assert_eq!(
+            merge3(BASE, BASE, "a\nb\nC\nd\ne"),
+            MergeResult::Clean("a\nb\nC\nd\ne".to_string())
+        );
+        assert_eq!(
+            merge3(BASE, "a\nb\nC\nd\ne", BASE),
+            MergeResult::Clean("a\nb\nC\nd\ne".to_string())
+        );
+        assert_eq!(
+            merge3(BASE, "a\nX\ne", "a\nX\ne"),
+            MergeResult::Clean("a\nX\ne".to_string())
+        );
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_edits_conflict() {
+        assert_eq!(
+            merge3(BASE, "a\nL\nc\nd\ne", "a\nR\nc\nd\ne"),
+            MergeResult::Conflicted(
+                "a\n<<<<<<< local\nL\n=======\nR\n>>>>>>> remote\nc\nd\ne".to_string()
+            )
+        );
+        // Like diff3, edits to neighbouring lines are not merged
+        assert_eq!(
+            merge3("a\nb\nc", "a\nB\nc", "a\nb\nC"),
+            MergeResult::Conflicted(
+                "a\n<<<<<<< local\nB\nc\n=======\nb\nC\n>>>>>>> remote".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn conflict_sides_recovers_both_versions() {
+        let (local, remote) = ("a\nB\nc", "a\nb\nC");
+        let MergeResult::Conflicted(merged) = merge3("a\nb\nc", local, remote) else {
+            panic!("expected a conflict");
+        };
+        assert!(has_conflict_markers(&merged));
+        assert_eq!(
+            conflict_sides(&merged),
+            Some((local.to_string(), remote.to_string()))
+        );
+        assert_eq!(conflict_sides(BASE), None);
+        assert!(!has_conflict_markers("<<<<<<< local\nonly one marker"));
+    }
+}
@@ -0,0 +1,62 @@
+// Counters for one TUI session, written to the log as a single line on a clean
+// exit (`general.metrics`). Meant for "risu felt slow" reports, so the draw
+// timings are kept whole and summarized only at the end.
+
+use std::time::{Duration, Instant};
+
+pub struct SessionMetrics {
+    started: Instant,
+    draws: Vec<Duration>,
+    pub keys: u64,
+    pub syncs_attempted: u64,
+    pub syncs_succeeded: u64,
+    pub notes_saved: u64,
+}
+
+impl SessionMetrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            draws: Vec::new(),
+            keys: 0,
+            syncs_attempted: 0,
+            syncs_succeeded: 0,
+            notes_saved: 0,
+        }
+    }
+
+    pub fn record_draw(&mut self, took: Duration) {
+        self.draws.push(took);
+    }
+
+    pub fn renders(&self) -> usize {
+        self.draws.len()
+    }
+
+    /// `key=value` pairs, so the line can be grepped and split by tools.
+    pub fn summary(&self, db_queue_high_water: usize) -> String {
+        let mut draws = self.draws.clone();
+        draws.sort();
+        let avg = if draws.is_empty() {
+            Duration::ZERO
+        } else {
+            draws.iter().sum::<Duration>() / draws.len() as u32
+        };
+        let p95 = draws
+            .get((draws.len() * 95).div_ceil(100).saturating_sub(1))
+            .copied()
+            .unwrap_or_default();
+        format!(
+            "Session metrics: duration_s={} renders={} keys={} syncs_attempted={} syncs_succeeded={} notes_saved={} draw_avg_ms={:.2} draw_p95_ms={:.2} db_queue_max={}",
+            self.started.elapsed().as_secs(),
+            self.renders(),
+            self.keys,
+            self.syncs_attempted,
+            self.syncs_succeeded,
+            self.notes_saved,
+            avg.as_secs_f64() * 1000.0,
+            p95.as_secs_f64() * 1000.0,
+            db_queue_high_water
+        )
+    }
+}
@@ -0,0 +1,338 @@
+// Cursor motions the textarea doesn't provide. Positions are (row, char column).
+
+fn bracket_pair(c: char) -> Option<(char, char, bool)> {
+    match c {
+        '(' => Some(('(', ')', true)),
+        ')' => Some(('(', ')', false)),
+        '[' => Some(('[', ']', true)),
+        ']' => Some(('[', ']', false)),
+        '{' => Some(('{', '}', true)),
+        '}' => Some(('{', '}', false)),
+        _ => None,
+    }
+}
+
+// Match for the bracket exactly at `pos`, scanning across lines with a nesting
+// counter. Brackets inside code spans are not special-cased.
+pub fn matching_bracket(lines: &[String], pos: (usize, usize)) -> Option<(usize, usize)> {
+    let (row, col) = pos;
+    let ch = lines.get(row)?.chars().nth(col)?;
+    let (open, close, forward) = bracket_pair(ch)?;
+    let mut depth = 0usize;
+    let mut visit = |c: char| {
+        if c == open {
+            depth = if forward {
+                depth + 1
+            } else {
+                depth.saturating_sub(1)
+            };
+        } else if c == close {
+            depth = if forward {
+                depth.saturating_sub(1)
+            } else {
+                depth + 1
+            };
+        }
+        depth == 0
+    };
+
+    if forward {
+        for (r, line) in lines.iter().enumerate().skip(row) {
+            let skip = if r == row { col } else { 0 };
+            for (c, ch) in line.chars().enumerate().skip(skip) {
+                if (ch == open || ch == close) && visit(ch) {
+                    return Some((r, c));
+                }
+            }
+        }
+    } else {
+        for r in (0..=row).rev() {
+            let chars: Vec<char> = lines[r].chars().collect();
+            let end = if r == row { col + 1 } else { chars.len() };
+            for c in (0..end).rev() {
+                if (chars[c] == open || chars[c] == close) && visit(chars[c]) {
+                    return Some((r, c));
+                }
+            }
+        }
+    }
+    None
+}
+
+// Target of `%`: like vim, uses the first bracket at or after the cursor on the
+// current line.
+pub fn bracket_jump(lines: &[String], cursor: (usize, usize)) -> Option<(usize, usize)> {
+    let (row, col) = cursor;
+    let start = lines
+        .get(row)?
+        .chars()
+        .enumerate()
+        .skip(col)
+        .find(|(_, c)| bracket_pair(*c).is_some())?
+        .0;
+    matching_bracket(lines, (row, start))
+}
+
+// Vim's word classes: keyword characters, other punctuation, and blanks
+fn char_class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+// End (exclusive column) of `dw` from `col`: the start of the next word, or
+// the end of the line after its last word, so the newline is kept.
+pub fn word_forward_end(line: &str, col: usize) -> usize {
+    let classes: Vec<u8> = line.chars().map(char_class).collect();
+    let mut i = col.min(classes.len());
+    if let Some(&class) = classes.get(i).filter(|&&c| c != 0) {
+        while classes.get(i) == Some(&class) {
+            i += 1;
+        }
+    }
+    while classes.get(i) == Some(&0) {
+        i += 1;
+    }
+    i
+}
+
+// End (exclusive column) of `de` from `col`: through the last character of
+// this word, or of the next one when the cursor already is on a word's end.
+pub fn word_end_end(line: &str, col: usize) -> usize {
+    let classes: Vec<u8> = line.chars().map(char_class).collect();
+    let mut i = col + 1;
+    while classes.get(i) == Some(&0) {
+        i += 1;
+    }
+    let Some(&class) = classes.get(i) else {
+        return classes.len();
+    };
+    while classes.get(i + 1) == Some(&class) {
+        i += 1;
+    }
+    i + 1
+}
+
+// Start column of `db` from `col`: the first character of the word before
+// the cursor. Stops at the start of the line.
+pub fn word_back_start(line: &str, col: usize) -> usize {
+    let classes: Vec<u8> = line.chars().map(char_class).collect();
+    let mut i = col.min(classes.len());
+    while i > 0 && classes[i - 1] == 0 {
+        i -= 1;
+    }
+    if i == 0 {
+        return 0;
+    }
+    let class = classes[i - 1];
+    while i > 0 && classes[i - 1] == class {
+        i -= 1;
+    }
+    i
+}
+
+// End (exclusive column) of `cw` from `col`. Inside a word it stops at the
+// word's end, so the blanks after it are kept; on blanks it acts like `dw`.
+pub fn change_word_end(line: &str, col: usize) -> usize {
+    let classes: Vec<u8> = line.chars().map(char_class).collect();
+    match classes.get(col) {
+        None => classes.len(),
+        Some(0) => word_forward_end(line, col),
+        Some(&class) => {
+            let mut i = col;
+            while classes.get(i) == Some(&class) {
+                i += 1;
+            }
+            i
+        }
+    }
+}
+
+// Columns `start..end` of `iw` at `col`: the run of one class under the
+// cursor, so a word, a stretch of punctuation, or the blanks between words.
+pub fn word_under(line: &str, col: usize) -> (usize, usize) {
+    let classes: Vec<u8> = line.chars().map(char_class).collect();
+    let Some(&class) = classes.get(col) else {
+        return (classes.len(), classes.len());
+    };
+    let mut start = col;
+    while start > 0 && classes[start - 1] == class {
+        start -= 1;
+    }
+    let mut end = col;
+    while classes.get(end) == Some(&class) {
+        end += 1;
+    }
+    (start, end)
+}
+
+// Column of `^`/`I`: the first non-blank character, or the end of a line
+// that is all blanks.
+pub fn first_non_blank(line: &str) -> usize {
+    line.chars()
+        .position(|c| !c.is_whitespace())
+        .unwrap_or_else(|| line.chars().count())
+}
+
+// Column `f`/`F`/`t`/`T` (`kind`) lands on for `target`, searching the line
+// from `col`; None when it isn't there. `repeat` is for `;` and `,`: a `t`
+// that already stands next to its target skips past it instead of staying.
+pub fn find_char(line: &str, col: usize, kind: char, target: char, repeat: bool) -> Option<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let till = matches!(kind, 't' | 'T');
+    let skip = usize::from(till && repeat);
+    if matches!(kind, 'f' | 't') {
+        let i = (col + 1 + skip..chars.len()).find(|&i| chars[i] == target)?;
+        Some(if till { i - 1 } else { i })
+    } else {
+        let i = (0..col.saturating_sub(skip))
+            .rev()
+            .find(|&i| chars[i] == target)?;
+        Some(if till { i + 1 } else { i })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn matching_bracket_skips_nested_pairs_across_lines() {
+        let text = lines(&["fn f(a: (u8, u8)) {", "    [a.0]", "}"]);
+        assert_eq!(matching_bracket(&text, (0, 4)), Some((0, 16)));
+        assert_eq!(matching_bracket(&text, (0, 16)), Some((0, 4)));
+        assert_eq!(matching_bracket(&text, (0, 18)), Some((2, 0)));
+        assert_eq!(matching_bracket(&text, (2, 0)), Some((0, 18)));
+        assert_eq!(matching_bracket(&text, (1, 4)), Some((1, 8)));
+    }
+
+    #[test]
+    fn matching_bracket_needs_a_bracket_and_its_partner() {
+        let text = lines(&["(open", "a ) b"]);
+        assert_eq!(matching_bracket(&text, (0, 1)), None);
+        assert_eq!(matching_bracket(&text, (0, 9)), None);
+        assert_eq!(matching_bracket(&text, (5, 0)), None);
+        assert_eq!(matching_bracket(&lines(&["(a"]), (0, 0)), None);
+        assert_eq!(matching_bracket(&lines(&["b)"]), (0, 1)), None);
+    }
+
+    #[test]
+    fn bracket_jump_uses_the_first_bracket_from_the_cursor() {
+        let text = lines(&["call(x) [y]"]);
+        assert_eq!(bracket_jump(&text, (0, 0)), Some((0, 6)));
+        assert_eq!(bracket_jump(&text, (0, 6)), Some((0, 4)));
+        assert_eq!(bracket_jump(&text, (0, 7)), Some((0, 10)));
+        assert_eq!(bracket_jump(&text, (0, 11)), None);
+        assert_eq!(bracket_jump(&lines(&["no brackets"]), (0, 0)), None);
+    }
+
+    #[test]
+    fn bracket_positions_count_chars_not_bytes() {
+        let text = lines(&["日本(語)"]);
+        assert_eq!(bracket_jump(&text, (0, 0)), Some((0, 4)));
+        assert_eq!(matching_bracket(&text, (0, 4)), Some((0, 2)));
+    }
+
+    // Words start at 0, 4, 7 (the dot), 8 and 13
+    const LINE: &str = "foo bar.baz  qux";
+
+    #[test]
+    fn word_forward_end_stops_at_the_next_word() {
+        assert_eq!(word_forward_end(LINE, 0), 4);
+        assert_eq!(word_forward_end(LINE, 1), 4);
+        assert_eq!(word_forward_end(LINE, 3), 4);
+        assert_eq!(word_forward_end(LINE, 4), 7);
+        assert_eq!(word_forward_end(LINE, 7), 8);
+        assert_eq!(word_forward_end(LINE, 8), 13);
+        // The last word runs to the end of the line, not into the next one
+        assert_eq!(word_forward_end(LINE, 13), 16);
+        assert_eq!(word_forward_end(LINE, 30), 16);
+        assert_eq!(word_forward_end("日本 語", 0), 3);
+    }
+
+    #[test]
+    fn word_end_end_moves_on_from_a_word_end() {
+        assert_eq!(word_end_end(LINE, 0), 3);
+        assert_eq!(word_end_end(LINE, 2), 7);
+        assert_eq!(word_end_end(LINE, 6), 8);
+        assert_eq!(word_end_end(LINE, 10), 16);
+        assert_eq!(word_end_end(LINE, 15), 16);
+        assert_eq!(word_end_end("", 0), 0);
+    }
+
+    #[test]
+    fn word_back_start_stops_at_the_line_start() {
+        assert_eq!(word_back_start(LINE, 4), 0);
+        assert_eq!(word_back_start(LINE, 6), 4);
+        assert_eq!(word_back_start(LINE, 8), 7);
+        assert_eq!(word_back_start(LINE, 13), 8);
+        assert_eq!(word_back_start(LINE, 0), 0);
+        assert_eq!(word_back_start("   x", 3), 0);
+        assert_eq!(word_back_start(LINE, 30), 13);
+    }
+
+    #[test]
+    fn change_word_end_keeps_the_blanks_after_a_word() {
+        assert_eq!(change_word_end(LINE, 0), 3);
+        assert_eq!(change_word_end(LINE, 1), 3);
+        assert_eq!(change_word_end(LINE, 7), 8);
+        assert_eq!(change_word_end(LINE, 13), 16);
+        // On blanks it is `dw`
+        assert_eq!(change_word_end(LINE, 3), 4);
+        assert_eq!(change_word_end(LINE, 11), 13);
+        assert_eq!(change_word_end(LINE, 30), 16);
+    }
+
+    #[test]
+    fn word_under_takes_the_run_of_one_class() {
+        assert_eq!(word_under(LINE, 1), (0, 3));
+        assert_eq!(word_under(LINE, 3), (3, 4));
+        assert_eq!(word_under(LINE, 7), (7, 8));
+        assert_eq!(word_under(LINE, 9), (8, 11));
+        assert_eq!(word_under(LINE, 12), (11, 13));
+        assert_eq!(word_under(LINE, 30), (16, 16));
+        assert_eq!(word_under("", 0), (0, 0));
+    }
+
+    #[test]
+    fn first_non_blank_skips_indentation() {
+        assert_eq!(first_non_blank("    - item"), 4);
+        assert_eq!(first_non_blank("\t\tx"), 2);
+        assert_eq!(first_non_blank("x"), 0);
+        assert_eq!(first_non_blank("   "), 3);
+        assert_eq!(first_non_blank(""), 0);
+    }
+
+    #[test]
+    fn find_char_lands_on_or_next_to_the_target() {
+        let line = "a,b,c;d";
+        assert_eq!(find_char(line, 0, 'f', ',', false), Some(1));
+        assert_eq!(find_char(line, 1, 'f', ',', false), Some(3));
+        assert_eq!(find_char(line, 0, 't', ',', false), Some(0));
+        assert_eq!(find_char(line, 6, 'F', ',', false), Some(3));
+        assert_eq!(find_char(line, 6, 'T', ',', false), Some(4));
+        assert_eq!(find_char(line, 0, 'f', 'x', false), None);
+        assert_eq!(find_char(line, 0, 'F', ',', false), None);
+        assert_eq!(find_char(line, 6, 'f', ',', false), None);
+        assert_eq!(find_char("日,本", 0, 'f', ',', false), Some(1));
+    }
+
+    #[test]
+    fn find_char_repeat_moves_a_till_past_its_target() {
+        let line = "a,b,c;d";
+        // `;` after `t,` stands before the first comma; it goes to the second
+        assert_eq!(find_char(line, 0, 't', ',', true), Some(2));
+        assert_eq!(find_char(line, 4, 'T', ',', true), Some(2));
+        // `f` needs no skip: it already stands on the target
+        assert_eq!(find_char(line, 1, 'f', ',', true), Some(3));
+        assert_eq!(find_char(line, 2, 't', ',', true), None);
+    }
+}
@@ -0,0 +1,60 @@
+// Whitespace cleanup applied on save with `editor.normalize_on_save`: LF line
+// endings, no trailing blanks except a markdown hard break (exactly two
+// spaces), at most two blank lines in a row, and one final newline.
+
+// Blank lines kept in a row; more are dropped
+const MAX_BLANK_RUN: usize = 2;
+
+pub fn note(content: &str) -> String {
+    let content = content.replace("\r\n", "\n");
+    let mut out = String::with_capacity(content.len() + 1);
+    let mut blank_run = 0;
+    for line in content.lines() {
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            blank_run += 1;
+            if blank_run > MAX_BLANK_RUN {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        out.push_str(trimmed);
+        if !trimmed.is_empty() && &line[trimmed.len()..] == "  " {
+            out.push_str("  ");
+        }
+        out.push('\n');
+    }
+    let end = out.trim_end_matches('\n').len();
+    out.truncate(end);
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_and_trailing_blanks() {
+        assert_eq!(note("a\r\nb\r\n"), "a\nb\n");
+        assert_eq!(note("a \t\nb   \n"), "a\nb\n");
+        // Exactly two spaces is a markdown hard break
+        assert_eq!(note("line  \nnext"), "line  \nnext\n");
+        assert_eq!(note("   \nx"), "\nx\n");
+    }
+
+    #[test]
+    fn blank_runs_and_final_newline() {
+        assert_eq!(note("a\n\n\n\n\nb"), "a\n\n\nb\n");
+        assert_eq!(note("a\n \n\t\n  \nb"), "a\n\n\nb\n");
+        assert_eq!(note("a\n\n\n"), "a\n");
+        assert_eq!(note("a"), "a\n");
+    }
+
+    #[test]
+    fn normalizing_twice_changes_nothing() {
+        let once = note("# Title  \r\n\r\n\r\n\r\nbody \ntext  \n\n\n");
+        assert_eq!(note(&once), once);
+    }
+}
@@ -0,0 +1,73 @@
+// Fits a drawn frame to what the terminal can show (`theme.color_mode`). Runs
+// over the finished buffer, so themed and hard-coded colors (markdown
+// preview, dialogs) are all covered in one place.
+
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier};
+
+use crate::config::{ColorMode, ThemeConfig};
+
+/// `mode` must already be resolved (not `Auto`).
+pub fn apply(buf: &mut Buffer, mode: ColorMode, theme: &ThemeConfig) {
+    match mode {
+        ColorMode::Auto | ColorMode::Truecolor => {}
+        ColorMode::Indexed => {
+            for cell in buf.content.iter_mut() {
+                cell.fg = to_indexed(cell.fg);
+                cell.bg = to_indexed(cell.bg);
+            }
+        }
+        ColorMode::Mono => {
+            for cell in buf.content.iter_mut() {
+                // A background marks something (selection, cursor line, a
+                // badge); keep the mark as an attribute
+                if cell.bg != Color::Reset && cell.bg != theme.background {
+                    cell.modifier |= if cell.bg == theme.editor_cursor_line {
+                        Modifier::UNDERLINED
+                    } else {
+                        Modifier::REVERSED
+                    };
+                }
+                // The focused pane's border is told apart by weight alone
+                if cell.fg == theme.border_active {
+                    cell.modifier |= Modifier::BOLD;
+                }
+                cell.fg = Color::Reset;
+                cell.bg = Color::Reset;
+            }
+        }
+    }
+}
+
+fn to_indexed(color: Color) -> Color {
+    match color {
+        Color::Rgb(r, g, b) => Color::Indexed(nearest_256(r, g, b)),
+        other => other,
+    }
+}
+
+// Nearest entry in the xterm 6x6x6 cube (16-231) or gray ramp (232-255)
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |v: u8| {
+        (0..6)
+            .min_by_key(|&i| (LEVELS[i] as i32 - v as i32).abs())
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = ((avg.saturating_sub(8)) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_index;
+
+    let dist = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(cr, r) + d(cg, g) + d(cb, b)
+    };
+    if dist((gray_level, gray_level, gray_level)) < dist(cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
@@ -0,0 +1,415 @@
+// Line-oriented interface (`risu tui --plain`) for screen readers: no
+// alternate screen, no borders or repaints. Every change of state is printed
+// as a line of its own, and notes are edited in $VISUAL/$EDITOR. Covers the
+// everyday subset: list, search, read, edit, create, delete and sync.
+
+use anyhow::{Context, Result};
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::config::{self, AppConfig};
+use crate::crypto;
+use crate::db::{short_id, Note, Repo, SHORT_ID_LEN};
+use crate::logger;
+use crate::sync::{SyncEvent, SyncManager, SyncStatus};
+use crate::{derive_title, format_note_time, NoteQuery};
+
+const HELP: &str = "Commands:
+  list              List the notes (the search applies)
+  search [text]     Only list notes matching text, is: and label: filters work; no text clears it
+  open <number>     Select a note and read it
+  read              Read the selected note again
+  edit [number]     Edit the selected (or given) note in your editor
+  new               Write a new note in your editor
+  delete [number]   Delete the selected (or given) note, after asking
+  sync              Sync now
+  help              Show this list
+  quit              Leave risu";
+
+struct Plain {
+    repo: Repo,
+    notes: Vec<Note>,
+    query: String,
+    selected: Option<String>,
+    // Whether saves are encrypted when pushed, and made without the key
+    e2e: bool,
+    locked: bool,
+    sync_trigger: Option<mpsc::Sender<()>>,
+}
+
+pub async fn run(repo: Repo, config: AppConfig) -> Result<()> {
+    let e2e = repo.get_salt().await?.is_some();
+    let crypto_key = Arc::new(Mutex::new(None));
+    let mut locked = e2e;
+    if e2e {
+        match config::get_passphrase()? {
+            Some(passphrase) => match crate::unlock_for_cli(&repo, passphrase, &crypto_key).await {
+                Ok(()) => locked = false,
+                Err(e) => say(&format!("Encryption stays locked: {:#}", e)),
+            },
+            None => say("Encryption is locked. Unlock once in the full TUI to sync."),
+        }
+    }
+
+    let mut sync_trigger = None;
+    let mut sync_handle = None;
+    if config.general.offline_mode {
+        say("Offline mode: notes are not synced.");
+    } else if !config::get_token().is_empty() {
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (trigger_tx, trigger_rx) = mpsc::channel(1);
+        let manager = SyncManager::new(
+            repo.clone(),
+            event_tx,
+            trigger_rx,
+            Arc::clone(&crypto_key),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(true)),
+            config.sync.clone(),
+        );
+        tokio::spawn(announce_sync(event_rx));
+        sync_handle = Some(tokio::spawn(manager.start()));
+        sync_trigger = Some(trigger_tx);
+    }
+
+    let mut plain = Plain {
+        repo,
+        notes: Vec::new(),
+        query: String::new(),
+        selected: None,
+        e2e,
+        locked,
+        sync_trigger,
+    };
+    plain.load().await?;
+    say(&format!(
+        "risu, plain mode. {}. Type help for the commands.",
+        count_text(plain.notes.len())
+    ));
+
+    loop {
+        print!("risu> ");
+        io::stdout().flush()?;
+        // Read only while prompting, so the editor gets the keyboard
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).map(|n| (n, line))
+        })
+        .await??;
+        // End of input
+        if line.0 == 0 {
+            println!();
+            break;
+        }
+        match plain.command(line.1.trim()).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => say(&format!("Error: {:#}", e)),
+        }
+    }
+
+    // A pass in flight finishes before the manager stops
+    plain.sync_trigger = None;
+    if let Some(handle) = sync_handle {
+        let _ = handle.await;
+    }
+    say("Goodbye.");
+    Ok(())
+}
+
+fn say(line: &str) {
+    println!("{}", line);
+}
+
+fn count_text(count: usize) -> String {
+    match count {
+        0 => "No notes".to_string(),
+        1 => "1 note".to_string(),
+        n => format!("{} notes", n),
+    }
+}
+
+// One line per finished sync pass; the steps in between aren't announced
+async fn announce_sync(mut event_rx: mpsc::Receiver<SyncEvent>) {
+    while let Some(event) = event_rx.recv().await {
+        let line = match event {
+            SyncEvent::StatusChanged(SyncStatus::Synced) => "Sync finished.".to_string(),
+            SyncEvent::StatusChanged(SyncStatus::Error(msg)) => format!("Sync failed: {}", msg),
+            SyncEvent::StatusChanged(
+                SyncStatus::Syncing | SyncStatus::Encrypting(_, _) | SyncStatus::Unlocking,
+            ) => continue,
+            SyncEvent::StatusChanged(status) => format!("Sync: {}.", status.as_str()),
+            SyncEvent::Conflicts(count) => format!(
+                "Sync finished. {} to merge; search is:conflict to find them.",
+                count_text(count)
+            ),
+            SyncEvent::NoteError { id, msg } => {
+                format!("Sync skipped note {}: {}", short_id(&id, SHORT_ID_LEN), msg)
+            }
+            _ => continue,
+        };
+        say(&line);
+    }
+}
+
+impl Plain {
+    async fn load(&mut self) -> Result<()> {
+        let query = NoteQuery::parse(&self.query);
+        self.notes = self
+            .repo
+            .get_notes(false)
+            .await?
+            .into_iter()
+            .filter(|n| query.matches(n))
+            .collect();
+        Ok(())
+    }
+
+    // Returns false to quit
+    async fn command(&mut self, line: &str) -> Result<bool> {
+        let (name, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        match name {
+            "" => {}
+            "help" | "?" => say(HELP),
+            "quit" | "exit" | "q" => return Ok(false),
+            "list" | "ls" => {
+                self.load().await?;
+                self.list();
+            }
+            "search" | "/" => {
+                self.query = arg.to_string();
+                self.load().await?;
+                if self.query.is_empty() {
+                    say(&format!(
+                        "Search cleared. {}.",
+                        count_text(self.notes.len())
+                    ));
+                } else {
+                    say(&format!(
+                        "{} matching {}.",
+                        count_text(self.notes.len()),
+                        self.query
+                    ));
+                }
+                self.list();
+            }
+            "open" => {
+                let note = self.pick(arg)?;
+                self.selected = Some(note.id.clone());
+                self.read(&note);
+            }
+            "read" => {
+                let note = self.pick("")?;
+                self.read(&note);
+            }
+            "edit" => {
+                let note = self.pick(arg)?;
+                self.selected = Some(note.id.clone());
+                self.edit(Some(note)).await?;
+            }
+            "new" => self.edit(None).await?,
+            "delete" => {
+                let note = self.pick(arg)?;
+                self.delete(note).await?;
+            }
+            "sync" => match &self.sync_trigger {
+                Some(trigger) => {
+                    let _ = trigger.try_send(());
+                    say("Syncing.");
+                }
+                None => say("Sync is off: not logged in, or offline mode."),
+            },
+            // A bare number opens that note
+            number if number.parse::<usize>().is_ok() => {
+                let note = self.pick(number)?;
+                self.selected = Some(note.id.clone());
+                self.read(&note);
+            }
+            _ => say(&format!(
+                "Unknown command {}. Type help for the list.",
+                name
+            )),
+        }
+        Ok(true)
+    }
+
+    fn list(&self) {
+        for (i, note) in self.notes.iter().enumerate() {
+            let mut marks = String::new();
+            if self.selected.as_deref() == Some(note.id.as_str()) {
+                marks.push_str(" [selected]");
+            }
+            if note.has_conflict != 0 {
+                marks.push_str(" [conflict]");
+            }
+            if note.is_synced == 0 {
+                marks.push_str(" [not synced]");
+            }
+            if let Some(label) = &note.label {
+                marks.push_str(&format!(" [label {}]", label));
+            }
+            say(&format!(
+                "{}. {}, updated {}{}",
+                i + 1,
+                derive_title(&note.content),
+                format_note_time(&note.updated_at),
+                marks
+            ));
+        }
+        if self.notes.is_empty() {
+            say("No notes.");
+        }
+    }
+
+    // The note numbered `arg` in the last list, or the selected one
+    fn pick(&self, arg: &str) -> Result<Note> {
+        if arg.is_empty() {
+            return self
+                .selected
+                .as_ref()
+                .and_then(|id| self.notes.iter().find(|n| &n.id == id))
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No note selected. Give its number from list."));
+        }
+        let number: usize = arg
+            .parse()
+            .map_err(|_| anyhow::anyhow!("{} is not a note number", arg))?;
+        number
+            .checked_sub(1)
+            .and_then(|i| self.notes.get(i))
+            .cloned()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No note number {}; there are {}.",
+                    number,
+                    count_text(self.notes.len()).to_lowercase()
+                )
+            })
+    }
+
+    fn read(&self, note: &Note) {
+        if crypto::looks_like_ciphertext(&note.content) {
+            say("This note is encrypted and can't be read until encryption is unlocked.");
+            return;
+        }
+        let lines = note.content.lines().count();
+        say(&format!(
+            "Opened {}. {} line{}.",
+            derive_title(&note.content),
+            lines,
+            if lines == 1 { "" } else { "s" }
+        ));
+        for line in note.content.lines() {
+            say(line);
+        }
+        say("End of note.");
+    }
+
+    async fn edit(&mut self, note: Option<Note>) -> Result<()> {
+        let before = note.as_ref().map_or(String::new(), |n| n.content.clone());
+        if crypto::looks_like_ciphertext(&before) {
+            say("This note is encrypted and can't be edited until encryption is unlocked.");
+            return Ok(());
+        }
+        let after = tokio::task::spawn_blocking(move || edit_in_editor(&before)).await??;
+        let after = after.trim_end().to_string();
+        let unchanged = note.as_ref().map_or(String::new(), |n| n.content.clone());
+        if after == unchanged.trim_end() {
+            say("No changes.");
+            return Ok(());
+        }
+        if after.trim().is_empty() {
+            match note {
+                Some(note) => return self.delete(note).await,
+                None => {
+                    say("Nothing saved: the note was empty.");
+                    return Ok(());
+                }
+            }
+        }
+        let is_new = note.is_none();
+        let id = self
+            .repo
+            .save_note(note.map(|n| n.id), after.clone(), self.e2e, self.locked)
+            .await?;
+        self.selected = Some(id);
+        self.load().await?;
+        say(&format!(
+            "{} {}.",
+            if is_new { "Created" } else { "Saved" },
+            derive_title(&after)
+        ));
+        if let Some(trigger) = &self.sync_trigger {
+            let _ = trigger.try_send(());
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, note: Note) -> Result<()> {
+        let title = derive_title(&note.content);
+        print!("Delete {}? Type y to confirm: ", title);
+        io::stdout().flush()?;
+        let answer = tokio::task::spawn_blocking(|| {
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).map(|_| answer)
+        })
+        .await??;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            say("Not deleted.");
+            return Ok(());
+        }
+        self.repo.delete_note(note.id.clone()).await?;
+        logger::audit(logger::AuditEvent::Delete {
+            via: logger::Via::Tui,
+            notes: vec![logger::AuditNote::of(&note)],
+        });
+        if self.selected.as_ref() == Some(&note.id) {
+            self.selected = None;
+        }
+        self.load().await?;
+        say(&format!("Deleted {}.", title));
+        if let Some(trigger) = &self.sync_trigger {
+            let _ = trigger.try_send(());
+        }
+        Ok(())
+    }
+}
+
+// Runs $VISUAL or $EDITOR on a private temp copy of `content` and returns what
+// was saved. The copy is removed afterwards.
+fn edit_in_editor(content: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+    let args = crate::split_command(&editor)?;
+    let Some((program, args)) = args.split_first() else {
+        return Err(anyhow::anyhow!("$EDITOR is empty"));
+    };
+    let path = std::env::temp_dir().join(format!("risu-{}.md", uuid::Uuid::new_v4()));
+    config::private_file_options()
+        .open(&path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Couldn't write {}", path.display()))?;
+    let status = std::process::Command::new(program)
+        .args(args)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Couldn't start {}", editor));
+    let result = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read {}", path.display())),
+        Ok(status) => Err(anyhow::anyhow!("{} exited with {}", editor, status)),
+        Err(e) => Err(e),
+    };
+    let _ = std::fs::remove_file(&path);
+    result
+}
@@ -0,0 +1,95 @@
+// `@due(...)` and `@remind(...)` tokens in note text. The date inside is read
+// in local time: an ISO date (`2024-07-01`, optionally with `14:30`), `today`,
+// `tomorrow`, or an offset from now (`+3d`, `+2w`, `+4h`), each optionally
+// followed by a time of day (`9am`, `9:30pm`, `14:00`). Dates without a time
+// are due at 09:00.
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use regex::Regex;
+use std::sync::LazyLock;
+
+static TOKEN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"@(?:due|remind)\(([^)\n]*)\)").unwrap());
+
+const DEFAULT_TIME: NaiveTime = match NaiveTime::from_hms_opt(9, 0, 0) {
+    Some(time) => time,
+    None => unreachable!(),
+};
+
+/// The date specs of every reminder token in `content`, trimmed, in order.
+pub fn tokens(content: &str) -> Vec<String> {
+    let mut specs: Vec<String> = TOKEN
+        .captures_iter(content)
+        .map(|c| c[1].trim().to_string())
+        .collect();
+    specs.dedup();
+    specs
+}
+
+/// When `spec` falls due, relative to `now`. None if it can't be read.
+pub fn parse_due(spec: &str, now: DateTime<Local>) -> Option<DateTime<Utc>> {
+    let spec = spec.trim().to_ascii_lowercase();
+    let mut words = spec.split_whitespace();
+    let day = words.next()?;
+    let time = match words.next() {
+        Some(word) => Some(parse_time(word)?),
+        None => None,
+    };
+    if words.next().is_some() {
+        return None;
+    }
+
+    let today = now.date_naive();
+    let local = match day {
+        "today" => today.and_time(time.unwrap_or(DEFAULT_TIME)),
+        "tomorrow" => today.succ_opt()?.and_time(time.unwrap_or(DEFAULT_TIME)),
+        offset if offset.starts_with('+') => {
+            let target = now.naive_local() + parse_offset(&offset[1..])?;
+            match time {
+                Some(time) => target.date().and_time(time),
+                None => target,
+            }
+        }
+        iso if iso.contains('t') && time.is_none() => {
+            NaiveDateTime::parse_from_str(iso, "%Y-%m-%dt%H:%M").ok()?
+        }
+        iso => NaiveDate::parse_from_str(iso, "%Y-%m-%d")
+            .ok()?
+            .and_time(time.unwrap_or(DEFAULT_TIME)),
+    };
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+// `3d`, `2w`, `4h`
+fn parse_offset(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'h' => Duration::try_hours(n),
+        'd' => Duration::try_days(n),
+        'w' => Duration::try_weeks(n),
+        _ => None,
+    }
+}
+
+// `9am`, `9:30pm`, `14:00`, `9`
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    let (clock, pm) = match (s.strip_suffix("am"), s.strip_suffix("pm")) {
+        (Some(clock), _) => (clock, Some(false)),
+        (_, Some(clock)) => (clock, Some(true)),
+        _ => (s, None),
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+    let hour = match pm {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Context, Result};
 use reqwest::{Client, Method, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
@@ -9,18 +10,24 @@ use zeroize::Zeroizing;
 
 use crate::config;
 use crate::crypto;
-use crate::db::{Note, Repo};
+use crate::db::{KeyRotation, Note, Repo};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum SyncStatus {
     Synced,
     Syncing,
     Offline,
-    Error,
+    /// Carries the message shown in the status dialog
+    Error(String),
     Unlocking,
-    Unlocked,
+    /// Unlock attempt failed; carries the message shown to the user
+    UnlockFailed(String),
     PaymentRequired,
+    /// The refresh token was rejected; only a new login helps
+    SessionExpired,
     Warning(String),
+    /// Push progress while encrypting a batch of notes (done, total)
+    Encrypting(usize, usize),
 }
 
 impl SyncStatus {
@@ -29,21 +36,397 @@ impl SyncStatus {
             SyncStatus::Synced => "Synced",
             SyncStatus::Syncing => "Syncing...",
             SyncStatus::Offline => "Offline",
-            SyncStatus::Error => "Error",
+            SyncStatus::Error(_) => "Error",
             SyncStatus::Unlocking => "Unlocking...",
-            SyncStatus::Unlocked => "Unlocked",
+            SyncStatus::UnlockFailed(_) => "Locked",
             SyncStatus::PaymentRequired => "Upgrade Required",
+            SyncStatus::SessionExpired => "Session Expired",
             SyncStatus::Warning(msg) => msg,
+            SyncStatus::Encrypting(_, _) => "Encrypting...",
         }
     }
 }
 
+/// What the sync manager and unlock tasks report to the UI.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncEvent {
+    StatusChanged(SyncStatus),
+    Progress {
+        phase: SyncPhase,
+        done: usize,
+        total: usize,
+    },
+    UnlockResult(Result<KeyCheck, UnlockError>),
+    /// The sync finished, leaving this many notes with conflict markers
+    Conflicts(usize),
+    /// One note couldn't be synced; the rest went ahead
+    NoteError {
+        id: String,
+        msg: String,
+    },
+    /// Seconds the server's clock is ahead of ours (negative: behind), as
+    /// measured by the last sync check
+    ClockSkew(i64),
+    /// A passphrase change can't go further until this is dealt with
+    RotationBlocked(String),
+}
+
+// Percent-encodes a query parameter. Cursors are timestamps, and the `+` of a
+// `+00:00` offset would otherwise reach the server as a space.
+fn query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Drops synced tombstones older than `sync.tombstone_retention_days`; run
+/// after each sync and on startup. Failures only cost disk space, so they are
+/// logged rather than returned.
+pub async fn compact_tombstones(repo: &Repo, retention_days: u64) {
+    if retention_days == 0 {
+        return;
+    }
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(retention_days.min(36_500) as i64);
+    match repo.compact_tombstones(cutoff.to_rfc3339()).await {
+        Ok(0) => {}
+        Ok(removed) => {
+            crate::logger::log(&format!(
+                "Compacted {} tombstones older than {} days",
+                removed, retention_days
+            ));
+            crate::logger::audit(crate::logger::AuditEvent::EmptyTrash {
+                via: crate::logger::Via::Auto,
+                count: removed,
+            });
+        }
+        Err(e) => crate::logger::log(&format!("Tombstone compaction failed: {:?}", e)),
+    }
+}
+
+/// Abandons a passphrase change the server hasn't taken yet: the old key is
+/// unwrapped with the new one and loaded again, and notes already pushed
+/// under the new key go out again under the old one. The local salt and
+/// validator were never replaced, so they stay as they are. Taken under the
+/// sync lease, so no pass is pushing under the new key meanwhile.
+pub async fn cancel_key_rotation(
+    repo: &Repo,
+    crypto_key: &Mutex<Option<Zeroizing<[u8; 32]>>>,
+) -> Result<()> {
+    let rotation = repo
+        .get_key_rotation()
+        .await?
+        .ok_or_else(|| anyhow!("No passphrase change in progress"))?;
+    if rotation.server_updated {
+        return Err(anyhow!(
+            "The server already has the new passphrase; the change can only finish"
+        ));
+    }
+    let old_key = {
+        let guard = crypto_key.lock().unwrap();
+        let new_key = guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("Unlock with the new passphrase first"))?;
+        crypto::unwrap_key(&rotation.wrapped_old_key, new_key)
+            .context("The loaded key isn't the new passphrase's")?
+    };
+
+    let owner = format!("cancel-rotation-{}", std::process::id());
+    let until = chrono::Utc::now() + chrono::Duration::minutes(1);
+    if !repo.take_sync_lease(&owner, until).await? {
+        return Err(anyhow!("A sync is running. Try again when it ends."));
+    }
+    let aborted = repo.abort_key_rotation(&rotation).await;
+    let _ = repo.release_sync_lease(&owner).await;
+    let requeued = aborted?;
+
+    *crypto_key.lock().unwrap() = Some(old_key);
+    crate::logger::log(&format!(
+        "Key rotation cancelled; {} notes queued to go out under the old key",
+        requeued
+    ));
+    Ok(())
+}
+
+/// What [`check_passphrase`] made of a passphrase.
+#[derive(Debug)]
+pub enum PassphraseCheck {
+    /// The key matches the account's validator, or the cached copy offline
+    Valid(Zeroizing<[u8; 32]>),
+    /// The validator couldn't be fetched and none was cached, with the error
+    Unverified(Zeroizing<[u8; 32]>, String),
+    WrongPassphrase,
+    NoSalt,
+    Failed(String),
+}
+
+/// Derives the key for `passphrase` and checks it against the account's
+/// validator, which is cached for the next offline unlock. While a passphrase
+/// change is in progress the new key is checked against the pending
+/// validator instead; the server still holds the old one.
+pub async fn check_passphrase(
+    repo: &Repo,
+    client: &APIClient,
+    passphrase: String,
+) -> PassphraseCheck {
+    if passphrase.is_empty() {
+        return PassphraseCheck::WrongPassphrase;
+    }
+
+    let rotation = repo.get_key_rotation().await.unwrap_or(None);
+    let salt = match &rotation {
+        Some(r) => r.new_salt.clone(),
+        None => match repo.get_salt().await {
+            Ok(Some(salt)) => salt,
+            Ok(None) => return PassphraseCheck::NoSalt,
+            Err(e) => return PassphraseCheck::Failed(e.to_string()),
+        },
+    };
+
+    let key = match crypto::derive_key_async(passphrase, salt).await {
+        Ok(key) => key,
+        Err(e) => return PassphraseCheck::Failed(e.to_string()),
+    };
+
+    // Validate against the server validator, falling back to the cached copy when offline
+    let mut network_error = None;
+    let validator = match &rotation {
+        Some(r) => Some(r.new_validator.clone()),
+        None => match client.get_me().await {
+            Ok(me) => {
+                if let Some(validator) = &me.encryption_validator {
+                    let _ = repo.set_validator(validator).await;
+                }
+                me.encryption_validator
+            }
+            Err(e) => {
+                crate::logger::log(&format!(
+                    "Warning: Could not fetch validator from server: {}",
+                    e
+                ));
+                network_error = Some(e.to_string());
+                repo.get_validator().await.unwrap_or(None)
+            }
+        },
+    };
+
+    match (validator, network_error) {
+        (Some(validator), _) if !crypto::verify_validator(&validator, &key) => {
+            crate::logger::log("Invalid passphrase: Validation failed.");
+            PassphraseCheck::WrongPassphrase
+        }
+        (Some(_), _) => {
+            crate::logger::log("Passphrase validated successfully.");
+            PassphraseCheck::Valid(key)
+        }
+        (None, Some(e)) => PassphraseCheck::Unverified(key, e),
+        // An account without a validator has nothing to check against
+        (None, None) => PassphraseCheck::Valid(key),
+    }
+}
+
+/// Skew beyond which local timestamps can lose edits to last-write-wins.
+pub const CLOCK_SKEW_WARN_SECS: i64 = 120;
+
+/// `/sync/check`: where the server's notes end, and how its clock compares.
+pub struct SyncCheck {
+    pub last_updated_at: String,
+    /// Server time minus local time, from the response's `Date` header
+    pub clock_skew: Option<chrono::Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncPhase {
+    Encrypting,
+}
+
+/// How far an installed key could be checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyCheck {
+    Verified,
+    /// The validator couldn't be fetched (offline) and none was cached
+    Unverified,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnlockError {
+    WrongPassphrase,
+    NoSalt,
+    /// The server salt changed under us; the stored passphrase is stale
+    PassphraseChanged,
+    /// Details are in the log
+    Other,
+}
+
+impl UnlockError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            UnlockError::WrongPassphrase => "Invalid Passphrase! Try Again",
+            UnlockError::NoSalt => "Encryption is not set up on this device",
+            UnlockError::PassphraseChanged => "Passphrase changed on another device",
+            UnlockError::Other => "Unlock failed (see log)",
+        }
+    }
+}
+
+/// The server rejected the refresh token (or there is none), so the session
+/// can't be renewed without logging in again.
+#[derive(Debug)]
+pub struct SessionExpired;
+
+impl std::fmt::Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Session expired")
+    }
+}
+
+impl std::error::Error for SessionExpired {}
+
+pub fn is_session_expired(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<SessionExpired>())
+}
+
+// Refresh tokens are single-use: the server rotates them on every refresh.
+// Every APIClient in the process (TUI, SyncManager, background checks) takes
+// this lock so concurrent 401s share one refresh instead of racing.
+static REFRESH_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// Connectivity probes: slow while online, quicker while waiting for the network
+const PROBE_INTERVAL_ONLINE: Duration = Duration::from_secs(60);
+const PROBE_INTERVAL_OFFLINE: Duration = Duration::from_secs(10);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// How long a sync pass may hold the lease before another process may take
+// it, in case the holder died mid-pass. The pass renews it before each pull
+// page and each pushed note, and releases it when it ends.
+const SYNC_LEASE: Duration = Duration::from_secs(120);
+
+fn is_network_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        let reqwest_error = match cause.downcast_ref::<SyncError>() {
+            Some(SyncError::Network(r)) => Some(r),
+            _ => cause.downcast_ref::<reqwest::Error>(),
+        };
+        reqwest_error.is_some_and(|r| r.is_connect() || r.is_timeout())
+    })
+}
+
+/// Why an API call failed. Carried as the root cause of the `anyhow::Error`
+/// so callers can classify failures without matching on message text.
+#[derive(Debug)]
+pub enum SyncError {
+    /// 401 that a token refresh didn't fix
+    Unauthorized,
+    /// 402/403: the plan doesn't include sync
+    PaymentRequired,
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    Network(reqwest::Error),
+    Server(StatusCode),
+    /// The response body wasn't what we expected
+    Decode(String),
+}
+
+impl std::fmt::Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::Unauthorized => write!(f, "Not authorized, try logging in again"),
+            SyncError::PaymentRequired => write!(f, "Sync requires a paid plan"),
+            SyncError::RateLimited {
+                retry_after: Some(after),
+            } => write!(
+                f,
+                "Rate limited by the server, retry in {}s",
+                after.as_secs()
+            ),
+            SyncError::RateLimited { retry_after: None } => {
+                write!(f, "Rate limited by the server, retry later")
+            }
+            SyncError::Network(e) => write!(f, "Network error: {}", e),
+            SyncError::Server(status) => write!(f, "Server error: {}", status),
+            SyncError::Decode(e) => write!(f, "Unexpected server response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl SyncError {
+    fn from_response(resp: &Response) -> Self {
+        match resp.status() {
+            StatusCode::UNAUTHORIZED => SyncError::Unauthorized,
+            StatusCode::PAYMENT_REQUIRED | StatusCode::FORBIDDEN => SyncError::PaymentRequired,
+            StatusCode::TOO_MANY_REQUESTS => SyncError::RateLimited {
+                retry_after: resp
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse().ok())
+                    .map(Duration::from_secs),
+            },
+            status => SyncError::Server(status),
+        }
+    }
+}
+
+/// The status shown for a failed sync; the one place errors become UI state.
+fn status_for_error(e: &anyhow::Error) -> SyncStatus {
+    if is_session_expired(e) {
+        return SyncStatus::SessionExpired;
+    }
+    if is_network_error(e) {
+        return SyncStatus::Offline;
+    }
+    match e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<SyncError>())
+    {
+        Some(SyncError::PaymentRequired) => SyncStatus::PaymentRequired,
+        Some(err) => SyncStatus::Error(err.to_string()),
+        None => SyncStatus::Error(e.to_string()),
+    }
+}
+
+// Non-200 responses become a `SyncError` under "`failure`: status"
+fn check_status(resp: Response, failure: &str) -> Result<Response> {
+    if resp.status() == StatusCode::OK {
+        return Ok(resp);
+    }
+    let err = SyncError::from_response(&resp);
+    Err(anyhow!(err).context(format!("{}: {}", failure, resp.status())))
+}
+
+async fn decode<T: serde::de::DeserializeOwned>(resp: Response) -> Result<T> {
+    resp.json()
+        .await
+        .map_err(|e| anyhow!(SyncError::Decode(e.to_string())))
+}
+
 #[derive(Clone, Debug)]
 pub struct APIClient {
     client: Client,
     base_url: String,
 }
 
+impl Default for APIClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl APIClient {
     pub fn new() -> Self {
         Self {
@@ -71,7 +454,7 @@ impl APIClient {
 
             let token = config::get_token();
             if !token.is_empty() {
-                builder = builder.bearer_auth(token);
+                builder = builder.bearer_auth(&token);
             }
 
             if let Some(b) = body {
@@ -82,11 +465,13 @@ impl APIClient {
 
             match res {
                 Ok(resp) => {
-                    if resp.status() == StatusCode::UNAUTHORIZED
-                        && attempts == 1
-                        && self.refresh_token().await.is_ok()
-                    {
-                        continue;
+                    if resp.status() == StatusCode::UNAUTHORIZED && attempts == 1 {
+                        match self.refresh_after_unauthorized(&token).await {
+                            Ok(()) => continue,
+                            Err(e) if is_session_expired(&e) => return Err(e),
+                            // Transient refresh failure: report the original 401
+                            Err(_) => {}
+                        }
                     }
 
                     if resp.status().is_server_error() && attempts < max_attempts {
@@ -96,19 +481,35 @@ impl APIClient {
 
                     return Ok(resp);
                 }
-                Err(e) if attempts < max_attempts => {
+                Err(_) if attempts < max_attempts => {
                     time::sleep(Duration::from_millis(500 * attempts)).await;
                     continue;
                 }
-                Err(e) => return Err(e.into()),
+                Err(e) => return Err(anyhow!(SyncError::Network(e))),
             }
         }
     }
 
-    async fn refresh_token(&self) -> Result<()> {
+    /// Called after `rejected_token` got a 401. If another task refreshed while
+    /// we waited for the lock, the new token is simply retried.
+    async fn refresh_after_unauthorized(&self, rejected_token: &str) -> Result<()> {
+        let _guard = REFRESH_LOCK.lock().await;
+        if config::get_token() != rejected_token {
+            return Ok(());
+        }
+        self.refresh_locked().await
+    }
+
+    pub async fn refresh_token(&self) -> Result<()> {
+        let _guard = REFRESH_LOCK.lock().await;
+        self.refresh_locked().await
+    }
+
+    // Caller holds REFRESH_LOCK
+    async fn refresh_locked(&self) -> Result<()> {
         let data = config::get_token_data();
         if data.refresh_token.is_empty() {
-            return Err(anyhow!("No refresh token"));
+            return Err(anyhow!(SessionExpired).context("No refresh token"));
         }
 
         let resp = self
@@ -118,8 +519,21 @@ impl APIClient {
             .send()
             .await?;
 
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Refresh failed: {}", resp.status()));
+        match resp.status() {
+            StatusCode::OK => {}
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED => {
+                // Drop the dead refresh token so later 401s fail fast instead of
+                // retrying it; the id token stays for the account display.
+                crate::logger::log(&format!(
+                    "APIClient: Refresh token rejected ({}). Session expired.",
+                    resp.status()
+                ));
+                config::save_token_data(&data.id_token, "")?;
+                return Err(
+                    anyhow!(SessionExpired).context(format!("Refresh rejected: {}", resp.status()))
+                );
+            }
+            status => return Err(anyhow!("Refresh failed: {}", status)),
         }
 
         #[derive(Deserialize)]
@@ -127,35 +541,41 @@ impl APIClient {
             id_token: String,
             refresh_token: String,
         }
-        let res: RefreshRes = resp.json().await?;
+        let res: RefreshRes = decode(resp).await?;
         config::save_token_data(&res.id_token, &res.refresh_token)?;
         Ok(())
     }
 
-    pub async fn check_sync(&self) -> Result<String> {
+    pub async fn check_sync(&self) -> Result<SyncCheck> {
         let resp = self
             .authenticated_request::<()>(Method::GET, "/sync/check", None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Sync check failed: {}", resp.status()));
-        }
+        let resp = check_status(resp, "Sync check failed")?;
+        // The header has whole seconds, plenty against a two-minute threshold
+        let clock_skew = resp
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok())
+            .map(|server| server.with_timezone(&chrono::Utc) - chrono::Utc::now());
         #[derive(Deserialize)]
         struct CheckRes {
             last_updated_at: String,
         }
-        let res: CheckRes = resp.json().await?;
-        Ok(res.last_updated_at)
+        let res: CheckRes = decode(resp).await?;
+        Ok(SyncCheck {
+            last_updated_at: res.last_updated_at,
+            clock_skew,
+        })
     }
 
     pub async fn pull_changes(&self, since: &str) -> Result<PullResult> {
-        let path = format!("/sync/pull?since={}", since);
+        let path = format!("/sync/pull?since={}", query_value(since));
         let resp = self
             .authenticated_request::<()>(Method::GET, &path, None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Pull failed: {}", resp.status()));
-        }
-        let res: PullResult = resp.json().await?;
+        let resp = check_status(resp, "Pull failed")?;
+        let res: PullResult = decode(resp).await?;
         Ok(res)
     }
 
@@ -164,26 +584,28 @@ impl APIClient {
             .authenticated_request(Method::POST, "/sync/push", Some(note))
             .await?;
 
-        if resp.status() == StatusCode::FORBIDDEN {
-            return Err(anyhow!("Payment Required"));
-        }
-
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Push failed: {}", resp.status()));
-        }
+        check_status(resp, "Push failed")?;
         Ok(())
     }
 
+    /// Whether the API host answers at all. Any HTTP status counts as reachable.
+    pub async fn probe(&self) -> bool {
+        self.client
+            .head(format!("{}/health", self.base_url))
+            .timeout(PROBE_TIMEOUT)
+            .send()
+            .await
+            .is_ok()
+    }
+
     pub async fn start_login_session(&self) -> Result<LoginSession> {
         let resp = self
             .client
             .post(format!("{}/auth/init", self.base_url))
             .send()
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Init failed: {}", resp.status()));
-        }
-        let session: LoginSession = resp.json().await?;
+        let resp = check_status(resp, "Init failed")?;
+        let session: LoginSession = decode(resp).await?;
         Ok(session)
     }
 
@@ -197,10 +619,8 @@ impl APIClient {
                 refresh_token: String::new(),
             });
         }
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Poll failed: {}", resp.status()));
-        }
-        let res: PollResult = resp.json().await?;
+        let resp = check_status(resp, "Poll failed")?;
+        let res: PollResult = decode(resp).await?;
         Ok(res)
     }
 
@@ -208,10 +628,8 @@ impl APIClient {
         let resp = self
             .authenticated_request::<()>(Method::GET, "/auth/me", None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Get me failed: {}", resp.status()));
-        }
-        let res: AuthMeResponse = resp.json().await?;
+        let resp = check_status(resp, "Get me failed")?;
+        let res: AuthMeResponse = decode(resp).await?;
         Ok(res)
     }
 
@@ -224,24 +642,34 @@ impl APIClient {
         let resp = self
             .authenticated_request(Method::POST, "/auth/e2e/enable", Some(&body))
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Enable E2E failed: {}", resp.status()));
-        }
+        let resp = check_status(resp, "Enable E2E failed")?;
         #[derive(Deserialize)]
         struct EnableRes {
             encryption_salt: String,
         }
-        let res: EnableRes = resp.json().await?;
+        let res: EnableRes = decode(resp).await?;
         Ok(res.encryption_salt)
     }
 
+    /// Replaces the account salt/validator once every note is on the new key.
+    pub async fn e2e_rotate(&self, salt: &str, validator: &str) -> Result<()> {
+        let body = serde_json::json!({
+            "salt": salt,
+            "validator": validator
+        });
+
+        let resp = self
+            .authenticated_request(Method::POST, "/auth/e2e/rotate", Some(&body))
+            .await?;
+        check_status(resp, "Rotate E2E key failed")?;
+        Ok(())
+    }
+
     pub async fn reset_remote(&self) -> Result<()> {
         let resp = self
             .authenticated_request::<()>(Method::POST, "/sync/reset", None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Reset remote failed: {}", resp.status()));
-        }
+        check_status(resp, "Reset remote failed")?;
         Ok(())
     }
 
@@ -249,14 +677,12 @@ impl APIClient {
         let resp = self
             .authenticated_request::<()>(Method::POST, "/billing/checkout", None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Failed to get checkout URL: {}", resp.status()));
-        }
+        let resp = check_status(resp, "Failed to get checkout URL")?;
         #[derive(Deserialize)]
         struct UrlRes {
             url: String,
         }
-        let res: UrlRes = resp.json().await?;
+        let res: UrlRes = decode(resp).await?;
         Ok(res.url)
     }
 
@@ -264,14 +690,12 @@ impl APIClient {
         let resp = self
             .authenticated_request::<()>(Method::POST, "/billing/portal", None)
             .await?;
-        if resp.status() != StatusCode::OK {
-            return Err(anyhow!("Failed to get portal URL: {}", resp.status()));
-        }
+        let resp = check_status(resp, "Failed to get portal URL")?;
         #[derive(Deserialize)]
         struct UrlRes {
             url: String,
         }
-        let res: UrlRes = resp.json().await?;
+        let res: UrlRes = decode(resp).await?;
         Ok(res.url)
     }
 }
@@ -280,13 +704,71 @@ impl APIClient {
 pub struct AuthMeResponse {
     #[serde(rename = "id")]
     pub _id: String,
-    pub plan: String,
+    pub plan: Plan,
     pub subscription_status: String,
     pub subscription_end_date: Option<String>,
     pub encryption_salt: Option<String>,
     pub encryption_validator: Option<String>,
 }
 
+/// Subscription plan from `/auth/me`. Parsing ignores case and surrounding
+/// whitespace; unrecognised names are kept as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    Free,
+    Pro,
+    /// Early-bird accounts; same features as Pro
+    Dev,
+    Other(String),
+}
+
+impl Plan {
+    /// Name as the server spells it, used for the account cache
+    pub fn as_str(&self) -> &str {
+        match self {
+            Plan::Free => "free",
+            Plan::Pro => "pro",
+            Plan::Dev => "dev",
+            Plan::Other(name) => name,
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        match self {
+            Plan::Free => "Free",
+            Plan::Pro => "Pro",
+            Plan::Dev => "Early bird",
+            Plan::Other(name) => name,
+        }
+    }
+
+    /// Plans that include cloud sync (and with it, E2E encryption)
+    pub fn is_sync_eligible(&self) -> bool {
+        matches!(self, Plan::Pro | Plan::Dev)
+    }
+}
+
+impl std::str::FromStr for Plan {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Ok(match name.as_str() {
+            "free" => Plan::Free,
+            "pro" => Plan::Pro,
+            "dev" => Plan::Dev,
+            _ => Plan::Other(name),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Plan {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Ok(plan) = String::deserialize(deserializer)?.parse();
+        Ok(plan)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct LoginSession {
     pub session_id: String,
@@ -307,39 +789,202 @@ pub struct PullResult {
     pub next_cursor: String,
 }
 
+// Notes encrypted per spawn_blocking call during push
+const ENCRYPT_BATCH_SIZE: usize = 20;
+
+/// A pulled note after decryption, for `pull` to log and count.
+enum PulledNote {
+    Decrypted(Note),
+    /// Flagged plaintext but was ciphertext one of the keys could read
+    Recovered(Note),
+    Plaintext(Note),
+    /// A key opened it but the plaintext isn't UTF-8 (a bad encrypt on some
+    /// client). Stored with the invalid bytes replaced and held back from
+    /// push until accepted; `received` is the note as the server sent it
+    Garbled {
+        note: Note,
+        received: Note,
+    },
+    KeyMissing(String),
+    Undecryptable(String),
+}
+
+// CPU-bound half of handling a pulled note, run on the crypto workers
+fn open_pulled_note(
+    mut note: Note,
+    keys: &[(Zeroizing<[u8; 32]>, i32)],
+    stale_version: i32,
+) -> PulledNote {
+    let try_decrypt = |content: &str| {
+        keys.iter().find_map(|(key, version)| {
+            crypto::decrypt_bytes(content, key)
+                .ok()
+                .map(|plaintext| (plaintext, *version))
+        })
+    };
+    // Valid UTF-8, or the lossy text and `true`
+    let text = |plaintext: Vec<u8>| match String::from_utf8(plaintext) {
+        Ok(text) => (text, false),
+        Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), true),
+    };
+
+    if note.is_encrypted == 1 {
+        if keys.is_empty() {
+            return PulledNote::KeyMissing(note.id);
+        }
+        return match try_decrypt(&note.content) {
+            Some((plaintext, version)) => {
+                let (content, garbled) = text(plaintext);
+                let received = garbled.then(|| note.clone());
+                note.content = content;
+                note.is_encrypted = 0; // Decrypted for local storage
+                note.key_version = version;
+                match received {
+                    Some(received) => garbled_note(note, received),
+                    None => PulledNote::Decrypted(note),
+                }
+            }
+            None => PulledNote::Undecryptable(note.id),
+        };
+    }
+
+    // Handle is_encrypted == 0 (Potential plaintext or mislabeled encrypted data)
+
+    // Try to decrypt even if flag says 0, just in case (Recovery logic)
+    // Only try if it looks like base64 and has enough length
+    let recovered = if note.content.len() > 24 && !note.content.contains(' ') {
+        try_decrypt(&note.content)
+    } else {
+        None
+    };
+    match recovered {
+        Some((plaintext, version)) => {
+            let (content, garbled) = text(plaintext);
+            let received = garbled.then(|| note.clone());
+            note.content = content;
+            note.is_encrypted = 0;
+            note.key_version = version;
+            match received {
+                Some(received) => garbled_note(note, received),
+                None => PulledNote::Recovered(note),
+            }
+        }
+        None => {
+            // Save as-is (Plaintext)
+            note.key_version = stale_version;
+            PulledNote::Plaintext(note)
+        }
+    }
+}
+
+// Also flagged as a conflict, so `is:conflict` finds it for review
+fn garbled_note(mut note: Note, received: Note) -> PulledNote {
+    note.has_conflict = 1;
+    note.garbled = 1;
+    PulledNote::Garbled { note, received }
+}
+
+/// Runs `f` over `items` on blocking threads, split into contiguous runs across
+/// at most `available_parallelism` workers, and returns the results in input
+/// order. `f` (and any key it holds) is dropped once the last run finishes.
+async fn map_blocking<T, R, F>(items: Vec<T>, f: F) -> Result<Vec<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len())
+        .max(1);
+    let run_len = items.len().div_ceil(workers).max(1);
+    let f = Arc::new(f);
+
+    let mut items = items.into_iter();
+    let mut handles = Vec::with_capacity(workers);
+    loop {
+        let run: Vec<T> = items.by_ref().take(run_len).collect();
+        if run.is_empty() {
+            break;
+        }
+        let f = Arc::clone(&f);
+        handles.push(tokio::task::spawn_blocking(move || {
+            run.into_iter().map(|item| f(item)).collect::<Vec<R>>()
+        }));
+    }
+    drop(f);
+
+    let mut results = Vec::new();
+    for handle in handles {
+        results.extend(handle.await.context("Crypto task panicked")?);
+    }
+    Ok(results)
+}
+
 pub struct SyncManager {
     client: APIClient,
     repo: Repo,
-    status_tx: mpsc::Sender<SyncStatus>,
+    event_tx: mpsc::Sender<SyncEvent>,
     trigger_rx: mpsc::Receiver<()>,
     crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+    cancel_push: Arc<AtomicBool>,
+    /// Result of the last connectivity probe, shared with the UI
+    online: Arc<AtomicBool>,
+    settings: config::SyncConfig,
+    /// The last pull stopped at its page limit with changes still on the server
+    pull_pending: AtomicBool,
+    /// Names this process in the sync lease
+    lease_owner: String,
 }
 
 pub struct PullStats {
     pub processed: usize,
     pub skipped: usize,
+    /// Notes whose concurrent edits could not be merged cleanly
+    pub conflicts: usize,
+    /// Stopped at the per-pass page limit; the next pass continues from the cursor
+    pub more_remaining: bool,
+}
+
+pub struct PushStats {
+    pub pushed: usize,
+    pub cancelled: bool,
 }
 
 impl SyncManager {
     pub fn new(
         repo: Repo,
-        status_tx: mpsc::Sender<SyncStatus>,
+        event_tx: mpsc::Sender<SyncEvent>,
         trigger_rx: mpsc::Receiver<()>,
         crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+        cancel_push: Arc<AtomicBool>,
+        online: Arc<AtomicBool>,
+        settings: config::SyncConfig,
     ) -> Self {
+        // Nobody waits on a sync pass, and a pull abandoned on timeout would
+        // still commit later while the pass reported it failed
+        let mut repo = repo;
+        repo.set_timeout(Duration::ZERO);
         Self {
             client: APIClient::new(),
             repo,
-            status_tx,
+            event_tx,
             trigger_rx,
             crypto_key,
+            cancel_push,
+            online,
+            settings,
+            pull_pending: AtomicBool::new(false),
+            lease_owner: format!("{}-{}", std::process::id(), uuid::Uuid::new_v4()),
         }
     }
 
     pub async fn start(mut self) {
         crate::logger::log("SyncManager: Started");
 
-        self.try_sync().await;
+        self.probe().await;
+        self.sync_until_clean().await;
+        let mut next_probe = time::Instant::now() + self.probe_interval();
 
         loop {
             tokio::select! {
@@ -348,16 +993,81 @@ impl SyncManager {
                         break;
                     }
                     crate::logger::log("SyncManager: Manual trigger received");
-                    self.try_sync().await;
+                    self.sync_until_clean().await;
+                    // A failed attempt may have marked us offline
+                    next_probe = next_probe.min(time::Instant::now() + self.probe_interval());
+                }
+                _ = time::sleep_until(next_probe) => {
+                    let was_online = self.online.load(Ordering::SeqCst);
+                    if self.probe().await && !was_online {
+                        crate::logger::log("SyncManager: Network is back. Syncing.");
+                        self.sync_until_clean().await;
+                    }
+                    next_probe = time::Instant::now() + self.probe_interval();
                 }
             }
         }
     }
 
+    async fn probe(&self) -> bool {
+        let online = self.client.probe().await;
+        if self.online.swap(online, Ordering::SeqCst) != online {
+            crate::logger::log(&format!(
+                "SyncManager: Connectivity changed: {}",
+                if online { "online" } else { "offline" }
+            ));
+        }
+        online
+    }
+
+    // Syncs until nothing asked for another pass. Triggers that arrive while
+    // a sync runs (e.g. a save mid-pull) may have missed its push, so they get
+    // one more pass right away. Any number of them collapse into that single
+    // pass. A pull cut off at its page limit also continues straight away.
+    async fn sync_until_clean(&mut self) {
+        loop {
+            self.try_sync().await;
+            let mut dirty = self.pull_pending.swap(false, Ordering::SeqCst);
+            while self.trigger_rx.try_recv().is_ok() {
+                dirty = true;
+            }
+            if !dirty {
+                break;
+            }
+            crate::logger::log("SyncManager: Triggered during sync. Syncing again.");
+        }
+    }
+
+    /// A single sync pass outside the background loop, for CLI commands.
+    pub async fn sync_once(&self) {
+        self.probe().await;
+        loop {
+            self.try_sync().await;
+            if !self.pull_pending.swap(false, Ordering::SeqCst) {
+                break;
+            }
+        }
+    }
+
+    fn probe_interval(&self) -> Duration {
+        if self.online.load(Ordering::SeqCst) {
+            PROBE_INTERVAL_ONLINE
+        } else {
+            PROBE_INTERVAL_OFFLINE
+        }
+    }
+
     async fn try_sync(&self) {
         let token = config::get_token();
         if token.is_empty() {
-            let _ = self.status_tx.send(SyncStatus::Offline).await;
+            self.set_status(SyncStatus::Offline).await;
+            return;
+        }
+
+        // The probe loop retries as soon as the network is back
+        if !self.online.load(Ordering::SeqCst) {
+            crate::logger::log("SyncManager: No network. Skipping sync.");
+            self.set_status(SyncStatus::Offline).await;
             return;
         }
 
@@ -366,15 +1076,15 @@ impl SyncManager {
             Ok(me) => me,
             Err(e) => {
                 crate::logger::log(&format!("SyncManager: Failed to fetch plan: {:?}", e));
-                let _ = self.status_tx.send(SyncStatus::Error).await;
+                self.report_error(&e).await;
                 return;
             }
         };
 
-        crate::logger::log(&format!("SyncManager: User Plan = {}", me.plan));
+        crate::logger::log(&format!("SyncManager: User Plan = {}", me.plan.as_str()));
 
         // 2. Handle Free Plan (Local Only)
-        if me.plan.trim().eq_ignore_ascii_case("free") {
+        if !me.plan.is_sync_eligible() {
             if self.repo.get_salt().await.unwrap_or(None).is_some() {
                 crate::logger::log("SyncManager: Detected Free plan but local E2E salt exists. Removing salt (Remote reset assumed).");
                 let _ = self.repo.delete_salt().await;
@@ -386,7 +1096,7 @@ impl SyncManager {
             }
 
             crate::logger::log("SyncManager: Free plan active. Sync disabled (Local Only).");
-            let _ = self.status_tx.send(SyncStatus::Offline).await;
+            self.set_status(SyncStatus::Offline).await;
             return;
         }
 
@@ -396,9 +1106,38 @@ impl SyncManager {
             guard.is_some()
         };
 
+        let rotation = match self.repo.get_key_rotation().await {
+            Ok(rotation) => rotation,
+            Err(e) => {
+                crate::logger::log(&format!(
+                    "SyncManager: Failed to read key rotation: {:?}",
+                    e
+                ));
+                None
+            }
+        };
+
         match self.repo.get_salt().await {
-            Ok(Some(_)) => {
-                // Salt exists, proceed
+            Ok(Some(local_salt)) => {
+                // Salt exists, proceed unless the passphrase was changed on another device
+                let remote_salt = me.encryption_salt.as_deref();
+                if rotation.is_none() && remote_salt.is_some_and(|s| s != local_salt) {
+                    crate::logger::log("SyncManager: Server salt changed (passphrase changed on another device). Locking.");
+                    let _ = self
+                        .repo
+                        .replace_salt(remote_salt.unwrap_or_default())
+                        .await;
+                    let _ = config::delete_passphrase();
+                    {
+                        let mut guard = self.crypto_key.lock().unwrap();
+                        *guard = None;
+                    }
+                    let _ = self
+                        .event_tx
+                        .send(SyncEvent::UnlockResult(Err(UnlockError::PassphraseChanged)))
+                        .await;
+                    return;
+                }
             }
             Ok(None) => {
                 // If remote has salt but local doesn't, we might need to sync it or wait for UI
@@ -410,73 +1149,321 @@ impl SyncManager {
                 } else {
                     crate::logger::log("SyncManager: No encryption salt found. Sync disabled (E2E Setup required).");
                 }
-                let _ = self.status_tx.send(SyncStatus::Offline).await;
+                self.set_status(SyncStatus::Offline).await;
                 return;
             }
             Err(e) => {
                 crate::logger::log(&format!("SyncManager: Failed to check salt: {:?}", e));
-                let _ = self.status_tx.send(SyncStatus::Error).await;
+                self.report_error(&e).await;
                 return;
             }
         }
 
         if !has_key {
             crate::logger::log("SyncManager: Encrypted but locked. Waiting for passphrase.");
-            let _ = self.status_tx.send(SyncStatus::Offline).await;
+            self.set_status(SyncStatus::Offline).await;
             return;
         }
 
+        // The new key must be loaded before anything is pushed under the rotation's version
+        if let Some(r) = &rotation {
+            let has_new_key = {
+                let guard = self.crypto_key.lock().unwrap();
+                guard
+                    .as_ref()
+                    .is_some_and(|k| crypto::unwrap_key(&r.wrapped_old_key, k).is_ok())
+            };
+            if !has_new_key {
+                crate::logger::log(
+                    "SyncManager: Key rotation in progress but new key not loaded. Waiting.",
+                );
+                self.set_status(SyncStatus::Offline).await;
+                return;
+            }
+        }
+
+        // Another risu process (the TUI, or `risu sync --watch`) is mid-pass;
+        // it syncs the same database, so this pass has nothing to add
+        match self.take_lease().await {
+            Ok(true) => {}
+            Ok(false) => {
+                crate::logger::log("SyncManager: Another risu process is syncing. Skipping.");
+                return;
+            }
+            Err(e) => {
+                crate::logger::log(&format!(
+                    "SyncManager: Failed to take the sync lease: {:?}",
+                    e
+                ));
+                self.report_error(&e).await;
+                return;
+            }
+        }
+
         crate::logger::log("SyncManager: try_sync starting (E2E Enforced)");
-        let _ = self.status_tx.send(SyncStatus::Syncing).await;
+        self.set_status(SyncStatus::Syncing).await;
 
-        match self.do_sync(&me.plan).await {
-            Ok(stats) => {
+        match self.do_sync(&me.plan, rotation.as_ref()).await {
+            Ok((_, push_stats)) if push_stats.cancelled => {
+                self.set_status(SyncStatus::Warning("Sync Paused".to_string()))
+                    .await;
+            }
+            Ok((stats, _)) => {
                 crate::logger::log("SyncManager: Sync finished successfully");
-                if stats.skipped > 0 {
+                let _ = self
+                    .repo
+                    .set_last_sync_time(&chrono::Utc::now().to_rfc3339())
+                    .await;
+                if stats.more_remaining {
+                    crate::logger::log(
+                        "SyncManager: Pull stopped at its page limit. Continuing in another pass.",
+                    );
+                    self.pull_pending.store(true, Ordering::SeqCst);
+                    self.set_status(SyncStatus::Warning("Sync Incomplete".to_string()))
+                        .await;
+                } else if stats.skipped > 0 {
+                    self.set_status(SyncStatus::Warning("Sync Warning".to_string()))
+                        .await;
+                } else if stats.conflicts > 0 {
+                    crate::logger::log(&format!(
+                        "SyncManager: {} notes need manual merge",
+                        stats.conflicts
+                    ));
                     let _ = self
-                        .status_tx
-                        .send(SyncStatus::Warning("Sync Warning".to_string()))
+                        .event_tx
+                        .send(SyncEvent::Conflicts(stats.conflicts))
                         .await;
                 } else {
-                    let _ = self.status_tx.send(SyncStatus::Synced).await;
+                    self.set_status(SyncStatus::Synced).await;
                 }
             }
             Err(e) => {
                 crate::logger::log(&format!("Sync Error: {:?}", e));
-                if e.to_string().contains("Payment Required") {
-                    let _ = self.status_tx.send(SyncStatus::PaymentRequired).await;
-                } else {
-                    let _ = self.status_tx.send(SyncStatus::Error).await;
-                }
+                self.report_error(&e).await;
             }
         }
+        if let Err(e) = self.repo.release_sync_lease(&self.lease_owner).await {
+            crate::logger::log(&format!(
+                "SyncManager: Failed to release the sync lease: {:?}",
+                e
+            ));
+        }
+    }
+
+    async fn take_lease(&self) -> Result<bool> {
+        let until = chrono::Utc::now()
+            + chrono::Duration::from_std(SYNC_LEASE).unwrap_or(chrono::Duration::zero());
+        self.repo.take_sync_lease(&self.lease_owner, until).await
+    }
+
+    /// Extends this pass's lease. Fails if it ran out and another process
+    /// took it, so two passes never overlap.
+    async fn renew_lease(&self) -> Result<()> {
+        if self.take_lease().await? {
+            Ok(())
+        } else {
+            Err(anyhow!("Another risu process took over the sync lease"))
+        }
+    }
+
+    async fn report_error(&self, e: &anyhow::Error) {
+        let status = status_for_error(e);
+        if status == SyncStatus::Offline {
+            self.online.store(false, Ordering::SeqCst);
+        }
+        self.set_status(status).await;
     }
 
-    async fn do_sync(&self, plan: &str) -> Result<PullStats> {
+    // Saves are stamped with the local clock, so a wrong clock decides merges.
+    // The offset is only applied past the warning threshold; below it the
+    // header's rounding would just add noise.
+    async fn note_clock_skew(&self, skew: i64) {
+        let off = skew.abs() > CLOCK_SKEW_WARN_SECS;
+        if off {
+            crate::logger::log(&format!(
+                "SyncManager: Local clock is {}s {} the server",
+                skew.abs(),
+                if skew > 0 { "behind" } else { "ahead of" }
+            ));
+        }
+        if self.settings.compensate_clock_skew {
+            self.repo.set_clock_offset(if off { skew } else { 0 });
+        }
+        let _ = self.event_tx.send(SyncEvent::ClockSkew(skew)).await;
+    }
+
+    async fn report_rotation_blocked(&self, reason: String) {
+        let _ = self.event_tx.send(SyncEvent::RotationBlocked(reason)).await;
+    }
+
+    async fn set_status(&self, status: SyncStatus) {
+        let _ = self.event_tx.send(SyncEvent::StatusChanged(status)).await;
+    }
+
+    async fn note_error(&self, id: &str, msg: &str) {
+        let _ = self
+            .event_tx
+            .send(SyncEvent::NoteError {
+                id: id.to_string(),
+                msg: msg.to_string(),
+            })
+            .await;
+    }
+
+    async fn do_sync(
+        &self,
+        plan: &Plan,
+        rotation: Option<&KeyRotation>,
+    ) -> Result<(PullStats, PushStats)> {
+        // Notes are tagged with the key version they were last pushed/pulled under
+        let key_version = match rotation {
+            Some(r) => r.target_version,
+            None => self.repo.get_key_version().await?,
+        };
+
+        // Edits made while locked are compared against every server copy, including
+        // ones an earlier locked sync skipped past, so pull from the start.
+        let full_pull = self.repo.count_modified_while_locked().await? > 0;
+
         // We still attempt pull even if plan is free (server filters it)
         // But push will fail if not pro.
-        let stats = self.pull().await.context("Pull failed")?;
+        let stats = self
+            .pull(rotation, key_version, full_pull)
+            .await
+            .context("Pull failed")?;
+
+        if let Some(r) = rotation {
+            let stale = self
+                .repo
+                .mark_unsynced_below_key_version(r.target_version)
+                .await?;
+            if stale > 0 {
+                crate::logger::log(&format!(
+                    "SyncManager: Key rotation queued {} notes for re-encryption",
+                    stale
+                ));
+            }
+        }
 
-        match self.push(plan).await {
-            Ok(_) => Ok(stats),
-            Err(e) => {
-                // Check if error is "Payment Required"
-                if e.to_string().contains("Payment Required") {
-                    return Err(anyhow!("Payment Required"));
+        // If some server copies still couldn't be read, pushing a locked edit
+        // could overwrite one of them blind
+        let hold_locked_edits = stats.skipped > 0;
+
+        match self.push(plan, key_version, hold_locked_edits).await {
+            Ok(push_stats) => {
+                if let Some(r) = rotation {
+                    if !push_stats.cancelled {
+                        self.finish_rotation(r)
+                            .await
+                            .context("Key rotation failed")?;
+                    }
                 }
-                Err(e).context("Push failed")
+                if !push_stats.cancelled {
+                    self.finish_reupload().await?;
+                    self.compact_tombstones().await;
+                }
+                Ok((stats, push_stats))
+            }
+            Err(e) => Err(e).context("Push failed"),
+        }
+    }
+
+    /// Moves the server to the rotation's salt/validator and discards the old
+    /// key, once no note is left on it. Called after a full push, so notes
+    /// still on it couldn't be pushed and hold the change up. A refused swap
+    /// leaves the job as it is: the next pass asks again, or the user cancels.
+    async fn finish_rotation(&self, rotation: &KeyRotation) -> Result<()> {
+        let remaining = self
+            .repo
+            .count_below_key_version(rotation.target_version)
+            .await?;
+        if remaining > 0 {
+            crate::logger::log(&format!(
+                "SyncManager: Key rotation pending, {} notes still on the old key",
+                remaining
+            ));
+            self.report_rotation_blocked(format!(
+                "{} notes couldn't be re-encrypted (see the log)",
+                remaining
+            ))
+            .await;
+            return Ok(());
+        }
+
+        if !rotation.server_updated {
+            if let Err(e) = self
+                .client
+                .e2e_rotate(&rotation.new_salt, &rotation.new_validator)
+                .await
+            {
+                crate::logger::log(&format!(
+                    "SyncManager: Server refused the new passphrase: {:?}",
+                    e
+                ));
+                self.report_rotation_blocked(format!(
+                    "the server refused the new passphrase ({:#})",
+                    e
+                ))
+                .await;
+                return Ok(());
             }
+            self.repo.mark_rotation_server_updated().await?;
+            crate::logger::log("SyncManager: Server switched to the new passphrase");
+        }
+
+        self.repo.finish_key_rotation(rotation).await?;
+        crate::logger::log("SyncManager: Key rotation complete. Old key discarded.");
+        Ok(())
+    }
+
+    async fn compact_tombstones(&self) {
+        compact_tombstones(&self.repo, self.settings.tombstone_retention_days).await;
+    }
+
+    /// Clears a bulk re-upload once every note has been pushed encrypted.
+    async fn finish_reupload(&self) -> Result<()> {
+        if !self.repo.is_reupload_pending().await? {
+            return Ok(());
+        }
+        let remaining = self.repo.get_unsynced_notes().await?.len();
+        if remaining > 0 {
+            crate::logger::log(&format!(
+                "SyncManager: Re-upload pending, {} notes left to push",
+                remaining
+            ));
+            return Ok(());
         }
+        self.repo.finish_reupload().await?;
+        crate::logger::log("SyncManager: Re-upload complete. Every note is stored encrypted.");
+        Ok(())
     }
 
-    async fn pull(&self) -> Result<PullStats> {
-        let cursor = self.repo.get_cursor().await?;
+    async fn pull(
+        &self,
+        rotation: Option<&KeyRotation>,
+        key_version: i32,
+        full: bool,
+    ) -> Result<PullStats> {
+        let cursor = if full {
+            crate::logger::log(
+                "SyncManager: Notes were edited while locked. Pulling from the start.",
+            );
+            "1970-01-01T00:00:00Z".to_string()
+        } else {
+            self.repo.get_cursor().await?
+        };
         let mut stats = PullStats {
             processed: 0,
             skipped: 0,
+            conflicts: 0,
+            more_remaining: false,
         };
 
-        let server_time = self.client.check_sync().await?;
+        let check = self.client.check_sync().await?;
+        if let Some(skew) = check.clock_skew {
+            self.note_clock_skew(skew.num_seconds()).await;
+        }
+        let server_time = check.last_updated_at;
 
         if server_time <= cursor {
             return Ok(stats);
@@ -484,6 +1471,8 @@ impl SyncManager {
 
         let mut current_cursor = cursor;
         let mut page_count = 0;
+        // Pages per pass; a bigger backlog continues in the next pass so the
+        // UI gets a status update (and pending pushes a chance) in between
         const MAX_PAGES: usize = 100;
 
         let key_opt = {
@@ -491,83 +1480,153 @@ impl SyncManager {
             key_guard.as_ref().map(|k| k.clone())
         };
 
+        // During rotation, notes not yet re-pushed are still under the old key.
+        // Anything that only the old key (or no key) can read is tagged stale.
+        let stale_version = rotation.map_or(key_version, |r| r.target_version - 1);
+        let old_key_opt = match (rotation, &key_opt) {
+            (Some(r), Some(key)) => match crypto::unwrap_key(&r.wrapped_old_key, key) {
+                Ok(old_key) => Some(old_key),
+                Err(e) => {
+                    crate::logger::log(&format!(
+                        "SyncManager: Failed to unwrap old key during rotation: {}",
+                        e
+                    ));
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        // Shared with the decrypt workers; the last copy goes when pull returns
+        let mut keys = Vec::new();
+        if let Some(key) = key_opt {
+            keys.push((key, key_version));
+        }
+        if let Some(old_key) = old_key_opt {
+            keys.push((old_key, stale_version));
+        }
+        let keys = Arc::new(keys);
+
         loop {
             if page_count >= MAX_PAGES {
+                stats.more_remaining = true;
                 break;
             }
             page_count += 1;
+            self.renew_lease().await?;
 
             let res = self.client.pull_changes(&current_cursor).await?;
-            let original_count = res.changes.len();
 
-            let mut decrypted_changes = Vec::new();
+            let page_keys = Arc::clone(&keys);
+            let opened = map_blocking(res.changes, move |note| {
+                open_pulled_note(note, &page_keys, stale_version)
+            })
+            .await?;
 
-            for mut note in res.changes {
-                let key_opt_ref = key_opt.as_ref();
-
-                if note.is_encrypted == 1 {
-                    if let Some(key) = key_opt_ref {
-                        match crypto::decrypt(&note.content, key) {
-                            Ok(plaintext) => {
-                                note.content = plaintext;
-                                note.is_encrypted = 0; // Decrypted for local storage
-                                decrypted_changes.push(note);
-                                stats.processed += 1;
-                            }
-                            Err(e) => {
-                                let err_msg = format!("Failed to decrypt note {}: {}", note.id, e);
-                                crate::logger::log(&err_msg);
-                                // Skip this note to prevent data corruption
-                                stats.skipped += 1;
-                            }
-                        }
-                    } else {
+            let mut decrypted_changes = Vec::new();
+            let mut garbled = Vec::new();
+            for opened in opened {
+                match opened {
+                    PulledNote::Decrypted(note) => {
+                        decrypted_changes.push(note);
+                        stats.processed += 1;
+                    }
+                    PulledNote::KeyMissing(id) => {
                         // Key missing but note is encrypted -> Critical failure for this batch
                         crate::logger::log(&format!(
                             "Skipping note {} because encryption key is missing",
-                            note.id
+                            id
                         ));
                         stats.skipped += 1;
                     }
-                } else {
-                    // Handle is_encrypted == 0 (Potential plaintext or mislabeled encrypted data)
-
-                    // Try to decrypt even if flag says 0, just in case (Recovery logic)
-                    let mut recovered = false;
-                    if let Some(key) = key_opt_ref {
-                        // Only try if it looks like base64 and has enough length
-                        if note.content.len() > 24 && !note.content.contains(' ') {
-                            if let Ok(plaintext) = crypto::decrypt(&note.content, key) {
-                                crate::logger::log(&format!(
-                                    "Recovered mislabeled encrypted note: {}",
-                                    note.id
-                                ));
-                                note.content = plaintext;
-                                note.is_encrypted = 0;
-                                decrypted_changes.push(note.clone());
-                                recovered = true;
-                                stats.processed += 1;
-                            }
-                        }
+                    PulledNote::Undecryptable(id) => {
+                        crate::logger::log(&format!(
+                            "Failed to decrypt note {}: no available key matched",
+                            id
+                        ));
+                        self.note_error(&id, "No available key could decrypt it")
+                            .await;
+                        // Skip this note to prevent data corruption
+                        stats.skipped += 1;
                     }
-
-                    if !recovered {
+                    PulledNote::Garbled { note, received } => {
+                        crate::logger::log(&format!(
+                            "Note {} decrypted to invalid UTF-8; kept with the bad bytes replaced and held back from push",
+                            note.id
+                        ));
+                        self.note_error(
+                            &note.id,
+                            "Decrypted text was not valid UTF-8; bad bytes were replaced. It won't sync until you review it (is:conflict) and run :accept",
+                        )
+                        .await;
+                        garbled.push(received);
+                        decrypted_changes.push(note);
+                        stats.processed += 1;
+                    }
+                    PulledNote::Recovered(note) => {
+                        crate::logger::log(&format!(
+                            "Recovered mislabeled encrypted note: {}",
+                            note.id
+                        ));
+                        decrypted_changes.push(note);
+                        stats.processed += 1;
+                    }
+                    PulledNote::Plaintext(note) => {
                         crate::logger::log(&format!(
                             "Accepting plaintext note {} (Warning: Plaintext sync is deprecated but allowed for recovery)",
                             note.id
                         ));
-                        // Save as-is (Plaintext)
                         decrypted_changes.push(note);
                         stats.processed += 1;
                     }
                 }
             }
 
+            if !self.settings.labels {
+                // Labels stay local; ignore any the server carries
+                for note in decrypted_changes.iter_mut() {
+                    note.label = None;
+                }
+            }
+
+            // The server copy is the only intact one; keep it before the
+            // lossy text is stored
+            if !garbled.is_empty() {
+                self.repo.quarantine_notes(garbled).await?;
+            }
+
             if !decrypted_changes.is_empty() {
-                self.repo
+                // Live notes this page deletes, for the audit log
+                let mut tombstoned = Vec::new();
+                for note in decrypted_changes.iter().filter(|n| n.is_deleted == 1) {
+                    if let Some(local) = self.repo.get_note(note.id.clone()).await? {
+                        if local.is_deleted == 0 {
+                            tombstoned.push(crate::logger::AuditNote::of(&local));
+                        }
+                    }
+                }
+                stats.conflicts += self
+                    .repo
                     .pull_upsert_notes(decrypted_changes, res.next_cursor.clone())
                     .await?;
-            } else if original_count > 0 {
+                // Local edits win over a remote delete, so check what applied
+                let mut deleted = Vec::new();
+                for note in tombstoned {
+                    if let Some(local) = self.repo.get_note(note.id.clone()).await? {
+                        if local.is_deleted == 1 {
+                            deleted.push(note);
+                        }
+                    }
+                }
+                if !deleted.is_empty() {
+                    crate::logger::audit(crate::logger::AuditEvent::Delete {
+                        via: crate::logger::Via::Sync,
+                        notes: deleted,
+                    });
+                }
+            } else {
+                // Nothing to apply (an empty page, or every note skipped).
+                //
                 // If all notes were skipped, we still update cursor?
                 // NO. If we update cursor, we lose the chance to retry fetching these notes later.
                 // BUT, if we don't update cursor, we get stuck in a loop fetching the same failing notes.
@@ -594,13 +1653,36 @@ impl SyncManager {
         Ok(stats)
     }
 
-    async fn push(&self, plan: &str) -> Result<()> {
-        if plan == "free" {
+    async fn push(
+        &self,
+        plan: &Plan,
+        key_version: i32,
+        hold_locked_edits: bool,
+    ) -> Result<PushStats> {
+        let mut stats = PushStats {
+            pushed: 0,
+            cancelled: false,
+        };
+
+        if !plan.is_sync_eligible() {
             crate::logger::log("SyncManager: Sync (Write) is disabled for Free plan.");
-            return Ok(());
+            return Ok(stats);
         }
 
-        let notes = self.repo.get_unsynced_notes().await?;
+        // Any cancellation requested before this push started is stale
+        self.cancel_push.store(false, Ordering::SeqCst);
+
+        let mut notes = self.repo.get_unsynced_notes().await?;
+        if hold_locked_edits {
+            let before = notes.len();
+            notes.retain(|n| n.modified_while_locked == 0);
+            if notes.len() < before {
+                crate::logger::log(&format!(
+                    "SyncManager: Holding back {} notes edited while locked until their server copies can be read",
+                    before - notes.len()
+                ));
+            }
+        }
 
         crate::logger::log(&format!(
             "SyncManager: push found {} unsynced notes",
@@ -612,38 +1694,269 @@ impl SyncManager {
             key_guard.as_ref().map(|k| k.clone())
         };
 
-        for n in notes {
-            let current_note_opt = self.repo.get_note(n.id.clone()).await?;
+        let Some(key) = key_opt else {
+            // This should theoretically be blocked by try_sync, but for safety:
+            crate::logger::log(&format!(
+                "Skipping push for {} notes: Key not available",
+                notes.len()
+            ));
+            return Ok(stats);
+        };
 
-            if let Some(mut latest_n) = current_note_opt {
-                // ALWAYS encrypt before pushing in the new model
-                if let Some(key) = &key_opt {
-                    match crypto::encrypt(&latest_n.content, key) {
-                        Ok(ciphertext) => {
-                            latest_n.content = ciphertext;
-                            latest_n.is_encrypted = 1;
-                        }
-                        Err(e) => {
-                            crate::logger::log(&format!(
-                                "Failed to encrypt note {}: {}",
-                                latest_n.id, e
-                            ));
-                            continue;
-                        }
-                    }
-                } else {
-                    // This should theoretically be blocked by try_sync, but for safety:
+        let total = notes.len();
+        let report_progress = total > 1;
+        let mut done = 0;
+
+        for batch in notes.chunks(ENCRYPT_BATCH_SIZE) {
+            let mut latest_notes = Vec::with_capacity(batch.len());
+            for n in batch {
+                if let Some(latest_n) = self.repo.get_note(n.id.clone()).await? {
+                    latest_notes.push(latest_n);
+                }
+            }
+
+            // ALWAYS encrypt before pushing in the new model.
+            // AEAD on large notes is CPU-bound, so keep it off the async workers.
+            let batch_key = key.clone();
+            let encrypted = map_blocking(latest_notes, move |n| {
+                let res = crypto::encrypt(&n.content, &batch_key);
+                (n, res)
+            })
+            .await?;
+
+            for (mut latest_n, res) in encrypted {
+                if self.cancel_push.swap(false, Ordering::SeqCst) {
                     crate::logger::log(&format!(
-                        "Skipping push for note {}: Key not available",
-                        latest_n.id
+                        "SyncManager: Push cancelled after {} of {} notes. Remaining notes stay unsynced.",
+                        done, total
                     ));
-                    continue;
+                    stats.cancelled = true;
+                    return Ok(stats);
                 }
 
+                done += 1;
+                let plaintext = match res {
+                    Ok(ciphertext) => {
+                        latest_n.is_encrypted = 1;
+                        std::mem::replace(&mut latest_n.content, ciphertext)
+                    }
+                    Err(e) => {
+                        crate::logger::log(&format!(
+                            "Failed to encrypt note {}: {}",
+                            latest_n.id, e
+                        ));
+                        self.note_error(&latest_n.id, &format!("Encryption failed: {}", e))
+                            .await;
+                        continue;
+                    }
+                };
+
+                // `""` tells other devices the label was removed
+                latest_n.label = self
+                    .settings
+                    .labels
+                    .then(|| latest_n.label.take().unwrap_or_default());
+                self.renew_lease().await?;
                 self.client.push_note(&latest_n).await?;
-                self.repo.mark_as_synced(latest_n.id.clone()).await?;
+                self.repo
+                    .mark_as_synced(
+                        latest_n.id.clone(),
+                        key_version,
+                        plaintext,
+                        latest_n.updated_at.clone(),
+                    )
+                    .await?;
+                stats.pushed += 1;
+
+                if report_progress {
+                    let _ = self
+                        .event_tx
+                        .send(SyncEvent::Progress {
+                            phase: SyncPhase::Encrypting,
+                            done,
+                            total,
+                        })
+                        .await;
+                }
             }
         }
-        Ok(())
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(content: &str, is_encrypted: i32) -> Note {
+        Note {
+            id: "n".to_string(),
+            content: content.to_string(),
+            updated_at: "2026-01-01T00:00:00Z".to_string(),
+            created_at: String::new(),
+            is_deleted: 0,
+            is_synced: 1,
+            is_encrypted,
+            key_version: 0,
+            has_conflict: 0,
+            garbled: 0,
+            modified_while_locked: 0,
+            remote_updated_at: None,
+            pushed_updated_at: None,
+            older_than_remote: false,
+            label: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn map_blocking_keeps_input_order() {
+        let items: Vec<usize> = (0..1000).collect();
+        let doubled = map_blocking(items, |n| n * 2).await.unwrap();
+        assert_eq!(doubled, (0..1000).map(|n| n * 2).collect::<Vec<_>>());
+
+        let empty = map_blocking(Vec::<usize>::new(), |n| n).await.unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn pulled_note_is_opened_with_whichever_key_reads_it() {
+        let (new_key, old_key) = (Zeroizing::new([1; 32]), Zeroizing::new([2; 32]));
+        let keys = [(new_key.clone(), 2), (old_key.clone(), 1)];
+
+        let sealed = crypto::encrypt("old text", &old_key).unwrap();
+        let PulledNote::Decrypted(opened) = open_pulled_note(note(&sealed, 1), &keys, 1) else {
+            panic!("expected the old key to open it");
+        };
+        assert_eq!(
+            (opened.content.as_str(), opened.key_version),
+            ("old text", 1)
+        );
+        assert_eq!(opened.is_encrypted, 0);
+
+        // Flagged plaintext, but really ciphertext
+        let sealed = crypto::encrypt("mislabeled", &new_key).unwrap();
+        assert!(matches!(
+            open_pulled_note(note(&sealed, 0), &keys, 1),
+            PulledNote::Recovered(n) if n.content == "mislabeled" && n.key_version == 2
+        ));
+
+        let foreign = crypto::encrypt("foreign", &[3; 32]).unwrap();
+        assert!(matches!(
+            open_pulled_note(note(&foreign, 1), &keys, 1),
+            PulledNote::Undecryptable(_)
+        ));
+        assert!(matches!(
+            open_pulled_note(note(&foreign, 1), &[], 1),
+            PulledNote::KeyMissing(_)
+        ));
+        assert!(matches!(
+            open_pulled_note(note("plain words", 0), &keys, 1),
+            PulledNote::Plaintext(n) if n.key_version == 1
+        ));
+    }
+
+    #[test]
+    fn plan_names_ignore_case_and_whitespace() {
+        assert_eq!("PRO".parse(), Ok(Plan::Pro));
+        assert_eq!(" free ".parse(), Ok(Plan::Free));
+        assert_eq!("Dev\n".parse(), Ok(Plan::Dev));
+        let unknown: Plan = " Team ".parse().unwrap();
+        assert_eq!(unknown, Plan::Other("team".to_string()));
+        assert_eq!(unknown.display_name(), "team");
+        assert!(!unknown.is_sync_eligible());
+
+        let plan: Plan = serde_json::from_str(r#"" Pro ""#).unwrap();
+        assert_eq!(plan, Plan::Pro);
+        assert!(plan.is_sync_eligible());
+    }
+
+    // A response from the server with `status`, a `Retry-After` header if
+    // given, and `body`
+    fn response(status: u16, retry_after: Option<&str>, body: &str) -> Response {
+        let mut builder = axum::http::Response::builder().status(status);
+        if let Some(after) = retry_after {
+            builder = builder.header("retry-after", after);
+        }
+        Response::from(builder.body(body.to_string()).unwrap())
+    }
+
+    #[test]
+    fn failed_responses_are_classified_by_status() {
+        let cases: [(u16, Option<&str>, SyncStatus); 9] = [
+            (
+                401,
+                None,
+                SyncStatus::Error("Not authorized, try logging in again".to_string()),
+            ),
+            (402, None, SyncStatus::PaymentRequired),
+            (403, None, SyncStatus::PaymentRequired),
+            (
+                409,
+                None,
+                SyncStatus::Error("Server error: 409 Conflict".to_string()),
+            ),
+            (
+                429,
+                Some("30"),
+                SyncStatus::Error("Rate limited by the server, retry in 30s".to_string()),
+            ),
+            (
+                429,
+                Some("soon"),
+                SyncStatus::Error("Rate limited by the server, retry later".to_string()),
+            ),
+            (
+                500,
+                None,
+                SyncStatus::Error("Server error: 500 Internal Server Error".to_string()),
+            ),
+            (
+                502,
+                None,
+                SyncStatus::Error("Server error: 502 Bad Gateway".to_string()),
+            ),
+            (
+                503,
+                Some("5"),
+                SyncStatus::Error("Server error: 503 Service Unavailable".to_string()),
+            ),
+        ];
+        for (status, retry_after, expected) in cases {
+            let err = check_status(response(status, retry_after, ""), "Pull failed")
+                .map(|_| ())
+                .unwrap_err()
+                .context("Sync failed");
+            assert_eq!(status_for_error(&err), expected, "{}", status);
+        }
+
+        assert!(check_status(response(200, None, ""), "Pull failed").is_ok());
+        assert!(matches!(
+            SyncError::from_response(&response(429, Some(" 7 "), "")),
+            SyncError::RateLimited { retry_after: Some(after) } if after == Duration::from_secs(7)
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_malformed_body_is_an_unexpected_response() {
+        #[derive(Debug, Deserialize)]
+        struct Check {
+            #[allow(dead_code)]
+            last_updated_at: String,
+        }
+
+        for body in ["", "<html>502</html>", r#"{"last_updated_at": 5}"#] {
+            let err = decode::<Check>(response(200, None, body))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err.downcast_ref::<SyncError>(), Some(SyncError::Decode(_))),
+                "{:?}",
+                body
+            );
+            assert!(matches!(
+                status_for_error(&err),
+                SyncStatus::Error(message) if message.starts_with("Unexpected server response")
+            ));
+        }
     }
 }
@@ -0,0 +1,84 @@
+// The focus timer (`:timer`, or the status dialog): a countdown shown in the
+// footer that logs each finished block to a note. Kept in kv_store with its
+// wall-clock end, so a restart or crash picks it up where it was.
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const KV_KEY: &str = "focus_timer";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusTimer {
+    pub minutes: u64,
+    pub state: TimerState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum TimerState {
+    Running { ends_at: DateTime<Utc> },
+    Paused { remaining_secs: i64 },
+}
+
+impl FocusTimer {
+    pub fn start(minutes: u64, now: DateTime<Utc>) -> Self {
+        Self {
+            minutes,
+            state: TimerState::Running {
+                ends_at: now + chrono::Duration::minutes(minutes as i64),
+            },
+        }
+    }
+
+    pub fn remaining_secs(&self, now: DateTime<Utc>) -> i64 {
+        match self.state {
+            TimerState::Running { ends_at } => (ends_at - now).num_seconds().max(0),
+            TimerState::Paused { remaining_secs } => remaining_secs,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        matches!(self.state, TimerState::Paused { .. })
+    }
+
+    /// Ran out while running; a paused timer never finishes.
+    pub fn is_done(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.state, TimerState::Running { ends_at } if now >= ends_at)
+    }
+
+    pub fn pause(&mut self, now: DateTime<Utc>) {
+        if !self.is_paused() {
+            self.state = TimerState::Paused {
+                remaining_secs: self.remaining_secs(now),
+            };
+        }
+    }
+
+    pub fn resume(&mut self, now: DateTime<Utc>) {
+        if let TimerState::Paused { remaining_secs } = self.state {
+            self.state = TimerState::Running {
+                ends_at: now + chrono::Duration::seconds(remaining_secs),
+            };
+        }
+    }
+
+    /// `⏱ 24:13` in the footer, `⏸` while paused.
+    pub fn label(&self, now: DateTime<Utc>) -> String {
+        let secs = self.remaining_secs(now);
+        format!(
+            " {} {:02}:{:02} ",
+            if self.is_paused() { "⏸" } else { "⏱" },
+            secs / 60,
+            secs % 60
+        )
+    }
+
+    /// The line appended to the log note for a finished block.
+    pub fn log_entry(&self, finished: DateTime<Local>) -> String {
+        format!(
+            "- [x] {}m focus block ({})",
+            self.minutes,
+            finished.format("%Y-%m-%d %H:%M")
+        )
+    }
+}
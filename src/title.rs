@@ -0,0 +1,99 @@
+// Note titles as the list, exports and logs show them.
+
+use crate::crypto;
+
+/// List title for notes whose content is still ciphertext.
+pub const LOCKED_NOTE_TITLE: &str = "🔒 Encrypted note";
+
+/// Title shown for a note: the first line with visible text, skipping leading
+/// front matter and without heading markers or list bullets.
+pub fn derive_title(content: &str) -> String {
+    if crypto::looks_like_ciphertext(content) {
+        return LOCKED_NOTE_TITLE.to_string();
+    }
+    let lines: Vec<&str> = content.lines().collect();
+    let mut start = lines
+        .iter()
+        .position(|l| !l.trim().is_empty())
+        .unwrap_or(lines.len());
+    if lines.get(start).is_some_and(|l| l.trim_end() == "---") {
+        // Only a closed block counts as front matter
+        if let Some(len) = lines[start + 1..]
+            .iter()
+            .position(|l| l.trim_end() == "---")
+        {
+            start += len + 2;
+        }
+    }
+    lines[start..]
+        .iter()
+        .map(|l| sanitize_title(strip_title_markup(l.trim())))
+        .find(|t| !t.is_empty())
+        .unwrap_or_else(|| "No Content".to_string())
+}
+
+fn strip_title_markup(line: &str) -> &str {
+    let heading = line.trim_start_matches('#');
+    if heading.len() < line.len() && (heading.is_empty() || heading.starts_with(' ')) {
+        return heading.trim_start();
+    }
+    for bullet in ["- [ ] ", "- [x] ", "- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return rest;
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        if let Some(rest) = line[digits..].strip_prefix(". ") {
+            return rest;
+        }
+    }
+    line
+}
+
+/// Control characters become spaces and runs of whitespace one space.
+pub fn sanitize_title(input: &str) -> String {
+    let sanitized: String = input
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+
+    // Collapse multiple spaces
+    sanitized.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_is_the_first_visible_line_without_markup() {
+        assert_eq!(derive_title("# Heading\nbody"), "Heading");
+        assert_eq!(derive_title("\n\n  Plain  \nmore"), "Plain");
+        assert_eq!(derive_title("###\n## Next"), "Next");
+        assert_eq!(derive_title("#hashtag"), "#hashtag");
+        assert_eq!(derive_title("- [ ] task"), "task");
+        assert_eq!(derive_title("- [x] done"), "done");
+        assert_eq!(derive_title("* star"), "star");
+        assert_eq!(derive_title("12. item"), "item");
+        assert_eq!(derive_title("12.5 km"), "12.5 km");
+        assert_eq!(derive_title("a\tb   c\u{7}"), "a b c");
+    }
+
+    #[test]
+    fn closed_front_matter_is_skipped() {
+        assert_eq!(derive_title("---\ntitle: x\n---\n\n## Real"), "Real");
+        assert_eq!(derive_title("\n---\ntags: []\n---\nBody"), "Body");
+        // Unclosed, it's just a line
+        assert_eq!(derive_title("---\nfoo"), "---");
+    }
+
+    #[test]
+    fn empty_and_encrypted_notes_get_placeholders() {
+        assert_eq!(derive_title(""), "No Content");
+        assert_eq!(derive_title("  \n\t\n"), "No Content");
+        assert_eq!(derive_title("---\na: 1\n---\n"), "No Content");
+        let ciphertext = crypto::encrypt("Secret", &[7; 32]).unwrap();
+        assert_eq!(derive_title(&ciphertext), LOCKED_NOTE_TITLE);
+    }
+}
@@ -0,0 +1,464 @@
+// Note list actions and the command palette (`:` or Ctrl+P) that lists them.
+// The list dispatches its keys through `Command::for_key`, so every command is
+// reachable from the palette and the shortcut shown there is the real binding.
+
+use crate::*;
+
+// kv_store key for the palette's most recently run commands, newest first
+const RECENT_KEY: &str = "palette_recent";
+const RECENT_LIMIT: usize = 10;
+// The editor's cheat sheet while there are no notes yet
+const GETTING_STARTED: [Command; 7] = [
+    Command::New,
+    Command::Scratch,
+    Command::Search,
+    Command::Sync,
+    Command::Status,
+    Command::Palette,
+    Command::Quit,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Open,
+    Edit,
+    NextPane,
+    New,
+    Scratch,
+    ClearScratch,
+    NextMatch,
+    PrevMatch,
+    Delete,
+    CycleLabel,
+    Search,
+    ToggleDue,
+    TogglePreview,
+    Sync,
+    ToggleLock,
+    CopyNote,
+    JumpBack,
+    JumpForward,
+    Status,
+    Palette,
+    Quit,
+}
+
+impl Command {
+    pub const ALL: [Command; 21] = [
+        Command::Open,
+        Command::Edit,
+        Command::NextPane,
+        Command::New,
+        Command::Scratch,
+        Command::ClearScratch,
+        Command::NextMatch,
+        Command::PrevMatch,
+        Command::Delete,
+        Command::CycleLabel,
+        Command::Search,
+        Command::ToggleDue,
+        Command::TogglePreview,
+        Command::Sync,
+        Command::ToggleLock,
+        Command::CopyNote,
+        Command::JumpBack,
+        Command::JumpForward,
+        Command::Status,
+        Command::Palette,
+        Command::Quit,
+    ];
+
+    /// Stable name for the recent list in kv_store.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Command::Open => "open",
+            Command::Edit => "edit",
+            Command::NextPane => "next-pane",
+            Command::New => "new",
+            Command::Scratch => "scratch",
+            Command::ClearScratch => "clear-scratch",
+            Command::NextMatch => "next-match",
+            Command::PrevMatch => "prev-match",
+            Command::Delete => "delete",
+            Command::CycleLabel => "cycle-label",
+            Command::Search => "search",
+            Command::ToggleDue => "toggle-due",
+            Command::TogglePreview => "toggle-preview",
+            Command::Sync => "sync",
+            Command::ToggleLock => "toggle-lock",
+            Command::CopyNote => "copy-note",
+            Command::JumpBack => "jump-back",
+            Command::JumpForward => "jump-forward",
+            Command::Status => "status",
+            Command::Palette => "palette",
+            Command::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Command> {
+        Command::ALL.into_iter().find(|c| c.as_str() == name)
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Command::Open => "Open Note",
+            Command::Edit => "Edit Note",
+            Command::NextPane => "Focus Next Pane",
+            Command::New => "New Note",
+            Command::Scratch => "Open Scratch Note",
+            Command::ClearScratch => "Clear Scratch Note",
+            Command::NextMatch => "Next Search Match",
+            Command::PrevMatch => "Previous Search Match",
+            Command::Delete => "Delete Note",
+            Command::CycleLabel => "Cycle Color Label",
+            Command::Search => "Filter Notes",
+            Command::ToggleDue => "Toggle Due View",
+            Command::TogglePreview => "Toggle Markdown Preview",
+            Command::Sync => "Sync Now",
+            Command::ToggleLock => "Lock/Unlock E2E",
+            Command::CopyNote => "Copy Note",
+            Command::JumpBack => "Jump Back",
+            Command::JumpForward => "Jump Forward",
+            Command::Status => "Account and Sync Status",
+            Command::Palette => "Command Palette",
+            Command::Quit => "Quit",
+        }
+    }
+
+    /// Keys that run the command from the note list. The first is the one shown.
+    fn keys(self) -> &'static [(KeyCode, bool)] {
+        match self {
+            Command::Open => &[(KeyCode::Enter, false)],
+            Command::Edit => &[(KeyCode::Char('i'), false)],
+            Command::NextPane => &[(KeyCode::Tab, false), (KeyCode::BackTab, false)],
+            Command::New => &[(KeyCode::Char('n'), false)],
+            Command::Scratch => &[(KeyCode::Char('S'), false), (KeyCode::Char('`'), true)],
+            Command::ClearScratch => &[],
+            // Only while a search is confirmed; `n` is New otherwise
+            Command::NextMatch => &[(KeyCode::Char('n'), false)],
+            Command::PrevMatch => &[(KeyCode::Char('N'), false)],
+            Command::Delete => &[(KeyCode::Char('d'), false)],
+            Command::CycleLabel => &[(KeyCode::Char('c'), false)],
+            Command::Search => &[(KeyCode::Char('/'), false)],
+            Command::ToggleDue => &[(KeyCode::Char('u'), false)],
+            Command::TogglePreview => &[],
+            Command::Sync => &[(KeyCode::Char('r'), false)],
+            Command::ToggleLock => &[(KeyCode::Char('L'), false)],
+            Command::CopyNote => &[(KeyCode::Char('Y'), false)],
+            Command::JumpBack => &[(KeyCode::Char('o'), true)],
+            Command::JumpForward => &[(KeyCode::Char('i'), true)],
+            Command::Status => &[(KeyCode::Char('g'), true)],
+            // Ctrl+Shift+P arrives as Ctrl+P on terminals without the kitty
+            // keyboard protocol
+            Command::Palette => &[
+                (KeyCode::Char(':'), false),
+                (KeyCode::Char('p'), true),
+                (KeyCode::Char('P'), true),
+            ],
+            Command::Quit => &[(KeyCode::Char('q'), false)],
+        }
+    }
+
+    /// The command a key runs in the note list. Shift is ignored; it is
+    /// already in the character.
+    pub fn for_key(key: &event::KeyEvent) -> Option<Command> {
+        let ctrl = key.modifiers.contains(event::KeyModifiers::CONTROL);
+        Command::ALL
+            .into_iter()
+            .find(|c| c.keys().contains(&(key.code, ctrl)))
+    }
+
+    pub fn key_label(self) -> String {
+        let Some(&(code, ctrl)) = self.keys().first() else {
+            return String::new();
+        };
+        let key = match code {
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::BackTab => "Shift+Tab".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            _ => String::new(),
+        };
+        if ctrl {
+            format!("Ctrl+{}", key)
+        } else {
+            key
+        }
+    }
+}
+
+/// Subsequence match of `query` in `text`, ignoring case. Higher is better:
+/// runs of consecutive letters and matches at word starts score more.
+pub(crate) fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut prev_matched = false;
+    let mut prev_char = ' ';
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let Some(&q) = query.peek() else {
+            break;
+        };
+        if c == q {
+            query.next();
+            score += 1;
+            if prev_matched {
+                score += 3;
+            }
+            if !prev_char.is_alphanumeric() {
+                score += 2;
+            }
+            prev_matched = true;
+        } else {
+            prev_matched = false;
+        }
+        prev_char = c;
+    }
+    query.peek().is_none().then_some(score)
+}
+
+impl Model<'_> {
+    /// What the list shows when it has no rows: no notes yet, nothing due, or
+    /// nothing matching the filter. Keys come from the command table, so they
+    /// are the real bindings.
+    pub(crate) fn empty_list_text(&self) -> String {
+        let filter = self.search_textarea.lines()[0].trim();
+        if !filter.is_empty() {
+            format!("No notes match \"{}\" — Esc to clear", filter)
+        } else if self.due_view {
+            format!(
+                "Nothing due — press {} for all notes",
+                Command::ToggleDue.key_label()
+            )
+        } else {
+            format!(
+                "No notes yet — press {} to create one, {} for commands",
+                Command::New.key_label(),
+                Command::Palette.key_label()
+            )
+        }
+    }
+
+    /// The editor's cheat sheet rows while there are no notes: key, then title.
+    pub(crate) fn getting_started(&self) -> Vec<(String, &'static str)> {
+        GETTING_STARTED
+            .into_iter()
+            .map(|c| (c.key_label(), c.title()))
+            .collect()
+    }
+
+    /// The palette's rows for the current query: best match first, ties and
+    /// the empty query going to the most recently run.
+    pub(crate) fn palette_matches(&self) -> Vec<Command> {
+        let query = self.palette_textarea.lines()[0].trim();
+        let recency = |c: &Command| {
+            self.palette_recent
+                .iter()
+                .position(|r| r == c)
+                .unwrap_or(usize::MAX)
+        };
+        let mut matches: Vec<(Command, i32)> = Command::ALL
+            .into_iter()
+            .filter(|c| *c != Command::Palette)
+            .filter_map(|c| fuzzy_score(query, c.title()).map(|score| (c, score)))
+            .collect();
+        matches.sort_by_key(|(c, score)| (std::cmp::Reverse(*score), recency(c)));
+        matches.into_iter().map(|(c, _)| c).collect()
+    }
+
+    async fn open_palette(&mut self) {
+        self.palette_recent = match self.repo.get_kv(RECENT_KEY).await {
+            Ok(Some(json)) => serde_json::from_str::<Vec<String>>(&json)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|s| Command::from_name(s))
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.palette_textarea = TextArea::default();
+        self.palette_textarea.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Command Palette ")
+                .border_style(Style::default().fg(self.config.theme.search_border)),
+        );
+        self.palette_state.select(Some(0));
+        self.active_pane = ActivePane::CommandPalette;
+    }
+
+    async fn remember_command(&mut self, command: Command) {
+        self.palette_recent.retain(|c| *c != command);
+        self.palette_recent.insert(0, command);
+        self.palette_recent.truncate(RECENT_LIMIT);
+        let names: Vec<&str> = self.palette_recent.iter().map(|c| c.as_str()).collect();
+        if let Ok(json) = serde_json::to_string(&names) {
+            let _ = self.repo.set_kv(RECENT_KEY, &json).await;
+        }
+    }
+
+    pub(crate) async fn run_command(&mut self, command: Command) -> Result<Action> {
+        match command {
+            Command::Open => {
+                self.active_pane = ActivePane::Editor;
+                self.mode = Mode::Normal;
+                self.record_note_visit();
+            }
+            Command::NextPane => self.focus_pane(ActivePane::Editor).await,
+            Command::Edit => {
+                self.active_pane = ActivePane::Editor;
+                self.mode = Mode::Insert;
+                self.textarea.move_cursor(CursorMove::Bottom);
+                self.textarea.move_cursor(CursorMove::End);
+                self.record_note_visit();
+            }
+            Command::New => {
+                self.current_note_id = None;
+                self.draft_id = uuid::Uuid::new_v4().to_string();
+                self.textarea = TextArea::default();
+                self.setup_textarea();
+                self.active_pane = ActivePane::Editor;
+                self.mode = Mode::Insert;
+            }
+            Command::Scratch => self.open_scratch().await?,
+            Command::ClearScratch => {
+                let scratch = self.repo.scratch_note().await?;
+                self.scratch_id = Some(scratch.id.clone());
+                self.note_to_delete = Some(scratch);
+                self.active_pane = ActivePane::DeleteConfirm;
+            }
+            Command::NextMatch => self.step_search_match(true),
+            Command::PrevMatch => self.step_search_match(false),
+            Command::Delete => {
+                if let Some(note) = self
+                    .list_state
+                    .selected()
+                    .and_then(|i| self.filtered_notes.get(i))
+                {
+                    self.note_to_delete = Some(note.clone());
+                    self.active_pane = ActivePane::DeleteConfirm;
+                }
+            }
+            Command::CycleLabel => self.cycle_label().await?,
+            Command::Search => {
+                self.active_pane = ActivePane::Search;
+                self.setup_search_textarea();
+            }
+            Command::ToggleDue => {
+                self.due_view = !self.due_view;
+                self.list_state.select(Some(0));
+                self.refresh_notes(true).await?;
+            }
+            Command::TogglePreview => {
+                self.show_preview = !self.show_preview;
+                self.preview_scroll = 0;
+            }
+            Command::Sync => {
+                let _ = self.sync_trigger.try_send(());
+            }
+            Command::ToggleLock => self.toggle_e2e_lock(),
+            Command::CopyNote => self.yank_selected_note(false),
+            Command::JumpBack => self.walk_jumplist(true).await?,
+            Command::JumpForward => self.walk_jumplist(false).await?,
+            Command::Status => {
+                self.load_session_info();
+                // Picks up plan changes made outside the app
+                self.spawn_account_check();
+                self.active_pane = ActivePane::StatusDialog;
+                self.status_list_state.select(Some(0));
+            }
+            Command::Palette => self.open_palette().await,
+            Command::Quit => return Ok(Action::Quit),
+        }
+        Ok(Action::None)
+    }
+
+    pub(crate) async fn handle_palette_key(&mut self, key: event::KeyEvent) -> Result<Action> {
+        let ctrl = key.modifiers.contains(event::KeyModifiers::CONTROL);
+        let count = self.palette_matches().len();
+        let selected = self.palette_state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc => self.active_pane = ActivePane::List,
+            KeyCode::Enter => {
+                let Some(&command) = self.palette_matches().get(selected) else {
+                    return Ok(Action::None);
+                };
+                self.active_pane = ActivePane::List;
+                self.remember_command(command).await;
+                return self.run_command(command).await;
+            }
+            KeyCode::Down | KeyCode::Tab => {
+                self.palette_state
+                    .select(Some((selected + 1).min(count.saturating_sub(1))));
+            }
+            KeyCode::Char('n') if ctrl => {
+                self.palette_state
+                    .select(Some((selected + 1).min(count.saturating_sub(1))));
+            }
+            KeyCode::Up | KeyCode::BackTab => {
+                self.palette_state.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Char('p') if ctrl => {
+                self.palette_state.select(Some(selected.saturating_sub(1)));
+            }
+            _ => {
+                if self.palette_textarea.input(key) {
+                    self.palette_state.select(Some(0));
+                }
+            }
+        }
+        Ok(Action::None)
+    }
+
+    pub(crate) fn render_palette(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let area = popup_rect(area, (60, 60), (40, 8), (90, 24));
+        f.render_widget(ratatui::widgets::Clear, area);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(1)])
+            .split(area);
+        f.render_widget(&self.palette_textarea, chunks[0]);
+
+        // Inner width less the highlight symbol
+        let width = (chunks[1].width as usize).saturating_sub(4);
+        let items: Vec<ListItem> = self
+            .palette_matches()
+            .into_iter()
+            .map(|c| {
+                let key = c.key_label();
+                let title = truncate_to_width(c.title(), width.saturating_sub(key.width() + 1));
+                let gap = width.saturating_sub(title.width() + key.width());
+                ListItem::new(Line::from(vec![
+                    Span::raw(title),
+                    Span::raw(" ".repeat(gap)),
+                    Span::styled(key, Style::default().fg(Color::DarkGray)),
+                ]))
+            })
+            .collect();
+        let empty = items.is_empty();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(theme.search_border)),
+            )
+            .highlight_style(
+                Style::default()
+                    .bg(theme.selection_bg)
+                    .fg(theme.selection_fg)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .highlight_symbol("> ");
+        if empty {
+            f.render_widget(
+                Paragraph::new(" No matching command").block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(theme.search_border)),
+                ),
+                chunks[1],
+            );
+        } else {
+            f.render_stateful_widget(list, chunks[1], &mut self.palette_state);
+        }
+    }
+}
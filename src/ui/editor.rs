@@ -0,0 +1,1007 @@
+// The editor (all Vim modes) and the diff viewer: keys and drawing.
+
+use crate::*;
+
+impl Model<'_> {
+    pub(crate) async fn handle_editor_key(
+        &mut self,
+        key: event::KeyEvent,
+        count: usize,
+    ) -> Result<Action> {
+        if self.editor_locked {
+            if key.code == KeyCode::Esc {
+                self.active_pane = ActivePane::List;
+                self.mode = Mode::Normal;
+                self.pending_key = PendingKey::None;
+                self.restore_list_offset(self.list_offset);
+            } else {
+                self.editor_message =
+                    Some(" Encrypted note is read-only until unlocked ".to_string());
+            }
+            return Ok(Action::None);
+        }
+        if self.editor_split && self.mode == Mode::Normal && !split_view_key(&key) {
+            self.editor_message =
+                Some(" Long lines are split for display and read-only — :raw to edit ".to_string());
+            self.pending_key = PendingKey::None;
+            return Ok(Action::None);
+        }
+        match self.mode {
+            Mode::Normal => match key.code {
+                KeyCode::Char(c) if self.pending_key == PendingKey::Q => {
+                    if c.is_ascii_lowercase() {
+                        self.macro_recording = Some((c, Vec::new()));
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c) if self.pending_key == PendingKey::At => {
+                    self.replay_macro(c, count);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c) if matches!(self.pending_key, PendingKey::Find(..)) => {
+                    if let PendingKey::Find(kind, delete) = self.pending_key {
+                        self.last_find = Some((kind, c));
+                        self.find_char(kind, c, delete, false);
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c) if self.pending_key == PendingKey::Z => {
+                    self.pending_view_align = match c {
+                        't' => Some(ViewAlign::Top),
+                        'z' => Some(ViewAlign::Center),
+                        'b' => Some(ViewAlign::Bottom),
+                        _ => None,
+                    };
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('z') if self.pending_key == PendingKey::None => {
+                    self.pending_key = PendingKey::Z;
+                }
+                KeyCode::Char(c @ ('f' | 'F' | 't' | 'T'))
+                    if matches!(self.pending_key, PendingKey::None | PendingKey::D) =>
+                {
+                    self.pending_key = PendingKey::Find(c, self.pending_key == PendingKey::D);
+                }
+                KeyCode::Char(c @ (';' | ',')) if self.pending_key == PendingKey::None => {
+                    self.repeat_find(c == ',');
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c) if self.pending_key == PendingKey::W => {
+                    match window_target(ActivePane::Editor, c) {
+                        Some(pane) => self.focus_pane(pane).await,
+                        None => self.pending_key = PendingKey::None,
+                    }
+                }
+                KeyCode::Char('w') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.pending_key = PendingKey::W;
+                }
+                KeyCode::BackTab => self.focus_pane(ActivePane::List).await,
+                KeyCode::Char(':') => {
+                    self.mode = Mode::Command;
+                    self.command_line.clear();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('o') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.walk_jumplist(true).await?;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('i') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.walk_jumplist(false).await?;
+                    self.pending_key = PendingKey::None;
+                }
+                // Terminals without keyboard enhancement report Ctrl+i as Tab
+                KeyCode::Tab => {
+                    self.walk_jumplist(false).await?;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c) if self.pending_key == PendingKey::Mark => {
+                    if c.is_ascii_lowercase() {
+                        self.set_mark(c).await;
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c)
+                    if matches!(
+                        self.pending_key,
+                        PendingKey::MarkJump | PendingKey::MarkLineJump
+                    ) =>
+                {
+                    let exact = self.pending_key == PendingKey::MarkJump;
+                    self.jump_to_mark(c, exact).await;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('i') if self.pending_key == PendingKey::C => {
+                    self.pending_key = PendingKey::CI;
+                }
+                KeyCode::Char(c) if matches!(self.pending_key, PendingKey::C | PendingKey::CI) => {
+                    let motion = match (self.pending_key, c) {
+                        (PendingKey::C, 'w' | 'e' | '$') => Some(c),
+                        (PendingKey::CI, 'w') => Some('i'),
+                        _ => None,
+                    };
+                    if let Some(motion) = motion {
+                        self.change_motion(motion);
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('c') => {
+                    self.pending_key = PendingKey::C;
+                }
+                KeyCode::Char('C') => {
+                    self.change_motion('$');
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('m') => {
+                    self.pending_key = PendingKey::Mark;
+                }
+                KeyCode::Char('`') => {
+                    self.pending_key = PendingKey::MarkJump;
+                }
+                KeyCode::Char('\'') => {
+                    self.pending_key = PendingKey::MarkLineJump;
+                }
+                KeyCode::Char('q') => {
+                    if let Some((register, mut keys)) = self.macro_recording.take() {
+                        keys.pop(); // the `q` that stopped recording
+                        self.macros.insert(register, keys);
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::Q;
+                    }
+                }
+                KeyCode::Char('@') => {
+                    self.pending_count = count;
+                    self.pending_key = PendingKey::At;
+                }
+                KeyCode::Esc => self.request_leave_editor().await,
+                KeyCode::Char('i') => {
+                    self.mode = Mode::Insert;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('a') => {
+                    // Forward at the end of a line would wrap to the next one
+                    let (row, col) = self.textarea.cursor();
+                    if col < self.textarea.lines()[row].chars().count() {
+                        self.textarea.move_cursor(CursorMove::Forward);
+                    }
+                    self.mode = Mode::Insert;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('A') => {
+                    self.textarea.move_cursor(CursorMove::End);
+                    self.mode = Mode::Insert;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('I') => {
+                    let (row, _) = self.textarea.cursor();
+                    let col = motion::first_non_blank(&self.textarea.lines()[row]);
+                    self.textarea
+                        .move_cursor(CursorMove::Jump(row as u16, col as u16));
+                    self.mode = Mode::Insert;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('h') | KeyCode::Left => {
+                    self.textarea.move_cursor(CursorMove::Back);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('j') | KeyCode::Down => {
+                    if self.show_preview {
+                        self.preview_scroll = self.preview_scroll.saturating_add(1);
+                    } else {
+                        self.textarea.move_cursor(CursorMove::Down);
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    if self.show_preview {
+                        self.preview_scroll = self.preview_scroll.saturating_sub(1);
+                    } else {
+                        self.textarea.move_cursor(CursorMove::Up);
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('l') | KeyCode::Right => {
+                    self.textarea.move_cursor(CursorMove::Forward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c @ ('w' | 'e' | 'b')) if self.pending_key == PendingKey::D => {
+                    self.delete_word_motion(c);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('w') => {
+                    self.textarea.move_cursor(CursorMove::WordForward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('b') => {
+                    self.textarea.move_cursor(CursorMove::WordBack);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('e') => {
+                    self.textarea.move_cursor(CursorMove::WordForward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c @ ('o' | 'O')) => {
+                    self.open_line(c == 'o');
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char(c @ ('x' | 'X')) => {
+                    self.delete_chars(c == 'x', count);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('0') => {
+                    self.textarea.move_cursor(CursorMove::Head);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('$') => {
+                    self.textarea.move_cursor(CursorMove::End);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('%') => {
+                    self.jump_to_matching_bracket();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('u') => {
+                    self.undo_edit();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('r') => {
+                    self.redo_edit();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('M') => {
+                    self.show_preview = !self.show_preview;
+                    self.preview_scroll = 0;
+                    self.pending_key = PendingKey::None;
+                }
+
+                KeyCode::Char('g') => {
+                    if self.pending_key == PendingKey::G {
+                        self.textarea.move_cursor(CursorMove::Top);
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::G;
+                    }
+                }
+                KeyCode::Char('G') => {
+                    self.textarea.move_cursor(CursorMove::Bottom);
+                    self.pending_key = PendingKey::None;
+                }
+                // There is no search inside a note, so these always step
+                // through the list's confirmed search
+                KeyCode::Char(c @ ('n' | 'N')) => {
+                    self.step_search_match_from_editor(c == 'n').await;
+                    self.pending_key = PendingKey::None;
+                }
+
+                KeyCode::Char('d') => {
+                    if self.pending_key == PendingKey::D {
+                        let (row, _) = self.textarea.cursor();
+                        let line = self.textarea.lines()[row].clone();
+                        self.copy_to_clipboard(&format!("{}\n", line));
+                        self.textarea.move_cursor(CursorMove::Head);
+                        self.textarea.delete_line_by_end();
+                        if !self.textarea.delete_next_char() {
+                            self.textarea.move_cursor(CursorMove::Back);
+                            self.textarea.delete_next_char();
+                        }
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::D;
+                    }
+                }
+
+                KeyCode::Char('y') => {
+                    if self.pending_key == PendingKey::Y {
+                        let (row, _) = self.textarea.cursor();
+                        let line = self.textarea.lines()[row].clone();
+                        self.copy_to_clipboard(&format!("{}\n", line));
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::Y;
+                    }
+                }
+
+                KeyCode::Char('p') => {
+                    let text = self.get_from_clipboard();
+                    // Blockwise only while the clipboard still holds the block yank
+                    let pieces = self
+                        .block_register
+                        .clone()
+                        .filter(|p| text.as_ref().is_none_or(|t| *t == p.join("\n")));
+                    if let Some(pieces) = pieces {
+                        self.paste_block(&pieces);
+                    } else if let Some(text) = text {
+                        self.textarea.insert_str(&text);
+                    }
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('v') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.mode = Mode::VisualBlock;
+                    self.visual_block_anchor = Some(self.textarea.cursor());
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('v') => {
+                    self.mode = Mode::Visual;
+                    self.textarea.start_selection();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('V') => {
+                    self.mode = Mode::VisualLine;
+                    let (row, _) = self.textarea.cursor();
+                    self.visual_anchor_row = Some(row);
+                    self.textarea.move_cursor(CursorMove::Head);
+                    self.textarea.start_selection();
+                    self.textarea.move_cursor(CursorMove::End);
+                    self.pending_key = PendingKey::None;
+                }
+
+                KeyCode::Char('s') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.keep_buffer().await;
+                    self.pending_key = PendingKey::None;
+                }
+                _ => {
+                    self.pending_key = PendingKey::None;
+                }
+            },
+            Mode::Insert => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.auto_pairs_open.clear();
+                    self.finish_block_insert();
+                    self.finish_open_line();
+                }
+                KeyCode::Char('s') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+                    self.keep_buffer().await;
+                }
+                _ => {
+                    if !(self.config.editor.auto_pairs && self.auto_pair_input(key)) {
+                        self.textarea.input(key);
+                    }
+                }
+            },
+            Mode::Command => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    self.mode = Mode::Normal;
+                    let command = std::mem::take(&mut self.command_line);
+                    self.run_editor_command(command.trim()).await;
+                }
+                KeyCode::Backspace if self.command_line.is_empty() => {
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Backspace => {
+                    self.command_line.pop();
+                }
+                KeyCode::Char(c) => self.command_line.push(c),
+                _ => {}
+            },
+            Mode::Visual => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.textarea.cancel_selection();
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('y') => {
+                    self.textarea.copy();
+                    let text = self.textarea.yank_text();
+                    self.copy_to_clipboard(&text);
+                    self.mode = Mode::Normal;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('d') => {
+                    self.textarea.cut();
+                    let text = self.textarea.yank_text();
+                    self.copy_to_clipboard(&text);
+                    self.mode = Mode::Normal;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('g') => {
+                    if self.pending_key == PendingKey::G {
+                        self.textarea.move_cursor(CursorMove::Top);
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::G;
+                    }
+                }
+                KeyCode::Char('G') => {
+                    self.textarea.move_cursor(CursorMove::Bottom);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('h') | KeyCode::Left => {
+                    self.textarea.move_cursor(CursorMove::Back);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('j') | KeyCode::Down => {
+                    self.textarea.move_cursor(CursorMove::Down);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    self.textarea.move_cursor(CursorMove::Up);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('l') | KeyCode::Right => {
+                    self.textarea.move_cursor(CursorMove::Forward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('w') => {
+                    self.textarea.move_cursor(CursorMove::WordForward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('b') => {
+                    self.textarea.move_cursor(CursorMove::WordBack);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('e') => {
+                    self.textarea.move_cursor(CursorMove::WordForward);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('0') => {
+                    self.textarea.move_cursor(CursorMove::Head);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('$') => {
+                    self.textarea.move_cursor(CursorMove::End);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('%') => {
+                    self.jump_to_matching_bracket();
+                    self.pending_key = PendingKey::None;
+                }
+                _ => {
+                    self.pending_key = PendingKey::None;
+                }
+            },
+            Mode::VisualBlock => match key.code {
+                KeyCode::Esc => self.exit_visual_block(),
+                KeyCode::Char('y') => {
+                    if let Some(block) = self.visual_block() {
+                        self.yank_block(&block);
+                        self.exit_visual_block();
+                        self.textarea
+                            .move_cursor(CursorMove::Jump(block.top as u16, block.left as u16));
+                    }
+                }
+                KeyCode::Char('d') | KeyCode::Char('x') => {
+                    if let Some(block) = self.visual_block() {
+                        self.yank_block(&block);
+                        let rows = block::delete(self.textarea.lines(), &block);
+                        self.replace_rows(block.top, block.bottom, rows);
+                        self.exit_visual_block();
+                        self.textarea
+                            .move_cursor(CursorMove::Jump(block.top as u16, block.left as u16));
+                    }
+                }
+                KeyCode::Char('I') => self.begin_block_insert(false),
+                KeyCode::Char('A') => self.begin_block_insert(true),
+                KeyCode::Char('$') => {
+                    self.textarea.move_cursor(CursorMove::End);
+                    self.visual_block_to_eol = true;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('%') => {
+                    self.jump_to_matching_bracket();
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('g') => {
+                    if self.pending_key == PendingKey::G {
+                        self.textarea.move_cursor(CursorMove::Top);
+                        self.pending_key = PendingKey::None;
+                    } else {
+                        self.pending_key = PendingKey::G;
+                    }
+                }
+                KeyCode::Char('G') => {
+                    self.textarea.move_cursor(CursorMove::Bottom);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('j') | KeyCode::Down => {
+                    self.textarea.move_cursor(CursorMove::Down);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    self.textarea.move_cursor(CursorMove::Up);
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('h') | KeyCode::Left => {
+                    self.textarea.move_cursor(CursorMove::Back);
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('l') | KeyCode::Right => {
+                    self.textarea.move_cursor(CursorMove::Forward);
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('w') | KeyCode::Char('e') => {
+                    self.textarea.move_cursor(CursorMove::WordForward);
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('b') => {
+                    self.textarea.move_cursor(CursorMove::WordBack);
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('0') => {
+                    self.textarea.move_cursor(CursorMove::Head);
+                    self.visual_block_to_eol = false;
+                    self.pending_key = PendingKey::None;
+                }
+                _ => {
+                    self.pending_key = PendingKey::None;
+                }
+            },
+            Mode::VisualLine => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.textarea.cancel_selection();
+                    self.visual_anchor_row = None;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('y') => {
+                    self.textarea.copy();
+                    let text = self.textarea.yank_text();
+                    self.copy_to_clipboard(&text);
+                    self.mode = Mode::Normal;
+                    self.visual_anchor_row = None;
+                    self.pending_key = PendingKey::None;
+                }
+                KeyCode::Char('d') => {
+                    self.textarea.cut();
+                    let text = self.textarea.yank_text();
+                    self.copy_to_clipboard(&text);
+                    self.mode = Mode::Normal;
+                    self.visual_anchor_row = None;
+                    self.pending_key = PendingKey::None;
+                }
+                _ => {
+                    match key.code {
+                        KeyCode::Char('j') | KeyCode::Down => {
+                            self.textarea.move_cursor(CursorMove::Down);
+                            self.pending_key = PendingKey::None;
+                        }
+                        KeyCode::Char('k') | KeyCode::Up => {
+                            self.textarea.move_cursor(CursorMove::Up);
+                            self.pending_key = PendingKey::None;
+                        }
+                        KeyCode::Char('g') => {
+                            if self.pending_key == PendingKey::G {
+                                self.textarea.move_cursor(CursorMove::Top);
+                                self.pending_key = PendingKey::None;
+                            } else {
+                                self.pending_key = PendingKey::G;
+                                return Ok(Action::None);
+                            }
+                        }
+                        KeyCode::Char('G') => {
+                            self.textarea.move_cursor(CursorMove::Bottom);
+                            self.pending_key = PendingKey::None;
+                        }
+                        KeyCode::Char('%') => {
+                            self.jump_to_matching_bracket();
+                            self.pending_key = PendingKey::None;
+                        }
+                        _ => {
+                            self.pending_key = PendingKey::None;
+                        }
+                    }
+
+                    if let Some(anchor) = self.visual_anchor_row {
+                        let (current_row, _) = self.textarea.cursor();
+                        self.textarea.cancel_selection();
+
+                        if current_row < anchor {
+                            self.textarea
+                                .move_cursor(CursorMove::Jump(anchor as u16, 0));
+                            self.textarea.move_cursor(CursorMove::End);
+                            self.textarea.start_selection();
+                            self.textarea
+                                .move_cursor(CursorMove::Jump(current_row as u16, 0));
+                            self.textarea.move_cursor(CursorMove::Head);
+                        } else {
+                            self.textarea
+                                .move_cursor(CursorMove::Jump(anchor as u16, 0));
+                            self.textarea.move_cursor(CursorMove::Head);
+                            self.textarea.start_selection();
+                            self.textarea
+                                .move_cursor(CursorMove::Jump(current_row as u16, 0));
+                            self.textarea.move_cursor(CursorMove::End);
+                        }
+                    }
+                }
+            },
+        };
+        Ok(Action::None)
+    }
+
+    pub(crate) async fn handle_diff_key(&mut self, key: event::KeyEvent) -> Result<Action> {
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.diff_scroll = self.diff_scroll.saturating_add(1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.diff_scroll = self.diff_scroll.saturating_sub(1);
+            }
+            KeyCode::Char('G') => {
+                let len = self.diff_view.as_ref().map_or(0, |(_, lines)| lines.len());
+                self.diff_scroll = len.saturating_sub(1).min(u16::MAX as usize) as u16;
+            }
+            KeyCode::Char('g') => self.diff_scroll = 0,
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.diff_view = None;
+                self.active_pane = ActivePane::Editor;
+            }
+            _ => {}
+        };
+        Ok(Action::None)
+    }
+}
+
+impl Model<'_> {
+    pub(crate) fn render_editor(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        if self.editor_locked {
+            self.render_locked_note(f, area);
+        } else if self.show_preview {
+            self.render_preview(f, area);
+        } else if self.notes.is_empty()
+            && self.active_pane != ActivePane::Editor
+            && !self.is_loading
+        {
+            self.render_getting_started(f, area);
+        } else {
+            self.render_textarea(f, area);
+        }
+    }
+
+    fn render_getting_started(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let rows = self.getting_started();
+        let key_width = rows.iter().map(|(key, _)| key.width()).max().unwrap_or(0);
+        let mut text = vec![
+            Line::from(""),
+            Line::from(Span::styled(
+                "Getting started",
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(""),
+        ];
+        text.extend(rows.into_iter().map(|(key, title)| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:>width$}  ", key, width = key_width),
+                    Style::default().fg(self.config.theme.border_active),
+                ),
+                Span::raw(format!("{:<24}", title)),
+            ])
+        }));
+        let paragraph = Paragraph::new(text)
+            .alignment(ratatui::layout::Alignment::Center)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(self.config.theme.border_inactive))
+                    .title(" Editor "),
+            );
+        f.render_widget(paragraph, area);
+    }
+
+    fn render_preview(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let content = self.textarea.lines().join("\n");
+        let markdown_text = self.preview_text(&content, area.width.saturating_sub(2) as usize);
+        let border = if self.active_pane == ActivePane::Editor {
+            theme.border_active
+        } else {
+            theme.border_inactive
+        };
+        let preview_block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Preview (Markdown) ")
+            .border_style(Style::default().fg(border));
+        let paragraph = Paragraph::new(markdown_text)
+            .block(preview_block)
+            .wrap(Wrap { trim: false })
+            .scroll((self.preview_scroll, 0));
+        f.render_widget(paragraph, area);
+    }
+
+    // A leading `# Heading` becomes a centered title over a rule as wide as the
+    // preview, with the note's metadata dimmed beneath. The paragraph wraps a
+    // long title and keeps each wrapped line centered.
+    fn preview_text<'c>(&self, content: &'c str, width: usize) -> ratatui::text::Text<'c> {
+        if content.len() > PREVIEW_MARKDOWN_LIMIT {
+            let mut text = ratatui::text::Text::raw(content);
+            text.lines.insert(
+                0,
+                Line::from(Span::styled(
+                    "Too large to render as markdown; shown as plain text",
+                    Style::default().fg(Color::DarkGray),
+                )),
+            );
+            return text;
+        }
+        let (first, rest) = content.split_once('\n').unwrap_or((content, ""));
+        let title = match markdown::blocks(first).first() {
+            Some(markdown::Block::Heading(1, title)) if self.config.theme.fancy_title => *title,
+            _ => return markdown::parse_markdown(content),
+        };
+
+        let updated = self
+            .current_note_id
+            .as_ref()
+            .and_then(|id| self.notes.iter().find(|n| &n.id == id))
+            .map_or_else(
+                || "Not saved yet".to_string(),
+                |n| format!("Updated {}", format_note_time(&n.updated_at)),
+            );
+        let words = content.split_whitespace().count();
+        let dim = Style::default()
+            .fg(Color::DarkGray)
+            .add_modifier(Modifier::DIM);
+
+        let mut lines = vec![
+            Line::from(""),
+            Line::from(Span::styled(
+                title,
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ))
+            .centered(),
+            Line::from(Span::styled(
+                "─".repeat(width),
+                Style::default().fg(Color::DarkGray),
+            )),
+            Line::from(Span::styled(
+                format!(
+                    "{}  ·  {} word{}",
+                    updated,
+                    words,
+                    if words == 1 { "" } else { "s" }
+                ),
+                dim,
+            ))
+            .centered(),
+            Line::from(""),
+        ];
+        lines.extend(markdown::parse_markdown(rest).lines);
+        ratatui::text::Text::from(lines)
+    }
+
+    fn render_textarea(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let mut editor_block = Block::default().borders(Borders::ALL);
+        if let ActivePane::Editor = self.active_pane {
+            let (color, title) = match self.mode {
+                Mode::Normal => (theme.mode_normal, " Editor (Normal) "),
+                Mode::Insert => (theme.mode_insert, " Editor (Insert) "),
+                Mode::Visual => (theme.mode_normal, " Editor (Visual) "),
+                Mode::VisualLine => (theme.mode_normal, " Editor (Visual Line) "),
+                Mode::VisualBlock => (theme.mode_normal, " Editor (Visual Block) "),
+                Mode::Command => (theme.mode_normal, " Editor (Command) "),
+            };
+            let title = match &self.macro_recording {
+                Some((register, _)) => format!("{}recording @{} ", title, register),
+                None => title.to_string(),
+            };
+            let title = if self.remote_changed_base.is_some() {
+                format!("{}changed remotely ", title)
+            } else {
+                title
+            };
+            let title = if self.editor_split {
+                format!("{}long lines split, read-only (:raw to edit) ", title)
+            } else {
+                title
+            };
+            editor_block = editor_block
+                .border_style(Style::default().fg(color))
+                .title(title);
+            // Restore cursor style and cursor line highlight when active
+            let cursor_line = theme.editor_cursor_line;
+            self.textarea
+                .set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
+            self.textarea
+                .set_cursor_line_style(Style::default().bg(cursor_line));
+        } else {
+            editor_block = editor_block
+                .border_style(Style::default().fg(theme.border_inactive))
+                .title(" Editor ");
+            // Hide cursor and disable cursor line highlight when not in editor pane
+            self.textarea.set_cursor_style(Style::default());
+            self.textarea.set_cursor_line_style(Style::default());
+        }
+
+        self.textarea.set_block(editor_block);
+        self.scroll_editor(area);
+        f.render_widget(&self.textarea, area);
+        self.render_editor_overlay(f, area);
+    }
+
+    /// Scrolls the textarea before it renders so that `editor.scrolloff` lines
+    /// stay visible around the cursor, and applies any `zt`/`zz`/`zb` request.
+    /// Uses the area about to be rendered, so the math follows resizes.
+    fn scroll_editor(&mut self, area: ratatui::layout::Rect) {
+        let height = Block::default().borders(Borders::ALL).inner(area).height as usize;
+        if height == 0 {
+            return;
+        }
+        let row = self.textarea.cursor().0;
+        let len = self.textarea.lines().len();
+        let prev = self.editor_viewport.0 as usize;
+        let mut top = next_scroll_top(prev as u16, row as u16, height as u16) as usize;
+        let so = self.config.editor.scrolloff.min((height - 1) / 2);
+
+        match self.pending_view_align.take() {
+            Some(ViewAlign::Top) => top = row.saturating_sub(so),
+            Some(ViewAlign::Center) => top = row.saturating_sub(height / 2),
+            Some(ViewAlign::Bottom) => top = (row + so + 1).saturating_sub(height),
+            None => {
+                if row < top + so {
+                    top = row.saturating_sub(so);
+                } else if row + so >= top + height {
+                    // No scrolling past the end just to show context
+                    top = (row + so + 1 - height)
+                        .min(len.saturating_sub(height))
+                        .max(top);
+                }
+            }
+        }
+
+        if top != prev {
+            let delta = top as i64 - prev as i64;
+            self.textarea.scroll(Scrolling::Delta {
+                rows: delta.clamp(i16::MIN as i64, i16::MAX as i64) as i16,
+                cols: 0,
+            });
+            self.editor_viewport.0 = top as u16;
+        }
+    }
+
+    fn render_locked_note(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let border = if self.active_pane == ActivePane::Editor {
+            theme.border_active
+        } else {
+            theme.border_inactive
+        };
+        let hint = if self.e2e_status == E2EState::Locked {
+            "Unlock E2E to read or edit it: press L in the note list."
+        } else {
+            "None of the available keys can decrypt it. If the passphrase was changed on another device, unlock with the new one."
+        };
+        let text = vec![
+            Line::from(""),
+            Line::from(Span::styled(
+                LOCKED_NOTE_TITLE,
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(""),
+            Line::from("This note is stored encrypted and is read-only here."),
+            Line::from(hint),
+        ];
+        let paragraph = Paragraph::new(text)
+            .alignment(ratatui::layout::Alignment::Center)
+            .wrap(Wrap { trim: true })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(border))
+                    .title(" Editor (Read-only) "),
+            );
+        f.render_widget(paragraph, area);
+    }
+
+    /// Styles cells over the rendered editor: the block selection and the
+    /// bracket matching the one under the cursor. tui-textarea keeps its scroll
+    /// offset private, so it is tracked here using the same rule the widget
+    /// applies when it renders.
+    fn render_editor_overlay(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let inner = Block::default().borders(Borders::ALL).inner(area);
+        let (row, col) = self.textarea.cursor();
+        let top_row = next_scroll_top(self.editor_viewport.0, row as u16, inner.height);
+        let top_col = next_scroll_top(self.editor_viewport.1, col as u16, inner.width);
+        self.editor_viewport = (top_row, top_col);
+
+        let mut cells = Vec::new();
+        if let Some(block) = self.visual_block() {
+            let style = self.textarea.selection_style();
+            for r in block.top..=block.bottom {
+                let (start, end) = block.span(&self.textarea.lines()[r]);
+                cells.extend((start..end).map(|c| (r, c, style)));
+            }
+        }
+        if self.config.editor.match_brackets && self.active_pane == ActivePane::Editor {
+            if let Some((r, c)) = motion::matching_bracket(self.textarea.lines(), (row, col)) {
+                let theme = &self.config.theme;
+                let style = Style::default()
+                    .bg(theme.selection_bg)
+                    .fg(theme.selection_fg)
+                    .add_modifier(Modifier::BOLD);
+                cells.push((r, c, style));
+            }
+        }
+
+        let (top_row, top_col) = (top_row as usize, top_col as usize);
+        let buf = f.buffer_mut();
+        for (r, c, style) in cells {
+            if r < top_row
+                || c < top_col
+                || r >= top_row + inner.height as usize
+                || c >= top_col + inner.width as usize
+            {
+                continue;
+            }
+            let (x, y) = (
+                inner.x + (c - top_col) as u16,
+                inner.y + (r - top_row) as u16,
+            );
+            buf[(x, y)].set_style(style);
+        }
+    }
+
+    pub(crate) fn render_diff(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let Some((title, lines)) = &self.diff_view else {
+            return;
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(title.as_str())
+            .border_style(Style::default().fg(theme.border_active));
+        let text = if lines.is_empty() {
+            vec![Line::from(" No changes ")]
+        } else {
+            lines.clone()
+        };
+        let p = Paragraph::new(text)
+            .block(block)
+            .scroll((self.diff_scroll, 0));
+
+        let diff_area = popup_rect(area, (80, 80), (40, 8), (200, 60));
+        f.render_widget(ratatui::widgets::Clear, diff_area);
+        f.render_widget(p, diff_area);
+    }
+}
+
+// Keys that only move around or leave, allowed while long lines are split
+fn split_view_key(key: &event::KeyEvent) -> bool {
+    if key.modifiers.contains(event::KeyModifiers::CONTROL) {
+        return !matches!(key.code, KeyCode::Char('r' | 'v'));
+    }
+    matches!(
+        key.code,
+        KeyCode::Esc
+            | KeyCode::Tab
+            | KeyCode::BackTab
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::PageUp
+            | KeyCode::PageDown
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::Char(
+                ':' | 'h'
+                    | 'j'
+                    | 'k'
+                    | 'l'
+                    | 'w'
+                    | 'b'
+                    | 'e'
+                    | '0'
+                    | '$'
+                    | 'g'
+                    | 'G'
+                    | 'z'
+                    | 't'
+                    | 'M'
+                    | 'n'
+                    | 'N'
+                    | '`'
+                    | '\''
+            )
+    )
+}
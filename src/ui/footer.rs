@@ -0,0 +1,284 @@
+// The status line: sync state, E2E padlock, and key hints or the latest message.
+
+use crate::*;
+
+impl Model<'_> {
+    pub(crate) fn render_footer(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let (sync_indicator, sync_color) = sync_indicator(self);
+        let help_text = footer_text(self);
+        let e2e_indicator = self.e2e_indicator();
+        let timer_label = self
+            .focus_timer
+            .as_ref()
+            .map(|t| t.label(Utc::now()))
+            .unwrap_or_default();
+        let footer_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Length((sync_indicator.chars().count() as u16).max(12)),
+                Constraint::Length(timer_label.width() as u16),
+                Constraint::Length(
+                    e2e_indicator
+                        .as_ref()
+                        .map_or(0, |(text, _)| text.width() as u16),
+                ),
+                Constraint::Min(1),
+            ])
+            .split(area);
+
+        f.render_widget(
+            Paragraph::new(sync_indicator)
+                .style(Style::default().fg(sync_color).add_modifier(Modifier::BOLD)),
+            footer_chunks[0],
+        );
+        f.render_widget(
+            Paragraph::new(timer_label).style(Style::default().fg(self.config.theme.sync_syncing)),
+            footer_chunks[1],
+        );
+        self.e2e_indicator_area = e2e_indicator.map(|(text, color)| {
+            f.render_widget(
+                Paragraph::new(text).style(Style::default().fg(color).add_modifier(Modifier::BOLD)),
+                footer_chunks[2],
+            );
+            footer_chunks[2]
+        });
+        // A focus block just ended
+        let help_style = if self.timer_flash_until.is_some() {
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Yellow)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(self.config.theme.border_inactive)
+        };
+        f.render_widget(
+            Paragraph::new(help_text)
+                .style(help_style)
+                .wrap(Wrap { trim: true }),
+            footer_chunks[3],
+        );
+    }
+
+    /// Footer padlock for the E2E state; None when E2E doesn't apply.
+    fn e2e_indicator(&self) -> Option<(String, Color)> {
+        // Guests have no E2E
+        self.user_email.as_ref()?;
+        let theme = &self.config.theme;
+        let (text, color) = match self.e2e_status {
+            E2EState::Unlocked if self.key_unverified => {
+                ("🔓 E2E*".to_string(), theme.sync_syncing)
+            }
+            E2EState::Unlocked => ("🔓 E2E".to_string(), theme.sync_synced),
+            E2EState::Unlocking => (
+                format!("{} E2E", SPINNER[self.spinner_index % SPINNER.len()]),
+                theme.sync_syncing,
+            ),
+            E2EState::Locked => ("🔒 E2E".to_string(), theme.sync_error),
+            E2EState::SetupRequired => ("🔒 E2E?".to_string(), theme.sync_offline),
+            E2EState::Disabled | E2EState::UpgradeRequired => return None,
+        };
+        Some((format!(" {} ", text), color))
+    }
+
+    /// Footer state when sync isn't running for a reason other than the last
+    /// sync result: account, plan, network, or E2E lock.
+    fn connection_label(&self) -> Option<&'static str> {
+        let is_free = self.user_plan == Some(Plan::Free);
+        if self.user_email.is_none() {
+            Some("Guest")
+        } else if is_free {
+            Some("Free (local only)")
+        } else if !self.network_online.load(Ordering::SeqCst) {
+            Some("Offline (no network)")
+        } else if self.sync_status != SyncStatus::Offline {
+            None
+        } else if self.e2e_status == E2EState::Locked {
+            Some("Locked")
+        } else {
+            Some("Online")
+        }
+    }
+}
+
+/// The sync state shown at the left of the footer, and its color.
+pub(crate) fn sync_indicator(model: &Model) -> (String, Color) {
+    let theme = &model.config.theme;
+    let show_feedback = model.showing_saved_feedback();
+    let connection_label = model.connection_label();
+    let color = if show_feedback {
+        theme.sync_synced
+    } else if model.config.general.offline_mode {
+        theme.sync_offline
+    } else if connection_label == Some("Online") {
+        theme.sync_synced
+    } else if connection_label.is_some() {
+        theme.sync_offline
+    } else {
+        match &model.sync_status {
+            SyncStatus::Synced => theme.sync_synced,
+            SyncStatus::Syncing => theme.sync_syncing,
+            SyncStatus::Offline => theme.sync_offline,
+            SyncStatus::Error(_) => theme.sync_error,
+            SyncStatus::PaymentRequired => theme.sync_payment_required,
+            SyncStatus::SessionExpired => theme.sync_error,
+            SyncStatus::Unlocking => theme.sync_syncing,
+            SyncStatus::UnlockFailed(_) => theme.sync_error,
+            SyncStatus::Warning(_) => Color::Yellow,
+            SyncStatus::Encrypting(_, _) => theme.sync_syncing,
+        }
+    };
+
+    let text = if show_feedback {
+        " Saved! ".to_string()
+    } else if model.config.general.offline_mode {
+        " Offline Mode ".to_string()
+    } else if matches!(
+        model.sync_status,
+        SyncStatus::Syncing | SyncStatus::Encrypting(_, _)
+    ) || model.is_loading
+        || model.showing_backup()
+    {
+        let s = SPINNER[model.spinner_index % SPINNER.len()];
+        if let SyncStatus::Encrypting(done, total) = model.sync_status {
+            format!(" {} Encrypting {}/{} ", s, done, total)
+        } else if model.showing_backup() {
+            format!(" {} Backing up... ", s)
+        } else if model.is_loading {
+            format!(" {} Loading... ", s)
+        } else {
+            format!(" {} Syncing... ", s)
+        }
+    } else if let Some(label) = connection_label {
+        format!(" {} ", label)
+    } else {
+        format!(" {} ", model.sync_status.as_str())
+    };
+    (text, color)
+}
+
+/// The key hints for the active pane and mode.
+pub(crate) fn help_text(model: &Model) -> String {
+    match model.active_pane {
+        ActivePane::List => {
+            if model.search_textarea.lines()[0].is_empty() {
+                format!(
+                    " j/k: Move  •  Enter: Open  •  i: Edit  •  n: New  •  S: Scratch  •  d: Delete  •  c: Label  •  u: {}  •  r: Sync  •  Ctrl+g: Info  •  :: Commands  •  q: Quit ",
+                    if model.due_view { "All Notes" } else { "Due" }
+                )
+            } else if model.confirmed_query.is_some() {
+                " n/N: Next/Prev Match  •  j/k: Move  •  Enter: Open  •  i: Edit  •  /: Filter  •  Esc: Clear Filter  •  q: Quit ".to_string()
+            } else {
+                " j/k: Move  •  Enter: Open  •  i: Edit  •  /: Filter  •  Esc: Clear Filter  •  q: Quit ".to_string()
+            }
+        }
+        ActivePane::Editor => match model.mode {
+            Mode::Normal => format!(" i: Insert  •  v: Visual  •  V: V-Line  •  Ctrl+V: V-Block  •  M: Preview  •  Esc: Back({})  •  Ctrl+S: Save \n dd: DelLine  •  yy: CopyLine  •  p: Paste  •  q{{a-z}}: Record  •  @{{a-z}}: Replay  •  m{{a-z}}: Mark  •  zz/zt/zb: View  •  Ctrl+O/I: Back/Fwd  •  Ctrl+W h/l: Focus  •  :diff {}", match model.config.editor.esc_behavior {
+                config::EscBehavior::Save => "Save",
+                config::EscBehavior::Prompt => "Ask",
+                config::EscBehavior::Discard => "Discard",
+            }, if model.confirmed_query.is_some() { " •  n/N: Next/Prev Match in List " } else { "" }),
+            Mode::Insert => " Esc: Normal Mode  •  Ctrl+S: Save ".to_string(),
+            Mode::Visual | Mode::VisualLine => " y: Yank  •  d: Delete  •  Esc: Normal Mode \n Move: h/j/k/l ".to_string(),
+            Mode::VisualBlock => " y: Yank  •  d: Delete  •  I/A: Insert/Append  •  Esc: Normal Mode \n Move: h/j/k/l/$ ".to_string(),
+            Mode::Command => format!(":{}_", model.command_line),
+        },
+        ActivePane::Diff => " j/k: Scroll  •  g/G: Top/Bottom  •  Esc/q: Close ".to_string(),
+        ActivePane::Login if model.polling_login => {
+            " Enter: Use Pasted Token  •  Esc: Skip(Offline) ".to_string()
+        }
+        ActivePane::Login => " Enter: Login  •  Esc: Skip(Offline)  •  q: Quit ".to_string(),
+        ActivePane::DeleteConfirm | ActivePane::ReuploadConfirm => {
+            " y: Confirm  •  n: Cancel ".to_string()
+        }
+        ActivePane::Recovery => " r/Enter: Restore  •  d/Esc: Discard ".to_string(),
+        ActivePane::UnsavedConfirm => {
+            " s/Enter: Save  •  d: Discard  •  c/Esc: Keep Editing ".to_string()
+        }
+        ActivePane::Search => " Enter: Confirm (n/N to step)  •  Esc: Close  •  is:unsynced/encrypted/conflict/locked: Filter by State  •  label:red/yellow/green/blue ".to_string(),
+        ActivePane::StatusDialog => {
+            if matches!(model.sync_status, SyncStatus::Encrypting(_, _)) {
+                " Esc: Cancel Encryption  •  Enter/q: Close ".to_string()
+            } else if model.subscription_poll.is_some() {
+                " Esc: Stop Waiting for Subscription  •  Enter/q: Close ".to_string()
+            } else if model.audit_popup.is_some() {
+                " Esc/Enter/q: Close ".to_string()
+            } else if model.error_popup_scroll.is_some() {
+                " j/k: Scroll  •  y: Copy  •  Esc/e/q: Close ".to_string()
+            } else if model.last_error.is_some() {
+                " y: Copy Error  •  e: Show Full Error  •  Esc/Enter/q: Close ".to_string()
+            } else {
+                " Esc/Enter/q: Close ".to_string()
+            }
+        }
+        ActivePane::PassphraseInput => " Enter: Unlock  •  Esc: Cancel ".to_string(),
+        ActivePane::E2ESetup => " Tab: Switch Field  •  Enter: Submit  •  Esc: Cancel ".to_string(),
+        ActivePane::CommandPalette => " Type to Filter  •  ↑/↓: Select  •  Enter: Run  •  Esc: Close ".to_string(),
+        ActivePane::ClearConfirm => " Type 'ClearAllData' + Enter: Confirm  •  Esc: Cancel ".to_string(),
+    }
+}
+
+/// The right part of the footer: the latest message or the key hints, with
+/// any subscription wait, jump position and pending key in front.
+pub(crate) fn footer_text(model: &Model) -> String {
+    let mut text = if let Some(msg) = &model.editor_message {
+        msg.clone()
+    } else if !model.unknown_filters.is_empty()
+        && matches!(model.active_pane, ActivePane::List | ActivePane::Search)
+    {
+        format!(
+            " Ignoring unknown filter {} (known: {}) ",
+            model.unknown_filters.join(", "),
+            NoteState::ALL
+                .iter()
+                .map(|s| format!("is:{}", s.as_str()))
+                .chain(Label::ALL.iter().map(|l| format!("label:{}", l.as_str())))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    } else {
+        help_text(model)
+    };
+
+    if model.subscription_poll.is_some() && model.active_pane != ActivePane::StatusDialog {
+        text = format!(
+            " Waiting for subscription… (cancel with Esc from the status dialog)  •{}",
+            text
+        );
+    }
+
+    if model.remote_changed_base.is_some() && model.editor_message.is_none() {
+        text = format!(" ⚠ Changed on another device; saving merges  •{}", text);
+    }
+
+    if let Some(skew) = clock_skew_text(model.clock_skew) {
+        text = format!(
+            " ⚠ Clock is {} the server; edits may lose to other devices  •{}",
+            skew, text
+        );
+    }
+
+    if model.jump_index + 1 < model.jumplist.len() {
+        text = format!(
+            " Jump {}/{}  •{}",
+            model.jump_index + 1,
+            model.jumplist.len(),
+            text
+        );
+    }
+
+    if model.pending_key != PendingKey::None {
+        let pending_char = match model.pending_key {
+            PendingKey::D => "d".to_string(),
+            PendingKey::Y => "y".to_string(),
+            PendingKey::G => "g".to_string(),
+            PendingKey::W => "Ctrl+w".to_string(),
+            PendingKey::C => "c".to_string(),
+            PendingKey::CI => "ci".to_string(),
+            PendingKey::Find(kind, false) => kind.to_string(),
+            PendingKey::Find(kind, true) => format!("d{}", kind),
+            _ => String::new(),
+        };
+        text = format!("(Pending: {}) {}", text, pending_char);
+    }
+    text
+}
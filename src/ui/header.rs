@@ -0,0 +1,34 @@
+// The logo banner above the panes.
+
+use crate::*;
+
+impl Model<'_> {
+    pub(crate) fn render_header(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let header_content = format!(
+            "{}\n {} • {}",
+            RISU_LOGO,
+            config::APP_VERSION,
+            mode_text(self.config.general.offline_mode)
+        );
+        let header = Paragraph::new(header_content)
+            .alignment(ratatui::layout::Alignment::Center)
+            .style(Style::default().fg(theme.logo).add_modifier(Modifier::BOLD));
+        f.render_widget(header, area);
+    }
+}
+
+/// Who risu is running as, from the saved session.
+pub(crate) fn mode_text(offline_mode: bool) -> String {
+    if offline_mode {
+        return "Offline Mode".to_string();
+    }
+    let token = config::get_token();
+    if token.is_empty() {
+        return "Guest Mode (Local Only)".to_string();
+    }
+    match config::get_user_id_from_token(&token) {
+        Ok(uid) => format!("User: {}", uid),
+        Err(_) => "Session Invalid".to_string(),
+    }
+}
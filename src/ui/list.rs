@@ -0,0 +1,338 @@
+// The note list and its search popup: keys and drawing.
+
+use crate::*;
+
+impl Model<'_> {
+    pub(crate) async fn handle_list_key(&mut self, key: event::KeyEvent) -> Result<Action> {
+        let pending = std::mem::replace(&mut self.pending_key, PendingKey::None);
+        if let (PendingKey::W, KeyCode::Char(c)) = (pending, key.code) {
+            if let Some(pane) = window_target(ActivePane::List, c) {
+                self.focus_pane(pane).await;
+            }
+            return Ok(Action::None);
+        }
+        if key.code == KeyCode::Char('w') && key.modifiers.contains(event::KeyModifiers::CONTROL) {
+            self.pending_key = PendingKey::W;
+            return Ok(Action::None);
+        }
+        if key.code == KeyCode::Char('n') && self.confirmed_query.is_some() {
+            return self.run_command(Command::NextMatch).await;
+        }
+        if let Some(command) = Command::for_key(&key) {
+            return self.run_command(command).await;
+        }
+        match key.code {
+            KeyCode::Esc if !self.search_textarea.lines()[0].is_empty() => {
+                self.search_textarea = TextArea::default();
+                self.confirmed_query = None;
+                self.setup_search_textarea();
+                self.refresh_notes(true).await?;
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.move_list_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => self.move_list_selection(-1),
+            KeyCode::Char('y') if pending == PendingKey::Y => self.yank_selected_note(true),
+            KeyCode::Char('y') => self.pending_key = PendingKey::Y,
+            _ => {}
+        }
+
+        Ok(Action::None)
+    }
+
+    pub(crate) async fn handle_search_key(&mut self, key: event::KeyEvent) -> Result<Action> {
+        match key.code {
+            KeyCode::Enter => {
+                let query = self.search_textarea.lines()[0].trim().to_string();
+                self.confirmed_query = (!query.is_empty()).then_some(query);
+                self.active_pane = ActivePane::List;
+            }
+            KeyCode::Esc => {
+                self.active_pane = ActivePane::List;
+            }
+            _ => {
+                if self.search_textarea.input(key) {
+                    self.refresh_notes(true).await?;
+                }
+            }
+        };
+        Ok(Action::None)
+    }
+
+    /// Selects the next (or previous) note in the list matching the confirmed
+    /// search, wrapping at the ends. Starts from the note being edited, so it
+    /// also works from the editor.
+    pub(crate) fn step_search_match(&mut self, forward: bool) {
+        match self.next_search_match(forward) {
+            Ok((next, message)) => {
+                self.editor_message = Some(message);
+                self.saved_feedback_until = None;
+                self.list_state.select(Some(next));
+                self.update_editor_from_selection();
+            }
+            Err(message) => self.editor_message = Some(message),
+        }
+    }
+
+    /// The list index `n` (or `N`) moves to and the message that goes with
+    /// it, or why there is none.
+    pub(crate) fn next_search_match(&self, forward: bool) -> Result<(usize, String), String> {
+        let Some(confirmed) = &self.confirmed_query else {
+            return Err(" No confirmed search — press / and Enter first ".to_string());
+        };
+        let query = NoteQuery::parse(confirmed);
+        let matches: Vec<usize> = self
+            .filtered_notes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| query.matches(n))
+            .map(|(i, _)| i)
+            .collect();
+        if matches.is_empty() {
+            return Err(format!(" No notes match \"{}\" ", confirmed));
+        }
+        let current = self
+            .current_note_id
+            .as_ref()
+            .and_then(|id| self.filtered_notes.iter().position(|n| n.id == *id))
+            .or(self.list_state.selected());
+        let (next, wrapped) = match current {
+            Some(current) if forward => match matches.iter().find(|&&i| i > current) {
+                Some(&i) => (i, false),
+                None => (matches[0], true),
+            },
+            Some(current) => match matches.iter().rev().find(|&&i| i < current) {
+                Some(&i) => (i, false),
+                None => (matches[matches.len() - 1], true),
+            },
+            None => (matches[0], false),
+        };
+        let position = matches.iter().position(|&i| i == next).unwrap_or(0) + 1;
+        let message = if wrapped {
+            format!(
+                " Match {}/{} — search wrapped to the {} ",
+                position,
+                matches.len(),
+                if forward { "top" } else { "bottom" }
+            )
+        } else {
+            format!(" Match {}/{} ", position, matches.len())
+        };
+        Ok((next, message))
+    }
+
+    /// `n` / `N` from the editor. A match in another note is opened once the
+    /// buffer is settled as `editor.esc_behavior` says; one in the same note
+    /// leaves the buffer alone.
+    pub(crate) async fn step_search_match_from_editor(&mut self, forward: bool) {
+        let leaving = self.next_search_match(forward).is_ok_and(|(next, _)| {
+            self.filtered_notes.get(next).map(|n| &n.id) != self.current_note_id.as_ref()
+        });
+        if leaving
+            && !self
+                .settle_buffer(NoteSwitch::SearchMatch { forward })
+                .await
+        {
+            return;
+        }
+        self.step_search_match(forward);
+    }
+
+    /// Moves the selected note to the next color label (`c`), back to none
+    /// after the last. Local-only unless `sync.labels` is set.
+    pub(crate) async fn cycle_label(&mut self) -> Result<()> {
+        let Some(note) = self
+            .list_state
+            .selected()
+            .and_then(|i| self.filtered_notes.get(i))
+        else {
+            return Ok(());
+        };
+        let id = note.id.clone();
+        let label = Label::next(Label::of(note));
+        let synced = self.config.sync.labels;
+        self.repo
+            .set_label(id.clone(), label.map(|l| l.as_str().to_string()), synced)
+            .await?;
+        self.refresh_notes(false).await?;
+        // Syncing labels bumps the note to the top of the list
+        if let Some(i) = self.filtered_notes.iter().position(|n| n.id == id) {
+            self.list_state.select(Some(i));
+        }
+        self.update_editor_from_selection();
+        self.editor_message = Some(match label {
+            Some(label) => format!(" Label: {} ", label.as_str()),
+            None => " Label removed ".to_string(),
+        });
+        if synced {
+            let _ = self.sync_trigger.try_send(());
+        }
+        Ok(())
+    }
+}
+
+impl Model<'_> {
+    pub(crate) fn render_list(&mut self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.config.theme;
+        let selected_index = self.list_state.selected();
+        let now = Utc::now().to_rfc3339();
+        // Rows lose the two borders and the `>>` highlight column; titles also
+        // lose their badge prefix
+        let row_width = (area.width as usize).saturating_sub(4);
+        let title_width = row_width.saturating_sub(LIST_BADGE_WIDTH);
+        let items: Vec<ListItem> = self
+            .filtered_notes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let title = if Some(&n.id) == self.scratch_id.as_ref() {
+                    let text = n.content.trim();
+                    let first = text.lines().next().unwrap_or_default();
+                    truncate_to_width(&format!("[scratch] {}", first), title_width)
+                } else {
+                    truncate_to_width(&derive_title(&n.content), title_width)
+                };
+                let is_selected = Some(i) == selected_index;
+
+                let due = self.reminders.get(&n.id).filter(|_| self.due_view);
+                let overdue = due.is_some_and(|due| due.as_str() < now.as_str());
+                let date_text = match (due, self.config.general.sort_by) {
+                    (Some(due), _) if overdue => {
+                        format!("    Overdue: {}", format_note_time(due))
+                    }
+                    (Some(due), _) => format!("    Due: {}", format_note_time(due)),
+                    (None, config::NoteSort::Updated) => {
+                        format!("    Updated: {}", format_note_time(&n.updated_at))
+                    }
+                    (None, config::NoteSort::Created) => {
+                        format!("    Created: {}", format_note_time(&n.created_at))
+                    }
+                };
+
+                let date_text = truncate_to_width(&date_text, row_width);
+                let date_line = if overdue {
+                    Line::from(Span::styled(
+                        date_text,
+                        Style::default().fg(theme.sync_error),
+                    ))
+                } else if is_selected {
+                    Line::from(date_text)
+                } else {
+                    Line::from(Span::styled(
+                        date_text,
+                        Style::default().fg(Color::DarkGray),
+                    ))
+                };
+
+                // The label bar takes the badge's leading column
+                let bar = match Label::of(n) {
+                    Some(label) => Span::styled("▎", Style::default().fg(label.color(theme))),
+                    None => Span::raw(" "),
+                };
+                let title_line = if n.has_conflict == 1 {
+                    Line::from(vec![
+                        bar,
+                        Span::styled("  ", Style::default().fg(Color::Red)),
+                        Span::raw(title),
+                    ])
+                } else {
+                    Line::from(vec![bar, Span::raw(format!("  {}", title))])
+                };
+                ListItem::new(vec![title_line, date_line])
+            })
+            .collect();
+
+        let query = NoteQuery::parse(&self.search_textarea.lines()[0]);
+        let border = if self.active_pane == ActivePane::List {
+            theme.border_active
+        } else {
+            theme.border_inactive
+        };
+        let list_block = Block::default()
+            .borders(Borders::ALL)
+            .title(list_title(self.due_view, &query))
+            .border_style(Style::default().fg(border));
+
+        let highlight_style = if self.showing_saved_feedback() {
+            Style::default()
+                .bg(theme.sync_synced)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+                .bg(theme.selection_bg)
+                .fg(theme.selection_fg)
+                .add_modifier(Modifier::BOLD)
+        };
+
+        let list = List::new(items)
+            .block(list_block)
+            .highlight_style(highlight_style)
+            .highlight_symbol(">>");
+
+        let list_area = if self.e2e_status == E2EState::Locked {
+            let banner = " E2E locked — some notes may be missing until you unlock, press L";
+            let width = area.width.max(1);
+            let height = (banner.chars().count() as u16).div_ceil(width).min(3);
+            let list_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(height), Constraint::Min(0)])
+                .split(area);
+            let banner = Paragraph::new(banner)
+                .style(Style::default().fg(Color::Black).bg(Color::Yellow))
+                .wrap(Wrap { trim: true });
+            f.render_widget(banner, list_chunks[0]);
+            list_chunks[1]
+        } else {
+            area
+        };
+
+        f.render_stateful_widget(list, list_area, &mut self.list_state);
+        if self.filtered_notes.is_empty() && !self.is_loading {
+            let inner = Block::default().borders(Borders::ALL).inner(list_area);
+            let text = self.empty_list_text();
+            let height = (text.width() as u16)
+                .div_ceil(inner.width.max(1))
+                .min(inner.height);
+            let message_area = ratatui::layout::Rect {
+                y: inner.y + (inner.height - height) / 2,
+                height,
+                ..inner
+            };
+            f.render_widget(
+                Paragraph::new(text)
+                    .style(Style::default().fg(Color::DarkGray))
+                    .alignment(ratatui::layout::Alignment::Center)
+                    .wrap(Wrap { trim: true }),
+                message_area,
+            );
+        }
+        // Each note takes two rows inside the borders
+        self.list_rows = (list_area.height.saturating_sub(2) / 2) as usize;
+        if self.active_pane == ActivePane::List {
+            self.list_offset = self.list_state.offset();
+        }
+    }
+}
+
+/// The list border title: the view, then any state filters and search text.
+pub(crate) fn list_title(due_view: bool, query: &NoteQuery) -> String {
+    let mut title = if due_view {
+        " Due ".to_string()
+    } else {
+        " Notes ".to_string()
+    };
+    for state in &query.states {
+        title.push_str(&format!("[is:{}] ", state.as_str()));
+    }
+    for label in &query.labels {
+        title.push_str(&format!("[label:{}] ", label.as_str()));
+    }
+    if !query.text.is_empty() {
+        let display_query = if query.text.chars().count() > 15 {
+            format!("{}..", query.text.chars().take(12).collect::<String>())
+        } else {
+            query.text.clone()
+        };
+        title.push_str(&format!("(Filter: \"{}\") ", display_query));
+    }
+    title
+}
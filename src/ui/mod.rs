@@ -0,0 +1,18 @@
+// Key handling and drawing, one module per pane. Handlers act on the model
+// directly and return what the event loop should do next; `Model::ui` only
+// lays out the screen and hands each area to a module's `render_*`.
+
+mod commands;
+mod dialogs;
+mod editor;
+mod footer;
+mod header;
+mod list;
+
+pub use commands::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    None,
+    Quit,
+}
@@ -0,0 +1,178 @@
+// Read-only window onto one note (`risu view <id>`). Meant to sit beside the
+// main TUI: it starts no sync manager and never writes, and it redraws when
+// another process commits to the database.
+
+use anyhow::Result;
+use crossterm::{
+    event::{Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Terminal,
+};
+use std::io;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::config::ThemeConfig;
+use crate::crypto;
+use crate::db::{Note, Repo};
+use crate::markdown;
+use crate::palette;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+struct Viewer {
+    id: String,
+    // None once the note has been deleted elsewhere
+    note: Option<Note>,
+    scroll: u16,
+    page: u16,
+    theme: ThemeConfig,
+}
+
+pub async fn run(repo: Repo, id: String, theme: ThemeConfig) -> Result<()> {
+    let note = repo
+        .get_note(id.clone())
+        .await?
+        .filter(|n| n.is_deleted == 0)
+        .ok_or_else(|| anyhow::anyhow!("No note with id {}", id))?;
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut viewer = Viewer {
+        id,
+        note: Some(note),
+        scroll: 0,
+        page: 0,
+        theme,
+    };
+    let result = viewer.run(&mut terminal, &repo).await;
+    let _ = crate::restore_terminal();
+    result
+}
+
+impl Viewer {
+    async fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>, repo: &Repo) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let input = crate::InputReader::spawn(tx);
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        let mut version = repo.data_version().await?;
+        let mode = self.theme.color_mode.resolve();
+
+        let result = loop {
+            let drawn = terminal.draw(|f| {
+                self.render(f);
+                palette::apply(f.buffer_mut(), mode, &self.theme);
+            });
+            if let Err(e) = drawn {
+                break Err(e.into());
+            }
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some((Event::Key(key), _)) if key.kind == KeyEventKind::Press => {
+                        if !self.handle_key(key.code, key.modifiers) {
+                            break Ok(());
+                        }
+                    }
+                    Some(_) => {}
+                    None => break Err(anyhow::anyhow!("Keyboard input stopped")),
+                },
+                _ = refresh.tick() => {
+                    let current = repo.data_version().await?;
+                    if current != version {
+                        version = current;
+                        self.note = repo
+                            .get_note(self.id.clone())
+                            .await?
+                            .filter(|n| n.is_deleted == 0);
+                    }
+                }
+            }
+        };
+        input.stop();
+        result
+    }
+
+    // Returns false to quit
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let half_page = (self.page / 2).max(1);
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('j') | KeyCode::Down => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Char('k') | KeyCode::Up => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Char('d') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.scroll = self.scroll.saturating_add(half_page)
+            }
+            KeyCode::Char('u') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.scroll = self.scroll.saturating_sub(half_page)
+            }
+            KeyCode::PageDown | KeyCode::Char(' ') => {
+                self.scroll = self.scroll.saturating_add(self.page.max(1))
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(self.page.max(1)),
+            KeyCode::Char('g') | KeyCode::Home => self.scroll = 0,
+            KeyCode::Char('G') | KeyCode::End => self.scroll = u16::MAX,
+            _ => {}
+        }
+        true
+    }
+
+    fn render(&mut self, f: &mut ratatui::Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(f.area());
+
+        let (title, body) = match &self.note {
+            Some(note) if crypto::looks_like_ciphertext(&note.content) => (
+                crate::derive_title(&note.content),
+                ratatui::text::Text::from(
+                    "This note is stored encrypted. Unlock it in risu to read it.",
+                ),
+            ),
+            Some(note) => (
+                crate::derive_title(&note.content),
+                markdown::parse_markdown(&note.content),
+            ),
+            None => (
+                "Deleted".to_string(),
+                ratatui::text::Text::from("This note was deleted."),
+            ),
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" {} (read-only) ", title))
+            .border_style(Style::default().fg(self.theme.border_inactive));
+        let inner = block.inner(chunks[0]);
+        // Rows after wrapping, so G lands on the last screenful rather than past the end
+        let width = inner.width.max(1) as usize;
+        let rows: usize = body
+            .lines
+            .iter()
+            .map(|line| line.width().div_ceil(width).max(1))
+            .sum();
+        let max_scroll = (rows as u16).saturating_sub(inner.height);
+        self.scroll = self.scroll.min(max_scroll);
+        self.page = inner.height;
+        let paragraph = Paragraph::new(body)
+            .block(block)
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        f.render_widget(paragraph, chunks[0]);
+
+        f.render_widget(
+            Paragraph::new(" j/k: Scroll  •  Ctrl+D/U: Half Page  •  g/G: Top/Bottom  •  q: Quit ")
+                .style(Style::default().fg(Color::DarkGray)),
+            chunks[1],
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Clock skew against a mock server whose `Date` header is set off from real
+// time: each pass reports the skew, and with `sync.compensate_clock_skew`
+// saves are stamped with the server's time once the skew passes the warning
+// threshold.
+
+mod support;
+
+use chrono::{DateTime, Duration, Utc};
+use risu::api::APIClient;
+use risu::config::SyncConfig;
+use risu::crypto;
+use risu::sync::{SyncEvent, SyncStatus, CLOCK_SKEW_WARN_SECS};
+use support::{Device, MockServer};
+
+// Signs in, sets E2E up on the account and returns an unlocked device
+async fn unlocked_device(server: &MockServer, settings: SyncConfig) -> Device {
+    server.login();
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key("passphrase", &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    APIClient::new()
+        .e2e_enable(Some(&salt), Some(&validator))
+        .await
+        .unwrap();
+    let device = Device::with_settings(settings);
+    device.repo.set_salt(&salt).await.unwrap();
+    device.repo.set_validator(&validator).await.unwrap();
+    device.unlock(key);
+    device
+}
+
+// The skew a pass reported, in seconds; it must report exactly one
+async fn measured_skew(device: &mut Device) -> i64 {
+    let events = device.sync_events().await;
+    assert!(events.contains(&SyncEvent::StatusChanged(SyncStatus::Synced)));
+    let skews: Vec<i64> = events
+        .iter()
+        .filter_map(|event| match event {
+            SyncEvent::ClockSkew(skew) => Some(*skew),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(skews.len(), 1, "{:?}", events);
+    skews[0]
+}
+
+// How far ahead of real time a new save is stamped, in seconds
+async fn save_offset(device: &Device) -> i64 {
+    let id = device
+        .repo
+        .save_note(None, format!("Saved at {}", Utc::now()), true, false)
+        .await
+        .unwrap();
+    let note = device.repo.get_note(id).await.unwrap().unwrap();
+    let stamped = DateTime::parse_from_rfc3339(&note.updated_at).unwrap();
+    (stamped.with_timezone(&Utc) - Utc::now()).num_seconds()
+}
+
+// The header has whole seconds, and the request takes a moment
+fn assert_near(actual: i64, expected: i64) {
+    assert!(
+        (actual - expected).abs() <= 2,
+        "{} is not {}",
+        actual,
+        expected
+    );
+}
+
+#[tokio::test]
+async fn a_clock_in_step_with_the_server_is_below_the_threshold() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let mut device = unlocked_device(&server, SyncConfig::default()).await;
+
+    let skew = measured_skew(&mut device).await;
+    assert_near(skew, 0);
+    assert!(skew.abs() <= CLOCK_SKEW_WARN_SECS);
+    assert_near(save_offset(&device).await, 0);
+}
+
+#[tokio::test]
+async fn a_skewed_clock_is_reported_but_left_alone_by_default() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let mut device = unlocked_device(&server, SyncConfig::default()).await;
+
+    // The server is three hours behind: this clock is ahead of it
+    server.set_clock_offset(Duration::hours(-3));
+    assert_near(measured_skew(&mut device).await, -3 * 3600);
+    assert_near(save_offset(&device).await, 0);
+
+    // And ahead of it
+    server.set_clock_offset(Duration::minutes(10));
+    assert_near(measured_skew(&mut device).await, 600);
+    assert_near(save_offset(&device).await, 0);
+}
+
+#[tokio::test]
+async fn compensation_follows_the_server_clock_past_the_threshold() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let settings = SyncConfig {
+        compensate_clock_skew: true,
+        ..SyncConfig::default()
+    };
+    let mut device = unlocked_device(&server, settings).await;
+
+    // This clock is three hours slow: saves are stamped three hours later
+    server.set_clock_offset(Duration::hours(3));
+    assert_near(measured_skew(&mut device).await, 3 * 3600);
+    assert_near(save_offset(&device).await, 3 * 3600);
+
+    // A minute off is under the threshold, so the offset is dropped
+    server.set_clock_offset(Duration::minutes(1));
+    assert_near(measured_skew(&mut device).await, 60);
+    assert_near(save_offset(&device).await, 0);
+
+    // Fast by five minutes
+    server.set_clock_offset(Duration::minutes(-5));
+    assert_near(measured_skew(&mut device).await, -300);
+    assert_near(save_offset(&device).await, -300);
+
+    // The clock was fixed
+    server.set_clock_offset(Duration::zero());
+    assert_near(measured_skew(&mut device).await, 0);
+    assert_near(save_offset(&device).await, 0);
+}
@@ -0,0 +1,135 @@
+// The client against a mock server end to end: device login, E2E setup, an
+// encrypted push, a pull on a second device, and a token refresh mid-sync.
+// Both devices share one config directory (and so one login), like two risu
+// installs signed in to the same account, but each has its own database.
+
+mod support;
+
+use risu::api::APIClient;
+use risu::config;
+use risu::crypto;
+use risu::sync::SyncStatus;
+use support::{Device, MockServer};
+
+const PASSPHRASE: &str = "correct horse battery staple";
+
+#[tokio::test]
+async fn login_enable_e2e_and_sync_two_devices() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let client = APIClient::new();
+
+    // Device login: poll until the browser side signs in
+    let session = client.start_login_session().await.unwrap();
+    let result = loop {
+        let result = client
+            .poll_login_session(&session.session_id)
+            .await
+            .unwrap();
+        if result.status == "success" {
+            break result;
+        }
+        assert_eq!(result.status, "pending");
+    };
+    config::save_token_data(&result.token, &result.refresh_token).unwrap();
+    assert_eq!(config::get_token(), server.id_token());
+    assert_eq!(
+        config::get_user_email_from_token(&config::get_token()).unwrap(),
+        "test@example.com"
+    );
+    assert_eq!(
+        client.get_checkout_url().await.unwrap(),
+        "https://example.com/checkout"
+    );
+    assert_eq!(
+        client.get_portal_url().await.unwrap(),
+        "https://example.com/portal"
+    );
+
+    // Nothing syncs until E2E is set up
+    let mut laptop = Device::new();
+    assert_eq!(laptop.sync().await, SyncStatus::Offline);
+
+    // E2E setup, as the TUI does it: the salt and validator go up together
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(PASSPHRASE, &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    assert_eq!(
+        client
+            .e2e_enable(Some(&salt), Some(&validator))
+            .await
+            .unwrap(),
+        salt
+    );
+    laptop.repo.set_salt(&salt).await.unwrap();
+    laptop.repo.set_notes_encrypted_status(1).await.unwrap();
+    laptop.unlock(key.clone());
+
+    // Encrypted push, pulled back in pages smaller than the backlog
+    server.set_page_size(2);
+    let mut ids = Vec::new();
+    for content in ["Groceries\nmilk", "Ideas\nmore sleep", "Trip\npack socks"] {
+        ids.push(
+            laptop
+                .repo
+                .save_note(None, content.to_string(), true, false)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(laptop.sync().await, SyncStatus::Synced);
+    assert_eq!(server.note_count(), 3);
+    for id in &ids {
+        let local = laptop.repo.get_note(id.clone()).await.unwrap().unwrap();
+        let stored = server.note(id).unwrap();
+        let ciphertext = stored["content"].as_str().unwrap();
+        assert_eq!(stored["is_encrypted"], 1);
+        assert!(crypto::looks_like_ciphertext(ciphertext));
+        assert_eq!(crypto::decrypt(ciphertext, &key).unwrap(), local.content);
+    }
+
+    // A second device picks up the salt, then unlocks with the same passphrase
+    let mut phone = Device::new();
+    assert_eq!(phone.sync().await, SyncStatus::Offline);
+    assert_eq!(phone.repo.get_salt().await.unwrap(), Some(salt.clone()));
+    let me = client.get_me().await.unwrap();
+    let phone_key = crypto::derive_key(PASSPHRASE, &me.encryption_salt.unwrap()).unwrap();
+    assert!(crypto::verify_validator(
+        &me.encryption_validator.unwrap(),
+        &phone_key
+    ));
+    phone.unlock(phone_key);
+    assert_eq!(phone.sync().await, SyncStatus::Synced);
+    assert_eq!(phone.contents().await, laptop.contents().await);
+    assert_eq!(server.pushes(), 3);
+
+    // The id token expires between the sync check and the pull; the client
+    // refreshes it and the pass carries on
+    phone
+        .repo
+        .save_note(
+            Some(ids[0].clone()),
+            "Groceries\nmilk\neggs".to_string(),
+            true,
+            false,
+        )
+        .await
+        .unwrap();
+    let expired = server.id_token();
+    server.expire_token_after(2);
+    assert_eq!(phone.sync().await, SyncStatus::Synced);
+    assert_eq!(server.refreshes(), 1);
+    assert_ne!(server.id_token(), expired);
+    assert_eq!(config::get_token(), server.id_token());
+    assert_eq!(server.pushes(), 4);
+
+    assert_eq!(laptop.sync().await, SyncStatus::Synced);
+    assert_eq!(
+        laptop.contents().await,
+        [
+            "Groceries\nmilk\neggs",
+            "Ideas\nmore sleep",
+            "Trip\npack socks"
+        ]
+    );
+}
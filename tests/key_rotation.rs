@@ -0,0 +1,155 @@
+// Passphrase changes against a mock server: every note is pushed again under
+// the new key before the account's salt and validator are swapped, and a
+// change the server refuses can be cancelled back to the old key.
+
+mod support;
+
+use risu::api::APIClient;
+use risu::crypto;
+use risu::db::KeyRotation;
+use risu::sync::{self, SyncEvent, SyncStatus};
+use support::{Device, MockServer};
+use zeroize::Zeroizing;
+
+const NOTES: [&str; 3] = ["Groceries\nmilk", "Ideas\nmore sleep", "Trip\npack socks"];
+
+// A device with E2E set up under `passphrase` and every note pushed
+async fn synced_device(server: &MockServer, passphrase: &str) -> (Device, Zeroizing<[u8; 32]>) {
+    server.login();
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(passphrase, &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    APIClient::new()
+        .e2e_enable(Some(&salt), Some(&validator))
+        .await
+        .unwrap();
+
+    let mut device = Device::new();
+    device.repo.set_salt(&salt).await.unwrap();
+    device.repo.set_validator(&validator).await.unwrap();
+    device.unlock(key.clone());
+    for content in NOTES {
+        device
+            .repo
+            .save_note(None, content.to_string(), true, false)
+            .await
+            .unwrap();
+    }
+    assert_eq!(device.sync().await, SyncStatus::Synced);
+    (device, key)
+}
+
+// Starts a change to `passphrase` as the TUI does, and loads the new key
+async fn begin_rotation(
+    device: &Device,
+    old_key: &[u8; 32],
+    passphrase: &str,
+) -> (KeyRotation, Zeroizing<[u8; 32]>) {
+    let salt = crypto::generate_salt();
+    let new_key = crypto::derive_key(passphrase, &salt).unwrap();
+    let rotation = KeyRotation {
+        target_version: device.repo.get_key_version().await.unwrap() + 1,
+        new_salt: salt,
+        new_validator: crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &new_key).unwrap(),
+        wrapped_old_key: crypto::wrap_key(old_key, &new_key).unwrap(),
+        server_updated: false,
+    };
+    device.repo.begin_key_rotation(&rotation).await.unwrap();
+    device.unlock(new_key.clone());
+    (rotation, new_key)
+}
+
+// Every note on the server, decrypted with `key`
+fn server_notes(server: &MockServer, key: &[u8; 32]) -> Vec<String> {
+    let mut notes: Vec<String> = server
+        .contents()
+        .into_iter()
+        .map(|(_, ciphertext)| crypto::decrypt(&ciphertext, key).unwrap())
+        .collect();
+    notes.sort();
+    notes
+}
+
+#[tokio::test]
+async fn the_server_key_is_swapped_only_after_every_note_is_on_the_new_one() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let (mut device, old_key) = synced_device(&server, "old passphrase").await;
+    let old_account_key = server.e2e_key();
+
+    let (rotation, new_key) = begin_rotation(&device, &old_key, "new passphrase").await;
+    assert_eq!(server.e2e_key(), old_account_key);
+
+    assert_eq!(device.sync().await, SyncStatus::Synced);
+    // The three notes went out again before the swap, not after
+    assert_eq!(server.pushes(), 6);
+    assert_eq!(server.pushes_at_rotation(), Some(6));
+    assert_eq!(
+        server.e2e_key(),
+        (
+            Some(rotation.new_salt.clone()),
+            Some(rotation.new_validator.clone())
+        )
+    );
+    assert_eq!(server_notes(&server, &new_key), NOTES);
+
+    assert!(device.repo.get_key_rotation().await.unwrap().is_none());
+    assert_eq!(device.repo.get_key_version().await.unwrap(), 1);
+    assert_eq!(
+        device.repo.get_salt().await.unwrap(),
+        Some(rotation.new_salt)
+    );
+    assert_eq!(
+        device.repo.get_validator().await.unwrap(),
+        Some(rotation.new_validator)
+    );
+    // Finished: there is nothing left to cancel
+    assert!(sync::cancel_key_rotation(&device.repo, &device.crypto_key)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn a_refused_change_is_kept_until_cancelled_back_to_the_old_key() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let (mut device, old_key) = synced_device(&server, "old passphrase").await;
+    let old_account_key = server.e2e_key();
+    let old_salt = device.repo.get_salt().await.unwrap();
+    server.refuse_rotation();
+
+    begin_rotation(&device, &old_key, "new passphrase").await;
+    let events = device.sync_events().await;
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, SyncEvent::RotationBlocked(reason) if reason.contains("refused"))));
+    // The notes were pushed, but the pass itself didn't fail
+    assert!(events.contains(&SyncEvent::StatusChanged(SyncStatus::Synced)));
+    assert_eq!(server.e2e_key(), old_account_key);
+    assert!(device.repo.get_key_rotation().await.unwrap().is_some());
+
+    // Refused again on the next pass; nothing is lost meanwhile
+    let events = device.sync_events().await;
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, SyncEvent::RotationBlocked(_))));
+    assert!(device.repo.get_key_rotation().await.unwrap().is_some());
+
+    sync::cancel_key_rotation(&device.repo, &device.crypto_key)
+        .await
+        .unwrap();
+    assert_eq!(
+        device.crypto_key.lock().unwrap().as_deref(),
+        Some(&*old_key)
+    );
+    assert!(device.repo.get_key_rotation().await.unwrap().is_none());
+    assert_eq!(device.repo.get_key_version().await.unwrap(), 0);
+    assert_eq!(device.repo.get_salt().await.unwrap(), old_salt);
+    assert_eq!(device.repo.get_unsynced_notes().await.unwrap().len(), 3);
+
+    // The notes go back to the server under the key it still has
+    assert_eq!(device.sync().await, SyncStatus::Synced);
+    assert_eq!(server_notes(&server, &old_key), NOTES);
+    assert_eq!(server.e2e_key(), old_account_key);
+    assert_eq!(server.pushes_at_rotation(), None);
+}
@@ -0,0 +1,113 @@
+// The client side of a test: risu's config directory and API URL pointed at
+// a temp dir and a mock server, and devices syncing in-memory databases.
+
+use risu::config::SyncConfig;
+use risu::sync::{SyncEvent, SyncManager, SyncStatus};
+use risu::Repo;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::sync::{mpsc, MutexGuard};
+use zeroize::Zeroizing;
+
+use super::MockServer;
+
+// HOME and RISU_API_URL are process-wide, so tests in one binary take turns
+static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// HOME (and so the config directory) is a fresh temp dir and the API is
+/// `server`, until this is dropped.
+pub struct Env {
+    pub home: TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+pub async fn isolate(server: &MockServer) -> Env {
+    let lock = ENV_LOCK.lock().await;
+    let home = tempfile::tempdir().unwrap();
+    // Read when the config directory is looked up and when a client is made
+    std::env::set_var("HOME", home.path());
+    std::env::set_var("RISU_API_URL", &server.url);
+    Env { home, _lock: lock }
+}
+
+/// One risu install: an in-memory database and a sync manager driving it.
+pub struct Device {
+    pub repo: Repo,
+    pub crypto_key: Arc<Mutex<Option<Zeroizing<[u8; 32]>>>>,
+    pub manager: SyncManager,
+    pub events: mpsc::Receiver<SyncEvent>,
+    /// Asks the manager for a sync, as a save does; also keeps its trigger
+    /// channel open
+    pub trigger: mpsc::Sender<()>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Self::with_settings(SyncConfig::default())
+    }
+
+    pub fn with_settings(settings: SyncConfig) -> Self {
+        let repo = Repo::open(Path::new(":memory:")).unwrap();
+        let crypto_key = Arc::new(Mutex::new(None));
+        let (event_tx, events) = mpsc::channel(1000);
+        let (trigger, trigger_rx) = mpsc::channel(1);
+        let manager = SyncManager::new(
+            repo.clone(),
+            event_tx,
+            trigger_rx,
+            Arc::clone(&crypto_key),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+            settings,
+        );
+        Self {
+            repo,
+            crypto_key,
+            manager,
+            events,
+            trigger,
+        }
+    }
+
+    pub fn unlock(&self, key: Zeroizing<[u8; 32]>) {
+        *self.crypto_key.lock().unwrap() = Some(key);
+    }
+
+    /// One sync pass; returns every event it sent.
+    pub async fn sync_events(&mut self) -> Vec<SyncEvent> {
+        self.manager.sync_once().await;
+        let mut events = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    /// One sync pass; returns the status it ended on.
+    pub async fn sync(&mut self) -> SyncStatus {
+        self.sync_events()
+            .await
+            .into_iter()
+            .rev()
+            .find_map(|event| match event {
+                SyncEvent::StatusChanged(s) => Some(s),
+                _ => None,
+            })
+            .expect("sync pass reported no status")
+    }
+
+    pub async fn contents(&self) -> Vec<String> {
+        let mut notes: Vec<String> = self
+            .repo
+            .get_notes(false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.content)
+            .collect();
+        notes.sort();
+        notes
+    }
+}
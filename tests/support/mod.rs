@@ -0,0 +1,460 @@
+// An in-process stand-in for the risu API: device login, the account and its
+// E2E salt/validator, note sync and billing links, all kept in memory. Only
+// what the client relies on is modelled; tokens are JWT-shaped but unsigned.
+// `device` has the client side: an isolated config directory and a sync
+// manager on an in-memory database.
+
+// Each test binary uses its own share of the helpers
+#![allow(dead_code, unused_imports)]
+
+mod device;
+
+pub use device::{isolate, Device};
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+const EPOCH: &str = "1970-01-01T00:00:00Z";
+
+#[derive(Default)]
+struct Account {
+    encryption_salt: Option<String>,
+    encryption_validator: Option<String>,
+}
+
+#[derive(Default)]
+struct Store {
+    account: Account,
+    // Login session id -> times polled
+    sessions: HashMap<String, usize>,
+    id_token: String,
+    refresh_token: String,
+    tokens_issued: usize,
+    refreshes: usize,
+    // How long `/auth/refresh` takes to answer
+    refresh_delay: std::time::Duration,
+    // Authenticated requests still accepted before the id token expires
+    token_lifetime: Option<usize>,
+    // Note id -> the note as last pushed
+    notes: BTreeMap<String, Value>,
+    pushes: usize,
+    pulls: usize,
+    page_size: usize,
+    // `/auth/e2e/rotate` answers 409 instead of taking the new key
+    refuse_rotation: bool,
+    // Pushes the server had taken when the key was rotated
+    pushes_at_rotation: Option<usize>,
+    // Holds the next `/sync/pull` until opened
+    pull_gate: Option<Arc<PullGate>>,
+    // How far the server's clock (its `Date` header) runs ahead of real time
+    clock_offset: chrono::Duration,
+}
+
+/// A `/sync/pull` held open so a test can act while the client waits on it.
+#[derive(Default)]
+pub struct PullGate {
+    reached: Notify,
+    open: Notify,
+}
+
+impl PullGate {
+    /// Waits until the held pull has arrived.
+    pub async fn reached(&self) {
+        self.reached.notified().await;
+    }
+
+    /// Lets the held pull answer.
+    pub fn open(&self) {
+        self.open.notify_one();
+    }
+}
+
+impl Store {
+    // A fresh id/refresh token pair, replacing the old one
+    fn issue_tokens(&mut self) -> (String, String) {
+        self.tokens_issued += 1;
+        let claims = json!({
+            "sub": "user-1",
+            "email": "test@example.com",
+            "iat": chrono::Utc::now().timestamp(),
+            "exp": chrono::Utc::now().timestamp() + 3600,
+            "n": self.tokens_issued,
+        });
+        self.id_token = format!(
+            "{}.{}.sig",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        self.refresh_token = format!("refresh-{}", self.tokens_issued);
+        (self.id_token.clone(), self.refresh_token.clone())
+    }
+
+    fn authorize(&mut self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let bearer = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if self.id_token.is_empty() || bearer != Some(self.id_token.as_str()) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        match &mut self.token_lifetime {
+            Some(0) => Err(StatusCode::UNAUTHORIZED),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+type Shared = Arc<Mutex<Store>>;
+
+pub struct MockServer {
+    pub url: String,
+    store: Shared,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let store: Shared = Arc::new(Mutex::new(Store {
+            page_size: 100,
+            ..Store::default()
+        }));
+        let app = Router::new()
+            .route("/health", get(|| async { StatusCode::OK }))
+            .route("/auth/init", post(auth_init))
+            .route("/auth/poll", get(auth_poll))
+            .route("/auth/refresh", post(auth_refresh))
+            .route("/auth/me", get(auth_me))
+            .route("/auth/e2e/enable", post(e2e_enable))
+            .route("/auth/e2e/rotate", post(e2e_rotate))
+            .route("/sync/check", get(sync_check))
+            .route("/sync/pull", get(sync_pull))
+            .route("/sync/push", post(sync_push))
+            .route("/sync/reset", post(sync_reset))
+            .route("/billing/checkout", post(billing_checkout))
+            .route("/billing/portal", post(billing_portal))
+            .with_state(Arc::clone(&store));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Self { url, store }
+    }
+
+    /// Notes per `/sync/pull` page.
+    pub fn set_page_size(&self, size: usize) {
+        self.store.lock().unwrap().page_size = size;
+    }
+
+    /// Holds the next `/sync/pull` until the returned gate is opened.
+    pub fn hold_next_pull(&self) -> Arc<PullGate> {
+        let gate = Arc::new(PullGate::default());
+        self.store.lock().unwrap().pull_gate = Some(Arc::clone(&gate));
+        gate
+    }
+
+    /// The current id token stops working after `requests` more
+    /// authenticated requests, as if it expired mid-sync.
+    pub fn expire_token_after(&self, requests: usize) {
+        self.store.lock().unwrap().token_lifetime = Some(requests);
+    }
+
+    /// Makes `/auth/refresh` take `delay`, so concurrent refreshes overlap.
+    pub fn slow_refresh(&self, delay: std::time::Duration) {
+        self.store.lock().unwrap().refresh_delay = delay;
+    }
+
+    /// Signs the client in without the device flow: the tokens go straight
+    /// to the config directory.
+    pub fn login(&self) {
+        let (token, refresh_token) = self.store.lock().unwrap().issue_tokens();
+        risu::config::save_token_data(&token, &refresh_token).unwrap();
+    }
+
+    /// Sets the server's clock `offset` ahead of real time (behind when
+    /// negative), as `/sync/check` reports it in its `Date` header.
+    pub fn set_clock_offset(&self, offset: chrono::Duration) {
+        self.store.lock().unwrap().clock_offset = offset;
+    }
+
+    /// Makes `/auth/e2e/rotate` refuse the new key.
+    pub fn refuse_rotation(&self) {
+        self.store.lock().unwrap().refuse_rotation = true;
+    }
+
+    /// The account's salt and validator.
+    pub fn e2e_key(&self) -> (Option<String>, Option<String>) {
+        let store = self.store.lock().unwrap();
+        (
+            store.account.encryption_salt.clone(),
+            store.account.encryption_validator.clone(),
+        )
+    }
+
+    /// How many pushes had arrived when the key was rotated, if it was.
+    pub fn pushes_at_rotation(&self) -> Option<usize> {
+        self.store.lock().unwrap().pushes_at_rotation
+    }
+
+    /// Every note's content as the server holds it, by id.
+    pub fn contents(&self) -> Vec<(String, String)> {
+        self.store
+            .lock()
+            .unwrap()
+            .notes
+            .iter()
+            .map(|(id, n)| {
+                (
+                    id.clone(),
+                    n["content"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn id_token(&self) -> String {
+        self.store.lock().unwrap().id_token.clone()
+    }
+
+    pub fn refreshes(&self) -> usize {
+        self.store.lock().unwrap().refreshes
+    }
+
+    pub fn pushes(&self) -> usize {
+        self.store.lock().unwrap().pushes
+    }
+
+    pub fn pulls(&self) -> usize {
+        self.store.lock().unwrap().pulls
+    }
+
+    /// The note as the server holds it.
+    pub fn note(&self, id: &str) -> Option<Value> {
+        self.store.lock().unwrap().notes.get(id).cloned()
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.store.lock().unwrap().notes.len()
+    }
+}
+
+async fn auth_init(State(store): State<Shared>) -> Json<Value> {
+    let mut store = store.lock().unwrap();
+    let session_id = format!("session-{}", store.sessions.len() + 1);
+    store.sessions.insert(session_id.clone(), 0);
+    Json(json!({
+        "session_id": session_id,
+        "url": format!("https://example.com/login?session={}", session_id),
+    }))
+}
+
+#[derive(Deserialize)]
+struct PollQuery {
+    session: String,
+}
+
+// The first poll is still pending, as if the user hadn't signed in yet
+async fn auth_poll(State(store): State<Shared>, Query(query): Query<PollQuery>) -> Response {
+    let mut store = store.lock().unwrap();
+    let Some(polls) = store.sessions.get_mut(&query.session) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    *polls += 1;
+    if *polls == 1 {
+        return Json(json!({ "status": "pending", "token": "", "refresh_token": "" }))
+            .into_response();
+    }
+    store.sessions.remove(&query.session);
+    let (token, refresh_token) = store.issue_tokens();
+    Json(json!({ "status": "success", "token": token, "refresh_token": refresh_token }))
+        .into_response()
+}
+
+async fn auth_refresh(State(store): State<Shared>, Json(body): Json<Value>) -> Response {
+    let delay = store.lock().unwrap().refresh_delay;
+    tokio::time::sleep(delay).await;
+    let mut store = store.lock().unwrap();
+    if body["refresh_token"].as_str() != Some(store.refresh_token.as_str()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    store.refreshes += 1;
+    store.token_lifetime = None;
+    let (id_token, refresh_token) = store.issue_tokens();
+    Json(json!({ "id_token": id_token, "refresh_token": refresh_token })).into_response()
+}
+
+async fn auth_me(State(store): State<Shared>, headers: HeaderMap) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    Json(json!({
+        "id": "user-1",
+        "plan": "pro",
+        "subscription_status": "active",
+        "subscription_end_date": null,
+        "encryption_salt": store.account.encryption_salt,
+        "encryption_validator": store.account.encryption_validator,
+    }))
+    .into_response()
+}
+
+// The first device to enable E2E wins; later calls get its salt back
+async fn e2e_enable(
+    State(store): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    if store.account.encryption_salt.is_none() {
+        let (Some(salt), Some(validator)) = (body["salt"].as_str(), body["validator"].as_str())
+        else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        store.account.encryption_salt = Some(salt.to_string());
+        store.account.encryption_validator = Some(validator.to_string());
+    }
+    Json(json!({ "encryption_salt": store.account.encryption_salt })).into_response()
+}
+
+// Only an account that already has a key can change it
+async fn e2e_rotate(
+    State(store): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    let (Some(salt), Some(validator)) = (body["salt"].as_str(), body["validator"].as_str()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if store.refuse_rotation || store.account.encryption_salt.is_none() {
+        return StatusCode::CONFLICT.into_response();
+    }
+    store.account.encryption_salt = Some(salt.to_string());
+    store.account.encryption_validator = Some(validator.to_string());
+    store.pushes_at_rotation = Some(store.pushes);
+    StatusCode::OK.into_response()
+}
+
+async fn sync_check(State(store): State<Shared>, headers: HeaderMap) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    let last_updated_at = store
+        .notes
+        .values()
+        .filter_map(|n| n["updated_at"].as_str())
+        .max()
+        .unwrap_or(EPOCH)
+        .to_string();
+    let date = (chrono::Utc::now() + store.clock_offset)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    (
+        [(axum::http::header::DATE, date)],
+        Json(json!({ "last_updated_at": last_updated_at })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct PullQuery {
+    since: String,
+}
+
+// Notes updated after `since`, oldest first, one page at a time
+async fn sync_pull(
+    State(store): State<Shared>,
+    headers: HeaderMap,
+    Query(query): Query<PullQuery>,
+) -> Response {
+    let gate = store.lock().unwrap().pull_gate.take();
+    if let Some(gate) = gate {
+        gate.reached.notify_one();
+        gate.open.notified().await;
+    }
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    store.pulls += 1;
+    let mut changes: Vec<&Value> = store
+        .notes
+        .values()
+        .filter(|n| {
+            n["updated_at"]
+                .as_str()
+                .is_some_and(|t| t > query.since.as_str())
+        })
+        .collect();
+    changes.sort_by_key(|n| n["updated_at"].as_str().unwrap_or_default().to_string());
+    let has_more = changes.len() > store.page_size;
+    changes.truncate(store.page_size);
+    let next_cursor = changes
+        .last()
+        .and_then(|n| n["updated_at"].as_str())
+        .unwrap_or(&query.since)
+        .to_string();
+    Json(json!({ "changes": changes, "has_more": has_more, "next_cursor": next_cursor }))
+        .into_response()
+}
+
+async fn sync_push(
+    State(store): State<Shared>,
+    headers: HeaderMap,
+    Json(note): Json<Value>,
+) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    let Some(id) = note["id"].as_str().map(str::to_string) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    store.notes.insert(id, note);
+    store.pushes += 1;
+    StatusCode::OK.into_response()
+}
+
+async fn sync_reset(State(store): State<Shared>, headers: HeaderMap) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(&headers) {
+        return status.into_response();
+    }
+    store.notes.clear();
+    store.account = Account::default();
+    StatusCode::OK.into_response()
+}
+
+async fn billing_checkout(State(store): State<Shared>, headers: HeaderMap) -> Response {
+    billing_url(&store, &headers, "https://example.com/checkout")
+}
+
+async fn billing_portal(State(store): State<Shared>, headers: HeaderMap) -> Response {
+    billing_url(&store, &headers, "https://example.com/portal")
+}
+
+fn billing_url(store: &Shared, headers: &HeaderMap, url: &str) -> Response {
+    let mut store = store.lock().unwrap();
+    if let Err(status) = store.authorize(headers) {
+        return status.into_response();
+    }
+    Json(json!({ "url": url })).into_response()
+}
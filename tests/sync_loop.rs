@@ -0,0 +1,151 @@
+// The background sync loop against a mock server: a save that lands while a
+// pass is mid-pull gets a pass of its own straight after, and an account too
+// big for one pass is pulled over several.
+
+mod support;
+
+use risu::api::APIClient;
+use risu::crypto;
+use risu::sync::{SyncEvent, SyncStatus};
+use std::time::Duration;
+use support::{Device, MockServer};
+use zeroize::Zeroizing;
+
+// A device with E2E set up and unlocked
+async fn unlocked_device(salt: &str, validator: &str, key: &[u8; 32]) -> Device {
+    let device = Device::new();
+    device.repo.set_salt(salt).await.unwrap();
+    device.repo.set_validator(validator).await.unwrap();
+    device.unlock(Zeroizing::new(*key));
+    device
+}
+
+#[tokio::test]
+async fn a_save_during_a_pull_gets_another_pass() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    server.login();
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key("passphrase", &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    APIClient::new()
+        .e2e_enable(Some(&salt), Some(&validator))
+        .await
+        .unwrap();
+
+    // Something on the server for the other device's first pass to pull
+    let mut laptop = unlocked_device(&salt, &validator, &key).await;
+    laptop
+        .repo
+        .save_note(None, "From the laptop".to_string(), true, false)
+        .await
+        .unwrap();
+    assert_eq!(laptop.sync().await, SyncStatus::Synced);
+
+    let gate = server.hold_next_pull();
+    let Device {
+        repo,
+        manager,
+        mut events,
+        trigger,
+        ..
+    } = unlocked_device(&salt, &validator, &key).await;
+    tokio::spawn(manager.start());
+
+    gate.reached().await;
+    let id = repo
+        .save_note(None, "Typed mid-pull".to_string(), true, false)
+        .await
+        .unwrap();
+    trigger.send(()).await.unwrap();
+    // More saves before the pass ends collapse into the same extra pass
+    let _ = trigger.try_send(());
+    gate.open();
+
+    let mut passes = 0;
+    while passes < 2 {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .expect("no second pass")
+            .unwrap();
+        if event == SyncEvent::StatusChanged(SyncStatus::Synced) {
+            passes += 1;
+        }
+    }
+    assert!(server.note(&id).is_some());
+    assert!(repo.get_unsynced_notes().await.unwrap().is_empty());
+    let mut contents = repo
+        .get_notes(false)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|n| n.content)
+        .collect::<Vec<_>>();
+    contents.sort();
+    assert_eq!(contents, ["From the laptop", "Typed mid-pull"]);
+
+    // Nothing asked for a third
+    let extra = tokio::time::timeout(Duration::from_millis(500), async {
+        loop {
+            if events.recv().await == Some(SyncEvent::StatusChanged(SyncStatus::Syncing)) {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(extra.is_err());
+}
+
+#[tokio::test]
+async fn a_250_page_account_is_pulled_over_several_passes() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    server.login();
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key("passphrase", &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    APIClient::new()
+        .e2e_enable(Some(&salt), Some(&validator))
+        .await
+        .unwrap();
+
+    let mut laptop = unlocked_device(&salt, &validator, &key).await;
+    for i in 0..250 {
+        laptop
+            .repo
+            .save_note(None, format!("Note {:03}", i), true, false)
+            .await
+            .unwrap();
+    }
+    assert_eq!(laptop.sync().await, SyncStatus::Synced);
+    assert_eq!(server.note_count(), 250);
+
+    // One note a page: 250 pages, more than one pass allows
+    server.set_page_size(1);
+    let pulls_before = server.pulls();
+    let mut phone = unlocked_device(&salt, &validator, &key).await;
+    let statuses: Vec<SyncStatus> = phone
+        .sync_events()
+        .await
+        .into_iter()
+        .filter_map(|event| match event {
+            SyncEvent::StatusChanged(status) if status != SyncStatus::Syncing => Some(status),
+            _ => None,
+        })
+        .collect();
+    let incomplete = SyncStatus::Warning("Sync Incomplete".to_string());
+    assert_eq!(
+        statuses,
+        [incomplete.clone(), incomplete, SyncStatus::Synced]
+    );
+    // Every page was fetched once; none was applied twice or skipped
+    assert_eq!(server.pulls() - pulls_before, 250);
+    let contents = phone.contents().await;
+    assert_eq!(contents.len(), 250);
+    assert_eq!(contents[0], "Note 000");
+    assert_eq!(contents[249], "Note 249");
+
+    // Caught up: the next pass doesn't pull at all
+    assert_eq!(phone.sync().await, SyncStatus::Synced);
+    assert_eq!(server.pulls() - pulls_before, 250);
+}
@@ -0,0 +1,61 @@
+// Token refreshes against a mock server. Refresh tokens are single-use, so
+// clients that hit an expired id token at the same time must share one
+// refresh; and a refresh the server turns down means signing in again.
+
+mod support;
+
+use risu::api::APIClient;
+use risu::config;
+use risu::sync;
+use std::time::Duration;
+use support::MockServer;
+
+#[tokio::test]
+async fn clients_that_expire_together_share_one_refresh() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    server.login();
+    let expired = server.id_token();
+    server.expire_token_after(0);
+    server.slow_refresh(Duration::from_millis(200));
+
+    // The TUI, the sync manager and a background check, all at once
+    let requests: Vec<_> = (0..8)
+        .map(|_| tokio::spawn(async { APIClient::new().get_me().await }))
+        .collect();
+    for request in requests {
+        let me = request
+            .await
+            .unwrap()
+            .expect("request failed after the refresh");
+        assert_eq!(me.plan.as_str(), "pro");
+    }
+    assert_eq!(server.refreshes(), 1);
+    assert_ne!(config::get_token(), expired);
+    assert_eq!(config::get_token(), server.id_token());
+
+    // The next expiry refreshes again, from the rotated refresh token
+    server.expire_token_after(0);
+    APIClient::new().get_me().await.unwrap();
+    assert_eq!(server.refreshes(), 2);
+}
+
+#[tokio::test]
+async fn a_rejected_refresh_expires_the_session() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    server.login();
+    let id_token = config::get_token();
+    // Another install already used this refresh token
+    config::save_token_data(&id_token, "refresh-used-elsewhere").unwrap();
+    server.expire_token_after(0);
+
+    let err = APIClient::new().get_me().await.unwrap_err();
+    assert!(sync::is_session_expired(&err), "{:#}", err);
+    assert_eq!(server.refreshes(), 0);
+    // The dead refresh token is dropped so later calls fail fast
+    assert_eq!(config::get_token_data().refresh_token, "");
+    assert_eq!(config::get_token(), id_token);
+    let err = APIClient::new().get_me().await.unwrap_err();
+    assert!(sync::is_session_expired(&err), "{:#}", err);
+}
@@ -0,0 +1,142 @@
+// Unlocking against a mock server: a wrong passphrase, an unreachable server
+// (with and without a cached validator), and a cached validator the server no
+// longer agrees with.
+
+mod support;
+
+use risu::api::APIClient;
+use risu::crypto;
+use risu::sync::{self, PassphraseCheck};
+use support::{Device, MockServer};
+
+const PASSPHRASE: &str = "correct horse battery staple";
+
+// Nothing listens here, so every request fails to connect
+const UNREACHABLE: &str = "http://127.0.0.1:9";
+
+// Signs in and sets E2E up on the account and on a fresh device, as the TUI
+// does. The device has the salt but not yet the validator.
+async fn set_up(server: &MockServer) -> (Device, String) {
+    server.login();
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(PASSPHRASE, &salt).unwrap();
+    let validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &key).unwrap();
+    APIClient::new()
+        .e2e_enable(Some(&salt), Some(&validator))
+        .await
+        .unwrap();
+    let device = Device::new();
+    device.repo.set_salt(&salt).await.unwrap();
+    (device, salt)
+}
+
+async fn check(device: &Device, passphrase: &str) -> PassphraseCheck {
+    sync::check_passphrase(&device.repo, &APIClient::new(), passphrase.to_string()).await
+}
+
+// Points new clients at a server that isn't there
+fn go_offline() {
+    std::env::set_var("RISU_API_URL", UNREACHABLE);
+}
+
+#[tokio::test]
+async fn a_wrong_passphrase_is_refused_online_and_offline() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let (device, _) = set_up(&server).await;
+
+    assert!(matches!(
+        check(&device, "wrong").await,
+        PassphraseCheck::WrongPassphrase
+    ));
+    assert!(matches!(
+        check(&device, "").await,
+        PassphraseCheck::WrongPassphrase
+    ));
+    // The right one still works, and leaves the validator cached
+    assert!(matches!(
+        check(&device, PASSPHRASE).await,
+        PassphraseCheck::Valid(_)
+    ));
+    assert_eq!(
+        device.repo.get_validator().await.unwrap(),
+        server.e2e_key().1
+    );
+
+    // The cached validator catches it without the server
+    go_offline();
+    assert!(matches!(
+        check(&device, "wrong").await,
+        PassphraseCheck::WrongPassphrase
+    ));
+}
+
+#[tokio::test]
+async fn an_unreachable_server_unlocks_against_the_cached_validator() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let (device, salt) = set_up(&server).await;
+    let key = crypto::derive_key(PASSPHRASE, &salt).unwrap();
+
+    // Nothing cached yet: the key is only as good as the passphrase typed
+    go_offline();
+    match check(&device, PASSPHRASE).await {
+        PassphraseCheck::Unverified(unverified, _) => assert_eq!(*unverified, *key),
+        other => panic!("expected an unverified key, got {:?}", other),
+    }
+    match check(&device, "wrong").await {
+        PassphraseCheck::Unverified(unverified, _) => assert_ne!(*unverified, *key),
+        other => panic!("expected an unverified key, got {:?}", other),
+    }
+
+    // Once a validator is cached, an offline unlock is checked
+    device
+        .repo
+        .set_validator(&server.e2e_key().1.unwrap())
+        .await
+        .unwrap();
+    match check(&device, PASSPHRASE).await {
+        PassphraseCheck::Valid(valid) => assert_eq!(*valid, *key),
+        other => panic!("expected a valid key, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn the_servers_validator_overrides_a_stale_cached_one() {
+    let server = MockServer::start().await;
+    let _env = support::isolate(&server).await;
+    let (device, salt) = set_up(&server).await;
+    assert!(matches!(
+        check(&device, PASSPHRASE).await,
+        PassphraseCheck::Valid(_)
+    ));
+    let cached = device.repo.get_validator().await.unwrap();
+
+    // Another device changed the passphrase; this one still has the old
+    // validator cached, which the old passphrase matches
+    let new_key = crypto::derive_key("new passphrase", &salt).unwrap();
+    let new_validator = crypto::encrypt(crypto::VALIDATOR_PLAINTEXT, &new_key).unwrap();
+    APIClient::new()
+        .e2e_rotate(&salt, &new_validator)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        check(&device, PASSPHRASE).await,
+        PassphraseCheck::WrongPassphrase
+    ));
+    let refreshed = device.repo.get_validator().await.unwrap();
+    assert_ne!(refreshed, cached);
+    assert_eq!(refreshed, Some(new_validator));
+    assert!(matches!(
+        check(&device, "new passphrase").await,
+        PassphraseCheck::Valid(_)
+    ));
+
+    // Offline, the refreshed cache still refuses the old passphrase
+    go_offline();
+    assert!(matches!(
+        check(&device, PASSPHRASE).await,
+        PassphraseCheck::WrongPassphrase
+    ));
+}